@@ -1,156 +1,1429 @@
-use crate::repo::user_account::{Access, ArcUserAccountRepo, Creds, UserAccount};
+use crate::crockford;
+use crate::hashing::{Hashers, VerifyError};
+use crate::jwt_keys::JwtKeySet;
+use crate::password_policy::{PasswordPolicy, PolicyError};
+use crate::repo::api_key::{ApiKey, ArcApiKeyRepo, Scope};
+use crate::repo::company::ArcCompanyRepo;
+use crate::repo::one_time_token::{ArcOneTimeTokenRepo, OneTimeToken, Purpose};
+use crate::repo::refresh_token::{ArcRefreshTokenRepo, RefreshToken};
+use crate::repo::session::{ArcSessionRepo, Session};
+use crate::repo::user_account::{Access, ArcUserAccountRepo, RoleGrant, Totp, UserAccount};
+use crate::repo::{DeleteError, ReplaceError};
+use crate::throttle::{
+    email_key, ip_key, mail_request_ip_key, mail_request_key, phone_verification_key,
+    sms_request_key, user_account_key, LoginThrottle,
+};
+use crate::totp;
+use anyhow::Context;
+use chrono::{DateTime, Duration, TimeZone, Utc};
 use data_encoding::HEXLOWER_PERMISSIVE;
-use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation};
-use ring::digest::SHA512_OUTPUT_LEN;
-use ring::pbkdf2;
+use jsonwebtoken::{Algorithm, Header, Validation};
+use ring::digest;
 use ring::rand::{SecureRandom, SystemRandom};
 use serde::{Deserialize, Serialize};
-use std::num::NonZeroU32;
 
-const N_ITER: u32 = 100_000;
-const CREDENTIAL_LEN: usize = SHA512_OUTPUT_LEN;
+const RANDOM_TOKEN_LEN: usize = 32;
+/// Makes API keys recognizable, such as to secret scanners.
+const API_KEY_PREFIX: &str = "sw_";
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_LEN: usize = 10;
+/// How long a user has to enter a second factor after their password.
+pub const LOGIN_CHALLENGE_TTL_MINUTES: i64 = 5;
+pub const EMAIL_VERIFICATION_TTL_HOURS: i64 = 24;
+pub const PHONE_VERIFICATION_TTL_MINUTES: i64 = 10;
+const PHONE_VERIFICATION_CODE_DIGITS: usize = 6;
 
 #[derive(thiserror::Error, Debug)]
-pub enum VerifyError {
-    #[error("incorrect password")]
-    IncorrectPassword,
+pub enum LoginError {
+    #[error("incorrect email or password")]
+    IncorrectCredentials,
+    #[error("too many failed login attempts")]
+    Throttled,
+    #[error("user account is deactivated or suspended")]
+    Inactive,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum TotpError {
+    #[error("two-factor authentication is already enabled")]
+    AlreadyEnabled,
+    #[error("two-factor authentication is not set up")]
+    NotEnrolled,
+    #[error("incorrect code")]
+    IncorrectCode,
+    #[error("too many incorrect codes")]
+    Throttled,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum VerificationError {
+    #[error("incorrect or expired code")]
+    IncorrectCode,
+    #[error("too many incorrect codes")]
+    Throttled,
+    #[error("email address or phone number changed since the code was sent")]
+    ContactChanged,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum InvitationError {
+    #[error("invalid or expired invitation")]
+    Invalid,
+    #[error("user account is already active")]
+    AlreadyActive,
     #[error(transparent)]
-    Other(#[from] anyhow::Error),
+    Policy(#[from] PolicyError),
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("too many requests")]
+pub struct RequestThrottled;
+
+#[derive(thiserror::Error, Debug)]
+pub enum RefreshError {
+    #[error("invalid refresh token")]
+    Invalid,
+    #[error("expired refresh token")]
+    Expired,
+    #[error("refresh token reuse detected")]
+    Reused,
 }
 
 #[derive(Clone)]
 pub struct AuthProvider {
+    /// Unscoped, since user accounts are looked up before their company is known.
     pub user_account_repo: ArcUserAccountRepo,
+    pub company_repo: ArcCompanyRepo,
+    pub api_key_repo: ArcApiKeyRepo,
+    pub refresh_token_repo: ArcRefreshTokenRepo,
+    pub session_repo: ArcSessionRepo,
+    pub one_time_token_repo: ArcOneTimeTokenRepo,
+    pub refresh_token_ttl: Duration,
+    pub password_reset_ttl: Duration,
+    pub invitation_ttl: Duration,
+    pub login_link_ttl: Duration,
+    pub password_policy: PasswordPolicy,
+    pub hashers: Hashers,
+    pub login_throttle: LoginThrottle,
 }
 
 impl AuthProvider {
-    pub async fn set_password(&self, user_account_id: &str, password: &str) -> anyhow::Result<()> {
-        let creds = create_creds(password);
+    /// Set the password of a user account if it follows the password policy. Existing sessions are
+    /// revoked, since they may belong to someone who knew the old password.
+    pub async fn set_password(
+        &self,
+        user_account_id: &str,
+        password: &str,
+    ) -> anyhow::Result<Result<(), PolicyError>> {
+        if let Err(e) = self.password_policy.check(password) {
+            return Ok(Err(e));
+        }
+        let mut history = self
+            .user_account_repo
+            .creds_history(user_account_id)
+            .await?;
+        history.truncate(self.password_policy.history_len);
+        if self
+            .hashers
+            .spawn_verify_any(password, history.clone())
+            .await?
+        {
+            return Ok(Err(PolicyError::Reused));
+        }
+        // The new password counts towards the history length.
+        history.truncate(self.password_policy.history_len.saturating_sub(1));
+        let creds = self.hashers.spawn_create_creds(password).await?;
         self.user_account_repo
-            .set_creds(user_account_id, creds)
+            .set_creds(user_account_id, creds, history)
             .await?;
-        Ok(())
+        self.revoke_sessions(user_account_id).await?;
+        Ok(Ok(()))
     }
 
+    /// Check the password of a user account. Once the password is known to be correct, it is
+    /// hashed again if its hash is outdated. A user account without a password, such as one whose
+    /// invitation has not been accepted, never matches.
     pub async fn verify_password(
         &self,
         user_account_id: &str,
         password: &str,
     ) -> anyhow::Result<Result<(), VerifyError>> {
-        let creds = self.user_account_repo.creds(user_account_id).await?;
-        Ok(match creds {
+        let mut history = self
+            .user_account_repo
+            .creds_history(user_account_id)
+            .await?;
+        if history.is_empty() {
+            // Check anyway so the response time does not reveal that there is no password.
+            let _ = self.verify_dummy_password(password).await;
+            return Ok(Err(VerifyError::IncorrectPassword));
+        }
+        let creds = history.remove(0);
+        if let Err(e) = self
+            .hashers
+            .spawn_verify_password(password, creds.clone())
+            .await
+        {
+            return Ok(Err(e));
+        }
+        if self.hashers.needs_rehash(&creds)? {
+            let creds = self.hashers.spawn_create_creds(password).await?;
+            self.user_account_repo
+                .set_creds(user_account_id, creds, history)
+                .await?;
+        }
+        Ok(Ok(()))
+    }
+
+    /// Check a password against credentials that never match, taking as long as a real check.
+    async fn verify_dummy_password(&self, password: &str) -> Result<(), VerifyError> {
+        self.hashers
+            .spawn_verify_password(password, self.hashers.dummy_creds.clone())
+            .await
+    }
+
+    /// Find the user account with an email address and check its password. Failures are throttled
+    /// by user account and by client IP address. The same error is returned whether or not the
+    /// user account exists.
+    pub async fn authenticate(
+        &self,
+        email: &str,
+        password: &str,
+        client_ip: Option<&str>,
+    ) -> anyhow::Result<Result<UserAccount, LoginError>> {
+        let user_account = self.user_account_repo.find_by_email(email).await?;
+        let mut keys = vec![match &user_account {
+            Some(user_account) => user_account_key(&user_account.id),
+            None => email_key(email),
+        }];
+        if let Some(client_ip) = client_ip {
+            keys.push(ip_key(client_ip));
+        }
+        if self.login_throttle.record_attempt(&keys).await? {
+            return Ok(Err(LoginError::Throttled));
+        }
+        let verified = match &user_account {
+            Some(user_account) => self
+                .verify_password(&user_account.id, password)
+                .await?
+                .is_ok(),
             None => {
-                if password.is_empty() {
-                    Ok(())
+                // Check anyway so the response time does not reveal that the account is missing.
+                let _ = self.verify_dummy_password(password).await;
+                false
+            }
+        };
+        match user_account {
+            Some(user_account) if verified => {
+                // With two-factor authentication, attempts are cleared once the second factor is
+                // accepted, so that logging in again with the password does not allow more guesses.
+                if !self.totp_enabled(&user_account.id).await? {
+                    self.login_throttle.reset(&keys[0]).await?;
+                }
+                self.login_throttle.take_back(&keys[1..]).await?;
+                if user_account.is_active(Utc::now()) {
+                    Ok(Ok(user_account))
                 } else {
-                    Err(VerifyError::IncorrectPassword)
+                    Ok(Err(LoginError::Inactive))
+                }
+            }
+            _ => Ok(Err(LoginError::IncorrectCredentials)),
+        }
+    }
+
+    /// Start a session for a user account and return its first refresh token.
+    pub async fn create_session(&self, user_account_id: &str, jti: &str) -> anyhow::Result<String> {
+        let now = Utc::now();
+        let session = Session {
+            id: crockford::random_id(),
+            user_account_id: user_account_id.to_string(),
+            jti: jti.to_string(),
+            created_at: now,
+            refreshed_at: now,
+            expires_at: now + self.refresh_token_ttl,
+            impersonated_by: None,
+        };
+        self.session_repo.insert_one(session.clone()).await?;
+        self.create_refresh_token(&session.user_account_id, &session.id, session.expires_at)
+            .await
+    }
+
+    /// Start a session for an admin acting as a user account. It has no refresh token, so it ends
+    /// when its access token expires.
+    pub async fn create_impersonation_session(
+        &self,
+        claims: &Claims,
+        actor_id: &str,
+    ) -> anyhow::Result<Session> {
+        let now = Utc::now();
+        let session = Session {
+            id: crockford::random_id(),
+            user_account_id: claims.sub.clone(),
+            jti: claims.jti.clone(),
+            created_at: now,
+            refreshed_at: now,
+            expires_at: Utc.timestamp(claims.exp, 0),
+            impersonated_by: Some(actor_id.to_string()),
+        };
+        self.session_repo.insert_one(session.clone()).await?;
+        Ok(session)
+    }
+
+    /// Move the session of a used refresh token to a new access token and return the next refresh
+    /// token.
+    pub async fn refresh_session(
+        &self,
+        used: &RefreshToken,
+        jti: &str,
+    ) -> anyhow::Result<Result<String, RefreshError>> {
+        let expires_at = Utc::now() + self.refresh_token_ttl;
+        match self
+            .session_repo
+            .refresh_one(&used.family_id, jti, expires_at)
+            .await
+        {
+            Ok(()) => Ok(Ok(self
+                .create_refresh_token(&used.user_account_id, &used.family_id, expires_at)
+                .await?)),
+            Err(ReplaceError::NotFound) => Ok(Err(RefreshError::Invalid)),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Check that the session of an access token has not been revoked or superseded. Deactivating
+    /// or suspending a user account revokes its sessions, so its tokens fail this check.
+    pub async fn verify_session(&self, jti: &str) -> anyhow::Result<bool> {
+        Ok(self.session_repo.find_one_by_jti(jti).await?.is_some())
+    }
+
+    /// Check that credentials are still valid, for connections that outlive the request that
+    /// checked them.
+    pub async fn verify_credentials(
+        &self,
+        claims: Option<&Claims>,
+        api_key: Option<&ApiKey>,
+    ) -> anyhow::Result<bool> {
+        if let Some(claims) = claims {
+            if !self.verify_session(&claims.jti).await? {
+                return Ok(false);
+            }
+        }
+        if let Some(api_key) = api_key {
+            if self
+                .api_key_repo
+                .find_by_hash(&api_key.key_hash)
+                .await?
+                .is_none()
+            {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    pub async fn revoke_session(&self, session_id: &str) -> anyhow::Result<()> {
+        match self.session_repo.delete_one(session_id).await {
+            Ok(()) | Err(DeleteError::NotFound) => {}
+            Err(DeleteError::Other(e)) => return Err(e),
+        }
+        self.refresh_token_repo.delete_family(session_id).await?;
+        Ok(())
+    }
+
+    pub async fn revoke_sessions(&self, user_account_id: &str) -> anyhow::Result<()> {
+        self.session_repo
+            .delete_by_user_account(user_account_id)
+            .await?;
+        self.refresh_token_repo
+            .delete_by_user_account(user_account_id)
+            .await?;
+        Ok(())
+    }
+
+    /// Consume a refresh token so it cannot be used again. If the token was already used, it has
+    /// likely been stolen, so its whole session is revoked.
+    pub async fn use_refresh_token(
+        &self,
+        token: &str,
+    ) -> anyhow::Result<Result<RefreshToken, RefreshError>> {
+        let token_hash = hash_token(token);
+        match self.refresh_token_repo.use_one(&token_hash).await? {
+            Some(refresh_token) if refresh_token.expires_at <= Utc::now() => {
+                Ok(Err(RefreshError::Expired))
+            }
+            Some(refresh_token) => Ok(Ok(refresh_token)),
+            None => match self.refresh_token_repo.find_one(&token_hash).await? {
+                Some(refresh_token) => {
+                    log::warn!(
+                        "Refresh token reuse detected for user account {}",
+                        refresh_token.user_account_id
+                    );
+                    self.revoke_session(&refresh_token.family_id).await?;
+                    Ok(Err(RefreshError::Reused))
                 }
+                None => Ok(Err(RefreshError::Invalid)),
+            },
+        }
+    }
+
+    /// Issue a single-use token for a user account, such as for a password reset.
+    pub async fn create_one_time_token(
+        &self,
+        user_account_id: &str,
+        purpose: Purpose,
+        ttl: Duration,
+    ) -> anyhow::Result<String> {
+        let token = random_token();
+        self.one_time_token_repo
+            .insert_one(OneTimeToken {
+                token_hash: hash_token(&token),
+                purpose,
+                user_account_id: user_account_id.to_string(),
+                expires_at: Utc::now() + ttl,
+                contact: None,
+            })
+            .await?;
+        Ok(token)
+    }
+
+    /// Consume a single-use token. Returns the ID of the user account it was issued to, or none if
+    /// the token is invalid or expired. Other tokens of the user account with the same purpose are
+    /// discarded.
+    pub async fn use_one_time_token(
+        &self,
+        token: &str,
+        purpose: Purpose,
+    ) -> anyhow::Result<Option<String>> {
+        let one_time_token = self
+            .one_time_token_repo
+            .take_one(&hash_token(token), purpose)
+            .await?;
+        match one_time_token {
+            None => Ok(None),
+            Some(one_time_token) => {
+                self.one_time_token_repo
+                    .delete_by_user_account(&one_time_token.user_account_id, purpose)
+                    .await?;
+                Ok(Some(one_time_token.user_account_id))
             }
-            Some(creds) => verify_password(password, &creds),
+        }
+    }
+
+    /// Issue an invitation token to a user account without a password. Earlier invitations stop
+    /// working, so only the most recent email can activate the user account.
+    pub async fn create_invitation(
+        &self,
+        user_account_id: &str,
+    ) -> anyhow::Result<Result<String, InvitationError>> {
+        if self
+            .user_account_repo
+            .creds(user_account_id)
+            .await?
+            .is_some()
+        {
+            return Ok(Err(InvitationError::AlreadyActive));
+        }
+        self.one_time_token_repo
+            .delete_by_user_account(user_account_id, Purpose::Invitation)
+            .await?;
+        Ok(Ok(self
+            .create_one_time_token(user_account_id, Purpose::Invitation, self.invitation_ttl)
+            .await?))
+    }
+
+    /// Activate an invited user account by setting its first password. An invitation cannot
+    /// replace the password of a user account that already has one. Other invitations of the user
+    /// account stop working either way.
+    pub async fn accept_invitation(
+        &self,
+        token: &str,
+        password: &str,
+    ) -> anyhow::Result<Result<(), InvitationError>> {
+        // Check the password first so a weak password does not use up the token.
+        if let Err(e) = self.password_policy.check(password) {
+            return Ok(Err(e.into()));
+        }
+        let user_account_id = match self.use_one_time_token(token, Purpose::Invitation).await? {
+            Some(user_account_id) => user_account_id,
+            None => return Ok(Err(InvitationError::Invalid)),
+        };
+        if self
+            .user_account_repo
+            .creds(&user_account_id)
+            .await?
+            .is_some()
+        {
+            return Ok(Err(InvitationError::AlreadyActive));
+        }
+        Ok(self
+            .set_password(&user_account_id, password)
+            .await?
+            .map_err(Into::into))
+    }
+
+    /// Issue a password reset token for the user account with an email address, if there is one.
+    /// Requests are throttled like login links.
+    pub async fn create_password_reset(
+        &self,
+        email: &str,
+        client_ip: Option<&str>,
+    ) -> anyhow::Result<Result<Option<(UserAccount, String)>, RequestThrottled>> {
+        if self.mail_request_throttled(email, client_ip).await? {
+            return Ok(Err(RequestThrottled));
+        }
+        let user_account = match self.user_account_repo.find_by_email(email).await? {
+            Some(user_account) => user_account,
+            None => return Ok(Ok(None)),
+        };
+        let token = self
+            .create_one_time_token(
+                &user_account.id,
+                Purpose::PasswordReset,
+                self.password_reset_ttl,
+            )
+            .await?;
+        Ok(Ok(Some((user_account, token))))
+    }
+
+    /// Issue a login link token for the user account with an email address, if it may log in that
+    /// way. Requests are throttled by email address and client IP address whether or not the user
+    /// account exists, so throttling does not reveal which email addresses have accounts.
+    pub async fn create_login_link(
+        &self,
+        email: &str,
+        client_ip: Option<&str>,
+    ) -> anyhow::Result<Result<Option<(UserAccount, String)>, RequestThrottled>> {
+        if self.mail_request_throttled(email, client_ip).await? {
+            return Ok(Err(RequestThrottled));
+        }
+        let user_account = match self.user_account_repo.find_by_email(email).await? {
+            Some(user_account) => user_account,
+            None => return Ok(Ok(None)),
+        };
+        // Invited user accounts must accept their invitation, which is how they prove they own the
+        // email address and choose a password.
+        if !user_account.is_active(Utc::now())
+            || self
+                .user_account_repo
+                .creds(&user_account.id)
+                .await?
+                .is_none()
+            || !self.login_links_enabled(&user_account).await?
+        {
+            return Ok(Ok(None));
+        }
+        let token = self
+            .create_one_time_token(&user_account.id, Purpose::LoginLink, self.login_link_ttl)
+            .await?;
+        Ok(Ok(Some((user_account, token))))
+    }
+
+    /// Count a request to email an address and return whether it must wait.
+    async fn mail_request_throttled(
+        &self,
+        email: &str,
+        client_ip: Option<&str>,
+    ) -> anyhow::Result<bool> {
+        let mut keys = vec![mail_request_key(email)];
+        if let Some(client_ip) = client_ip {
+            keys.push(mail_request_ip_key(client_ip));
+        }
+        self.login_throttle.record_attempt(&keys).await
+    }
+
+    /// Issue a token to email to a user account, which proves it receives email at its address.
+    /// Requests are throttled by email address like login links.
+    pub async fn create_email_verification_token(
+        &self,
+        user_account: &UserAccount,
+    ) -> anyhow::Result<Result<String, RequestThrottled>> {
+        if self
+            .login_throttle
+            .record_attempt(&[mail_request_key(&user_account.email)])
+            .await?
+        {
+            return Ok(Err(RequestThrottled));
+        }
+        let token = random_token();
+        self.one_time_token_repo
+            .insert_one(OneTimeToken {
+                token_hash: hash_token(&token),
+                purpose: Purpose::EmailVerification,
+                user_account_id: user_account.id.clone(),
+                expires_at: Utc::now() + Duration::hours(EMAIL_VERIFICATION_TTL_HOURS),
+                contact: Some(user_account.email.clone()),
+            })
+            .await?;
+        Ok(Ok(token))
+    }
+
+    /// Consume an email verification token and mark the address it was sent to verified, unless
+    /// the user account changed its address since.
+    pub async fn verify_email(&self, token: &str) -> anyhow::Result<Result<(), VerificationError>> {
+        let one_time_token = match self
+            .one_time_token_repo
+            .take_one(&hash_token(token), Purpose::EmailVerification)
+            .await?
+        {
+            Some(one_time_token) => one_time_token,
+            None => return Ok(Err(VerificationError::IncorrectCode)),
+        };
+        let verified = match &one_time_token.contact {
+            Some(email) => {
+                self.user_account_repo
+                    .set_email_verified(&one_time_token.user_account_id, email)
+                    .await?
+            }
+            None => false,
+        };
+        Ok(if verified {
+            Ok(())
+        } else {
+            Err(VerificationError::ContactChanged)
+        })
+    }
+
+    /// Create a code to text to the phone number of a user account. Earlier codes stop working.
+    /// Codes are short enough to type, so they are only unique per user account. Requests are
+    /// throttled by phone number, so that nobody can flood it with texts.
+    pub async fn create_phone_verification_code(
+        &self,
+        user_account: &UserAccount,
+    ) -> anyhow::Result<Result<String, RequestThrottled>> {
+        if self
+            .login_throttle
+            .record_attempt(&[sms_request_key(&user_account.phone)])
+            .await?
+        {
+            return Ok(Err(RequestThrottled));
+        }
+        self.one_time_token_repo
+            .delete_by_user_account(&user_account.id, Purpose::PhoneVerification)
+            .await?;
+        let code = random_code(PHONE_VERIFICATION_CODE_DIGITS);
+        self.one_time_token_repo
+            .insert_one(OneTimeToken {
+                token_hash: phone_verification_hash(&user_account.id, &code),
+                purpose: Purpose::PhoneVerification,
+                user_account_id: user_account.id.clone(),
+                expires_at: Utc::now() + Duration::minutes(PHONE_VERIFICATION_TTL_MINUTES),
+                contact: Some(user_account.phone.clone()),
+            })
+            .await?;
+        Ok(Ok(code))
+    }
+
+    /// Consume a phone verification code and mark the number it was sent to verified, unless the
+    /// user account changed its number since. Guesses are throttled like passwords, since codes
+    /// are short.
+    pub async fn verify_phone(
+        &self,
+        user_account_id: &str,
+        code: &str,
+    ) -> anyhow::Result<Result<(), VerificationError>> {
+        let keys = [phone_verification_key(user_account_id)];
+        if self.login_throttle.record_attempt(&keys).await? {
+            return Ok(Err(VerificationError::Throttled));
+        }
+        let token_hash = phone_verification_hash(user_account_id, code.trim());
+        let one_time_token = match self
+            .one_time_token_repo
+            .take_one(&token_hash, Purpose::PhoneVerification)
+            .await?
+        {
+            Some(one_time_token) => one_time_token,
+            None => return Ok(Err(VerificationError::IncorrectCode)),
+        };
+        self.login_throttle.reset(&keys[0]).await?;
+        let verified = match &one_time_token.contact {
+            Some(phone) => {
+                self.user_account_repo
+                    .set_phone_verified(user_account_id, phone)
+                    .await?
+            }
+            None => false,
+        };
+        Ok(if verified {
+            Ok(())
+        } else {
+            Err(VerificationError::ContactChanged)
         })
     }
+
+    /// Whether a user account must enter a second factor to log in.
+    pub async fn totp_enabled(&self, user_account_id: &str) -> anyhow::Result<bool> {
+        Ok(matches!(
+            self.user_account_repo.totp(user_account_id).await?,
+            Some(totp) if totp.enabled
+        ))
+    }
+
+    /// Whether the company of a user account requires it to use two-factor authentication.
+    pub async fn requires_totp(&self, user_account: &UserAccount) -> anyhow::Result<bool> {
+        if !matches!(user_account.access, Access::Admin | Access::SuperAdmin) {
+            return Ok(false);
+        }
+        let company = self.company_repo.find_one(&user_account.company_id).await?;
+        Ok(matches!(company, Some(company) if company.require_admin_two_factor))
+    }
+
+    /// Whether the company of a user account allows logging in with emailed links.
+    pub async fn login_links_enabled(&self, user_account: &UserAccount) -> anyhow::Result<bool> {
+        let company = self.company_repo.find_one(&user_account.company_id).await?;
+        Ok(matches!(company, Some(company) if company.login_links_enabled))
+    }
+
+    /// Start enrolling a user account in two-factor authentication. Returns the secret to add to
+    /// an authenticator app. A secret that is not yet confirmed is reused, so a failed attempt to
+    /// confirm does not require adding the account to the app again.
+    pub async fn start_totp_enrollment(
+        &self,
+        user_account_id: &str,
+    ) -> anyhow::Result<Result<String, TotpError>> {
+        match self.user_account_repo.totp(user_account_id).await? {
+            Some(totp) if totp.enabled => Ok(Err(TotpError::AlreadyEnabled)),
+            Some(totp) => Ok(Ok(totp.secret)),
+            None => {
+                let secret = totp::generate_secret();
+                self.user_account_repo
+                    .set_totp(
+                        user_account_id,
+                        Some(Totp {
+                            secret: secret.clone(),
+                            enabled: false,
+                            recovery_code_hashes: Vec::new(),
+                            last_used_step: None,
+                        }),
+                    )
+                    .await?;
+                Ok(Ok(secret))
+            }
+        }
+    }
+
+    /// Enable two-factor authentication once the user proves their app produces valid codes.
+    /// Returns recovery codes, which can each be used once in place of a code.
+    pub async fn confirm_totp_enrollment(
+        &self,
+        user_account_id: &str,
+        code: &str,
+    ) -> anyhow::Result<Result<Vec<String>, TotpError>> {
+        let totp = match self.user_account_repo.totp(user_account_id).await? {
+            None => return Ok(Err(TotpError::NotEnrolled)),
+            Some(totp) if totp.enabled => return Ok(Err(TotpError::AlreadyEnabled)),
+            Some(totp) => totp,
+        };
+        let step = match totp::verify(&totp.secret, code, Utc::now())? {
+            Some(step) => step,
+            None => return Ok(Err(TotpError::IncorrectCode)),
+        };
+        let recovery_codes: Vec<_> = (0..RECOVERY_CODE_COUNT)
+            .map(|_| crockford::random_id()[..RECOVERY_CODE_LEN].to_string())
+            .collect();
+        self.user_account_repo
+            .set_totp(
+                user_account_id,
+                Some(Totp {
+                    secret: totp.secret,
+                    enabled: true,
+                    recovery_code_hashes: recovery_codes
+                        .iter()
+                        .map(|code| hash_token(code))
+                        .collect(),
+                    last_used_step: Some(step),
+                }),
+            )
+            .await?;
+        Ok(Ok(recovery_codes))
+    }
+
+    /// Check a code from an authenticator app or a recovery code. Each code is accepted once.
+    /// Failures are throttled by user account, together with failed passwords.
+    pub async fn verify_second_factor(
+        &self,
+        user_account_id: &str,
+        code: &str,
+    ) -> anyhow::Result<Result<(), TotpError>> {
+        let totp = match self.user_account_repo.totp(user_account_id).await? {
+            Some(totp) if totp.enabled => totp,
+            _ => return Ok(Err(TotpError::NotEnrolled)),
+        };
+        let keys = [user_account_key(user_account_id)];
+        if self.login_throttle.record_attempt(&keys).await? {
+            return Ok(Err(TotpError::Throttled));
+        }
+        let accepted = match totp::verify(&totp.secret, code, Utc::now())? {
+            Some(step) => {
+                self.user_account_repo
+                    .use_totp_step(user_account_id, step)
+                    .await?
+            }
+            None => {
+                let code_hash = hash_token(&normalize_recovery_code(code));
+                self.user_account_repo
+                    .use_recovery_code(user_account_id, &code_hash)
+                    .await?
+            }
+        };
+        if accepted {
+            self.login_throttle.reset(&keys[0]).await?;
+            Ok(Ok(()))
+        } else {
+            Ok(Err(TotpError::IncorrectCode))
+        }
+    }
+
+    pub async fn disable_totp(&self, user_account_id: &str) -> anyhow::Result<()> {
+        self.user_account_repo.set_totp(user_account_id, None).await
+    }
+
+    /// Issue an API key for a company. Returns the stored key and the key itself, which cannot be
+    /// recovered later.
+    pub async fn create_api_key(
+        &self,
+        company_id: &str,
+        name: &str,
+        scopes: Vec<Scope>,
+    ) -> anyhow::Result<(ApiKey, String)> {
+        let key = format!("{}{}", API_KEY_PREFIX, random_token());
+        let api_key = ApiKey {
+            id: crockford::random_id(),
+            company_id: company_id.to_string(),
+            name: name.to_string(),
+            key_hash: hash_token(&key),
+            scopes,
+            created_at: Utc::now(),
+        };
+        self.api_key_repo.insert_one(api_key.clone()).await?;
+        Ok((api_key, key))
+    }
+
+    pub async fn verify_api_key(&self, key: &str) -> anyhow::Result<Option<ApiKey>> {
+        self.api_key_repo.find_by_hash(&hash_token(key)).await
+    }
+
+    async fn create_refresh_token(
+        &self,
+        user_account_id: &str,
+        family_id: &str,
+        expires_at: DateTime<Utc>,
+    ) -> anyhow::Result<String> {
+        let token = random_token();
+        self.refresh_token_repo
+            .insert_one(RefreshToken {
+                token_hash: hash_token(&token),
+                family_id: family_id.to_string(),
+                user_account_id: user_account_id.to_string(),
+                expires_at,
+                used: false,
+            })
+            .await?;
+        Ok(token)
+    }
 }
 
-pub fn create_creds(password: &str) -> Creds {
+/// Generate a random, high-entropy token, encoded as hex.
+pub fn random_token() -> String {
     let rng = SystemRandom::new();
-    let mut salt = [0u8; CREDENTIAL_LEN];
-    rng.fill(&mut salt).unwrap();
-    let mut pbkdf2_hash = [0u8; CREDENTIAL_LEN];
-    pbkdf2::derive(
-        pbkdf2::PBKDF2_HMAC_SHA512,
-        NonZeroU32::new(N_ITER).unwrap(),
-        &salt,
-        password.as_bytes(),
-        &mut pbkdf2_hash,
-    );
-    Creds {
-        password_hash: HEXLOWER_PERMISSIVE.encode(&pbkdf2_hash),
-        salt: HEXLOWER_PERMISSIVE.encode(&salt),
-    }
-}
-
-pub fn verify_password(password: &str, creds: &Creds) -> Result<(), VerifyError> {
-    let password_hash = HEXLOWER_PERMISSIVE
-        .decode(creds.password_hash.as_bytes())
-        .map_err(anyhow::Error::from)?;
-    let salt = HEXLOWER_PERMISSIVE
-        .decode(creds.salt.as_bytes())
-        .map_err(anyhow::Error::from)?;
-    pbkdf2::verify(
-        pbkdf2::PBKDF2_HMAC_SHA512,
-        NonZeroU32::new(N_ITER).unwrap(),
-        &salt,
-        password.as_bytes(),
-        &password_hash,
-    )
-    .map_err(|_| VerifyError::IncorrectPassword)
-}
-
-/// A user account bearer token. The token is missing recommended fields like exp and iat for simplicity.
+    let mut token_bytes = [0u8; RANDOM_TOKEN_LEN];
+    rng.fill(&mut token_bytes).unwrap();
+    HEXLOWER_PERMISSIVE.encode(&token_bytes)
+}
+
+/// Generate a random numeric code, padded with zeros.
+pub fn random_code(digits: usize) -> String {
+    let rng = SystemRandom::new();
+    (0..digits)
+        .map(|_| {
+            // Reject bytes that would make some digits more likely than others.
+            loop {
+                let mut byte = [0u8; 1];
+                rng.fill(&mut byte).unwrap();
+                if byte[0] < 250 {
+                    return char::from(b'0' + byte[0] % 10);
+                }
+            }
+        })
+        .collect()
+}
+
+fn phone_verification_hash(user_account_id: &str, code: &str) -> String {
+    hash_token(&format!("{}:{}", user_account_id, code))
+}
+
+/// Recovery codes are case-insensitive and may be entered with spaces or dashes.
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// Hash a random, high-entropy token for storage. Salting is unnecessary for such tokens.
+pub fn hash_token(token: &str) -> String {
+    HEXLOWER_PERMISSIVE.encode(digest::digest(&digest::SHA256, token.as_bytes()).as_ref())
+}
+
+/// A short-lived user account bearer token.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
     /// Subject is user account ID.
     pub sub: String,
     pub access: Access,
+    /// Company of the user account. Requests are limited to its data unless the user account is a
+    /// super admin.
+    pub company_id: String,
+    /// Roles in addition to the access level.
+    #[serde(default)]
+    pub roles: Vec<RoleGrant>,
+    /// Person the user account belongs to.
+    #[serde(default)]
+    pub person_id: Option<String>,
+    /// Admin acting as the subject, if the token is an impersonation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<Actor>,
+    /// Issued at, in seconds since the Unix epoch.
+    pub iat: i64,
+    /// Expiration time, in seconds since the Unix epoch.
+    pub exp: i64,
+    /// Token ID, unique for each token.
+    pub jti: String,
 }
 
-#[derive(Debug, Clone)]
+/// The party acting on behalf of the subject of a token, as in RFC 8693.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Actor {
+    /// User account ID of the actor.
+    pub sub: String,
+}
+
+#[derive(Clone)]
 pub struct ClaimsProvider {
-    pub private_key: String,
+    pub jwt_key_set: JwtKeySet,
+    pub access_token_ttl: Duration,
+    pub impersonation_ttl: Duration,
 }
 
 impl ClaimsProvider {
-    pub fn create_token(&self, user_account: &UserAccount) -> anyhow::Result<String> {
-        Ok(jsonwebtoken::encode(
-            &Header::default(),
-            &Claims {
-                sub: user_account.id.to_string(),
-                access: user_account.access,
-            },
-            &EncodingKey::from_secret(self.private_key.as_bytes()),
-        )?)
+    pub fn create_claims(&self, user_account: &UserAccount) -> Claims {
+        let now = Utc::now();
+        Claims {
+            sub: user_account.id.to_string(),
+            access: user_account.access,
+            company_id: user_account.company_id.clone(),
+            roles: user_account.roles.clone(),
+            person_id: user_account.person_id.clone(),
+            act: None,
+            iat: now.timestamp(),
+            exp: (now + self.access_token_ttl).timestamp(),
+            jti: crockford::random_id(),
+        }
+    }
+
+    /// Claims for an admin acting as a user account, which expire sooner than usual.
+    pub fn create_impersonation_claims(
+        &self,
+        user_account: &UserAccount,
+        actor_id: &str,
+    ) -> Claims {
+        let mut claims = self.create_claims(user_account);
+        claims.act = Some(Actor {
+            sub: actor_id.to_string(),
+        });
+        claims.exp = claims.iat + self.impersonation_ttl.num_seconds();
+        claims
+    }
+
+    pub fn create_token(&self, claims: &Claims) -> anyhow::Result<String> {
+        let key = self
+            .jwt_key_set
+            .signing_key(Utc::now())
+            .context("No JWT signing key is active")?;
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = Some(key.kid.clone());
+        Ok(jsonwebtoken::encode(&header, claims, &key.encoding_key)?)
     }
 
     pub fn verify_token(&self, token: &str) -> anyhow::Result<Claims> {
-        let mut validation = Validation::default();
-        validation.validate_exp = false;
-        validation.required_spec_claims.remove("exp");
-        Ok(jsonwebtoken::decode(
-            token,
-            &DecodingKey::from_secret(self.private_key.as_bytes()),
-            &validation,
-        )?
-        .claims)
+        let header = jsonwebtoken::decode_header(token)?;
+        let kid = header.kid.context("Token has no key ID")?;
+        let key = self
+            .jwt_key_set
+            .verifying_key(&kid, Utc::now())
+            .context("Unknown token key ID")?;
+        let validation = Validation::new(Algorithm::EdDSA);
+        Ok(jsonwebtoken::decode(token, &key.decoding_key(), &validation)?.claims)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::repo::company::Company;
+    use crate::repo::memory::{
+        MemoryApiKeyRepo, MemoryCompanyRepo, MemoryLoginAttemptRepo, MemoryOneTimeTokenRepo,
+        MemoryRefreshTokenRepo, MemorySessionRepo, MemoryUserAccountRepo,
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn test_verify_token_valid() {
+        // Arrange.
+        let claims_provider = claims_provider(Duration::minutes(15));
+        let claims = claims_provider.create_claims(&user_account());
+        let token = claims_provider.create_token(&claims).unwrap();
+
+        // Act.
+        let res = claims_provider.verify_token(&token);
+
+        // Assert.
+        assert_eq!(res.unwrap().sub, "user");
+    }
 
     #[test]
-    fn test_verify_correct() {
+    fn test_verify_token_expired() {
         // Arrange.
-        let password = "rightpass";
-        let creds = create_creds(password);
+        let claims_provider = claims_provider(Duration::minutes(-15));
+        let claims = claims_provider.create_claims(&user_account());
+        let token = claims_provider.create_token(&claims).unwrap();
 
         // Act.
-        let res = verify_password(password, &creds);
+        let res = claims_provider.verify_token(&token);
 
         // Assert.
-        assert!(res.is_ok());
+        assert!(res.is_err());
     }
 
     #[test]
-    fn test_verify_incorrect() {
+    fn test_verify_token_other_key() {
+        // Arrange.
+        let other_claims_provider = claims_provider(Duration::minutes(15));
+        let claims = other_claims_provider.create_claims(&user_account());
+        let token = other_claims_provider.create_token(&claims).unwrap();
+        let claims_provider = claims_provider(Duration::minutes(15));
+
+        // Act.
+        let res = claims_provider.verify_token(&token);
+
+        // Assert.
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_random_code() {
+        // Act.
+        let code = random_code(6);
+
+        // Assert.
+        assert_eq!(code.len(), 6);
+        assert!(code.chars().all(|c| c.is_ascii_digit()));
+    }
+
+    #[tokio::test]
+    async fn test_accept_invitation() {
+        // Arrange.
+        let auth_provider = auth_provider();
+        let earlier = invite(&auth_provider).await;
+        let token = invite(&auth_provider).await;
+
+        // Act.
+        let res = auth_provider.accept_invitation(&token, PASSWORD).await;
+        let earlier_res = auth_provider.accept_invitation(&earlier, PASSWORD).await;
+
+        // Assert.
+        assert!(matches!(res, Ok(Ok(()))));
+        assert!(matches!(earlier_res, Ok(Err(InvitationError::Invalid))));
+        let verified = auth_provider.verify_password("user", PASSWORD).await;
+        assert!(matches!(verified, Ok(Ok(()))));
+    }
+
+    #[tokio::test]
+    async fn test_accept_invitation_with_password() {
+        // Arrange.
+        let auth_provider = auth_provider();
+        let token = invite(&auth_provider).await;
+        let set = auth_provider.set_password("user", PASSWORD).await;
+        assert!(matches!(set, Ok(Ok(()))));
+
+        // Act.
+        let res = auth_provider
+            .accept_invitation(&token, "another correct horse")
+            .await;
+
+        // Assert.
+        assert!(matches!(res, Ok(Err(InvitationError::AlreadyActive))));
+        let verified = auth_provider.verify_password("user", PASSWORD).await;
+        assert!(matches!(verified, Ok(Ok(()))));
+    }
+
+    #[tokio::test]
+    async fn test_create_invitation_with_password() {
+        // Arrange.
+        let auth_provider = auth_provider();
+        invite(&auth_provider).await;
+        let set = auth_provider.set_password("user", PASSWORD).await;
+        assert!(matches!(set, Ok(Ok(()))));
+
+        // Act.
+        let res = auth_provider.create_invitation("user").await;
+
+        // Assert.
+        assert!(matches!(res, Ok(Err(InvitationError::AlreadyActive))));
+    }
+
+    #[tokio::test]
+    async fn test_verify_second_factor_throttled() {
+        // Arrange.
+        let auth_provider = auth_provider();
+        let set = auth_provider.set_password("user", PASSWORD).await;
+        assert!(matches!(set, Ok(Ok(()))));
+        auth_provider
+            .user_account_repo
+            .set_totp(
+                "user",
+                Some(Totp {
+                    secret: totp::generate_secret(),
+                    enabled: true,
+                    recovery_code_hashes: vec![hash_token("abcd1234")],
+                    last_used_step: None,
+                }),
+            )
+            .await
+            .unwrap();
+        for _ in 0..3 {
+            let res = auth_provider.verify_second_factor("user", "0000").await;
+            assert!(matches!(res, Ok(Err(TotpError::IncorrectCode))));
+        }
+        // Logging in with the password again does not allow more guesses.
+        let login = auth_provider
+            .authenticate(&user_account().email, PASSWORD, None)
+            .await;
+        assert!(matches!(login, Ok(Err(LoginError::Throttled))));
+
+        // Act.
+        let res = auth_provider
+            .verify_second_factor("user", "ABCD-1234")
+            .await;
+
+        // Assert.
+        assert!(matches!(res, Ok(Err(TotpError::Throttled))));
+    }
+
+    #[tokio::test]
+    async fn test_verify_credentials_revoked() {
+        // Arrange.
+        let auth_provider = auth_provider();
+        let claims = claims_provider(Duration::minutes(15)).create_claims(&user_account());
+        auth_provider
+            .create_session("user", &claims.jti)
+            .await
+            .unwrap();
+        let (api_key, _) = auth_provider
+            .create_api_key("company", "Gateway", Vec::new())
+            .await
+            .unwrap();
+        let valid = auth_provider
+            .verify_credentials(Some(&claims), Some(&api_key))
+            .await;
+        assert!(matches!(valid, Ok(true)));
+
+        // Act.
+        auth_provider.revoke_sessions("user").await.unwrap();
+        auth_provider
+            .api_key_repo
+            .delete_one(&api_key.id)
+            .await
+            .unwrap();
+        let session_res = auth_provider.verify_credentials(Some(&claims), None).await;
+        let api_key_res = auth_provider.verify_credentials(None, Some(&api_key)).await;
+
+        // Assert.
+        assert!(matches!(session_res, Ok(false)));
+        assert!(matches!(api_key_res, Ok(false)));
+    }
+
+    #[tokio::test]
+    async fn test_create_password_reset_throttled() {
+        // Arrange.
+        let auth_provider = auth_provider();
+        for _ in 0..3 {
+            let res = auth_provider
+                .create_password_reset("user@example.com", None)
+                .await;
+            assert!(matches!(res, Ok(Ok(Some(_)))));
+        }
+
+        // Act.
+        let res = auth_provider
+            .create_password_reset("USER@example.com", Some("203.0.113.8"))
+            .await;
+
+        // Assert.
+        assert!(matches!(res, Ok(Err(RequestThrottled))));
+    }
+
+    #[tokio::test]
+    async fn test_create_login_link() {
         // Arrange.
-        let password = "rightpass";
-        let creds = create_creds(password);
+        let auth_provider = auth_provider();
+        enable_login_links(&auth_provider).await;
+        let set = auth_provider.set_password("user", PASSWORD).await;
+        assert!(matches!(set, Ok(Ok(()))));
 
         // Act.
-        let res = verify_password("wrongpass", &creds);
+        let res = auth_provider
+            .create_login_link("user@example.com", Some("203.0.113.7"))
+            .await;
 
         // Assert.
-        assert!(matches!(res, Err(VerifyError::IncorrectPassword)));
+        assert!(matches!(res, Ok(Ok(Some((user_account, _)))) if user_account.id == "user"));
+    }
+
+    #[tokio::test]
+    async fn test_create_login_link_invited() {
+        // Arrange.
+        let auth_provider = auth_provider();
+        enable_login_links(&auth_provider).await;
+
+        // Act.
+        let res = auth_provider
+            .create_login_link("user@example.com", Some("203.0.113.7"))
+            .await;
+
+        // Assert.
+        assert!(matches!(res, Ok(Ok(None))));
+    }
+
+    #[tokio::test]
+    async fn test_create_login_link_throttled() {
+        // Arrange.
+        let auth_provider = auth_provider();
+        for i in 0..3 {
+            let email = format!("unknown{}@example.com", i);
+            let res = auth_provider
+                .create_login_link(&email, Some("203.0.113.7"))
+                .await;
+            assert!(matches!(res, Ok(Ok(None))));
+        }
+
+        // Act.
+        let res = auth_provider
+            .create_login_link("another@example.com", Some("203.0.113.7"))
+            .await;
+
+        // Assert.
+        assert!(matches!(res, Ok(Err(RequestThrottled))));
+    }
+
+    #[tokio::test]
+    async fn test_verify_email() {
+        // Arrange.
+        let auth_provider = auth_provider();
+        let token = auth_provider
+            .create_email_verification_token(&user_account())
+            .await
+            .unwrap()
+            .unwrap();
+
+        // Act.
+        let res = auth_provider.verify_email(&token).await;
+
+        // Assert.
+        assert!(matches!(res, Ok(Ok(()))));
+        assert!(stored_user_account(&auth_provider).await.email_verified);
+    }
+
+    #[tokio::test]
+    async fn test_verify_email_changed() {
+        // Arrange.
+        let auth_provider = auth_provider();
+        let token = auth_provider
+            .create_email_verification_token(&user_account())
+            .await
+            .unwrap()
+            .unwrap();
+        let mut changed = user_account();
+        changed.set_contact("other@example.com".to_string(), "".to_string());
+        auth_provider
+            .user_account_repo
+            .replace_one(changed)
+            .await
+            .unwrap();
+
+        // Act.
+        let res = auth_provider.verify_email(&token).await;
+
+        // Assert.
+        assert!(matches!(res, Ok(Err(VerificationError::ContactChanged))));
+        assert!(!stored_user_account(&auth_provider).await.email_verified);
+    }
+
+    #[tokio::test]
+    async fn test_verify_email_case_changed() {
+        // Arrange.
+        let auth_provider = auth_provider();
+        let token = auth_provider
+            .create_email_verification_token(&user_account())
+            .await
+            .unwrap()
+            .unwrap();
+        let mut changed = user_account();
+        changed.set_contact(changed.email.to_uppercase(), "".to_string());
+        auth_provider
+            .user_account_repo
+            .replace_one(changed)
+            .await
+            .unwrap();
+
+        // Act.
+        let res = auth_provider.verify_email(&token).await;
+
+        // Assert.
+        assert!(matches!(res, Ok(Ok(()))));
+        assert!(stored_user_account(&auth_provider).await.email_verified);
+    }
+
+    #[tokio::test]
+    async fn test_verify_phone_changed() {
+        // Arrange.
+        let auth_provider = auth_provider();
+        let mut user_account = user_account();
+        user_account.set_contact(user_account.email.clone(), "111-1111".to_string());
+        auth_provider
+            .user_account_repo
+            .replace_one(user_account.clone())
+            .await
+            .unwrap();
+        let code = auth_provider
+            .create_phone_verification_code(&user_account)
+            .await
+            .unwrap()
+            .unwrap();
+        user_account.set_contact(user_account.email.clone(), "222-2222".to_string());
+        auth_provider
+            .user_account_repo
+            .replace_one(user_account)
+            .await
+            .unwrap();
+
+        // Act.
+        let res = auth_provider.verify_phone("user", &code).await;
+
+        // Assert.
+        assert!(matches!(res, Ok(Err(VerificationError::ContactChanged))));
+        assert!(!stored_user_account(&auth_provider).await.phone_verified);
+    }
+
+    #[tokio::test]
+    async fn test_create_phone_verification_code_throttled() {
+        // Arrange.
+        let auth_provider = auth_provider();
+        let user_account = UserAccount {
+            phone: "111-1111".to_string(),
+            ..user_account()
+        };
+        for _ in 0..3 {
+            let res = auth_provider
+                .create_phone_verification_code(&user_account)
+                .await;
+            assert!(matches!(res, Ok(Ok(_))));
+        }
+
+        // Act.
+        let res = auth_provider
+            .create_phone_verification_code(&user_account)
+            .await;
+
+        // Assert.
+        assert!(matches!(res, Ok(Err(RequestThrottled))));
+    }
+
+    async fn stored_user_account(auth_provider: &AuthProvider) -> UserAccount {
+        auth_provider
+            .user_account_repo
+            .find_one("user")
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    async fn enable_login_links(auth_provider: &AuthProvider) {
+        let company = Company {
+            id: "company".to_string(),
+            name: "Company".to_string(),
+            require_admin_two_factor: false,
+            login_links_enabled: true,
+            oidc: None,
+        };
+        auth_provider
+            .company_repo
+            .insert_one(company)
+            .await
+            .unwrap();
+    }
+
+    async fn invite(auth_provider: &AuthProvider) -> String {
+        auth_provider
+            .create_invitation("user")
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    const PASSWORD: &str = "correct horse battery";
+
+    /// Auth provider with in-memory repos and a single user account without a password.
+    fn auth_provider() -> AuthProvider {
+        let user_account_repo = MemoryUserAccountRepo::default();
+        user_account_repo
+            .user_accounts
+            .lock()
+            .unwrap()
+            .insert("user".to_string(), user_account());
+        AuthProvider {
+            user_account_repo: Arc::new(user_account_repo),
+            company_repo: Arc::new(MemoryCompanyRepo::default()),
+            api_key_repo: Arc::new(MemoryApiKeyRepo::default()),
+            refresh_token_repo: Arc::new(MemoryRefreshTokenRepo::default()),
+            session_repo: Arc::new(MemorySessionRepo::default()),
+            one_time_token_repo: Arc::new(MemoryOneTimeTokenRepo::default()),
+            refresh_token_ttl: Duration::days(30),
+            password_reset_ttl: Duration::hours(1),
+            invitation_ttl: Duration::hours(72),
+            login_link_ttl: Duration::minutes(15),
+            password_policy: PasswordPolicy::new(8, 3),
+            hashers: Hashers::default(),
+            login_throttle: LoginThrottle {
+                login_attempt_repo: Arc::new(MemoryLoginAttemptRepo::default()),
+                free_attempts: 3,
+                base_delay: Duration::seconds(1),
+                max_delay: Duration::minutes(15),
+            },
+        }
+    }
+
+    fn claims_provider(access_token_ttl: Duration) -> ClaimsProvider {
+        ClaimsProvider {
+            jwt_key_set: JwtKeySet::ephemeral().unwrap(),
+            access_token_ttl,
+            impersonation_ttl: Duration::minutes(5),
+        }
+    }
+
+    fn user_account() -> UserAccount {
+        UserAccount {
+            id: "user".to_string(),
+            name: "User".to_string(),
+            access: Access::View,
+            title: "".to_string(),
+            email: "user@example.com".to_string(),
+            email_verified: false,
+            phone: "".to_string(),
+            phone_verified: false,
+            company_id: "company".to_string(),
+            roles: Vec::new(),
+            person_id: None,
+            active: true,
+            suspended_until: None,
+        }
     }
 }
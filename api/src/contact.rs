@@ -0,0 +1,52 @@
+const MIN_PHONE_DIGITS: usize = 7;
+const MAX_PHONE_DIGITS: usize = 15;
+
+/// Whether an email address looks valid. This only catches obvious mistakes, while verification
+/// proves that the address reaches the user.
+pub fn is_valid_email(email: &str) -> bool {
+    match email.rsplit_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !email.chars().any(char::is_whitespace)
+        }
+        None => false,
+    }
+}
+
+/// Whether a phone number looks valid. It may be formatted with spaces, dashes, dots and
+/// parentheses, and may start with a plus sign.
+pub fn is_valid_phone(phone: &str) -> bool {
+    let number = phone.strip_prefix('+').unwrap_or(phone);
+    let digits = number.chars().filter(char::is_ascii_digit).count();
+    number
+        .chars()
+        .all(|c| c.is_ascii_digit() || matches!(c, ' ' | '-' | '.' | '(' | ')'))
+        && (MIN_PHONE_DIGITS..=MAX_PHONE_DIGITS).contains(&digits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_valid_email() {
+        // Assert.
+        assert!(is_valid_email("user.a@example.com"));
+        assert!(!is_valid_email("user.a"));
+        assert!(!is_valid_email("@example.com"));
+        assert!(!is_valid_email("user.a@localhost"));
+        assert!(!is_valid_email("user a@example.com"));
+    }
+
+    #[test]
+    fn test_is_valid_phone() {
+        // Assert.
+        assert!(is_valid_phone("(111) 111-1111"));
+        assert!(is_valid_phone("+1 111.111.1111"));
+        assert!(!is_valid_phone("111"));
+        assert!(!is_valid_phone("call me"));
+    }
+}
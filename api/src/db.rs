@@ -1,17 +1,27 @@
 use crate::crockford;
+use crate::repo::mongo_util::{name_words, NAME_WORDS};
 use bson::Document;
-use mongodb::options::IndexOptions;
+use futures_util::TryStreamExt;
+use mongodb::options::{AggregateOptions, Collation, CollationStrength, FindOptions, IndexOptions};
 use mongodb::{Client, Collection, Database, IndexModel};
+use std::time::Duration;
 
 pub const DB_NAME: &str = "sw";
 
 pub mod coll {
+    pub const API_KEY: &str = "api_key";
     pub const COMPANY: &str = "company";
     pub const DEVICE: &str = "device";
     pub const GAS_READING: &str = "gas_reading";
+    pub const IMPERSONATION: &str = "impersonation";
     pub const INCIDENT: &str = "incident";
     pub const LOCATION_READING: &str = "location_reading";
+    pub const LOGIN_ATTEMPT: &str = "login_attempt";
+    pub const OIDC_LOGIN: &str = "oidc_login";
+    pub const ONE_TIME_TOKEN: &str = "one_time_token";
     pub const PERSON: &str = "person";
+    pub const REFRESH_TOKEN: &str = "refresh_token";
+    pub const SESSION: &str = "session";
     pub const TEAM: &str = "team";
     pub const TEAM_PERSON: &str = "team_person";
     pub const USER_ACCOUNT: &str = "user_account";
@@ -21,6 +31,7 @@ pub mod coll {
 
 pub mod err_code {
     pub const NAMESPACE_EXISTS: i32 = 48;
+    pub const DUPLICATE_KEY: i32 = 11000;
 }
 
 pub async fn connect(db_uri: &str) -> anyhow::Result<Database> {
@@ -43,27 +54,60 @@ pub async fn test_connection(db: &Database) -> anyhow::Result<()> {
 }
 
 pub async fn prepare(db: &Database) -> anyhow::Result<()> {
+    prepare_coll_api_key(db).await?;
+    prepare_coll_device(db).await?;
     prepare_coll_gas_reading(db).await?;
+    prepare_coll_impersonation(db).await?;
     prepare_coll_incident(db).await?;
     prepare_coll_location_reading(db).await?;
+    prepare_coll_login_attempt(db).await?;
+    prepare_coll_oidc_login(db).await?;
+    prepare_coll_one_time_token(db).await?;
     prepare_coll_person(db).await?;
+    prepare_coll_refresh_token(db).await?;
+    prepare_coll_session(db).await?;
+    prepare_coll_team(db).await?;
     prepare_coll_team_person(db).await?;
+    prepare_coll_user_account(db).await?;
     prepare_coll_user_account_creds(db).await?;
     prepare_coll_user_account_profile_image(db).await?;
     Ok(())
 }
 
+pub async fn prepare_coll_api_key(db: &Database) -> anyhow::Result<()> {
+    let collection = db.collection(coll::API_KEY);
+    create_simple_index(&collection, "key_hash", true).await?;
+    create_simple_index(&collection, "company_id", false).await?;
+    Ok(())
+}
+
+pub async fn prepare_coll_device(db: &Database) -> anyhow::Result<()> {
+    let collection = db.collection(coll::DEVICE);
+    create_simple_index(&collection, "owner_id", false).await?;
+    Ok(())
+}
+
 pub async fn prepare_coll_gas_reading(db: &Database) -> anyhow::Result<()> {
     let collection = db.collection(coll::GAS_READING);
     create_simple_index(&collection, "person_id", false).await?;
+    create_simple_compound_index(&collection, "timestamp", "_id", false).await?;
     create_simple_index(&collection, "gas", false).await?;
     create_2dsphere_index(&collection, "location").await?;
     Ok(())
 }
 
+pub async fn prepare_coll_impersonation(db: &Database) -> anyhow::Result<()> {
+    let collection = db.collection(coll::IMPERSONATION);
+    create_simple_index(&collection, "company_id", false).await?;
+    create_simple_index(&collection, "user_account_id", false).await?;
+    Ok(())
+}
+
 pub async fn prepare_coll_incident(db: &Database) -> anyhow::Result<()> {
     let collection = db.collection(coll::INCIDENT);
     create_simple_index(&collection, "person_id", false).await?;
+    create_simple_index(&collection, "type", false).await?;
+    create_simple_compound_index(&collection, "timestamp", "_id", false).await?;
     create_2dsphere_index(&collection, "location").await?;
     Ok(())
 }
@@ -71,22 +115,107 @@ pub async fn prepare_coll_incident(db: &Database) -> anyhow::Result<()> {
 pub async fn prepare_coll_location_reading(db: &Database) -> anyhow::Result<()> {
     let collection = db.collection(coll::LOCATION_READING);
     create_simple_index(&collection, "person_id", false).await?;
+    create_simple_compound_index(&collection, "timestamp", "_id", false).await?;
     create_2dsphere_index(&collection, "location").await?;
     Ok(())
 }
 
+pub async fn prepare_coll_login_attempt(db: &Database) -> anyhow::Result<()> {
+    let collection = db.collection(coll::LOGIN_ATTEMPT);
+    create_ttl_index(&collection, "expires_at").await?;
+    Ok(())
+}
+
+pub async fn prepare_coll_oidc_login(db: &Database) -> anyhow::Result<()> {
+    let collection = db.collection(coll::OIDC_LOGIN);
+    create_ttl_index(&collection, "expires_at").await?;
+    Ok(())
+}
+
+pub async fn prepare_coll_one_time_token(db: &Database) -> anyhow::Result<()> {
+    let collection = db.collection(coll::ONE_TIME_TOKEN);
+    create_simple_index(&collection, "user_account_id", false).await?;
+    create_ttl_index(&collection, "expires_at").await?;
+    Ok(())
+}
+
 pub async fn prepare_coll_person(db: &Database) -> anyhow::Result<()> {
     let collection = db.collection(coll::PERSON);
     create_simple_index(&collection, "company_id", false).await?;
+    backfill_name_words(&collection).await?;
+    create_simple_index(&collection, NAME_WORDS, false).await?;
+    Ok(())
+}
+
+pub async fn prepare_coll_refresh_token(db: &Database) -> anyhow::Result<()> {
+    let collection = db.collection(coll::REFRESH_TOKEN);
+    create_simple_index(&collection, "family_id", false).await?;
+    create_simple_index(&collection, "user_account_id", false).await?;
+    create_ttl_index(&collection, "expires_at").await?;
+    Ok(())
+}
+
+pub async fn prepare_coll_session(db: &Database) -> anyhow::Result<()> {
+    let collection = db.collection(coll::SESSION);
+    create_simple_index(&collection, "jti", true).await?;
+    create_simple_index(&collection, "user_account_id", false).await?;
+    create_ttl_index(&collection, "expires_at").await?;
+    Ok(())
+}
+
+pub async fn prepare_coll_team(db: &Database) -> anyhow::Result<()> {
+    let collection = db.collection(coll::TEAM);
+    create_simple_index(&collection, "company_id", false).await?;
+    backfill_name_words(&collection).await?;
+    create_simple_index(&collection, NAME_WORDS, false).await?;
     Ok(())
 }
 
 pub async fn prepare_coll_team_person(db: &Database) -> anyhow::Result<()> {
     let collection = db.collection(coll::TEAM_PERSON);
     create_simple_compound_index(&collection, "team_id", "person_id", true).await?;
+    create_simple_index(&collection, "person_id", false).await?;
     Ok(())
 }
 
+pub async fn prepare_coll_user_account(db: &Database) -> anyhow::Result<()> {
+    let collection = db.collection(coll::USER_ACCOUNT);
+    if let Err(err) = create_case_insensitive_index(&collection, "email", true).await {
+        let duplicates = duplicate_emails(&collection).await?;
+        if !duplicates.is_empty() {
+            anyhow::bail!(
+                "Email addresses must be unique regardless of case, but more than one user account \
+                 has each of: {}. Change the email of all but one of them and start again.",
+                duplicates.join(", ")
+            );
+        }
+        return Err(err);
+    }
+    create_simple_index(&collection, "company_id", false).await?;
+    create_simple_index(&collection, "person_id", false).await?;
+    backfill_name_words(&collection).await?;
+    create_simple_index(&collection, NAME_WORDS, false).await?;
+    Ok(())
+}
+
+/// Email addresses that more than one user account has, ignoring case.
+async fn duplicate_emails(collection: &Collection<Document>) -> anyhow::Result<Vec<String>> {
+    let pipeline = [
+        bson::doc! { "$match": { "email": { "$gt": "" } } },
+        bson::doc! { "$group": { "_id": "$email", "count": { "$sum": 1 } } },
+        bson::doc! { "$match": { "count": { "$gt": 1 } } },
+    ];
+    let options = AggregateOptions::builder()
+        .collation(case_insensitive_collation())
+        .build();
+    let mut cursor = collection.aggregate(pipeline, options).await?;
+    let mut emails = Vec::new();
+    while let Some(doc) = cursor.try_next().await? {
+        emails.push(doc.get_str("_id")?.to_string());
+    }
+    Ok(emails)
+}
+
 pub async fn prepare_coll_user_account_creds(db: &Database) -> anyhow::Result<()> {
     let collection = db.collection(coll::USER_ACCOUNT_CREDS);
     create_simple_index(&collection, "user_account_id", true).await?;
@@ -99,6 +228,30 @@ pub async fn prepare_coll_user_account_profile_image(db: &Database) -> anyhow::R
     Ok(())
 }
 
+/// Store the words of the names of documents written before the words were, so that name filters
+/// find them.
+pub async fn backfill_name_words(collection: &Collection<Document>) -> anyhow::Result<()> {
+    let mut cursor = collection
+        .find(
+            bson::doc! { NAME_WORDS: { "$exists": false } },
+            FindOptions::builder()
+                .projection(bson::doc! { "name": 1 })
+                .build(),
+        )
+        .await?;
+    while let Some(doc) = cursor.try_next().await? {
+        let words = name_words(doc.get_str("name").unwrap_or_default());
+        collection
+            .update_one(
+                bson::doc! { "_id": doc.get("_id") },
+                bson::doc! { "$set": { NAME_WORDS: words } },
+                None,
+            )
+            .await?;
+    }
+    Ok(())
+}
+
 pub async fn create_simple_index(
     collection: &Collection<Document>,
     field: &str,
@@ -116,6 +269,38 @@ pub async fn create_simple_index(
     Ok(())
 }
 
+/// Create an index that compares strings without regard to case. Queries must use the same
+/// collation to use the index. Empty strings are left out of the index, so they never conflict.
+pub async fn create_case_insensitive_index(
+    collection: &Collection<Document>,
+    field: &str,
+    unique: bool,
+) -> anyhow::Result<()> {
+    collection
+        .create_index(
+            IndexModel::builder()
+                .keys(bson::doc! { field: 1 })
+                .options(Some(
+                    IndexOptions::builder()
+                        .unique(Some(unique))
+                        .collation(Some(case_insensitive_collation()))
+                        .partial_filter_expression(Some(bson::doc! { field: { "$gt": "" } }))
+                        .build(),
+                ))
+                .build(),
+            None,
+        )
+        .await?;
+    Ok(())
+}
+
+pub fn case_insensitive_collation() -> Collation {
+    Collation::builder()
+        .locale("en")
+        .strength(CollationStrength::Secondary)
+        .build()
+}
+
 pub async fn create_simple_compound_index(
     collection: &Collection<Document>,
     field_1: &str,
@@ -148,3 +333,24 @@ pub async fn create_2dsphere_index(
         .await?;
     Ok(())
 }
+
+/// Create an index that deletes each document once the time in the field has passed.
+pub async fn create_ttl_index(
+    collection: &Collection<Document>,
+    field: &str,
+) -> anyhow::Result<()> {
+    collection
+        .create_index(
+            IndexModel::builder()
+                .keys(bson::doc! { field: 1 })
+                .options(Some(
+                    IndexOptions::builder()
+                        .expire_after(Some(Duration::from_secs(0)))
+                        .build(),
+                ))
+                .build(),
+            None,
+        )
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,90 @@
+use crate::repo::gas_reading::GasReading;
+use crate::repo::incident::Incident;
+use crate::repo::location_reading::LocationReading;
+use futures_util::{stream, Stream};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+/// Events buffered for each subscriber. A subscriber that falls further behind misses events.
+const CAPACITY: usize = 1024;
+
+/// A change stored by a repo.
+#[derive(Debug, Clone)]
+pub enum Event {
+    LocationReadingAdded(LocationReading),
+    GasReadingAdded(GasReading),
+    IncidentCreated(Incident),
+}
+
+/// Broadcasts events from repos to GraphQL subscriptions. Events only reach subscribers of the same
+/// API instance.
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<Event>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CAPACITY);
+        Self { sender }
+    }
+
+    pub fn publish(&self, event: Event) {
+        // Sending only fails when there are no subscribers, in which case nobody misses it.
+        let _ = self.sender.send(event);
+    }
+
+    /// Events published from now on.
+    pub fn subscribe(&self) -> impl Stream<Item = Event> + Send + 'static {
+        stream::unfold(self.sender.subscribe(), |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => return Some((event, receiver)),
+                    Err(RecvError::Lagged(count)) => {
+                        log::warn!("Subscriber fell behind and missed {} events", count)
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        })
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use futures_util::StreamExt;
+
+    #[tokio::test]
+    async fn test_subscribe_receives_later_events() {
+        // Arrange.
+        let events = EventBus::new();
+        events.publish(event("before"));
+        let mut subscription = Box::pin(events.subscribe());
+
+        // Act.
+        events.publish(event("after"));
+
+        // Assert.
+        match subscription.next().await {
+            Some(Event::LocationReadingAdded(reading)) => assert_eq!(reading.person_id, "after"),
+            _ => panic!("expected a location reading"),
+        }
+    }
+
+    fn event(person_id: &str) -> Event {
+        Event::LocationReadingAdded(LocationReading {
+            id: crate::repo::mongo_util::new_object_id(),
+            timestamp: Utc::now(),
+            person_id: person_id.to_string(),
+            coordinates: vec![0.0, 0.0],
+        })
+    }
+}
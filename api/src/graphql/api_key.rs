@@ -0,0 +1,119 @@
+use crate::graphql::company::Company;
+use crate::graphql::{limits, unauthorized_error, Context};
+use crate::repo::api_key;
+use chrono::{DateTime, Utc};
+use derive_more::{Deref, DerefMut, From};
+use juniper::{FieldResult, ID};
+
+#[derive(Clone, From, Deref, DerefMut)]
+pub struct ApiKey(pub api_key::ApiKey);
+
+#[derive(Debug, Copy, Clone, juniper::GraphQLEnum)]
+pub enum ApiKeyScope {
+    IngestReadings,
+    ReadIncidents,
+}
+
+impl From<ApiKeyScope> for api_key::Scope {
+    fn from(value: ApiKeyScope) -> Self {
+        match value {
+            ApiKeyScope::IngestReadings => Self::IngestReadings,
+            ApiKeyScope::ReadIncidents => Self::ReadIncidents,
+        }
+    }
+}
+
+impl From<api_key::Scope> for ApiKeyScope {
+    fn from(value: api_key::Scope) -> Self {
+        match value {
+            api_key::Scope::IngestReadings => Self::IngestReadings,
+            api_key::Scope::ReadIncidents => Self::ReadIncidents,
+        }
+    }
+}
+
+#[derive(juniper::GraphQLInputObject)]
+pub struct ApiKeyInput {
+    pub company_id: ID,
+    pub name: String,
+    pub scopes: Vec<ApiKeyScope>,
+}
+
+/// A newly created API key. The key itself is only returned once.
+#[derive(juniper::GraphQLObject)]
+#[graphql(context = Context)]
+pub struct CreatedApiKey {
+    pub api_key: ApiKey,
+    pub key: String,
+}
+
+#[juniper::graphql_object(context = Context)]
+impl ApiKey {
+    pub fn id(&self) -> ID {
+        self.id.clone().into()
+    }
+
+    pub async fn company(&self, context: &Context) -> FieldResult<Option<Company>> {
+        Ok(context
+            .company_loader
+            .load(&self.company_id)
+            .await?
+            .map(Into::into))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn scopes(&self) -> Vec<ApiKeyScope> {
+        self.scopes.iter().copied().map(Into::into).collect()
+    }
+
+    pub fn created_at(&self) -> &DateTime<Utc> {
+        &self.created_at
+    }
+}
+
+pub async fn list(context: &Context, company_id: Option<ID>) -> FieldResult<Vec<ApiKey>> {
+    let items = context
+        .auth_provider
+        .api_key_repo
+        .find(api_key::ApiKeyFilter {
+            company_ids: context
+                .tenant
+                .restrict(company_id.map(|id| vec![id.to_string()])),
+        })
+        .await?;
+    limits::collect(context, items).await
+}
+
+pub async fn create(context: &Context, input: ApiKeyInput) -> FieldResult<CreatedApiKey> {
+    if !context.tenant.allows(&input.company_id) {
+        return Err(unauthorized_error());
+    }
+    let (api_key, key) = context
+        .auth_provider
+        .create_api_key(
+            &input.company_id,
+            &input.name,
+            input.scopes.into_iter().map(Into::into).collect(),
+        )
+        .await?;
+    Ok(CreatedApiKey {
+        api_key: api_key.into(),
+        key,
+    })
+}
+
+pub async fn revoke(context: &Context, id: ID) -> FieldResult<ID> {
+    let api_key = context.auth_provider.api_key_repo.find_one(&id).await?;
+    if !matches!(api_key, Some(api_key) if context.tenant.allows(&api_key.company_id)) {
+        return Err("API key not found".into());
+    }
+    context
+        .auth_provider
+        .api_key_repo
+        .delete_one(&id.clone().to_string())
+        .await?;
+    Ok(id)
+}
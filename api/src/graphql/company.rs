@@ -1,15 +1,13 @@
+use crate::graphql::connection::PageArgs;
 use crate::graphql::incident_stats::{IncidentStats, IncidentStatsFilter};
-use crate::graphql::person::Person;
-use crate::graphql::team::Team;
-use crate::graphql::user_account::UserAccount;
-use crate::graphql::Context;
+use crate::graphql::person::{PersonConnection, PersonFilter};
+use crate::graphql::team::{TeamConnection, TeamFilter};
+use crate::graphql::user_account::{UserAccountConnection, UserAccountFilter};
+use crate::graphql::{incident_stats, limits, person, team, user_account, Context};
 use crate::repo::company;
-use crate::repo::person::PersonFilter;
-use crate::repo::team::TeamFilter;
-use crate::repo::user_account::UserAccountFilter;
-use crate::{crockford, repo};
+use crate::{crockford, oidc};
+use anyhow::Context as AnyhowContext;
 use derive_more::{Deref, DerefMut, From};
-use futures_util::TryStreamExt;
 use juniper::{FieldResult, ID};
 
 #[derive(Clone, From, Deref, DerefMut)]
@@ -18,6 +16,32 @@ pub struct Company(pub company::Company);
 #[derive(juniper::GraphQLInputObject)]
 pub struct CompanyInput {
     pub name: String,
+    /// Off for new companies and unchanged for existing ones if not given.
+    pub require_admin_two_factor: Option<bool>,
+    /// Off for new companies and unchanged for existing ones if not given.
+    pub login_links_enabled: Option<bool>,
+}
+
+#[derive(Clone, From, Deref, DerefMut)]
+pub struct OidcConfig(pub company::OidcConfig);
+
+#[derive(juniper::GraphQLInputObject)]
+pub struct OidcConfigInput {
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+}
+
+/// The client secret is write-only.
+#[juniper::graphql_object(context = Context)]
+impl OidcConfig {
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    pub fn client_id(&self) -> &str {
+        &self.client_id
+    }
 }
 
 #[juniper::graphql_object(context = Context)]
@@ -30,103 +54,150 @@ impl Company {
         &self.name
     }
 
+    pub fn require_admin_two_factor(&self) -> bool {
+        self.require_admin_two_factor
+    }
+
+    /// Whether user accounts may log in with a link emailed to them instead of a password.
+    pub fn login_links_enabled(&self) -> bool {
+        self.login_links_enabled
+    }
+
+    pub fn oidc(&self) -> Option<OidcConfig> {
+        self.oidc.clone().map(Into::into)
+    }
+
     pub async fn incident_stats(
         &self,
         context: &Context,
         filter: Option<IncidentStatsFilter>,
     ) -> FieldResult<Vec<IncidentStats>> {
-        let filter = filter.unwrap_or_default();
-        let person_ids = context
-            .person_repo
-            .find(PersonFilter {
-                company_ids: Some(vec![self.id.clone()]),
-            })
-            .await?
-            .map_ok(|p| p.id)
-            .try_collect()
-            .await?;
-        Ok(context
-            .incident_stats_repo
-            .find(repo::incident_stats::IncidentStatsFilter {
-                person_ids: Some(person_ids),
-                min_timestamp: filter.min_timestamp,
-                max_timestamp: filter.max_timestamp,
-            })
-            .await?
-            .map_ok(Into::into)
-            .try_collect()
-            .await?)
+        incident_stats::list(context, None, Some(vec![self.id.clone()]), filter).await
     }
 
-    pub async fn people(&self, context: &Context) -> FieldResult<Vec<Person>> {
-        Ok(context
-            .person_repo
-            .find(PersonFilter {
-                company_ids: Some(vec![self.id.clone()]),
-            })
-            .await?
-            .map_ok(Into::into)
-            .try_collect()
-            .await?)
+    pub async fn people(
+        &self,
+        context: &Context,
+        filter: Option<PersonFilter>,
+        first: Option<i32>,
+        after: Option<String>,
+        last: Option<i32>,
+        before: Option<String>,
+    ) -> FieldResult<PersonConnection> {
+        let args = PageArgs {
+            first,
+            after,
+            last,
+            before,
+        };
+        person::list(context, Some(vec![self.id.clone()]), filter, args).await
     }
 
-    pub async fn teams(&self, context: &Context) -> FieldResult<Vec<Team>> {
-        Ok(context
-            .team_repo
-            .find(TeamFilter {
-                company_ids: Some(vec![self.id.clone()]),
-            })
-            .await?
-            .map_ok(Into::into)
-            .try_collect()
-            .await?)
+    pub async fn teams(
+        &self,
+        context: &Context,
+        filter: Option<TeamFilter>,
+        first: Option<i32>,
+        after: Option<String>,
+        last: Option<i32>,
+        before: Option<String>,
+    ) -> FieldResult<TeamConnection> {
+        let args = PageArgs {
+            first,
+            after,
+            last,
+            before,
+        };
+        team::list(context, Some(vec![self.id.clone()]), filter, args).await
     }
 
-    pub async fn user_accounts(&self, context: &Context) -> FieldResult<Vec<UserAccount>> {
-        Ok(context
-            .user_account_repo
-            .find(UserAccountFilter {
-                company_ids: Some(vec![self.id.clone()]),
-            })
-            .await?
-            .map_ok(Into::into)
-            .try_collect()
-            .await?)
+    pub async fn user_accounts(
+        &self,
+        context: &Context,
+        filter: Option<UserAccountFilter>,
+        first: Option<i32>,
+        after: Option<String>,
+        last: Option<i32>,
+        before: Option<String>,
+    ) -> FieldResult<UserAccountConnection> {
+        let args = PageArgs {
+            first,
+            after,
+            last,
+            before,
+        };
+        user_account::list(context, Some(vec![self.id.clone()]), filter, args).await
     }
 }
 
+#[allow(clippy::explicit_auto_deref)]
 pub async fn get(context: &Context, id: ID) -> FieldResult<Option<Company>> {
     Ok(context.company_repo.find_one(&*id).await?.map(Into::into))
 }
 
 pub async fn list(context: &Context) -> FieldResult<Vec<Company>> {
-    Ok(context
-        .company_repo
-        .find()
-        .await?
-        .map_ok(Into::into)
-        .try_collect()
-        .await?)
+    let items = context.company_repo.find().await?;
+    limits::collect(context, items).await
 }
 
 pub async fn create(context: &Context, input: CompanyInput) -> FieldResult<Company> {
     let item = company::Company {
         id: crockford::random_id(),
         name: input.name,
+        require_admin_two_factor: input.require_admin_two_factor.unwrap_or_default(),
+        login_links_enabled: input.login_links_enabled.unwrap_or_default(),
+        oidc: None,
     };
     context.company_repo.insert_one(item.clone()).await?;
     Ok(item.into())
 }
 
 pub async fn replace(context: &Context, id: ID, input: CompanyInput) -> FieldResult<Company> {
+    let existing = context
+        .company_repo
+        .find_one(&id)
+        .await?
+        .context("Company not found")?;
     let item = company::Company {
         id: id.to_string(),
         name: input.name,
+        require_admin_two_factor: input
+            .require_admin_two_factor
+            .unwrap_or(existing.require_admin_two_factor),
+        login_links_enabled: input
+            .login_links_enabled
+            .unwrap_or(existing.login_links_enabled),
+        oidc: existing.oidc,
     };
     context.company_repo.replace_one(item.clone()).await?;
     Ok(item.into())
 }
 
+/// Configure single sign-on for a company, or turn it off with no input.
+pub async fn set_oidc(
+    context: &Context,
+    id: ID,
+    input: Option<OidcConfigInput>,
+) -> FieldResult<Company> {
+    if let Some(input) = &input {
+        if !oidc::is_valid_issuer(&input.issuer) {
+            return Err("Issuer must be an HTTPS URL".into());
+        }
+    }
+    let mut item = context
+        .company_repo
+        .find_one(&id)
+        .await?
+        .context("Company not found")?;
+    item.oidc = input.map(|input| company::OidcConfig {
+        issuer: input.issuer.trim_end_matches('/').to_string(),
+        client_id: input.client_id,
+        client_secret: input.client_secret,
+    });
+    context.company_repo.replace_one(item.clone()).await?;
+    Ok(item.into())
+}
+
 pub async fn delete(context: &Context, id: ID) -> FieldResult<ID> {
     context
         .company_repo
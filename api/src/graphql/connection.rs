@@ -0,0 +1,192 @@
+use crate::graphql::limits::LimitError;
+use crate::repo::{Cursor, ItemStream, Page};
+use chrono::{TimeZone, Utc};
+use data_encoding::BASE64URL_NOPAD;
+use futures_util::TryStreamExt;
+use juniper::{FieldResult, IntoFieldError};
+use std::future::Future;
+
+/// Items in a page when neither `first` nor `last` is given.
+pub const DEFAULT_PAGE_SIZE: i32 = 100;
+
+#[derive(juniper::GraphQLObject, Debug, Clone, PartialEq)]
+pub struct PageInfo {
+    pub has_next_page: bool,
+    pub has_previous_page: bool,
+    pub start_cursor: Option<String>,
+    pub end_cursor: Option<String>,
+}
+
+/// Arguments of a connection field, as in the Relay cursor connections spec. Pages start after
+/// `after` and end before `before`, and are limited to the `first` or `last` items between them.
+#[derive(Default, Debug, Clone)]
+pub struct PageArgs {
+    pub first: Option<i32>,
+    pub after: Option<String>,
+    pub last: Option<i32>,
+    pub before: Option<String>,
+}
+
+/// Items of a page with their cursors, in list order. Connection types are built from it.
+pub struct Slice<T> {
+    pub edges: Vec<(String, T)>,
+    pub page_info: PageInfo,
+}
+
+/// Find a page of items with `find`, where `cursor` tells the position of an item. Pages may hold
+/// up to `max_size` items.
+pub async fn paginate<T, F, Fut>(
+    args: PageArgs,
+    max_size: usize,
+    cursor: fn(&T) -> Cursor,
+    find: F,
+) -> FieldResult<Slice<T>>
+where
+    T: Unpin + Send,
+    F: FnOnce(Page) -> Fut,
+    Fut: Future<Output = anyhow::Result<Box<dyn ItemStream<T>>>>,
+{
+    let (count, from_end) = match (args.first, args.last) {
+        (Some(_), Some(_)) => return Err("Only one of first and last may be given".into()),
+        (Some(first), None) => (first, false),
+        (None, Some(last)) => (last, true),
+        (None, None) => (DEFAULT_PAGE_SIZE, false),
+    };
+    if count < 0 {
+        return Err("Page size must not be negative".into());
+    }
+    let count = count as usize;
+    if count > max_size {
+        return Err(LimitError::ListItems { max: max_size }.into_field_error());
+    }
+    let page = Page {
+        after: args.after.as_deref().map(decode_cursor).transpose()?,
+        before: args.before.as_deref().map(decode_cursor).transpose()?,
+        // One more item than the page holds tells whether there are more.
+        limit: Some(count as i64 + 1),
+        from_end,
+    };
+    let (has_after, has_before) = (page.after.is_some(), page.before.is_some());
+    let mut items: Vec<T> = find(page).await?.try_collect().await?;
+    let has_more = items.len() > count;
+    items.truncate(count);
+    if from_end {
+        items.reverse();
+    }
+    let edges: Vec<(String, T)> = items
+        .into_iter()
+        .map(|item| (encode_cursor(&cursor(&item)), item))
+        .collect();
+    Ok(Slice {
+        page_info: PageInfo {
+            has_next_page: if from_end { has_before } else { has_more },
+            has_previous_page: if from_end { has_more } else { has_after },
+            start_cursor: edges.first().map(|(cursor, _)| cursor.clone()),
+            end_cursor: edges.last().map(|(cursor, _)| cursor.clone()),
+        },
+        edges,
+    })
+}
+
+/// Cursors are opaque to clients. They hold the timestamp in milliseconds, if any, and the ID.
+pub fn encode_cursor(cursor: &Cursor) -> String {
+    let timestamp = cursor
+        .timestamp
+        .map(|timestamp| timestamp.timestamp_millis().to_string())
+        .unwrap_or_default();
+    BASE64URL_NOPAD.encode(format!("{}:{}", timestamp, cursor.id).as_bytes())
+}
+
+pub fn decode_cursor(value: &str) -> FieldResult<Cursor> {
+    let decoded = BASE64URL_NOPAD
+        .decode(value.as_bytes())
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .ok_or("Invalid cursor")?;
+    let (timestamp, id) = decoded.split_once(':').ok_or("Invalid cursor")?;
+    let timestamp = match timestamp {
+        "" => None,
+        millis => Some(
+            millis
+                .parse()
+                .ok()
+                .and_then(|millis| Utc.timestamp_millis_opt(millis).single())
+                .ok_or("Invalid cursor")?,
+        ),
+    };
+    Ok(Cursor {
+        timestamp,
+        id: id.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::stream;
+
+    #[test]
+    fn test_cursor_round_trip() {
+        // Arrange.
+        let cursors = [
+            Cursor {
+                timestamp: Some(Utc.timestamp_millis(1_650_000_000_123)),
+                id: "62a1b2c3d4e5f60718293a4b".to_string(),
+            },
+            Cursor {
+                timestamp: None,
+                id: "id:with:colons".to_string(),
+            },
+        ];
+
+        // Act.
+        let decoded: Vec<Cursor> = cursors
+            .iter()
+            .map(|cursor| decode_cursor(&encode_cursor(cursor)).unwrap())
+            .collect();
+
+        // Assert.
+        assert_eq!(decoded, cursors);
+        assert!(decode_cursor("not a cursor").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_paginate_last_returns_items_in_order() {
+        // Arrange.
+        let args = PageArgs {
+            last: Some(2),
+            ..Default::default()
+        };
+
+        // Act.
+        let slice = paginate(
+            args,
+            1000,
+            |id: &String| Cursor {
+                timestamp: None,
+                id: id.clone(),
+            },
+            |page| async move {
+                assert!(page.from_end);
+                assert_eq!(page.limit, Some(3));
+                let items = ["c", "b", "a"].iter().map(|id| Ok(id.to_string()));
+                Ok(Box::new(stream::iter(items)) as Box<dyn ItemStream<String>>)
+            },
+        )
+        .await
+        .unwrap();
+
+        // Assert.
+        let ids: Vec<&str> = slice.edges.iter().map(|(_, id)| id.as_str()).collect();
+        assert_eq!(ids, vec!["b", "c"]);
+        assert!(slice.page_info.has_previous_page);
+        assert!(!slice.page_info.has_next_page);
+        assert_eq!(
+            slice.page_info.end_cursor,
+            Some(encode_cursor(&Cursor {
+                timestamp: None,
+                id: "c".to_string(),
+            }))
+        );
+    }
+}
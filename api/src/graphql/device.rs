@@ -1,13 +1,50 @@
-use crate::graphql::person::Person;
+use crate::graphql::connection::{paginate, PageArgs, PageInfo, Slice};
+use crate::graphql::person::{select_ids, to_strings, Person};
 use crate::graphql::Context;
-use crate::repo::device;
+use crate::repo::{device, Cursor};
 use derive_more::{Deref, DerefMut, From};
-use futures_util::TryStreamExt;
 use juniper::{FieldResult, ID};
 
 #[derive(Clone, From, Deref, DerefMut)]
 pub struct Device(pub device::Device);
 
+#[derive(juniper::GraphQLObject)]
+#[graphql(context = Context)]
+pub struct DeviceConnection {
+    pub edges: Vec<DeviceEdge>,
+    pub page_info: PageInfo,
+}
+
+#[derive(juniper::GraphQLObject)]
+#[graphql(context = Context)]
+pub struct DeviceEdge {
+    pub cursor: String,
+    pub node: Device,
+}
+
+impl From<Slice<device::Device>> for DeviceConnection {
+    fn from(value: Slice<device::Device>) -> Self {
+        Self {
+            edges: value
+                .edges
+                .into_iter()
+                .map(|(cursor, node)| DeviceEdge {
+                    cursor,
+                    node: node.into(),
+                })
+                .collect(),
+            page_info: value.page_info,
+        }
+    }
+}
+
+#[derive(juniper::GraphQLInputObject, Default)]
+pub struct DeviceFilter {
+    pub owner_ids: Option<Vec<ID>>,
+    pub team_ids: Option<Vec<ID>>,
+    pub company_ids: Option<Vec<ID>>,
+}
+
 #[derive(juniper::GraphQLInputObject)]
 pub struct DeviceInput {
     pub id: ID,
@@ -22,25 +59,43 @@ impl Device {
 
     pub async fn owner(&self, context: &Context) -> FieldResult<Option<Person>> {
         Ok(context
-            .person_repo
-            .find_one(&self.owner_id)
+            .person_loader
+            .load(&self.owner_id)
             .await?
             .map(Into::into))
     }
 }
 
+#[allow(clippy::explicit_auto_deref)]
 pub async fn get(context: &Context, id: ID) -> FieldResult<Option<Device>> {
     Ok(context.device_repo.find_one(&*id).await?.map(Into::into))
 }
 
-pub async fn list(context: &Context) -> FieldResult<Vec<Device>> {
-    Ok(context
-        .device_repo
-        .find(Default::default())
-        .await?
-        .map_ok(Into::into)
-        .try_collect()
-        .await?)
+/// A page of devices, ordered by ID.
+pub async fn list(
+    context: &Context,
+    filter: Option<DeviceFilter>,
+    args: PageArgs,
+) -> FieldResult<DeviceConnection> {
+    let filter = filter.unwrap_or_default();
+    let owner_ids = select_ids(context, to_strings(filter.owner_ids), filter.team_ids).await?;
+    let company_ids = to_strings(filter.company_ids);
+    let slice = paginate(args, context.limits.max_list_items, cursor, |page| {
+        context.device_repo.find(device::DeviceFilter {
+            owner_ids,
+            company_ids,
+            page,
+        })
+    })
+    .await?;
+    Ok(slice.into())
+}
+
+fn cursor(device: &device::Device) -> Cursor {
+    Cursor {
+        timestamp: None,
+        id: device.id.clone(),
+    }
 }
 
 pub async fn create(context: &Context, input: DeviceInput) -> FieldResult<Device> {
@@ -1,31 +1,80 @@
-use crate::graphql::person::Person;
+use crate::graphql::connection::{paginate, PageArgs, PageInfo, Slice};
+use crate::graphql::person::{select_ids, to_strings, Person};
 use crate::graphql::Context;
-use crate::repo;
 use crate::repo::gas_reading;
+use crate::repo::mongo_util::new_object_id;
+use crate::repo::{self, intersect, Cursor};
 use chrono::{DateTime, Utc};
 use derive_more::{Deref, DerefMut, From};
-use futures_util::TryStreamExt;
-use juniper::FieldResult;
+use juniper::{FieldResult, ID};
 
 #[derive(Clone, From, Deref, DerefMut)]
 pub struct GasReading(pub gas_reading::GasReading);
 
 #[derive(juniper::GraphQLInputObject, Default)]
 pub struct GasReadingFilter {
+    pub person_ids: Option<Vec<ID>>,
+    pub team_ids: Option<Vec<ID>>,
+    pub company_ids: Option<Vec<ID>>,
+    pub gases: Option<Vec<String>>,
     pub min_timestamp: Option<DateTime<Utc>>,
     pub max_timestamp: Option<DateTime<Utc>>,
 }
 
+#[derive(juniper::GraphQLObject)]
+#[graphql(context = Context)]
+pub struct GasReadingConnection {
+    pub edges: Vec<GasReadingEdge>,
+    pub page_info: PageInfo,
+}
+
+#[derive(juniper::GraphQLObject)]
+#[graphql(context = Context)]
+pub struct GasReadingEdge {
+    pub cursor: String,
+    pub node: GasReading,
+}
+
+impl From<Slice<gas_reading::GasReading>> for GasReadingConnection {
+    fn from(value: Slice<gas_reading::GasReading>) -> Self {
+        Self {
+            edges: value
+                .edges
+                .into_iter()
+                .map(|(cursor, node)| GasReadingEdge {
+                    cursor,
+                    node: node.into(),
+                })
+                .collect(),
+            page_info: value.page_info,
+        }
+    }
+}
+
+#[derive(juniper::GraphQLInputObject)]
+pub struct GasReadingInput {
+    pub timestamp: DateTime<Utc>,
+    pub person_id: ID,
+    pub gas: String,
+    pub density: f64,
+    pub density_units: String,
+    pub coordinates: Vec<f64>,
+}
+
 #[juniper::graphql_object(context = Context)]
 impl GasReading {
+    pub fn id(&self) -> ID {
+        self.id.clone().into()
+    }
+
     pub fn timestamp(&self) -> &DateTime<Utc> {
         &self.timestamp
     }
 
     pub async fn person(&self, context: &Context) -> FieldResult<Option<Person>> {
         Ok(context
-            .person_repo
-            .find_one(&self.person_id)
+            .person_loader
+            .load(&self.person_id)
             .await?
             .map(Into::into))
     }
@@ -47,20 +96,68 @@ impl GasReading {
     }
 }
 
+/// A page of readings, oldest first, of the given people or of everyone.
 pub async fn list(
     context: &Context,
+    person_ids: Option<Vec<String>>,
     filter: Option<GasReadingFilter>,
-) -> FieldResult<Vec<GasReading>> {
-    let filter = filter.unwrap_or_default();
-    Ok(context
-        .gas_reading_repo
-        .find(repo::gas_reading::GasReadingFilter {
-            person_ids: None,
-            min_timestamp: filter.min_timestamp,
-            max_timestamp: filter.max_timestamp,
+    args: PageArgs,
+) -> FieldResult<GasReadingConnection> {
+    let GasReadingFilter {
+        person_ids: filter_person_ids,
+        team_ids,
+        company_ids,
+        gases,
+        min_timestamp,
+        max_timestamp,
+    } = filter.unwrap_or_default();
+    let person_ids = select_ids(
+        context,
+        intersect(person_ids, to_strings(filter_person_ids)),
+        team_ids,
+    )
+    .await?;
+    let slice = paginate(args, context.limits.max_list_items, cursor, |page| {
+        context
+            .gas_reading_repo
+            .find(repo::gas_reading::GasReadingFilter {
+                person_ids,
+                company_ids: to_strings(company_ids),
+                gases,
+                min_timestamp,
+                max_timestamp,
+                page,
+            })
+    })
+    .await?;
+    Ok(slice.into())
+}
+
+fn cursor(reading: &gas_reading::GasReading) -> Cursor {
+    Cursor {
+        timestamp: Some(reading.timestamp),
+        id: reading.id.clone(),
+    }
+}
+
+/// Store readings sent by a device. Returns the number of readings stored.
+pub async fn ingest(context: &Context, input: Vec<GasReadingInput>) -> FieldResult<i32> {
+    let count = input.len() as i32;
+    if count == 0 {
+        return Ok(0);
+    }
+    let items = input
+        .into_iter()
+        .map(|r| gas_reading::GasReading {
+            id: new_object_id(),
+            timestamp: r.timestamp,
+            person_id: r.person_id.to_string(),
+            gas: r.gas,
+            density: r.density,
+            density_units: r.density_units,
+            coordinates: r.coordinates,
         })
-        .await?
-        .map_ok(Into::into)
-        .try_collect()
-        .await?)
+        .collect();
+    context.gas_reading_repo.insert_many(items).await?;
+    Ok(count)
 }
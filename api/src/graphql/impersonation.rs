@@ -0,0 +1,131 @@
+use crate::crockford;
+use crate::graphql::user_account::UserAccount;
+use crate::graphql::{limits, unauthorized_error, Context};
+use crate::repo::impersonation;
+use crate::repo::impersonation::ImpersonationFilter;
+use crate::repo::user_account::Access;
+use anyhow::Context as AnyhowContext;
+use chrono::{DateTime, Utc};
+use derive_more::{Deref, DerefMut, From};
+use juniper::{FieldResult, ID};
+
+#[derive(Clone, From, Deref, DerefMut)]
+pub struct Impersonation(pub impersonation::Impersonation);
+
+/// A short-lived access token for acting as another user account. It cannot be refreshed.
+#[derive(juniper::GraphQLObject)]
+pub struct ImpersonationToken {
+    pub access_token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[juniper::graphql_object(context = Context)]
+impl Impersonation {
+    pub fn id(&self) -> ID {
+        self.id.clone().into()
+    }
+
+    /// Admin who acted as the user account.
+    pub async fn actor(&self, context: &Context) -> FieldResult<Option<UserAccount>> {
+        Ok(context
+            .auth_provider
+            .user_account_repo
+            .find_one(&self.actor_id)
+            .await?
+            .map(Into::into))
+    }
+
+    pub async fn user_account(&self, context: &Context) -> FieldResult<Option<UserAccount>> {
+        Ok(context
+            .user_account_loader
+            .load(&self.user_account_id)
+            .await?
+            .map(Into::into))
+    }
+
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+
+    pub fn created_at(&self) -> &DateTime<Utc> {
+        &self.created_at
+    }
+
+    pub fn expires_at(&self) -> &DateTime<Utc> {
+        &self.expires_at
+    }
+}
+
+pub async fn list(
+    context: &Context,
+    user_account_id: Option<ID>,
+) -> FieldResult<Vec<Impersonation>> {
+    let items = context
+        .impersonation_repo
+        .find(ImpersonationFilter {
+            company_ids: None,
+            user_account_ids: user_account_id.map(|id| vec![id.to_string()]),
+        })
+        .await?;
+    limits::collect(context, items).await
+}
+
+/// Issue a token for the requesting admin to act as a user account. Every impersonation is
+/// recorded with its reason.
+pub async fn impersonate(
+    context: &Context,
+    user_account_id: ID,
+    reason: String,
+) -> FieldResult<ImpersonationToken> {
+    let claims = context.claims.as_ref().ok_or_else(unauthorized_error)?;
+    if claims.sub == *user_account_id {
+        return Err("Cannot impersonate yourself".into());
+    }
+    let reason = reason.trim();
+    if reason.is_empty() {
+        return Err("A reason is required".into());
+    }
+    let user_account = context
+        .user_account_repo
+        .find_one(&user_account_id)
+        .await?
+        .context("User account not found")?;
+    if user_account.access == Access::SuperAdmin && claims.access != Access::SuperAdmin {
+        return Err(unauthorized_error());
+    }
+    if !user_account.is_active(Utc::now()) {
+        return Err("User account is deactivated or suspended".into());
+    }
+    let impersonation_claims = context
+        .claims_provider
+        .create_impersonation_claims(&user_account, &claims.sub);
+    let access_token = context
+        .claims_provider
+        .create_token(&impersonation_claims)?;
+    let session = context
+        .auth_provider
+        .create_impersonation_session(&impersonation_claims, &claims.sub)
+        .await?;
+    context
+        .impersonation_repo
+        .insert_one(impersonation::Impersonation {
+            id: crockford::random_id(),
+            actor_id: claims.sub.clone(),
+            user_account_id: user_account.id.clone(),
+            company_id: user_account.company_id.clone(),
+            reason: reason.to_string(),
+            session_id: session.id,
+            created_at: session.created_at,
+            expires_at: session.expires_at,
+        })
+        .await?;
+    log::info!(
+        "User account {} is impersonating user account {}",
+        claims.sub,
+        user_account.id
+    );
+    Ok(ImpersonationToken {
+        access_token,
+        expires_at: session.expires_at,
+    })
+}
@@ -1,10 +1,11 @@
-use crate::graphql::person::Person;
+use crate::graphql::connection::{paginate, PageArgs, PageInfo, Slice};
+use crate::graphql::person::{select_ids, to_strings, Person};
 use crate::graphql::Context;
 use crate::repo::incident;
+use crate::repo::{intersect, Cursor};
 use crate::{crockford, repo};
 use chrono::{DateTime, Utc};
 use derive_more::{Deref, DerefMut, From};
-use futures_util::TryStreamExt;
 use juniper::{FieldResult, ID};
 
 #[derive(Clone, From, Deref, DerefMut)]
@@ -20,10 +21,44 @@ pub struct IncidentInput {
 
 #[derive(juniper::GraphQLInputObject, Default)]
 pub struct IncidentFilter {
+    pub person_ids: Option<Vec<ID>>,
+    pub team_ids: Option<Vec<ID>>,
+    pub company_ids: Option<Vec<ID>>,
+    pub types: Option<Vec<String>>,
     pub min_timestamp: Option<DateTime<Utc>>,
     pub max_timestamp: Option<DateTime<Utc>>,
 }
 
+#[derive(juniper::GraphQLObject)]
+#[graphql(context = Context)]
+pub struct IncidentConnection {
+    pub edges: Vec<IncidentEdge>,
+    pub page_info: PageInfo,
+}
+
+#[derive(juniper::GraphQLObject)]
+#[graphql(context = Context)]
+pub struct IncidentEdge {
+    pub cursor: String,
+    pub node: Incident,
+}
+
+impl From<Slice<incident::Incident>> for IncidentConnection {
+    fn from(value: Slice<incident::Incident>) -> Self {
+        Self {
+            edges: value
+                .edges
+                .into_iter()
+                .map(|(cursor, node)| IncidentEdge {
+                    cursor,
+                    node: node.into(),
+                })
+                .collect(),
+            page_info: value.page_info,
+        }
+    }
+}
+
 #[juniper::graphql_object(context = Context)]
 impl Incident {
     pub fn id(&self) -> ID {
@@ -36,8 +71,8 @@ impl Incident {
 
     pub async fn person(&self, context: &Context) -> FieldResult<Option<Person>> {
         Ok(context
-            .person_repo
-            .find_one(&self.person_id)
+            .person_loader
+            .load(&self.person_id)
             .await?
             .map(Into::into))
     }
@@ -51,23 +86,51 @@ impl Incident {
     }
 }
 
+#[allow(clippy::explicit_auto_deref)]
 pub async fn get(context: &Context, id: ID) -> FieldResult<Option<Incident>> {
     Ok(context.incident_repo.find_one(&*id).await?.map(Into::into))
 }
 
-pub async fn list(context: &Context, filter: Option<IncidentFilter>) -> FieldResult<Vec<Incident>> {
-    let filter = filter.unwrap_or_default();
-    Ok(context
-        .incident_repo
-        .find(repo::incident::IncidentFilter {
-            person_ids: None,
-            min_timestamp: filter.min_timestamp,
-            max_timestamp: filter.max_timestamp,
+/// A page of incidents, oldest first, of the given people or of everyone.
+pub async fn list(
+    context: &Context,
+    person_ids: Option<Vec<String>>,
+    filter: Option<IncidentFilter>,
+    args: PageArgs,
+) -> FieldResult<IncidentConnection> {
+    let IncidentFilter {
+        person_ids: filter_person_ids,
+        team_ids,
+        company_ids,
+        types,
+        min_timestamp,
+        max_timestamp,
+    } = filter.unwrap_or_default();
+    let person_ids = select_ids(
+        context,
+        intersect(person_ids, to_strings(filter_person_ids)),
+        team_ids,
+    )
+    .await?;
+    let slice = paginate(args, context.limits.max_list_items, cursor, |page| {
+        context.incident_repo.find(repo::incident::IncidentFilter {
+            person_ids,
+            company_ids: to_strings(company_ids),
+            types,
+            min_timestamp,
+            max_timestamp,
+            page,
         })
-        .await?
-        .map_ok(Into::into)
-        .try_collect()
-        .await?)
+    })
+    .await?;
+    Ok(slice.into())
+}
+
+fn cursor(incident: &incident::Incident) -> Cursor {
+    Cursor {
+        timestamp: Some(incident.timestamp),
+        id: incident.id.clone(),
+    }
 }
 
 pub async fn create(context: &Context, input: IncidentInput) -> FieldResult<Incident> {
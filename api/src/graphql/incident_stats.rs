@@ -1,16 +1,20 @@
-use crate::graphql::Context;
-use crate::repo;
+use crate::graphql::person::{select_ids, to_strings};
+use crate::graphql::{limits, Context};
 use crate::repo::incident_stats;
+use crate::repo::{self, intersect};
 use chrono::{DateTime, Utc};
 use derive_more::{Deref, DerefMut, From};
-use futures_util::TryStreamExt;
-use juniper::FieldResult;
+use juniper::{FieldResult, ID};
 
 #[derive(Clone, From, Deref, DerefMut)]
 pub struct IncidentStats(pub incident_stats::IncidentStats);
 
 #[derive(juniper::GraphQLInputObject, Default)]
 pub struct IncidentStatsFilter {
+    pub person_ids: Option<Vec<ID>>,
+    pub team_ids: Option<Vec<ID>>,
+    pub company_ids: Option<Vec<ID>>,
+    pub types: Option<Vec<String>>,
     pub min_timestamp: Option<DateTime<Utc>>,
     pub max_timestamp: Option<DateTime<Utc>>,
 }
@@ -26,20 +30,29 @@ impl IncidentStats {
     }
 }
 
+/// Incident counts of the given people or of everyone, of the given companies or of every company.
 pub async fn list(
     context: &Context,
+    person_ids: Option<Vec<String>>,
+    company_ids: Option<Vec<String>>,
     filter: Option<IncidentStatsFilter>,
 ) -> FieldResult<Vec<IncidentStats>> {
     let filter = filter.unwrap_or_default();
-    Ok(context
+    let person_ids = select_ids(
+        context,
+        intersect(person_ids, to_strings(filter.person_ids)),
+        filter.team_ids,
+    )
+    .await?;
+    let items = context
         .incident_stats_repo
         .find(repo::incident_stats::IncidentStatsFilter {
-            person_ids: None,
+            person_ids,
+            company_ids: intersect(company_ids, to_strings(filter.company_ids)),
+            types: filter.types,
             min_timestamp: filter.min_timestamp,
             max_timestamp: filter.max_timestamp,
         })
-        .await?
-        .map_ok(Into::into)
-        .try_collect()
-        .await?)
+        .await?;
+    limits::collect(context, items).await
 }
@@ -0,0 +1,356 @@
+use crate::graphql::connection::DEFAULT_PAGE_SIZE;
+use crate::graphql::Context;
+use futures_util::{Stream, StreamExt, TryStreamExt};
+use juniper::parser::parse_document_source;
+use juniper::{
+    graphql_value, DefaultScalarValue, Definition, FieldError, FieldResult, InputValue,
+    IntoFieldError, OperationType, SchemaType, Selection, Type, Variables,
+};
+use std::collections::HashMap;
+
+/// Limits on the work a request may ask for, so that deeply nested queries cannot overload the API.
+#[derive(Debug, Clone, Copy)]
+pub struct QueryLimits {
+    /// Most levels of nested fields in a query.
+    pub max_depth: usize,
+    /// Highest estimated number of fields a request may resolve. See `Analysis::measure`.
+    pub max_cost: usize,
+    /// Most items any list field returns, including pages of connections.
+    pub max_list_items: usize,
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub enum LimitError {
+    #[error("Query depth of {depth} exceeds the limit of {max}")]
+    Depth { depth: usize, max: usize },
+    #[error("Query cost of {cost} exceeds the limit of {max}")]
+    Cost { cost: usize, max: usize },
+    #[error("List has more than the limit of {max} items")]
+    ListItems { max: usize },
+}
+
+impl LimitError {
+    /// Name of the limit that was hit, as clients see it.
+    pub fn limit(&self) -> &'static str {
+        match self {
+            LimitError::Depth { .. } => "depth",
+            LimitError::Cost { .. } => "cost",
+            LimitError::ListItems { .. } => "listItems",
+        }
+    }
+
+    pub fn max(&self) -> usize {
+        match *self {
+            LimitError::Depth { max, .. } => max,
+            LimitError::Cost { max, .. } => max,
+            LimitError::ListItems { max } => max,
+        }
+    }
+}
+
+impl IntoFieldError for LimitError {
+    fn into_field_error(self) -> FieldError {
+        let limit = self.limit();
+        let max = self.max().min(i32::MAX as usize) as i32;
+        FieldError::new(
+            self.to_string(),
+            graphql_value!({ "code": "LIMIT_EXCEEDED", "limit": limit, "max": max }),
+        )
+    }
+}
+
+/// Depth and estimated cost of a selection.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct Measure {
+    depth: usize,
+    cost: usize,
+}
+
+impl Measure {
+    fn add(self, other: Measure) -> Measure {
+        Measure {
+            depth: self.depth.max(other.depth),
+            cost: self.cost.saturating_add(other.cost),
+        }
+    }
+}
+
+impl QueryLimits {
+    /// Check the operation of a request against the depth and cost limits before it runs. Returns
+    /// the cost of the operation.
+    ///
+    /// Returns `Ok(0)` if the query does not parse or has no operation to run. Such requests are not
+    /// let through: execution validates every request first and rejects them with a clearer error.
+    pub fn check(
+        &self,
+        schema: &SchemaType<DefaultScalarValue>,
+        query: &str,
+        operation_name: Option<&str>,
+        variables: &Variables,
+    ) -> Result<usize, LimitError> {
+        let document = match parse_document_source(query, schema) {
+            Ok(document) => document,
+            Err(_) => return Ok(0),
+        };
+        let mut fragments = HashMap::new();
+        let mut operation = None;
+        for definition in &document {
+            match definition {
+                Definition::Fragment(fragment) => {
+                    let fragment = &fragment.item;
+                    fragments.insert(
+                        fragment.name.item,
+                        (fragment.type_condition.item, &fragment.selection_set[..]),
+                    );
+                }
+                Definition::Operation(op) => {
+                    let name = op.item.name.as_ref().map(|name| name.item);
+                    if operation.is_none() && (operation_name.is_none() || operation_name == name) {
+                        operation = Some(&op.item);
+                    }
+                }
+            }
+        }
+        let operation = match operation {
+            Some(operation) => operation,
+            None => return Ok(0),
+        };
+        let root = match operation.operation_type {
+            OperationType::Query => Some(schema.concrete_query_type()),
+            OperationType::Mutation => schema.concrete_mutation_type(),
+            OperationType::Subscription => schema.concrete_subscription_type(),
+        };
+        let root = match root.and_then(|root| root.name()) {
+            Some(root) => root,
+            None => return Ok(0),
+        };
+        let analysis = Analysis {
+            limits: self,
+            schema,
+            fragments,
+            variables,
+        };
+        let measure = analysis.measure(root, &operation.selection_set, None, &mut Vec::new());
+        if measure.depth > self.max_depth {
+            return Err(LimitError::Depth {
+                depth: measure.depth,
+                max: self.max_depth,
+            });
+        }
+        self.check_cost(measure.cost)?;
+        Ok(measure.cost)
+    }
+
+    pub fn check_cost(&self, cost: usize) -> Result<(), LimitError> {
+        if cost > self.max_cost {
+            return Err(LimitError::Cost {
+                cost,
+                max: self.max_cost,
+            });
+        }
+        Ok(())
+    }
+}
+
+struct Analysis<'a> {
+    limits: &'a QueryLimits,
+    schema: &'a SchemaType<'a, DefaultScalarValue>,
+    fragments: HashMap<&'a str, (&'a str, &'a [Selection<'a, DefaultScalarValue>])>,
+    variables: &'a Variables,
+}
+
+impl<'a> Analysis<'a> {
+    /// Each field costs one, and the fields selected on the items of a list cost once for each
+    /// item. Connections hold as many items as the page asks for. Other lists may hold as many
+    /// items as the list limit allows.
+    fn measure(
+        &self,
+        type_name: &str,
+        selections: &'a [Selection<'a, DefaultScalarValue>],
+        page_size: Option<usize>,
+        spread: &mut Vec<&'a str>,
+    ) -> Measure {
+        let mut total = Measure::default();
+        for selection in selections {
+            let measure = match selection {
+                Selection::Field(field) => {
+                    let field = &field.item;
+                    let meta = self
+                        .schema
+                        .concrete_type_by_name(type_name)
+                        .and_then(|meta| meta.field_by_name(field.name.item));
+                    let (meta, selection_set) = match (meta, &field.selection_set) {
+                        (Some(meta), Some(selection_set)) => (meta, selection_set),
+                        // Leaves and introspection fields.
+                        _ => {
+                            total = total.add(Measure { depth: 1, cost: 1 });
+                            continue;
+                        }
+                    };
+                    let paged = meta
+                        .arguments
+                        .iter()
+                        .flatten()
+                        .any(|arg| arg.name == "first" || arg.name == "last");
+                    let is_list = matches!(meta.field_type, Type::List(_) | Type::NonNullList(_));
+                    let (count, child_page_size) = if paged {
+                        let arguments = field.arguments.as_ref().map(|args| &args.item.items);
+                        let size = arguments
+                            .into_iter()
+                            .flatten()
+                            .filter(|(name, _)| name.item == "first" || name.item == "last")
+                            .filter_map(|(_, value)| self.int_value(&value.item))
+                            .max()
+                            .unwrap_or(DEFAULT_PAGE_SIZE as usize);
+                        (1, Some(size))
+                    } else if is_list {
+                        (page_size.unwrap_or(self.limits.max_list_items), None)
+                    } else {
+                        (1, None)
+                    };
+                    let child_type = meta.field_type.innermost_name();
+                    let children = self.measure(child_type, selection_set, child_page_size, spread);
+                    Measure {
+                        depth: children.depth + 1,
+                        cost: children.cost.saturating_mul(count).saturating_add(1),
+                    }
+                }
+                Selection::FragmentSpread(fragment) => {
+                    let name = fragment.item.name.item;
+                    // Cycles are rejected when the query is validated.
+                    match self.fragments.get(name) {
+                        Some((type_name, selections)) if !spread.contains(&name) => {
+                            spread.push(name);
+                            let measure = self.measure(type_name, selections, page_size, spread);
+                            spread.pop();
+                            measure
+                        }
+                        _ => Measure::default(),
+                    }
+                }
+                Selection::InlineFragment(fragment) => {
+                    let fragment = &fragment.item;
+                    let type_name = fragment
+                        .type_condition
+                        .as_ref()
+                        .map(|condition| condition.item)
+                        .unwrap_or(type_name);
+                    self.measure(type_name, &fragment.selection_set, page_size, spread)
+                }
+            };
+            total = total.add(measure);
+        }
+        total
+    }
+
+    fn int_value(&self, value: &InputValue) -> Option<usize> {
+        let value = match value {
+            InputValue::Variable(name) => self.variables.get(name)?,
+            value => value,
+        };
+        value.as_int_value().map(|value| value.max(0) as usize)
+    }
+}
+
+/// Collect the items of a list field, failing if there are more than the list limit allows.
+pub async fn collect<T, U>(
+    context: &Context,
+    items: impl Stream<Item = anyhow::Result<T>>,
+) -> FieldResult<Vec<U>>
+where
+    T: Into<U>,
+{
+    let max = context.limits.max_list_items;
+    let items: Vec<T> = items.take(max.saturating_add(1)).try_collect().await?;
+    if items.len() > max {
+        return Err(LimitError::ListItems { max }.into_field_error());
+    }
+    Ok(items.into_iter().map(Into::into).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphql::schema;
+
+    const LIMITS: QueryLimits = QueryLimits {
+        max_depth: 10,
+        max_cost: 100_000,
+        max_list_items: 1000,
+    };
+
+    fn check(query: &str, variables: Variables) -> Result<usize, LimitError> {
+        LIMITS.check(&schema().schema, query, None, &variables)
+    }
+
+    #[test]
+    fn test_check_counts_pages_and_lists() {
+        // Arrange.
+        let query = "{
+            people(first: 10) { edges { node { name devices { id } } } }
+            companies { name }
+        }";
+
+        // Act.
+        let res = check(query, Variables::new());
+
+        // Assert.
+        // Each of the 10 nodes may have as many devices as the list limit allows, and there may be
+        // as many companies.
+        assert_eq!(res, Ok(1 + (1 + 10 * (1 + 1 + (1 + 1000))) + (1 + 1000)));
+    }
+
+    #[test]
+    fn test_check_reads_page_size_from_variables() {
+        // Arrange.
+        let query = "query People($first: Int) { people(first: $first) { edges { cursor } } }";
+        let mut variables = Variables::new();
+        variables.insert("first".to_string(), InputValue::scalar(5));
+
+        // Act.
+        let res = check(query, variables);
+
+        // Assert.
+        assert_eq!(res, Ok(1 + (1 + 5)));
+    }
+
+    #[test]
+    fn test_check_rejects_deep_query() {
+        // Arrange.
+        let query = "{
+            companies { people { edges { node { locationReadings { edges { node {
+                person { company { people { edges { node { name } } } } }
+            } } } } } } }
+        }";
+
+        // Act.
+        let res = check(query, Variables::new());
+
+        // Assert.
+        assert_eq!(res, Err(LimitError::Depth { depth: 13, max: 10 }));
+    }
+
+    #[test]
+    fn test_check_rejects_costly_query() {
+        // Arrange.
+        let query = "fragment Readings on Person {
+            gasReadings(first: 1000) { edges { node { gas density } } }
+        }
+        { companies { people(first: 1000) { edges { node { ...Readings } } } } }";
+
+        // Act.
+        let res = check(query, Variables::new());
+
+        // Assert.
+        assert!(matches!(res, Err(LimitError::Cost { max: 100_000, .. })));
+    }
+
+    #[test]
+    fn test_check_passes_invalid_query() {
+        // Act.
+        let res = check("{ people {", Variables::new());
+
+        // Assert.
+        assert_eq!(res, Ok(0));
+    }
+}
@@ -0,0 +1,187 @@
+use crate::repo::company::{ArcCompanyRepo, Company};
+use crate::repo::person::{ArcPersonRepo, Person};
+use crate::repo::team::{ArcTeamRepo, Team};
+use crate::repo::user_account::{ArcUserAccountRepo, UserAccount};
+use futures_util::future::{BoxFuture, Shared};
+use futures_util::FutureExt;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, Weak};
+
+/// Times a batch yields before fetching, so sibling resolvers can add their IDs to it.
+const BATCH_YIELDS: usize = 10;
+
+type Fetch<T> =
+    Arc<dyn Fn(Vec<String>) -> BoxFuture<'static, anyhow::Result<Vec<T>>> + Send + Sync>;
+
+type Batch<T> = Shared<BoxFuture<'static, Result<Arc<HashMap<String, T>>, Arc<anyhow::Error>>>>;
+
+/// The batch that IDs are currently added to, before it fetches them.
+struct Pending<T> {
+    ids: HashSet<String>,
+    batch: Batch<T>,
+}
+
+/// Merges lookups by ID made while resolving a request into one query per batch. Resolvers of
+/// sibling fields run concurrently, so their lookups land in the same batch. Results are not
+/// cached beyond their batch, since the context of a subscription lives as long as its connection.
+pub struct Loader<T> {
+    fetch: Fetch<T>,
+    key: fn(&T) -> &str,
+    pending: Arc<Mutex<Option<Pending<T>>>>,
+}
+
+impl<T> Clone for Loader<T> {
+    fn clone(&self) -> Self {
+        Self {
+            fetch: self.fetch.clone(),
+            key: self.key,
+            pending: self.pending.clone(),
+        }
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> Loader<T> {
+    pub fn new<F>(fetch: F, key: fn(&T) -> &str) -> Self
+    where
+        F: Fn(Vec<String>) -> BoxFuture<'static, anyhow::Result<Vec<T>>> + Send + Sync + 'static,
+    {
+        Self {
+            fetch: Arc::new(fetch),
+            key,
+            pending: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub async fn load(&self, id: &str) -> anyhow::Result<Option<T>> {
+        let items = self.batch(std::iter::once(id)).await?;
+        Ok(items.get(id).cloned())
+    }
+
+    /// Items in the order of their IDs. Unknown IDs are skipped.
+    pub async fn load_many(&self, ids: &[String]) -> anyhow::Result<Vec<T>> {
+        let items = self.batch(ids.iter().map(String::as_str)).await?;
+        Ok(ids.iter().filter_map(|id| items.get(id).cloned()).collect())
+    }
+
+    async fn batch<'a>(
+        &self,
+        ids: impl Iterator<Item = &'a str>,
+    ) -> anyhow::Result<Arc<HashMap<String, T>>> {
+        let batch = {
+            let mut pending = self.pending.lock().unwrap();
+            let pending = pending.get_or_insert_with(|| Pending {
+                ids: HashSet::new(),
+                batch: self.start_batch(),
+            });
+            pending.ids.extend(ids.map(str::to_string));
+            pending.batch.clone()
+        };
+        batch.await.map_err(|e| anyhow::anyhow!("{:#}", e))
+    }
+
+    fn start_batch(&self) -> Batch<T> {
+        // Weak, so a batch that is never awaited does not keep the loader alive.
+        let pending: Weak<Mutex<Option<Pending<T>>>> = Arc::downgrade(&self.pending);
+        let fetch = self.fetch.clone();
+        let key = self.key;
+        async move {
+            for _ in 0..BATCH_YIELDS {
+                tokio::task::yield_now().await;
+            }
+            let ids = pending
+                .upgrade()
+                .and_then(|pending| pending.lock().unwrap().take())
+                .map(|pending| pending.ids)
+                .unwrap_or_default();
+            let items = fetch(ids.into_iter().collect()).await.map_err(Arc::new)?;
+            Ok(Arc::new(
+                items
+                    .into_iter()
+                    .map(|item| (key(&item).to_string(), item))
+                    .collect(),
+            ))
+        }
+        .boxed()
+        .shared()
+    }
+}
+
+pub fn company_loader(repo: ArcCompanyRepo) -> Loader<Company> {
+    Loader::new(
+        move |ids| {
+            let repo = repo.clone();
+            async move { repo.find_many(&ids).await }.boxed()
+        },
+        |company| &company.id,
+    )
+}
+
+pub fn person_loader(repo: ArcPersonRepo) -> Loader<Person> {
+    Loader::new(
+        move |ids| {
+            let repo = repo.clone();
+            async move { repo.find_many(&ids).await }.boxed()
+        },
+        |person| &person.id,
+    )
+}
+
+pub fn team_loader(repo: ArcTeamRepo) -> Loader<Team> {
+    Loader::new(
+        move |ids| {
+            let repo = repo.clone();
+            async move { repo.find_many(&ids).await }.boxed()
+        },
+        |team| &team.id,
+    )
+}
+
+pub fn user_account_loader(repo: ArcUserAccountRepo) -> Loader<UserAccount> {
+    Loader::new(
+        move |ids| {
+            let repo = repo.clone();
+            async move { repo.find_many(&ids).await }.boxed()
+        },
+        |user_account| &user_account.id,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::future;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_concurrent_loads_are_batched() {
+        // Arrange.
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let loader = {
+            let fetches = fetches.clone();
+            Loader::new(
+                move |ids: Vec<String>| {
+                    fetches.fetch_add(1, Ordering::SeqCst);
+                    async move { Ok(ids.into_iter().filter(|id| id != "unknown").collect()) }
+                        .boxed()
+                },
+                |id: &String| id.as_str(),
+            )
+        };
+
+        // Act.
+        let (a, b, unknown, many) = future::join4(
+            loader.load("a"),
+            loader.load("b"),
+            loader.load("unknown"),
+            loader.load_many(&["b".to_string(), "c".to_string()]),
+        )
+        .await;
+
+        // Assert.
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+        assert_eq!(a.unwrap().as_deref(), Some("a"));
+        assert_eq!(b.unwrap().as_deref(), Some("b"));
+        assert_eq!(unknown.unwrap(), None);
+        assert_eq!(many.unwrap(), vec!["b", "c"]);
+    }
+}
@@ -1,31 +1,76 @@
-use crate::graphql::person::Person;
+use crate::graphql::connection::{paginate, PageArgs, PageInfo, Slice};
+use crate::graphql::person::{select_ids, to_strings, Person};
 use crate::graphql::Context;
-use crate::repo;
 use crate::repo::location_reading;
+use crate::repo::mongo_util::new_object_id;
+use crate::repo::{self, intersect, Cursor};
 use chrono::{DateTime, Utc};
 use derive_more::{Deref, DerefMut, From};
-use futures_util::TryStreamExt;
-use juniper::FieldResult;
+use juniper::{FieldResult, ID};
 
 #[derive(Clone, From, Deref, DerefMut)]
 pub struct LocationReading(pub location_reading::LocationReading);
 
 #[derive(juniper::GraphQLInputObject, Default)]
 pub struct LocationReadingFilter {
+    pub person_ids: Option<Vec<ID>>,
+    pub team_ids: Option<Vec<ID>>,
+    pub company_ids: Option<Vec<ID>>,
     pub min_timestamp: Option<DateTime<Utc>>,
     pub max_timestamp: Option<DateTime<Utc>>,
 }
 
+#[derive(juniper::GraphQLObject)]
+#[graphql(context = Context)]
+pub struct LocationReadingConnection {
+    pub edges: Vec<LocationReadingEdge>,
+    pub page_info: PageInfo,
+}
+
+#[derive(juniper::GraphQLObject)]
+#[graphql(context = Context)]
+pub struct LocationReadingEdge {
+    pub cursor: String,
+    pub node: LocationReading,
+}
+
+impl From<Slice<location_reading::LocationReading>> for LocationReadingConnection {
+    fn from(value: Slice<location_reading::LocationReading>) -> Self {
+        Self {
+            edges: value
+                .edges
+                .into_iter()
+                .map(|(cursor, node)| LocationReadingEdge {
+                    cursor,
+                    node: node.into(),
+                })
+                .collect(),
+            page_info: value.page_info,
+        }
+    }
+}
+
+#[derive(juniper::GraphQLInputObject)]
+pub struct LocationReadingInput {
+    pub timestamp: DateTime<Utc>,
+    pub person_id: ID,
+    pub coordinates: Vec<f64>,
+}
+
 #[juniper::graphql_object(context = Context)]
 impl LocationReading {
+    pub fn id(&self) -> ID {
+        self.id.clone().into()
+    }
+
     pub fn timestamp(&self) -> &DateTime<Utc> {
         &self.timestamp
     }
 
     pub async fn person(&self, context: &Context) -> FieldResult<Option<Person>> {
         Ok(context
-            .person_repo
-            .find_one(&self.person_id)
+            .person_loader
+            .load(&self.person_id)
             .await?
             .map(Into::into))
     }
@@ -35,20 +80,63 @@ impl LocationReading {
     }
 }
 
+/// A page of readings, oldest first, of the given people or of everyone.
 pub async fn list(
     context: &Context,
+    person_ids: Option<Vec<String>>,
     filter: Option<LocationReadingFilter>,
-) -> FieldResult<Vec<LocationReading>> {
-    let filter = filter.unwrap_or_default();
-    Ok(context
-        .location_reading_repo
-        .find(repo::location_reading::LocationReadingFilter {
-            person_ids: None,
-            min_timestamp: filter.min_timestamp,
-            max_timestamp: filter.max_timestamp,
+    args: PageArgs,
+) -> FieldResult<LocationReadingConnection> {
+    let LocationReadingFilter {
+        person_ids: filter_person_ids,
+        team_ids,
+        company_ids,
+        min_timestamp,
+        max_timestamp,
+    } = filter.unwrap_or_default();
+    let person_ids = select_ids(
+        context,
+        intersect(person_ids, to_strings(filter_person_ids)),
+        team_ids,
+    )
+    .await?;
+    let slice = paginate(args, context.limits.max_list_items, cursor, |page| {
+        context
+            .location_reading_repo
+            .find(repo::location_reading::LocationReadingFilter {
+                person_ids,
+                company_ids: to_strings(company_ids),
+                min_timestamp,
+                max_timestamp,
+                page,
+            })
+    })
+    .await?;
+    Ok(slice.into())
+}
+
+fn cursor(reading: &location_reading::LocationReading) -> Cursor {
+    Cursor {
+        timestamp: Some(reading.timestamp),
+        id: reading.id.clone(),
+    }
+}
+
+/// Store readings sent by a device. Returns the number of readings stored.
+pub async fn ingest(context: &Context, input: Vec<LocationReadingInput>) -> FieldResult<i32> {
+    let count = input.len() as i32;
+    if count == 0 {
+        return Ok(0);
+    }
+    let items = input
+        .into_iter()
+        .map(|r| location_reading::LocationReading {
+            id: new_object_id(),
+            timestamp: r.timestamp,
+            person_id: r.person_id.to_string(),
+            coordinates: r.coordinates,
         })
-        .await?
-        .map_ok(Into::into)
-        .try_collect()
-        .await?)
+        .collect();
+    context.location_reading_repo.insert_many(items).await?;
+    Ok(count)
 }
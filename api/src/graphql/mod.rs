@@ -1,105 +1,521 @@
+pub mod api_key;
 pub mod company;
+pub mod connection;
 pub mod device;
 pub mod gas_reading;
+pub mod impersonation;
 pub mod incident;
 pub mod incident_stats;
+pub mod limits;
+pub mod loader;
 pub mod location_reading;
 pub mod person;
+pub mod session;
+pub mod subscription;
 pub mod team;
 pub mod user_account;
 
 use crate::auth::{AuthProvider, Claims, ClaimsProvider};
-use crate::graphql::company::{Company, CompanyInput};
+use crate::event::EventBus;
+use crate::graphql::api_key::{ApiKey, ApiKeyInput, CreatedApiKey};
+use crate::graphql::company::{Company, CompanyInput, OidcConfigInput};
+use crate::graphql::connection::PageArgs;
 use crate::graphql::device::Device;
-use crate::graphql::device::DeviceInput;
-use crate::graphql::gas_reading::{GasReading, GasReadingFilter};
-use crate::graphql::incident::{Incident, IncidentFilter, IncidentInput};
+use crate::graphql::device::{DeviceConnection, DeviceFilter, DeviceInput};
+use crate::graphql::gas_reading::{GasReadingConnection, GasReadingFilter, GasReadingInput};
+use crate::graphql::impersonation::{Impersonation, ImpersonationToken};
+use crate::graphql::incident::{Incident, IncidentConnection, IncidentFilter, IncidentInput};
 use crate::graphql::incident_stats::{IncidentStats, IncidentStatsFilter};
-use crate::graphql::location_reading::{LocationReading, LocationReadingFilter};
-use crate::graphql::person::{Person, PersonInput};
-use crate::graphql::team::{Team, TeamInput};
-use crate::graphql::user_account::{UserAccount, UserAccountInput};
+use crate::graphql::limits::{LimitError, QueryLimits};
+use crate::graphql::loader::Loader;
+use crate::graphql::location_reading::{
+    LocationReadingConnection, LocationReadingFilter, LocationReadingInput,
+};
+use crate::graphql::person::{Person, PersonConnection, PersonFilter, PersonInput};
+use crate::graphql::session::Session;
+use crate::graphql::subscription::Subscription;
+use crate::graphql::team::{Team, TeamConnection, TeamFilter, TeamInput};
+use crate::graphql::user_account::{
+    AuthTokens, LoginResult, TotpConfirmation, TotpEnrollment, UserAccount, UserAccountConnection,
+    UserAccountFilter, UserAccountInput,
+};
+use crate::mail::ArcMailer;
+use crate::permission::{Permission, Permissions};
 use crate::repo::company::ArcCompanyRepo;
 use crate::repo::device::ArcDeviceRepo;
 use crate::repo::gas_reading::ArcGasReadingRepo;
+use crate::repo::impersonation::ArcImpersonationRepo;
 use crate::repo::incident::ArcIncidentRepo;
 use crate::repo::incident_stats::ArcIncidentStatsRepo;
 use crate::repo::location_reading::ArcLocationReadingRepo;
 use crate::repo::person::ArcPersonRepo;
+use crate::repo::session::ArcSessionRepo;
 use crate::repo::team::ArcTeamRepo;
-use crate::repo::user_account::{Access, ArcUserAccountRepo};
-use crate::warp_ext;
+use crate::repo::tenant::{Tenant, TenantScope};
+use crate::repo::user_account::{Access, ArcUserAccountRepo, Role};
+use crate::sms::ArcSmsSender;
 use crate::warp_ext::BoxReply;
-use juniper::{graphql_object, EmptySubscription, FieldError, FieldResult, RootNode, ID};
+use crate::{repo, warp_ext};
+use chrono::{DateTime, Utc};
+use futures_util::future::{self, Either};
+use futures_util::{stream, SinkExt, StreamExt, TryStreamExt};
+use juniper::http::{GraphQLRequest, GraphQLResponse};
+use juniper::{
+    graphql_object, graphql_value, DefaultScalarValue, ExecutionError, FieldError, FieldResult,
+    InputValue, IntoFieldError, RootNode, Value, Variables, ID,
+};
+use juniper_graphql_ws::{
+    ArcSchema, ClientMessage, Connection, ConnectionConfig, DataPayload, Init, ServerMessage,
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::convert::TryFrom;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use warp::filters::ws::{Message, WebSocket, Ws};
 use warp::filters::BoxedFilter;
 use warp::http::header::AUTHORIZATION;
 use warp::http::Response;
 use warp::{Filter, Reply};
 
+const API_KEY_HEADER: &str = "x-api-key";
+const SUBSCRIPTION_KEEP_ALIVE: Duration = Duration::from_secs(15);
+const WS_PROTOCOL_HEADER: &str = "sec-websocket-protocol";
+const WS_PROTOCOL: &str = "graphql-ws";
+
 #[derive(Clone)]
 pub struct Deps {
     pub company_repo: ArcCompanyRepo,
     pub device_repo: ArcDeviceRepo,
     pub gas_reading_repo: ArcGasReadingRepo,
+    pub impersonation_repo: ArcImpersonationRepo,
     pub incident_repo: ArcIncidentRepo,
     pub incident_stats_repo: ArcIncidentStatsRepo,
     pub location_reading_repo: ArcLocationReadingRepo,
     pub person_repo: ArcPersonRepo,
+    pub session_repo: ArcSessionRepo,
     pub team_repo: ArcTeamRepo,
     pub user_account_repo: ArcUserAccountRepo,
     pub auth_provider: AuthProvider,
     pub claims_provider: ClaimsProvider,
+    pub events: EventBus,
+    pub mailer: ArcMailer,
+    pub sms_sender: ArcSmsSender,
+    pub app_url: String,
+    pub limits: QueryLimits,
+    /// Reverse proxies whose X-Forwarded-For header is trusted.
+    pub trusted_proxies: Vec<IpAddr>,
 }
 
 #[derive(Clone)]
 pub struct Context {
     pub claims: Option<Claims>,
+    pub api_key: Option<repo::api_key::ApiKey>,
+    pub client_ip: Option<String>,
+    /// Companies the request may access. Repos below are limited to them.
+    pub tenant: Tenant,
+    /// Checks which records the request may access, for records that are not read through a repo.
+    pub scope: TenantScope,
+    pub permissions: Permissions,
+    /// Batch lookups of related records through the repos below. Prefer them over `find_one` in
+    /// field resolvers, which run once for each item of a list.
+    pub company_loader: Loader<repo::company::Company>,
+    pub person_loader: Loader<repo::person::Person>,
+    pub team_loader: Loader<repo::team::Team>,
+    pub user_account_loader: Loader<repo::user_account::UserAccount>,
     pub company_repo: ArcCompanyRepo,
     pub device_repo: ArcDeviceRepo,
     pub gas_reading_repo: ArcGasReadingRepo,
+    pub impersonation_repo: ArcImpersonationRepo,
     pub incident_repo: ArcIncidentRepo,
     pub incident_stats_repo: ArcIncidentStatsRepo,
     pub location_reading_repo: ArcLocationReadingRepo,
     pub person_repo: ArcPersonRepo,
+    pub session_repo: ArcSessionRepo,
     pub team_repo: ArcTeamRepo,
     pub user_account_repo: ArcUserAccountRepo,
     pub auth_provider: AuthProvider,
     pub claims_provider: ClaimsProvider,
+    pub events: EventBus,
+    pub mailer: ArcMailer,
+    pub sms_sender: ArcSmsSender,
+    pub app_url: String,
+    pub limits: QueryLimits,
 }
 
 impl juniper::Context for Context {}
 
 pub fn graphql_filter(deps: Deps) -> BoxedFilter<(Box<dyn Reply>,)> {
+    let subscriptions = subscriptions_filter(deps.clone());
     let state = state_filter(deps);
-    let schema = schema();
-    (warp::get().or(warp::post()).unify())
-        .and(warp::path("graphql"))
-        .and(juniper_warp::make_graphql_filter(schema, state))
-        .map(|r: Response<Vec<u8>>| r.boxed())
+    let schema = Arc::new(schema());
+    subscriptions
+        .or((warp::get().or(warp::post()).unify())
+            .and(warp::path("graphql"))
+            .and(execute_filter(schema, state))
+            .map(|r: Response<Vec<u8>>| r.boxed()))
+        .unify()
+        .boxed()
+}
+
+/// Body of a GraphQL request. Unlike `juniper::http::GraphQLRequest`, the query can be read to
+/// check it against the query limits.
+#[derive(Deserialize)]
+struct RequestBody {
+    query: String,
+    #[serde(rename = "operationName")]
+    operation_name: Option<String>,
+    variables: Option<InputValue>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BatchRequestBody {
+    Single(RequestBody),
+    Batch(Vec<RequestBody>),
+}
+
+/// Execute requests sent as JSON, as GraphQL in a POST body or in the query string of a GET, like
+/// `juniper_warp::make_graphql_filter`. Requests over the query limits are rejected before they
+/// run.
+fn execute_filter(
+    schema: Arc<Schema>,
+    state: BoxedFilter<(Context,)>,
+) -> BoxedFilter<(Response<Vec<u8>>,)> {
+    let get_schema = schema.clone();
+    let get = warp::get()
+        .and(state.clone())
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(
+            move |context: Context, mut params: HashMap<String, String>| {
+                let schema = get_schema.clone();
+                async move {
+                    let variables = params.remove("variables").map(|v| serde_json::from_str(&v));
+                    let body = match (params.remove("query"), variables.transpose()) {
+                        (Some(query), Ok(variables)) => BatchRequestBody::Single(RequestBody {
+                            query,
+                            operation_name: params
+                                .remove("operationName")
+                                .or_else(|| params.remove("operation_name")),
+                            variables,
+                        }),
+                        _ => return Ok(bad_request()),
+                    };
+                    execute(&schema, &context, body).await
+                }
+            },
+        );
+    let json_schema = schema.clone();
+    let post_json = warp::post()
+        .and(state.clone())
+        .and(warp::body::json())
+        .and_then(move |context: Context, body: BatchRequestBody| {
+            let schema = json_schema.clone();
+            async move { execute(&schema, &context, body).await }
+        });
+    let post_graphql = warp::post().and(state).and(warp::body::bytes()).and_then(
+        move |context: Context, bytes: warp::hyper::body::Bytes| {
+            let schema = schema.clone();
+            async move {
+                let query = match String::from_utf8(bytes.to_vec()) {
+                    Ok(query) => query,
+                    Err(_) => return Ok(bad_request()),
+                };
+                let body = BatchRequestBody::Single(RequestBody {
+                    query,
+                    operation_name: None,
+                    variables: None,
+                });
+                execute(&schema, &context, body).await
+            }
+        },
+    );
+    get.or(post_json).unify().or(post_graphql).unify().boxed()
+}
+
+async fn execute(
+    schema: &Schema,
+    context: &Context,
+    body: BatchRequestBody,
+) -> Result<Response<Vec<u8>>, Infallible> {
+    let res = match body {
+        BatchRequestBody::Single(request) => {
+            let check = check_limits(schema, context, &request);
+            execute_one(schema, context, request, check).await
+        }
+        BatchRequestBody::Batch(requests) => {
+            let checks: Vec<_> = requests
+                .iter()
+                .map(|request| check_limits(schema, context, request))
+                .collect();
+            // A batch costs as much as all of its requests.
+            let cost = checks
+                .iter()
+                .flatten()
+                .fold(0usize, |total, cost| total.saturating_add(*cost));
+            let batch_check = context.limits.check_cost(cost);
+            let responses =
+                future::join_all(requests.into_iter().zip(checks).map(|(request, check)| {
+                    let check = check.and_then(|cost| batch_check.clone().map(|_| cost));
+                    execute_one(schema, context, request, check)
+                }))
+                .await;
+            responses
+                .into_iter()
+                .collect::<serde_json::Result<Vec<_>>>()
+                .map(|responses| {
+                    let is_ok = responses.iter().all(|(_, is_ok)| *is_ok);
+                    let values: Vec<_> = responses.into_iter().map(|(value, _)| value).collect();
+                    (serde_json::Value::from(values), is_ok)
+                })
+        }
+    };
+    Ok(
+        match res.and_then(|(value, is_ok)| Ok((serde_json::to_vec(&value)?, is_ok))) {
+            Ok((body, is_ok)) => Response::builder()
+                .status(if is_ok { 200 } else { 400 })
+                .header("content-type", "application/json")
+                .body(body)
+                .expect("response is valid"),
+            Err(e) => {
+                log::error!("{:?}", e);
+                Response::builder()
+                    .status(500)
+                    .body(Vec::new())
+                    .expect("response is valid")
+            }
+        },
+    )
+}
+
+fn check_limits(
+    schema: &Schema,
+    context: &Context,
+    request: &RequestBody,
+) -> Result<usize, LimitError> {
+    let variables: Variables = request
+        .variables
+        .as_ref()
+        .and_then(|variables| variables.to_object_value())
+        .map(|variables| {
+            variables
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value.clone()))
+                .collect()
+        })
+        .unwrap_or_default();
+    context.limits.check(
+        &schema.schema,
+        &request.query,
+        request.operation_name.as_deref(),
+        &variables,
+    )
+}
+
+/// Run a request unless it failed the query limits. Returns the response and whether the request
+/// was valid.
+async fn execute_one(
+    schema: &Schema,
+    context: &Context,
+    request: RequestBody,
+    check: Result<usize, LimitError>,
+) -> serde_json::Result<(serde_json::Value, bool)> {
+    if let Err(e) = check {
+        log::warn!("Request over the query limits: {}", e);
+        let res: GraphQLResponse = GraphQLResponse::error(e.into_field_error());
+        return Ok((serde_json::to_value(&res)?, res.is_ok()));
+    }
+    let request = GraphQLRequest::new(request.query, request.operation_name, request.variables);
+    let res = request.execute(schema, context).await;
+    Ok((serde_json::to_value(&res)?, res.is_ok()))
+}
+
+fn bad_request() -> Response<Vec<u8>> {
+    Response::builder()
+        .status(400)
+        .body(Vec::new())
+        .expect("response is valid")
+}
+
+/// Serve subscriptions over WebSocket with the graphql-ws protocol. Browsers cannot set headers on
+/// WebSocket connections, so credentials are sent in the payload of the connection init message
+/// instead, with the same names as the headers.
+fn subscriptions_filter(deps: Deps) -> BoxedFilter<(Box<dyn Reply>,)> {
+    let schema = Arc::new(schema());
+    warp::path("graphql")
+        .and(warp::ws())
+        .and(warp::header::optional::<String>(WS_PROTOCOL_HEADER))
+        .and(warp_ext::client_ip(deps.trusted_proxies.clone()))
+        .and(warp_ext::with_clone(deps))
+        .map(
+            move |ws: Ws, protocols: Option<String>, client_ip: Option<String>, deps: Deps| {
+                let schema = schema.clone();
+                let reply = ws.on_upgrade(move |websocket| async move {
+                    let limits = deps.limits;
+                    let init = move |params: Variables| connection_config(deps, client_ip, params);
+                    if let Err(e) = serve_graphql_ws(websocket, schema, limits, init).await {
+                        log::warn!("Subscription connection failed: {}", e);
+                    }
+                });
+                // Clients must close connections that select a protocol they did not offer.
+                if offers_protocol(protocols.as_deref(), WS_PROTOCOL) {
+                    warp::reply::with_header(reply, WS_PROTOCOL_HEADER, WS_PROTOCOL).boxed()
+                } else {
+                    reply.boxed()
+                }
+            },
+        )
         .boxed()
 }
 
+/// Whether a comma-separated list of WebSocket subprotocols includes one.
+fn offers_protocol(protocols: Option<&str>, protocol: &str) -> bool {
+    protocols.is_some_and(|protocols| protocols.split(',').any(|p| p.trim() == protocol))
+}
+
+/// Like `juniper_warp::subscriptions::serve_graphql_ws`, but queries and mutations, which can also
+/// be sent over the connection, are checked against the query limits before they run.
+async fn serve_graphql_ws<I>(
+    websocket: WebSocket,
+    schema: Arc<Schema>,
+    limits: QueryLimits,
+    init: I,
+) -> anyhow::Result<()>
+where
+    I: Init<DefaultScalarValue, Context> + Send,
+{
+    let (ws_tx, ws_rx) = websocket.split();
+    let (s_tx, s_rx) = Connection::new(ArcSchema(schema.clone()), init).split();
+    let (rejected_tx, rejected_rx) = tokio::sync::mpsc::unbounded_channel();
+    let ws_rx = ws_rx
+        .map_err(anyhow::Error::from)
+        .try_filter_map(move |msg| {
+            let msg = WsMessage(msg);
+            let forward = match ClientMessage::try_from(msg.clone()) {
+                Ok(ClientMessage::Start { id, payload }) => {
+                    let check = limits.check(
+                        &schema.schema,
+                        &payload.query,
+                        payload.operation_name.as_deref(),
+                        &payload.variables,
+                    );
+                    match check {
+                        Ok(_) => true,
+                        Err(e) => {
+                            log::warn!("Request over the query limits: {}", e);
+                            let errors = vec![ExecutionError::at_origin(e.into_field_error())];
+                            let data = ServerMessage::Data {
+                                id: id.clone(),
+                                payload: DataPayload {
+                                    data: Value::null(),
+                                    errors,
+                                },
+                            };
+                            // The receiver only closes with the connection.
+                            let _ = rejected_tx.send(data);
+                            let _ = rejected_tx.send(ServerMessage::Complete { id });
+                            false
+                        }
+                    }
+                }
+                _ => true,
+            };
+            future::ready(Ok(forward.then_some(msg)))
+        });
+    let rejected_rx = stream::unfold(rejected_rx, |mut rx| async move {
+        rx.recv().await.map(|msg| (msg, rx))
+    })
+    .boxed();
+    let s_rx = stream::select(s_rx, rejected_rx)
+        .map(|msg| Ok::<_, anyhow::Error>(Message::text(serde_json::to_string(&msg)?)));
+    match future::select(
+        ws_rx.forward(s_tx.sink_err_into()),
+        s_rx.forward(ws_tx.sink_err_into()),
+    )
+    .await
+    {
+        Either::Left((r, _)) => r,
+        Either::Right((r, _)) => r,
+    }
+}
+
+/// WebSocket message read as a graphql-ws client message.
+#[derive(Clone)]
+struct WsMessage(Message);
+
+impl TryFrom<WsMessage> for ClientMessage<DefaultScalarValue> {
+    type Error = serde_json::Error;
+
+    fn try_from(msg: WsMessage) -> serde_json::Result<Self> {
+        serde_json::from_slice(msg.0.as_bytes())
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("Invalid credentials")]
+struct InvalidCredentials;
+
+async fn connection_config(
+    deps: Deps,
+    client_ip: Option<String>,
+    params: Variables,
+) -> Result<ConnectionConfig<Context>, InvalidCredentials> {
+    let param = |name: &str| {
+        params
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .and_then(|(_, value)| value.as_string_value())
+            .map(str::to_string)
+    };
+    let claims = match param(AUTHORIZATION.as_str()) {
+        Some(token) => Some(
+            verify_claims(&deps.claims_provider, &deps.auth_provider, &token)
+                .await
+                .ok_or(InvalidCredentials)?,
+        ),
+        None => None,
+    };
+    let api_key = match param(API_KEY_HEADER) {
+        Some(key) => Some(
+            verify_api_key(&deps.auth_provider, &key)
+                .await
+                .ok_or(InvalidCredentials)?,
+        ),
+        None => None,
+    };
+    let context = create_context(deps, claims, api_key, client_ip);
+    Ok(ConnectionConfig::new(context).with_keep_alive_interval(SUBSCRIPTION_KEEP_ALIVE))
+}
+
 pub fn state_filter(deps: Deps) -> BoxedFilter<(Context,)> {
     // Todo: Extract claims on each request.
-    claims_filter(deps.claims_provider.clone())
+    claims_filter(deps.claims_provider.clone(), deps.auth_provider.clone())
+        .and(api_key_filter(deps.auth_provider.clone()))
+        .and(warp_ext::client_ip(deps.trusted_proxies.clone()))
         .and(warp_ext::with_clone(deps))
-        .map(|claims: Option<Claims>, deps: Deps| create_context(deps, claims))
+        .map(
+            |claims: Option<Claims>,
+             api_key: Option<repo::api_key::ApiKey>,
+             client_ip: Option<String>,
+             deps: Deps| { create_context(deps, claims, api_key, client_ip) },
+        )
         .boxed()
 }
 
-pub fn claims_filter(claims_provider: ClaimsProvider) -> BoxedFilter<(Option<Claims>,)> {
+pub fn claims_filter(
+    claims_provider: ClaimsProvider,
+    auth_provider: AuthProvider,
+) -> BoxedFilter<(Option<Claims>,)> {
     warp::header(AUTHORIZATION.as_str())
         .and(warp_ext::with_clone(claims_provider))
+        .and(warp_ext::with_clone(auth_provider))
         .and_then(
-            |token: String, claims_provider: ClaimsProvider| async move {
-                let token = token.trim_start_matches("Bearer ");
-                let res = claims_provider.verify_token(token);
-                match res {
-                    Ok(claims) => Ok(Some(claims)),
-                    Err(e) => {
-                        log::warn!("Invalid token: {}", e);
-                        Err(warp::reject())
-                    }
+            |token: String, claims_provider: ClaimsProvider, auth_provider: AuthProvider| async move {
+                match verify_claims(&claims_provider, &auth_provider, &token).await {
+                    Some(claims) => Ok(Some(claims)),
+                    None => Err(warp::reject()),
                 }
             },
         )
@@ -108,34 +524,162 @@ pub fn claims_filter(claims_provider: ClaimsProvider) -> BoxedFilter<(Option<Cla
         .boxed()
 }
 
+/// Claims of a bearer token, unless it is invalid or its session was revoked.
+async fn verify_claims(
+    claims_provider: &ClaimsProvider,
+    auth_provider: &AuthProvider,
+    token: &str,
+) -> Option<Claims> {
+    let token = token.trim_start_matches("Bearer ");
+    let claims = match claims_provider.verify_token(token) {
+        Ok(claims) => claims,
+        Err(e) => {
+            log::warn!("Invalid token: {}", e);
+            return None;
+        }
+    };
+    match auth_provider.verify_session(&claims.jti).await {
+        Ok(true) => Some(claims),
+        Ok(false) => {
+            log::warn!("Revoked token for user account {}", claims.sub);
+            None
+        }
+        Err(e) => {
+            log::error!("{:?}", e);
+            None
+        }
+    }
+}
+
+/// Authenticate a machine client by the API key in the `X-Api-Key` header.
+pub fn api_key_filter(
+    auth_provider: AuthProvider,
+) -> BoxedFilter<(Option<repo::api_key::ApiKey>,)> {
+    warp::header(API_KEY_HEADER)
+        .and(warp_ext::with_clone(auth_provider))
+        .and_then(|key: String, auth_provider: AuthProvider| async move {
+            match verify_api_key(&auth_provider, &key).await {
+                Some(api_key) => Ok(Some(api_key)),
+                None => Err(warp::reject()),
+            }
+        })
+        .or(warp::any().map(|| None))
+        .unify()
+        .boxed()
+}
+
+async fn verify_api_key(auth_provider: &AuthProvider, key: &str) -> Option<repo::api_key::ApiKey> {
+    match auth_provider.verify_api_key(key).await {
+        Ok(Some(api_key)) => Some(api_key),
+        Ok(None) => {
+            log::warn!("Invalid API key");
+            None
+        }
+        Err(e) => {
+            log::error!("{:?}", e);
+            None
+        }
+    }
+}
+
 pub fn playground_filter() -> BoxedFilter<(Box<dyn Reply>,)> {
     warp::get()
         .and(warp::path("playground"))
-        .and(juniper_warp::playground_filter("/graphql", None))
+        .and(juniper_warp::playground_filter(
+            "/graphql",
+            Some("/graphql"),
+        ))
         .map(|r: Response<Vec<u8>>| r.boxed())
         .boxed()
 }
 
-type Schema = RootNode<'static, Query, Mutation, EmptySubscription<Context>>;
+type Schema = RootNode<'static, Query, Mutation, Subscription>;
 
 fn schema() -> Schema {
-    Schema::new(Query, Mutation, EmptySubscription::<Context>::new())
+    Schema::new(Query, Mutation, Subscription)
 }
 
-fn create_context(deps: Deps, claims: Option<Claims>) -> Context {
+fn create_context(
+    deps: Deps,
+    claims: Option<Claims>,
+    api_key: Option<repo::api_key::ApiKey>,
+    client_ip: Option<String>,
+) -> Context {
+    let tenant = tenant(&claims, &api_key);
+    let scope = TenantScope::new(
+        tenant.clone(),
+        lead_person_id(&claims),
+        deps.person_repo.clone(),
+        deps.team_repo.clone(),
+    );
+    let permissions = match (&claims, &api_key) {
+        (Some(claims), _) => Permissions::for_claims(claims),
+        (None, Some(api_key)) => Permissions::for_api_key(api_key),
+        (None, None) => Permissions::default(),
+    };
+    let company_repo = scope.company_repo(deps.company_repo);
+    let person_repo = scope.person_repo(deps.person_repo);
+    let team_repo = scope.team_repo(deps.team_repo);
+    let user_account_repo = scope.user_account_repo(deps.user_account_repo);
     Context {
         claims,
-        company_repo: deps.company_repo,
-        device_repo: deps.device_repo,
-        gas_reading_repo: deps.gas_reading_repo,
-        incident_repo: deps.incident_repo,
-        incident_stats_repo: deps.incident_stats_repo,
-        location_reading_repo: deps.location_reading_repo,
-        person_repo: deps.person_repo,
-        team_repo: deps.team_repo,
-        user_account_repo: deps.user_account_repo,
+        api_key,
+        client_ip,
+        tenant,
+        scope: scope.clone(),
+        permissions,
+        company_loader: loader::company_loader(company_repo.clone()),
+        person_loader: loader::person_loader(person_repo.clone()),
+        team_loader: loader::team_loader(team_repo.clone()),
+        user_account_loader: loader::user_account_loader(user_account_repo.clone()),
+        company_repo,
+        device_repo: scope.device_repo(deps.device_repo),
+        gas_reading_repo: scope.gas_reading_repo(deps.gas_reading_repo),
+        impersonation_repo: scope.impersonation_repo(deps.impersonation_repo),
+        incident_repo: scope.incident_repo(deps.incident_repo),
+        incident_stats_repo: scope.incident_stats_repo(deps.incident_stats_repo),
+        location_reading_repo: scope.location_reading_repo(deps.location_reading_repo),
+        person_repo,
+        session_repo: deps.session_repo,
+        team_repo,
+        user_account_repo,
         auth_provider: deps.auth_provider,
         claims_provider: deps.claims_provider,
+        events: deps.events,
+        mailer: deps.mailer,
+        sms_sender: deps.sms_sender,
+        app_url: deps.app_url,
+        limits: deps.limits,
+    }
+}
+
+/// Super admins may access every company. Other user accounts and API keys may only access their
+/// own company.
+fn tenant(claims: &Option<Claims>, api_key: &Option<repo::api_key::ApiKey>) -> Tenant {
+    match (claims, api_key) {
+        (Some(claims), _) if claims.access == Access::SuperAdmin => Tenant::All,
+        (Some(claims), _) => Tenant::Company(claims.company_id.clone()),
+        (None, Some(api_key)) => Tenant::Company(api_key.company_id.clone()),
+        (None, None) => Tenant::Nobody,
+    }
+}
+
+/// Person whose led teams limit what the request can see. Users with view access and no
+/// company-wide role who are granted the team lead role only see the readings, incidents and teams
+/// of the teams they lead. Other users with view access see their whole company.
+fn lead_person_id(claims: &Option<Claims>) -> Option<String> {
+    match claims {
+        Some(claims)
+            if claims.access == Access::View
+                && claims.roles.iter().all(|grant| grant.team_id.is_some())
+                && claims
+                    .roles
+                    .iter()
+                    .any(|grant| grant.role == Role::TeamLead) =>
+        {
+            claims.person_id.clone()
+        }
+        _ => None,
     }
 }
 
@@ -143,98 +687,218 @@ pub struct Query;
 
 #[graphql_object(context = Context)]
 impl Query {
+    async fn api_keys(
+        #[graphql(context)] context: &Context,
+        company_id: Option<ID>,
+    ) -> FieldResult<Vec<ApiKey>> {
+        require(context, Permission::ApiKeyAdmin)?;
+        api_key::list(context, company_id).await
+    }
+
     async fn company(
         #[graphql(context)] context: &Context,
         id: ID,
     ) -> FieldResult<Option<Company>> {
-        verify_view(&context.claims)?;
+        require(context, Permission::CompanyRead)?;
         company::get(context, id).await
     }
 
     async fn companies(#[graphql(context)] context: &Context) -> FieldResult<Vec<Company>> {
-        verify_view(&context.claims)?;
+        require(context, Permission::CompanyRead)?;
         company::list(context).await
     }
 
     async fn device(#[graphql(context)] context: &Context, id: ID) -> FieldResult<Option<Device>> {
-        verify_view(&context.claims)?;
+        require(context, Permission::DeviceRead)?;
         device::get(context, id).await
     }
 
-    async fn devices(#[graphql(context)] context: &Context) -> FieldResult<Vec<Device>> {
-        verify_view(&context.claims)?;
-        device::list(context).await
+    async fn devices(
+        #[graphql(context)] context: &Context,
+        filter: Option<DeviceFilter>,
+        first: Option<i32>,
+        after: Option<String>,
+        last: Option<i32>,
+        before: Option<String>,
+    ) -> FieldResult<DeviceConnection> {
+        require(context, Permission::DeviceRead)?;
+        let args = PageArgs {
+            first,
+            after,
+            last,
+            before,
+        };
+        device::list(context, filter, args).await
     }
 
     async fn gas_readings(
         #[graphql(context)] context: &Context,
         filter: Option<GasReadingFilter>,
-    ) -> FieldResult<Vec<GasReading>> {
-        verify_view(&context.claims)?;
-        gas_reading::list(context, filter).await
+        first: Option<i32>,
+        after: Option<String>,
+        last: Option<i32>,
+        before: Option<String>,
+    ) -> FieldResult<GasReadingConnection> {
+        require(context, Permission::ReadingRead)?;
+        let args = PageArgs {
+            first,
+            after,
+            last,
+            before,
+        };
+        gas_reading::list(context, None, filter, args).await
+    }
+
+    /// Records of admins acting as user accounts, most recent first.
+    async fn impersonations(
+        #[graphql(context)] context: &Context,
+        user_account_id: Option<ID>,
+    ) -> FieldResult<Vec<Impersonation>> {
+        require(context, Permission::UserAdmin)?;
+        impersonation::list(context, user_account_id).await
     }
 
     async fn incident(
         #[graphql(context)] context: &Context,
         id: ID,
     ) -> FieldResult<Option<Incident>> {
-        verify_view(&context.claims)?;
+        require(context, Permission::IncidentRead)?;
         incident::get(context, id).await
     }
 
     async fn incidents(
         #[graphql(context)] context: &Context,
         filter: Option<IncidentFilter>,
-    ) -> FieldResult<Vec<Incident>> {
-        verify_view(&context.claims)?;
-        incident::list(context, filter).await
+        first: Option<i32>,
+        after: Option<String>,
+        last: Option<i32>,
+        before: Option<String>,
+    ) -> FieldResult<IncidentConnection> {
+        require(context, Permission::IncidentRead)?;
+        let args = PageArgs {
+            first,
+            after,
+            last,
+            before,
+        };
+        incident::list(context, None, filter, args).await
     }
 
     async fn incident_stats(
         #[graphql(context)] context: &Context,
         filter: Option<IncidentStatsFilter>,
     ) -> FieldResult<Vec<IncidentStats>> {
-        verify_view(&context.claims)?;
-        incident_stats::list(context, filter).await
+        require(context, Permission::IncidentRead)?;
+        incident_stats::list(context, None, None, filter).await
     }
 
     async fn location_readings(
         #[graphql(context)] context: &Context,
         filter: Option<LocationReadingFilter>,
-    ) -> FieldResult<Vec<LocationReading>> {
-        verify_view(&context.claims)?;
-        location_reading::list(context, filter).await
+        first: Option<i32>,
+        after: Option<String>,
+        last: Option<i32>,
+        before: Option<String>,
+    ) -> FieldResult<LocationReadingConnection> {
+        require(context, Permission::ReadingRead)?;
+        let args = PageArgs {
+            first,
+            after,
+            last,
+            before,
+        };
+        location_reading::list(context, None, filter, args).await
     }
 
     async fn person(#[graphql(context)] context: &Context, id: ID) -> FieldResult<Option<Person>> {
-        verify_view(&context.claims)?;
+        require(context, Permission::PersonRead)?;
         person::get(context, id).await
     }
 
-    async fn people(#[graphql(context)] context: &Context) -> FieldResult<Vec<Person>> {
-        verify_view(&context.claims)?;
-        person::list(context).await
+    async fn people(
+        #[graphql(context)] context: &Context,
+        filter: Option<PersonFilter>,
+        first: Option<i32>,
+        after: Option<String>,
+        last: Option<i32>,
+        before: Option<String>,
+    ) -> FieldResult<PersonConnection> {
+        require(context, Permission::PersonRead)?;
+        let args = PageArgs {
+            first,
+            after,
+            last,
+            before,
+        };
+        person::list(context, None, filter, args).await
+    }
+
+    /// Permissions of the requesting user account, across its company and for a team if one is
+    /// given, so clients can hide controls the user cannot use.
+    async fn my_permissions(
+        #[graphql(context)] context: &Context,
+        team_id: Option<ID>,
+    ) -> Vec<String> {
+        context
+            .permissions
+            .list(team_id.as_deref())
+            .into_iter()
+            .map(|p| p.name().to_string())
+            .collect()
+    }
+
+    async fn my_sessions(#[graphql(context)] context: &Context) -> FieldResult<Vec<Session>> {
+        require(context, Permission::AccountOwn)?;
+        session::list_mine(context).await
     }
 
     async fn team(#[graphql(context)] context: &Context, id: ID) -> FieldResult<Option<Team>> {
-        verify_view(&context.claims)?;
+        require(context, Permission::TeamRead)?;
         team::get(context, id).await
     }
 
-    async fn teams(#[graphql(context)] context: &Context) -> FieldResult<Vec<Team>> {
-        verify_view(&context.claims)?;
-        team::list(context).await
+    async fn teams(
+        #[graphql(context)] context: &Context,
+        filter: Option<TeamFilter>,
+        first: Option<i32>,
+        after: Option<String>,
+        last: Option<i32>,
+        before: Option<String>,
+    ) -> FieldResult<TeamConnection> {
+        require(context, Permission::TeamRead)?;
+        let args = PageArgs {
+            first,
+            after,
+            last,
+            before,
+        };
+        team::list(context, None, filter, args).await
     }
 
     async fn user_account(
         #[graphql(context)] context: &Context,
         id: ID,
     ) -> FieldResult<Option<UserAccount>> {
+        require(context, Permission::UserRead)?;
         user_account::get(context, id).await
     }
 
-    async fn user_accounts(#[graphql(context)] context: &Context) -> FieldResult<Vec<UserAccount>> {
-        user_account::list(context).await
+    async fn user_accounts(
+        #[graphql(context)] context: &Context,
+        filter: Option<UserAccountFilter>,
+        first: Option<i32>,
+        after: Option<String>,
+        last: Option<i32>,
+        before: Option<String>,
+    ) -> FieldResult<UserAccountConnection> {
+        require(context, Permission::UserRead)?;
+        let args = PageArgs {
+            first,
+            after,
+            last,
+            before,
+        };
+        user_account::list(context, None, filter, args).await
     }
 }
 
@@ -242,11 +906,24 @@ pub struct Mutation;
 
 #[graphql_object(context = Context)]
 impl Mutation {
+    async fn create_api_key(
+        #[graphql(context)] context: &Context,
+        input: ApiKeyInput,
+    ) -> FieldResult<CreatedApiKey> {
+        require(context, Permission::ApiKeyAdmin)?;
+        api_key::create(context, input).await
+    }
+
+    async fn revoke_api_key(#[graphql(context)] context: &Context, id: ID) -> FieldResult<ID> {
+        require(context, Permission::ApiKeyAdmin)?;
+        api_key::revoke(context, id).await
+    }
+
     async fn create_company(
         #[graphql(context)] context: &Context,
         input: CompanyInput,
     ) -> FieldResult<Company> {
-        verify_admin(&context.claims)?;
+        require(context, Permission::CompanyAdmin)?;
         company::create(context, input).await
     }
 
@@ -255,12 +932,25 @@ impl Mutation {
         id: ID,
         input: CompanyInput,
     ) -> FieldResult<Company> {
-        verify_admin(&context.claims)?;
+        require(context, Permission::CompanyWrite)?;
         company::replace(context, id, input).await
     }
 
+    /// Configure single sign-on through an OpenID Connect identity provider. Omit the input to turn
+    /// it off.
+    async fn set_company_oidc(
+        #[graphql(context)] context: &Context,
+        company_id: ID,
+        input: Option<OidcConfigInput>,
+    ) -> FieldResult<Company> {
+        // The API logs users in with whatever identity provider is configured, so only super
+        // admins may change it.
+        require(context, Permission::CompanyAdmin)?;
+        company::set_oidc(context, company_id, input).await
+    }
+
     async fn delete_company(#[graphql(context)] context: &Context, id: ID) -> FieldResult<ID> {
-        verify_admin(&context.claims)?;
+        require(context, Permission::CompanyAdmin)?;
         company::delete(context, id).await
     }
 
@@ -268,7 +958,7 @@ impl Mutation {
         #[graphql(context)] context: &Context,
         input: DeviceInput,
     ) -> FieldResult<Device> {
-        verify_admin(&context.claims)?;
+        require(context, Permission::DeviceWrite)?;
         device::create(context, input).await
     }
 
@@ -276,20 +966,28 @@ impl Mutation {
         #[graphql(context)] context: &Context,
         input: DeviceInput,
     ) -> FieldResult<Device> {
-        verify_admin(&context.claims)?;
+        require(context, Permission::DeviceWrite)?;
         device::replace(context, input).await
     }
 
     async fn delete_device(#[graphql(context)] context: &Context, id: ID) -> FieldResult<ID> {
-        verify_admin(&context.claims)?;
+        require(context, Permission::DeviceWrite)?;
         device::delete(context, id).await
     }
 
+    async fn ingest_gas_readings(
+        #[graphql(context)] context: &Context,
+        input: Vec<GasReadingInput>,
+    ) -> FieldResult<i32> {
+        require(context, Permission::ReadingWrite)?;
+        gas_reading::ingest(context, input).await
+    }
+
     async fn create_incident(
         #[graphql(context)] context: &Context,
         input: IncidentInput,
     ) -> FieldResult<Incident> {
-        verify_admin(&context.claims)?;
+        require(context, Permission::IncidentWrite)?;
         incident::create(context, input).await
     }
 
@@ -298,20 +996,28 @@ impl Mutation {
         id: ID,
         input: IncidentInput,
     ) -> FieldResult<Incident> {
-        verify_admin(&context.claims)?;
+        require(context, Permission::IncidentWrite)?;
         incident::replace(context, id, input).await
     }
 
     async fn delete_incident(#[graphql(context)] context: &Context, id: ID) -> FieldResult<ID> {
-        verify_admin(&context.claims)?;
+        require(context, Permission::IncidentWrite)?;
         incident::delete(context, id).await
     }
 
+    async fn ingest_location_readings(
+        #[graphql(context)] context: &Context,
+        input: Vec<LocationReadingInput>,
+    ) -> FieldResult<i32> {
+        require(context, Permission::ReadingWrite)?;
+        location_reading::ingest(context, input).await
+    }
+
     async fn create_person(
         #[graphql(context)] context: &Context,
         input: PersonInput,
     ) -> FieldResult<Person> {
-        verify_admin(&context.claims)?;
+        require(context, Permission::PersonWrite)?;
         person::create(context, input).await
     }
 
@@ -320,12 +1026,12 @@ impl Mutation {
         id: ID,
         input: PersonInput,
     ) -> FieldResult<Person> {
-        verify_admin(&context.claims)?;
+        require(context, Permission::PersonWrite)?;
         person::replace(context, id, input).await
     }
 
     async fn delete_person(#[graphql(context)] context: &Context, id: ID) -> FieldResult<ID> {
-        verify_admin(&context.claims)?;
+        require(context, Permission::PersonWrite)?;
         person::delete(context, id).await
     }
 
@@ -333,12 +1039,12 @@ impl Mutation {
         #[graphql(context)] context: &Context,
         input: TeamInput,
     ) -> FieldResult<Team> {
-        verify_admin(&context.claims)?;
+        require(context, Permission::TeamWrite)?;
         team::create(context, input).await
     }
 
     async fn delete_team(#[graphql(context)] context: &Context, id: ID) -> FieldResult<ID> {
-        verify_admin(&context.claims)?;
+        require(context, Permission::TeamWrite)?;
         team::delete(context, id).await
     }
 
@@ -346,9 +1052,12 @@ impl Mutation {
         context: &Context,
         team_id: ID,
         person_id: ID,
+        lead: Option<bool>,
     ) -> FieldResult<Option<Team>> {
-        verify_admin(&context.claims)?;
-        team::add_person(context, team_id, person_id).await
+        // Team leads may not add people to their teams, since that would widen what they can see,
+        // nor make others lead them.
+        require(context, Permission::TeamWrite)?;
+        team::add_person(context, team_id, person_id, lead.unwrap_or(false)).await
     }
 
     pub async fn team_remove_person(
@@ -356,7 +1065,7 @@ impl Mutation {
         team_id: ID,
         person_id: ID,
     ) -> FieldResult<Option<Team>> {
-        verify_admin(&context.claims)?;
+        require_in_team(context, Permission::TeamWrite, &team_id).await?;
         team::remove_person(context, team_id, person_id).await
     }
 
@@ -364,7 +1073,7 @@ impl Mutation {
         #[graphql(context)] context: &Context,
         input: UserAccountInput,
     ) -> FieldResult<UserAccount> {
-        verify_admin(&context.claims)?;
+        require(context, Permission::UserAdmin)?;
         user_account::create(context, input).await
     }
 
@@ -373,21 +1082,113 @@ impl Mutation {
         id: ID,
         input: UserAccountInput,
     ) -> FieldResult<UserAccount> {
-        verify_admin(&context.claims)?;
+        require(context, Permission::UserAdmin)?;
         user_account::replace(context, id, input).await
     }
 
+    /// Stop a user account from logging in without deleting it. With `suspendedUntil`, the user
+    /// account may log in again after that time.
+    async fn deactivate_user_account(
+        #[graphql(context)] context: &Context,
+        id: ID,
+        suspended_until: Option<DateTime<Utc>>,
+    ) -> FieldResult<UserAccount> {
+        require(context, Permission::UserAdmin)?;
+        user_account::deactivate(context, id, suspended_until).await
+    }
+
+    async fn reactivate_user_account(
+        #[graphql(context)] context: &Context,
+        id: ID,
+    ) -> FieldResult<UserAccount> {
+        require(context, Permission::UserAdmin)?;
+        user_account::reactivate(context, id).await
+    }
+
+    /// Act as a user account, such as to see the app as they do. The token can only read, and the
+    /// impersonation is recorded with its reason.
+    async fn impersonate(
+        #[graphql(context)] context: &Context,
+        user_account_id: ID,
+        reason: String,
+    ) -> FieldResult<ImpersonationToken> {
+        require(context, Permission::UserImpersonate)?;
+        impersonation::impersonate(context, user_account_id, reason).await
+    }
+
     async fn delete_user_account(#[graphql(context)] context: &Context, id: ID) -> FieldResult<ID> {
-        verify_admin(&context.claims)?;
+        require(context, Permission::UserAdmin)?;
         user_account::delete(context, id).await
     }
 
     async fn login(
         #[graphql(context)] context: &Context,
-        user_account_id: ID,
+        email: String,
         password: String,
-    ) -> FieldResult<String> {
-        user_account::login(context, user_account_id, password).await
+    ) -> FieldResult<LoginResult> {
+        user_account::login(context, email, password).await
+    }
+
+    async fn login_with_totp(
+        #[graphql(context)] context: &Context,
+        challenge_token: String,
+        code: String,
+    ) -> FieldResult<AuthTokens> {
+        user_account::login_with_totp(context, challenge_token, code).await
+    }
+
+    async fn enroll_totp(
+        #[graphql(context)] context: &Context,
+        challenge_token: Option<String>,
+    ) -> FieldResult<TotpEnrollment> {
+        user_account::enroll_totp(context, challenge_token).await
+    }
+
+    async fn confirm_totp(
+        #[graphql(context)] context: &Context,
+        code: String,
+        challenge_token: Option<String>,
+    ) -> FieldResult<TotpConfirmation> {
+        user_account::confirm_totp(context, code, challenge_token).await
+    }
+
+    async fn disable_my_totp(
+        #[graphql(context)] context: &Context,
+        current_password: String,
+    ) -> FieldResult<bool> {
+        require(context, Permission::AccountOwn)?;
+        user_account::disable_my_totp(context, current_password).await
+    }
+
+    async fn reset_user_account_totp(
+        #[graphql(context)] context: &Context,
+        user_account_id: ID,
+    ) -> FieldResult<bool> {
+        require(context, Permission::UserAdmin)?;
+        user_account::reset_totp(context, user_account_id).await
+    }
+
+    async fn refresh_token(
+        #[graphql(context)] context: &Context,
+        refresh_token: String,
+    ) -> FieldResult<AuthTokens> {
+        user_account::refresh_token(context, refresh_token).await
+    }
+
+    async fn logout(#[graphql(context)] context: &Context) -> FieldResult<bool> {
+        // Impersonated tokens may end their own session, unlike other changes.
+        if !is_impersonated(context) {
+            require(context, Permission::AccountOwn)?;
+        }
+        session::logout(context).await
+    }
+
+    async fn revoke_sessions(
+        #[graphql(context)] context: &Context,
+        user_account_id: ID,
+    ) -> FieldResult<bool> {
+        require(context, Permission::UserAdmin)?;
+        session::revoke_all(context, user_account_id).await
     }
 
     async fn set_user_account_password(
@@ -395,37 +1196,423 @@ impl Mutation {
         user_account_id: ID,
         password: String,
     ) -> FieldResult<bool> {
-        verify_admin(&context.claims)?;
+        require(context, Permission::UserAdmin)?;
         user_account::set_password(context, user_account_id, password).await
     }
 
+    async fn change_my_password(
+        #[graphql(context)] context: &Context,
+        current_password: String,
+        new_password: String,
+    ) -> FieldResult<AuthTokens> {
+        require(context, Permission::AccountOwn)?;
+        user_account::change_my_password(context, current_password, new_password).await
+    }
+
+    async fn unlock_user_account(
+        #[graphql(context)] context: &Context,
+        user_account_id: ID,
+    ) -> FieldResult<bool> {
+        require(context, Permission::UserAdmin)?;
+        user_account::unlock(context, user_account_id).await
+    }
+
+    async fn request_password_reset(
+        #[graphql(context)] context: &Context,
+        email: String,
+    ) -> FieldResult<bool> {
+        user_account::request_password_reset(context, email).await
+    }
+
+    /// Email a link that logs in without a password. Companies enable this with
+    /// `loginLinksEnabled`.
+    async fn request_login_link(
+        #[graphql(context)] context: &Context,
+        email: String,
+    ) -> FieldResult<bool> {
+        user_account::request_login_link(context, email).await
+    }
+
+    async fn redeem_login_link(
+        #[graphql(context)] context: &Context,
+        token: String,
+    ) -> FieldResult<LoginResult> {
+        user_account::redeem_login_link(context, token).await
+    }
+
+    /// Email the requesting user a link to `{app URL}/verify-email?token={token}`.
+    async fn request_email_verification(
+        #[graphql(context)] context: &Context,
+    ) -> FieldResult<bool> {
+        require(context, Permission::AccountOwn)?;
+        user_account::request_email_verification(context).await
+    }
+
+    async fn verify_email(
+        #[graphql(context)] context: &Context,
+        token: String,
+    ) -> FieldResult<bool> {
+        user_account::verify_email(context, token).await
+    }
+
+    /// Text the requesting user a code for `verifyPhone`.
+    async fn request_phone_verification(
+        #[graphql(context)] context: &Context,
+    ) -> FieldResult<bool> {
+        require(context, Permission::AccountOwn)?;
+        user_account::request_phone_verification(context).await
+    }
+
+    async fn verify_phone(
+        #[graphql(context)] context: &Context,
+        code: String,
+    ) -> FieldResult<bool> {
+        require(context, Permission::AccountOwn)?;
+        user_account::verify_phone(context, code).await
+    }
+
+    async fn reset_password(
+        #[graphql(context)] context: &Context,
+        token: String,
+        new_password: String,
+    ) -> FieldResult<bool> {
+        user_account::reset_password(context, token, new_password).await
+    }
+
+    async fn resend_invitation(
+        #[graphql(context)] context: &Context,
+        user_account_id: ID,
+    ) -> FieldResult<bool> {
+        require(context, Permission::UserAdmin)?;
+        user_account::resend_invitation(context, user_account_id).await
+    }
+
+    async fn accept_invitation(
+        #[graphql(context)] context: &Context,
+        token: String,
+        password: String,
+    ) -> FieldResult<bool> {
+        user_account::accept_invitation(context, token, password).await
+    }
+
     async fn set_user_account_profile_image(
         #[graphql(context)] context: &Context,
         user_account_id: ID,
         image_base64: String,
     ) -> FieldResult<String> {
-        verify_admin(&context.claims)?;
+        require(context, Permission::UserAdmin)?;
         user_account::set_profile_image(context, user_account_id, image_base64).await
     }
 }
 
-fn verify_view(claims: &Option<Claims>) -> Result<(), FieldError> {
-    // Any authenticated user has at least view authorization.
-    if claims.is_some() {
+/// Check that the request holds a permission across its company. Permissions come from the access
+/// token, so a change of access level or roles takes effect once the token is refreshed.
+fn require(context: &Context, permission: Permission) -> Result<(), FieldError> {
+    if context.permissions.has(permission) {
         Ok(())
+    } else if is_impersonated(context) {
+        Err(impersonation_error())
     } else {
         Err(unauthorized_error())
     }
 }
 
-fn verify_admin(claims: &Option<Claims>) -> Result<(), FieldError> {
-    if matches!(claims.as_ref().map(|c| c.access), Some(Access::Admin)) {
+/// Whether an admin is acting as the user account of the request.
+fn is_impersonated(context: &Context) -> bool {
+    matches!(&context.claims, Some(claims) if claims.act.is_some())
+}
+
+/// Check that the request holds a permission across its company or for a team. Roles in an access
+/// token only change when it is refreshed, so team roles are checked against the user account as
+/// well, to stop a team lead whose role was taken away from changing the team in the meantime.
+async fn require_in_team(
+    context: &Context,
+    permission: Permission,
+    team_id: &str,
+) -> Result<(), FieldError> {
+    if context.permissions.has_in_team(permission, team_id)
+        && current_permissions(context)
+            .await?
+            .has_in_team(permission, team_id)
+    {
         Ok(())
+    } else if is_impersonated(context) {
+        Err(impersonation_error())
     } else {
         Err(unauthorized_error())
     }
 }
 
+/// Permissions of the request from the access level and roles the user account has now, rather
+/// than those in its access token.
+async fn current_permissions(context: &Context) -> anyhow::Result<Permissions> {
+    let claims = match &context.claims {
+        Some(claims) => claims,
+        None => return Ok(context.permissions.clone()),
+    };
+    Ok(
+        match context.user_account_repo.find_one(&claims.sub).await? {
+            Some(user_account) => Permissions::for_claims(&Claims {
+                access: user_account.access,
+                roles: user_account.roles,
+                ..claims.clone()
+            }),
+            None => Permissions::default(),
+        },
+    )
+}
+
 fn unauthorized_error() -> FieldError {
     anyhow::Error::msg("Unauthorized").into()
 }
+
+fn impersonation_error() -> FieldError {
+    FieldError::new(
+        "Not allowed while impersonating a user account",
+        graphql_value!({ "code": "IMPERSONATION" }),
+    )
+}
+
+fn conflict_error(message: &str) -> FieldError {
+    FieldError::new(message, graphql_value!({ "code": "CONFLICT" }))
+}
+
+/// Context of a request by the given claims, with in-memory repos holding the user accounts and
+/// the people and teams of `company_with_teams`.
+#[cfg(test)]
+async fn test_context(
+    user_accounts: Vec<repo::user_account::UserAccount>,
+    claims: Option<Claims>,
+) -> Context {
+    use crate::event::EventBus;
+    use crate::hashing::Hashers;
+    use crate::jwt_keys::JwtKeySet;
+    use crate::mail::FileMailer;
+    use crate::password_policy::PasswordPolicy;
+    use crate::repo::memory::{
+        company_with_teams, MemoryApiKeyRepo, MemoryCompanyRepo, MemoryLoginAttemptRepo,
+        MemoryOneTimeTokenRepo, MemoryRefreshTokenRepo, MemorySessionRepo, MemoryUserAccountRepo,
+        UnsupportedRepo,
+    };
+    use crate::sms::FileSmsSender;
+    use crate::throttle::LoginThrottle;
+    use chrono::Duration;
+
+    let user_account_repo = MemoryUserAccountRepo::default();
+    for user_account in user_accounts {
+        user_account_repo
+            .user_accounts
+            .lock()
+            .unwrap()
+            .insert(user_account.id.clone(), user_account);
+    }
+    let user_account_repo = Arc::new(user_account_repo);
+    let company_repo = Arc::new(MemoryCompanyRepo::default());
+    let session_repo = Arc::new(MemorySessionRepo::default());
+    let (person_repo, team_repo) = company_with_teams().await;
+    let auth_provider = AuthProvider {
+        user_account_repo: user_account_repo.clone(),
+        company_repo: company_repo.clone(),
+        api_key_repo: Arc::new(MemoryApiKeyRepo::default()),
+        refresh_token_repo: Arc::new(MemoryRefreshTokenRepo::default()),
+        session_repo: session_repo.clone(),
+        one_time_token_repo: Arc::new(MemoryOneTimeTokenRepo::default()),
+        refresh_token_ttl: Duration::days(30),
+        password_reset_ttl: Duration::hours(1),
+        invitation_ttl: Duration::hours(72),
+        login_link_ttl: Duration::minutes(15),
+        password_policy: PasswordPolicy::new(8, 3),
+        hashers: Hashers::default(),
+        login_throttle: LoginThrottle {
+            login_attempt_repo: Arc::new(MemoryLoginAttemptRepo::default()),
+            free_attempts: 3,
+            base_delay: Duration::seconds(1),
+            max_delay: Duration::minutes(15),
+        },
+    };
+    let deps = Deps {
+        company_repo,
+        device_repo: Arc::new(UnsupportedRepo),
+        gas_reading_repo: Arc::new(UnsupportedRepo),
+        impersonation_repo: Arc::new(UnsupportedRepo),
+        incident_repo: Arc::new(UnsupportedRepo),
+        incident_stats_repo: Arc::new(UnsupportedRepo),
+        location_reading_repo: Arc::new(UnsupportedRepo),
+        person_repo,
+        session_repo,
+        team_repo,
+        user_account_repo,
+        auth_provider,
+        claims_provider: ClaimsProvider {
+            jwt_key_set: JwtKeySet::ephemeral().unwrap(),
+            access_token_ttl: Duration::minutes(15),
+            impersonation_ttl: Duration::minutes(5),
+        },
+        events: EventBus::new(),
+        mailer: FileMailer::new(std::env::temp_dir()).into(),
+        sms_sender: FileSmsSender::new(std::env::temp_dir()).into(),
+        app_url: "https://app.example.com".to_string(),
+        limits: limits::QueryLimits {
+            max_depth: 10,
+            max_cost: 100_000,
+            max_list_items: 1000,
+        },
+        trusted_proxies: Vec::new(),
+    };
+    create_context(deps, claims, None, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::user_account::{RoleGrant, UserAccount};
+
+    #[tokio::test]
+    async fn test_ws_rejects_query_over_limits() {
+        // Arrange.
+        let schema = Arc::new(schema());
+        let limits = QueryLimits {
+            max_depth: 3,
+            max_cost: 100_000,
+            max_list_items: 1000,
+        };
+        let filter = warp::ws().map(move |ws: Ws| {
+            let schema = schema.clone();
+            ws.on_upgrade(move |websocket| async move {
+                let init = |_: Variables| {
+                    future::ready(Err::<ConnectionConfig<Context>, _>(InvalidCredentials))
+                };
+                let _ = serve_graphql_ws(websocket, schema, limits, init).await;
+            })
+        });
+        let mut client = warp::test::ws().handshake(filter).await.unwrap();
+
+        // Act.
+        client
+            .send_text(
+                r#"{"type": "start", "id": "1", "payload": {
+                    "query": "{ companies { people { edges { node { name } } } } }"
+                }}"#,
+            )
+            .await;
+        let data = client.recv().await.unwrap();
+        let complete = client.recv().await.unwrap();
+
+        // Assert.
+        let data: serde_json::Value = serde_json::from_str(data.to_str().unwrap()).unwrap();
+        assert_eq!(data["type"], "data");
+        assert_eq!(data["id"], "1");
+        let extensions = &data["payload"]["errors"][0]["extensions"];
+        assert_eq!(extensions["code"], "LIMIT_EXCEEDED");
+        assert_eq!(extensions["limit"], "depth");
+        let complete: serde_json::Value = serde_json::from_str(complete.to_str().unwrap()).unwrap();
+        assert_eq!(complete["type"], "complete");
+    }
+
+    #[tokio::test]
+    async fn test_require_in_team() {
+        // Arrange.
+        let roles = vec![team_lead_grant()];
+        let context = test_context(vec![lead(roles.clone())], Some(lead_claims(roles))).await;
+
+        // Act.
+        let res = require_in_team(&context, Permission::TeamWrite, "team").await;
+
+        // Assert.
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_require_in_team_role_taken_away() {
+        // Arrange.
+        let claims = lead_claims(vec![team_lead_grant()]);
+        let context = test_context(vec![lead(Vec::new())], Some(claims)).await;
+
+        // Act.
+        let res = require_in_team(&context, Permission::TeamWrite, "team").await;
+
+        // Assert.
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_lead_person_id() {
+        // Arrange.
+        let claims = Claims {
+            person_id: Some("person".to_string()),
+            ..lead_claims(vec![team_lead_grant()])
+        };
+
+        // Act.
+        let lead_person_id = lead_person_id(&Some(claims));
+
+        // Assert.
+        assert_eq!(lead_person_id.as_deref(), Some("person"));
+    }
+
+    #[test]
+    fn test_lead_person_id_not_lead() {
+        // Arrange.
+        let claims = Claims {
+            person_id: Some("person".to_string()),
+            ..lead_claims(Vec::new())
+        };
+
+        // Act.
+        let lead_person_id = lead_person_id(&Some(claims));
+
+        // Assert.
+        assert_eq!(lead_person_id, None);
+    }
+
+    fn team_lead_grant() -> RoleGrant {
+        RoleGrant {
+            role: Role::TeamLead,
+            team_id: Some("team".to_string()),
+        }
+    }
+
+    fn lead(roles: Vec<RoleGrant>) -> UserAccount {
+        UserAccount {
+            id: "lead".to_string(),
+            name: "Lead".to_string(),
+            access: Access::View,
+            title: "".to_string(),
+            email: "lead@example.com".to_string(),
+            email_verified: false,
+            phone: "".to_string(),
+            phone_verified: false,
+            company_id: "company".to_string(),
+            roles,
+            person_id: None,
+            active: true,
+            suspended_until: None,
+        }
+    }
+
+    fn lead_claims(roles: Vec<RoleGrant>) -> Claims {
+        Claims {
+            sub: "lead".to_string(),
+            access: Access::View,
+            company_id: "company".to_string(),
+            roles,
+            person_id: None,
+            act: None,
+            iat: 0,
+            exp: 0,
+            jti: "jti".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_offers_protocol() {
+        // Assert.
+        assert!(offers_protocol(Some("graphql-ws"), "graphql-ws"));
+        assert!(offers_protocol(
+            Some("graphql-transport-ws, graphql-ws"),
+            "graphql-ws"
+        ));
+        assert!(!offers_protocol(Some("graphql-transport-ws"), "graphql-ws"));
+        assert!(!offers_protocol(None, "graphql-ws"));
+    }
+}
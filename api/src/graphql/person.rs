@@ -1,13 +1,15 @@
 use crate::graphql::company::Company;
+use crate::graphql::connection::{paginate, PageArgs, PageInfo, Slice};
 use crate::graphql::device::Device;
-use crate::graphql::gas_reading::GasReading;
-use crate::graphql::incident::{Incident, IncidentFilter};
+use crate::graphql::gas_reading::GasReadingConnection;
+use crate::graphql::incident::{IncidentConnection, IncidentFilter};
 use crate::graphql::incident_stats::{IncidentStats, IncidentStatsFilter};
-use crate::graphql::location_reading::LocationReading;
-use crate::graphql::Context;
+use crate::graphql::location_reading::LocationReadingConnection;
 use crate::graphql::GasReadingFilter;
 use crate::graphql::LocationReadingFilter;
+use crate::graphql::{gas_reading, incident, incident_stats, limits, location_reading, Context};
 use crate::repo::person;
+use crate::repo::{intersect, Cursor};
 use crate::{crockford, repo};
 use derive_more::{Deref, DerefMut, From};
 use futures_util::TryStreamExt;
@@ -16,6 +18,46 @@ use juniper::{FieldResult, ID};
 #[derive(Clone, From, Deref, DerefMut)]
 pub struct Person(pub person::Person);
 
+#[derive(juniper::GraphQLObject)]
+#[graphql(context = Context)]
+pub struct PersonConnection {
+    pub edges: Vec<PersonEdge>,
+    pub page_info: PageInfo,
+}
+
+#[derive(juniper::GraphQLObject)]
+#[graphql(context = Context)]
+pub struct PersonEdge {
+    pub cursor: String,
+    pub node: Person,
+}
+
+impl From<Slice<person::Person>> for PersonConnection {
+    fn from(value: Slice<person::Person>) -> Self {
+        Self {
+            edges: value
+                .edges
+                .into_iter()
+                .map(|(cursor, node)| PersonEdge {
+                    cursor,
+                    node: node.into(),
+                })
+                .collect(),
+            page_info: value.page_info,
+        }
+    }
+}
+
+#[derive(juniper::GraphQLInputObject, Default)]
+pub struct PersonFilter {
+    pub ids: Option<Vec<ID>>,
+    pub team_ids: Option<Vec<ID>>,
+    pub company_ids: Option<Vec<ID>>,
+    /// Text whose words each start a word of the name, ignoring case. For example, "jo sm"
+    /// matches "John Smith".
+    pub name: Option<String>,
+}
+
 #[derive(juniper::GraphQLInputObject)]
 pub struct PersonInput {
     pub name: String,
@@ -34,79 +76,75 @@ impl Person {
 
     pub async fn company(&self, context: &Context) -> FieldResult<Option<Company>> {
         Ok(context
-            .company_repo
-            .find_one(&self.company_id)
+            .company_loader
+            .load(&self.company_id)
             .await?
             .map(Into::into))
     }
 
     pub async fn devices(&self, context: &Context) -> FieldResult<Vec<Device>> {
-        Ok(context
+        let items = context
             .device_repo
             .find(repo::device::DeviceFilter {
                 owner_ids: Some(vec![self.id.clone()]),
+                ..Default::default()
             })
-            .await?
-            .map_ok(Into::into)
-            .try_collect()
-            .await?)
+            .await?;
+        limits::collect(context, items).await
     }
 
     pub async fn gas_readings(
         &self,
         context: &Context,
         filter: Option<GasReadingFilter>,
-    ) -> FieldResult<Vec<GasReading>> {
-        let filter = filter.unwrap_or_default();
-        Ok(context
-            .gas_reading_repo
-            .find(repo::gas_reading::GasReadingFilter {
-                person_ids: Some(vec![self.id.clone()]),
-                min_timestamp: filter.min_timestamp,
-                max_timestamp: filter.max_timestamp,
-            })
-            .await?
-            .map_ok(Into::into)
-            .try_collect()
-            .await?)
+        first: Option<i32>,
+        after: Option<String>,
+        last: Option<i32>,
+        before: Option<String>,
+    ) -> FieldResult<GasReadingConnection> {
+        let args = PageArgs {
+            first,
+            after,
+            last,
+            before,
+        };
+        gas_reading::list(context, Some(vec![self.id.clone()]), filter, args).await
     }
 
     pub async fn incidents(
         &self,
         context: &Context,
         filter: Option<IncidentFilter>,
-    ) -> FieldResult<Vec<Incident>> {
-        let filter = filter.unwrap_or_default();
-        Ok(context
-            .incident_repo
-            .find(repo::incident::IncidentFilter {
-                person_ids: Some(vec![self.id.clone()]),
-                min_timestamp: filter.min_timestamp,
-                max_timestamp: filter.max_timestamp,
-            })
-            .await?
-            .map_ok(Into::into)
-            .try_collect()
-            .await?)
+        first: Option<i32>,
+        after: Option<String>,
+        last: Option<i32>,
+        before: Option<String>,
+    ) -> FieldResult<IncidentConnection> {
+        let args = PageArgs {
+            first,
+            after,
+            last,
+            before,
+        };
+        incident::list(context, Some(vec![self.id.clone()]), filter, args).await
     }
 
     pub async fn location_readings(
         &self,
         context: &Context,
         filter: Option<LocationReadingFilter>,
-    ) -> FieldResult<Vec<LocationReading>> {
-        let filter = filter.unwrap_or_default();
-        Ok(context
-            .location_reading_repo
-            .find(repo::location_reading::LocationReadingFilter {
-                person_ids: Some(vec![self.id.clone()]),
-                min_timestamp: filter.min_timestamp,
-                max_timestamp: filter.max_timestamp,
-            })
-            .await?
-            .map_ok(Into::into)
-            .try_collect()
-            .await?)
+        first: Option<i32>,
+        after: Option<String>,
+        last: Option<i32>,
+        before: Option<String>,
+    ) -> FieldResult<LocationReadingConnection> {
+        let args = PageArgs {
+            first,
+            after,
+            last,
+            before,
+        };
+        location_reading::list(context, Some(vec![self.id.clone()]), filter, args).await
     }
 
     pub async fn incident_stats(
@@ -114,33 +152,64 @@ impl Person {
         context: &Context,
         filter: Option<IncidentStatsFilter>,
     ) -> FieldResult<Vec<IncidentStats>> {
-        let filter = filter.unwrap_or_default();
-        Ok(context
-            .incident_stats_repo
-            .find(repo::incident_stats::IncidentStatsFilter {
-                person_ids: Some(vec![self.id.clone()]),
-                min_timestamp: filter.min_timestamp,
-                max_timestamp: filter.max_timestamp,
-            })
-            .await?
-            .map_ok(Into::into)
-            .try_collect()
-            .await?)
+        incident_stats::list(context, Some(vec![self.id.clone()]), None, filter).await
     }
 }
 
+#[allow(clippy::explicit_auto_deref)]
 pub async fn get(context: &Context, id: ID) -> FieldResult<Option<Person>> {
     Ok(context.person_repo.find_one(&*id).await?.map(Into::into))
 }
 
-pub async fn list(context: &Context) -> FieldResult<Vec<Person>> {
-    Ok(context
-        .person_repo
-        .find(Default::default())
-        .await?
-        .map_ok(Into::into)
-        .try_collect()
-        .await?)
+/// A page of the people of the given companies or of every company, ordered by ID.
+pub async fn list(
+    context: &Context,
+    company_ids: Option<Vec<String>>,
+    filter: Option<PersonFilter>,
+    args: PageArgs,
+) -> FieldResult<PersonConnection> {
+    let filter = filter.unwrap_or_default();
+    let ids = select_ids(context, to_strings(filter.ids), filter.team_ids).await?;
+    let company_ids = intersect(company_ids, to_strings(filter.company_ids));
+    let name = filter.name;
+    let slice = paginate(args, context.limits.max_list_items, cursor, |page| {
+        context.person_repo.find(person::PersonFilter {
+            ids,
+            company_ids,
+            name,
+            page,
+        })
+    })
+    .await?;
+    Ok(slice.into())
+}
+
+/// IDs of the people who are among the given people and belong to one of the given teams. Teams
+/// resolve to their members, as many as a list may hold. None means anyone. Companies are left to
+/// the query itself, rather than resolving to all their people.
+pub async fn select_ids(
+    context: &Context,
+    person_ids: Option<Vec<String>>,
+    team_ids: Option<Vec<ID>>,
+) -> FieldResult<Option<Vec<String>>> {
+    let team_ids = match to_strings(team_ids) {
+        None => return Ok(person_ids),
+        Some(team_ids) => team_ids,
+    };
+    let members = context.team_repo.find_members(&team_ids).await?;
+    let members = limits::collect(context, members.map_ok(|tp| tp.person_id)).await?;
+    Ok(intersect(person_ids, Some(members)))
+}
+
+pub fn to_strings(ids: Option<Vec<ID>>) -> Option<Vec<String>> {
+    ids.map(|ids| ids.into_iter().map(|id| id.to_string()).collect())
+}
+
+fn cursor(person: &person::Person) -> Cursor {
+    Cursor {
+        timestamp: None,
+        id: person.id.clone(),
+    }
 }
 
 pub async fn create(context: &Context, input: PersonInput) -> FieldResult<Person> {
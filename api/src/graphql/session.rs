@@ -0,0 +1,68 @@
+use crate::graphql::{limits, user_account, Context};
+use crate::repo::session;
+use crate::repo::session::SessionFilter;
+use anyhow::Context as AnyhowContext;
+use chrono::{DateTime, Utc};
+use derive_more::{Deref, DerefMut, From};
+use juniper::{FieldResult, ID};
+
+#[derive(Clone, From, Deref, DerefMut)]
+pub struct Session(pub session::Session);
+
+#[juniper::graphql_object(context = Context)]
+impl Session {
+    pub fn id(&self) -> ID {
+        self.id.clone().into()
+    }
+
+    pub fn created_at(&self) -> &DateTime<Utc> {
+        &self.created_at
+    }
+
+    pub fn refreshed_at(&self) -> &DateTime<Utc> {
+        &self.refreshed_at
+    }
+
+    pub fn expires_at(&self) -> &DateTime<Utc> {
+        &self.expires_at
+    }
+
+    /// Whether an admin started the session to act as the user account.
+    pub fn impersonated(&self) -> bool {
+        self.impersonated_by.is_some()
+    }
+
+    /// Whether this is the session of the token used for the request.
+    pub fn current(&self, context: &Context) -> bool {
+        matches!(&context.claims, Some(claims) if claims.jti == self.jti)
+    }
+}
+
+pub async fn list_mine(context: &Context) -> FieldResult<Vec<Session>> {
+    let claims = context.claims.as_ref().context("Unauthorized")?;
+    let items = context
+        .session_repo
+        .find(SessionFilter {
+            user_account_ids: Some(vec![claims.sub.clone()]),
+        })
+        .await?;
+    limits::collect(context, items).await
+}
+
+pub async fn logout(context: &Context) -> FieldResult<bool> {
+    let claims = context.claims.as_ref().context("Unauthorized")?;
+    let session = context.session_repo.find_one_by_jti(&claims.jti).await?;
+    if let Some(session) = session {
+        context.auth_provider.revoke_session(&session.id).await?;
+    }
+    Ok(true)
+}
+
+pub async fn revoke_all(context: &Context, user_account_id: ID) -> FieldResult<bool> {
+    user_account::verify_tenant(context, &user_account_id).await?;
+    context
+        .auth_provider
+        .revoke_sessions(&user_account_id)
+        .await?;
+    Ok(true)
+}
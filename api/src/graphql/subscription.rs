@@ -0,0 +1,326 @@
+use crate::event::Event;
+use crate::graphql::gas_reading::GasReading;
+use crate::graphql::incident::Incident;
+use crate::graphql::location_reading::LocationReading;
+use crate::graphql::{require, Context};
+use crate::permission::Permission;
+use crate::repo::person::ArcPersonRepo;
+use crate::repo::team::ArcTeamRepo;
+use crate::repo::tenant::TenantScope;
+use anyhow::Context as AnyhowContext;
+use chrono::{TimeZone, Utc};
+use futures_util::{future, Future, Stream, StreamExt, TryStreamExt};
+use juniper::{FieldResult, ID};
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time;
+
+/// How often feeds check that their credentials are still valid.
+const REVALIDATE_INTERVAL: Duration = Duration::from_secs(60);
+
+pub type FeedStream<T> = Pin<Box<dyn Stream<Item = FieldResult<T>> + Send>>;
+
+/// Limits a feed to events of some people. Every condition that is set must match.
+#[derive(juniper::GraphQLInputObject, Default)]
+pub struct FeedFilter {
+    pub person_ids: Option<Vec<ID>>,
+    /// Only people who belong to the team.
+    pub team_id: Option<ID>,
+    /// Only people of the company, for super admins who can see every company.
+    pub company_id: Option<ID>,
+}
+
+pub struct Subscription;
+
+#[juniper::graphql_subscription(context = Context)]
+impl Subscription {
+    async fn location_reading_added(
+        context: &Context,
+        filter: Option<FeedFilter>,
+    ) -> FieldResult<FeedStream<LocationReading>> {
+        require(context, Permission::ReadingRead)?;
+        let feed = Feed::new(context, filter).await?;
+        Ok(feed.stream(context, |event| match event {
+            Event::LocationReadingAdded(reading) => Some((reading.person_id.clone(), reading)),
+            _ => None,
+        }))
+    }
+
+    async fn gas_reading_added(
+        context: &Context,
+        filter: Option<FeedFilter>,
+    ) -> FieldResult<FeedStream<GasReading>> {
+        require(context, Permission::ReadingRead)?;
+        let feed = Feed::new(context, filter).await?;
+        Ok(feed.stream(context, |event| match event {
+            Event::GasReadingAdded(reading) => Some((reading.person_id.clone(), reading)),
+            _ => None,
+        }))
+    }
+
+    async fn incident_created(
+        context: &Context,
+        filter: Option<FeedFilter>,
+    ) -> FieldResult<FeedStream<Incident>> {
+        require(context, Permission::IncidentRead)?;
+        let feed = Feed::new(context, filter).await?;
+        Ok(feed.stream(context, |event| match event {
+            Event::IncidentCreated(incident) => Some((incident.person_id.clone(), incident)),
+            _ => None,
+        }))
+    }
+}
+
+/// Events of a subscription, limited to the people the request may access and the filter allows.
+struct Feed {
+    filter: FeedFilter,
+    /// Replaced when the feed is refreshed, so that led teams are found again.
+    scope: Mutex<TenantScope>,
+    person_repo: ArcPersonRepo,
+    team_repo: ArcTeamRepo,
+    /// Whether events of each person are sent, decided on their first event after each refresh.
+    allowed: Mutex<HashMap<String, bool>>,
+}
+
+impl Feed {
+    async fn new(context: &Context, filter: Option<FeedFilter>) -> FieldResult<Arc<Self>> {
+        let filter = filter.unwrap_or_default();
+        if let Some(team_id) = &filter.team_id {
+            context
+                .team_repo
+                .find_one(team_id)
+                .await?
+                .context("Team not found")?;
+        }
+        if let Some(company_id) = &filter.company_id {
+            context
+                .company_repo
+                .find_one(company_id)
+                .await?
+                .context("Company not found")?;
+        }
+        Ok(Arc::new(Self {
+            filter,
+            scope: Mutex::new(context.scope.clone()),
+            person_repo: context.person_repo.clone(),
+            team_repo: context.team_repo.clone(),
+            allowed: Mutex::new(HashMap::new()),
+        }))
+    }
+
+    fn stream<T, G>(
+        self: Arc<Self>,
+        context: &Context,
+        select: fn(Event) -> Option<(String, T)>,
+    ) -> FeedStream<G>
+    where
+        T: Send + 'static,
+        G: From<T> + Send + 'static,
+    {
+        // Access tokens are only checked when connecting, so feeds end when the token expires and
+        // the client must reconnect with a new one.
+        let expired = match &context.claims {
+            Some(claims) => {
+                let remaining = Utc.timestamp(claims.exp, 0) - Utc::now();
+                future::Either::Left(time::sleep(remaining.to_std().unwrap_or_default()))
+            }
+            None => future::Either::Right(future::pending()),
+        };
+        let revoked = self.clone().revalidate(context);
+        context
+            .events
+            .subscribe()
+            .take_until(future::select(Box::pin(expired), Box::pin(revoked)))
+            .filter_map(move |event| future::ready(select(event)))
+            .filter_map(move |(person_id, item)| {
+                let feed = self.clone();
+                async move {
+                    match feed.allows(&person_id).await {
+                        Ok(true) => Some(Ok(item.into())),
+                        Ok(false) => None,
+                        Err(e) => Some(Err(e.into())),
+                    }
+                }
+            })
+            .boxed()
+    }
+
+    /// Check the credentials of the subscription periodically, and refresh what it may access.
+    /// Completes once the session is revoked, such as when the user account is deactivated, or
+    /// the API key is deleted.
+    fn revalidate(self: Arc<Self>, context: &Context) -> impl Future<Output = ()> + Send {
+        let auth_provider = context.auth_provider.clone();
+        let claims = context.claims.clone();
+        let api_key = context.api_key.clone();
+        async move {
+            let mut interval = time::interval(REVALIDATE_INTERVAL);
+            // The first tick completes immediately.
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                match auth_provider
+                    .verify_credentials(claims.as_ref(), api_key.as_ref())
+                    .await
+                {
+                    Ok(true) => self.refresh().await,
+                    Ok(false) => break,
+                    Err(e) => {
+                        log::error!("{:?}", e);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Forget which people are allowed, so that changes to teams and people take effect.
+    async fn refresh(&self) {
+        let mut scope = self.scope.lock().await;
+        *scope = scope.refreshed();
+        self.allowed.lock().await.clear();
+    }
+
+    async fn allows(&self, person_id: &str) -> anyhow::Result<bool> {
+        if let Some(&allowed) = self.allowed.lock().await.get(person_id) {
+            return Ok(allowed);
+        }
+        let allowed = self.check(person_id).await?;
+        self.allowed
+            .lock()
+            .await
+            .insert(person_id.to_string(), allowed);
+        Ok(allowed)
+    }
+
+    async fn check(&self, person_id: &str) -> anyhow::Result<bool> {
+        if let Some(person_ids) = &self.filter.person_ids {
+            if !person_ids.iter().any(|id| id.to_string() == person_id) {
+                return Ok(false);
+            }
+        }
+        let scope = self.scope.lock().await.clone();
+        if !scope.allows_subject(person_id).await? {
+            return Ok(false);
+        }
+        if let Some(company_id) = &self.filter.company_id {
+            let person = self.person_repo.find_one(person_id).await?;
+            if !matches!(person, Some(person) if person.company_id == company_id.to_string()) {
+                return Ok(false);
+            }
+        }
+        if let Some(team_id) = &self.filter.team_id {
+            let team_id = team_id.to_string();
+            let membership = self
+                .team_repo
+                .find_memberships(person_id)
+                .await?
+                .try_filter(|membership| future::ready(membership.team_id == team_id))
+                .try_next()
+                .await?;
+            return Ok(membership.is_some());
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::memory::{company_with_teams, MemoryTeamRepo};
+    use crate::repo::team::TeamRepo;
+    use crate::repo::tenant::Tenant;
+
+    #[tokio::test]
+    async fn test_allows_led_teams_refreshed() {
+        // Arrange.
+        let (feed, team_repo) = feed(Some("lead"), FeedFilter::default()).await;
+        assert!(feed.allows("member").await.unwrap());
+        assert!(!feed.allows("other").await.unwrap());
+        team_repo.remove_person("a", "member").await.unwrap();
+        team_repo.add_person("a", "other", false).await.unwrap();
+
+        // Act.
+        let cached = feed.allows("member").await.unwrap();
+        feed.refresh().await;
+        let removed = feed.allows("member").await.unwrap();
+        let added = feed.allows("other").await.unwrap();
+
+        // Assert.
+        assert!(cached);
+        assert!(!removed);
+        assert!(added);
+    }
+
+    #[tokio::test]
+    async fn test_allows_filter() {
+        // Arrange.
+        let filter = FeedFilter {
+            person_ids: Some(vec![ID::from("member".to_string())]),
+            ..Default::default()
+        };
+        let (feed, _) = feed(None, filter).await;
+
+        // Act.
+        let member = feed.allows("member").await.unwrap();
+        let other = feed.allows("other").await.unwrap();
+
+        // Assert.
+        assert!(member);
+        assert!(!other);
+    }
+
+    #[tokio::test]
+    async fn test_allows_team_filter() {
+        // Arrange.
+        let filter = FeedFilter {
+            team_id: Some(ID::from("b".to_string())),
+            ..Default::default()
+        };
+        let (feed, _) = feed(None, filter).await;
+
+        // Act.
+        let member = feed.allows("member").await.unwrap();
+        let other = feed.allows("other").await.unwrap();
+
+        // Assert.
+        assert!(!member);
+        assert!(other);
+    }
+
+    #[tokio::test]
+    async fn test_allows_other_company() {
+        // Arrange.
+        let (feed, _) = feed(None, FeedFilter::default()).await;
+
+        // Act.
+        let res = feed.allows("outsider").await.unwrap();
+
+        // Assert.
+        assert!(!res);
+    }
+
+    /// Feed of company "c" from `company_with_teams`.
+    async fn feed(
+        lead_person_id: Option<&str>,
+        filter: FeedFilter,
+    ) -> (Arc<Feed>, Arc<MemoryTeamRepo>) {
+        let (person_repo, team_repo) = company_with_teams().await;
+        let scope = TenantScope::new(
+            Tenant::Company("c".to_string()),
+            lead_person_id.map(str::to_string),
+            person_repo.clone(),
+            team_repo.clone(),
+        );
+        let feed = Feed {
+            filter,
+            scope: Mutex::new(scope),
+            person_repo,
+            team_repo: team_repo.clone(),
+            allowed: Mutex::new(HashMap::new()),
+        };
+        (Arc::new(feed), team_repo)
+    }
+}
@@ -1,17 +1,57 @@
+use crate::crockford;
 use crate::graphql::company::Company;
+use crate::graphql::connection::{paginate, PageArgs, PageInfo, Slice};
 use crate::graphql::incident_stats::{IncidentStats, IncidentStatsFilter};
-use crate::graphql::Context;
+use crate::graphql::person::{select_ids, to_strings};
 use crate::graphql::Person;
-use crate::repo::team;
-use crate::{crockford, repo};
+use crate::graphql::{incident_stats, limits, Context};
+use crate::repo::{intersect, team, Cursor};
 use derive_more::{Deref, DerefMut, From};
-use futures_util::StreamExt;
 use futures_util::TryStreamExt;
 use juniper::{FieldResult, ID};
 
 #[derive(Clone, From, Deref, DerefMut)]
 pub struct Team(pub team::Team);
 
+#[derive(juniper::GraphQLObject)]
+#[graphql(context = Context)]
+pub struct TeamConnection {
+    pub edges: Vec<TeamEdge>,
+    pub page_info: PageInfo,
+}
+
+#[derive(juniper::GraphQLObject)]
+#[graphql(context = Context)]
+pub struct TeamEdge {
+    pub cursor: String,
+    pub node: Team,
+}
+
+impl From<Slice<team::Team>> for TeamConnection {
+    fn from(value: Slice<team::Team>) -> Self {
+        Self {
+            edges: value
+                .edges
+                .into_iter()
+                .map(|(cursor, node)| TeamEdge {
+                    cursor,
+                    node: node.into(),
+                })
+                .collect(),
+            page_info: value.page_info,
+        }
+    }
+}
+
+#[derive(juniper::GraphQLInputObject, Default)]
+pub struct TeamFilter {
+    pub ids: Option<Vec<ID>>,
+    pub company_ids: Option<Vec<ID>>,
+    /// Text whose words each start a word of the name, ignoring case. For example, "jo sm"
+    /// matches "John Smith".
+    pub name: Option<String>,
+}
+
 #[derive(juniper::GraphQLInputObject)]
 pub struct TeamInput {
     pub name: String,
@@ -30,8 +70,8 @@ impl Team {
 
     pub async fn company(&self, context: &Context) -> FieldResult<Option<Company>> {
         Ok(context
-            .company_repo
-            .find_one(&self.company_id)
+            .company_loader
+            .load(&self.company_id)
             .await?
             .map(Into::into))
     }
@@ -41,59 +81,74 @@ impl Team {
         context: &Context,
         filter: Option<IncidentStatsFilter>,
     ) -> FieldResult<Vec<IncidentStats>> {
-        let filter = filter.unwrap_or_default();
-        let person_ids = context
-            .team_repo
-            .find_people(&self.id)
-            .await?
-            .map_ok(|p| p.person_id)
-            .try_collect()
-            .await?;
-        Ok(context
-            .incident_stats_repo
-            .find(repo::incident_stats::IncidentStatsFilter {
-                person_ids: Some(person_ids),
-                min_timestamp: filter.min_timestamp,
-                max_timestamp: filter.max_timestamp,
-            })
-            .await?
-            .map_ok(Into::into)
-            .try_collect()
-            .await?)
+        let person_ids = select_ids(context, None, Some(vec![self.id.clone().into()])).await?;
+        incident_stats::list(context, person_ids, None, filter).await
     }
 
     pub async fn people(&self, context: &Context) -> FieldResult<Vec<Person>> {
+        let people = context.team_repo.find_people(&self.id).await?;
+        let person_ids: Vec<String> =
+            limits::collect(context, people.map_ok(|tp| tp.person_id)).await?;
         Ok(context
+            .person_loader
+            .load_many(&person_ids)
+            .await?
+            .into_iter()
+            .map(Into::into)
+            .collect())
+    }
+
+    pub async fn leads(&self, context: &Context) -> FieldResult<Vec<Person>> {
+        let leads = context
             .team_repo
             .find_people(&self.id)
             .await?
-            .map_err(anyhow::Error::from)
-            .and_then(|tp| async move {
-                context
-                    .person_repo
-                    .find_one(&tp.person_id)
-                    .await
-                    .map_err(Into::into)
-            })
-            .filter_map(|o| async move { o.transpose() })
-            .map_ok(Into::into)
-            .try_collect()
-            .await?)
+            .try_filter(|tp| futures_util::future::ready(tp.lead))
+            .map_ok(|tp| tp.person_id);
+        let person_ids: Vec<String> = limits::collect(context, leads).await?;
+        Ok(context
+            .person_loader
+            .load_many(&person_ids)
+            .await?
+            .into_iter()
+            .map(Into::into)
+            .collect())
     }
 }
 
+#[allow(clippy::explicit_auto_deref)]
 pub async fn get(context: &Context, id: ID) -> FieldResult<Option<Team>> {
     Ok(context.team_repo.find_one(&*id).await?.map(Into::into))
 }
 
-pub async fn list(context: &Context) -> FieldResult<Vec<Team>> {
-    Ok(context
-        .team_repo
-        .find(Default::default())
-        .await?
-        .map_ok(Into::into)
-        .try_collect()
-        .await?)
+/// A page of the teams of the given companies or of every company, ordered by ID.
+pub async fn list(
+    context: &Context,
+    company_ids: Option<Vec<String>>,
+    filter: Option<TeamFilter>,
+    args: PageArgs,
+) -> FieldResult<TeamConnection> {
+    let filter = filter.unwrap_or_default();
+    let ids = to_strings(filter.ids);
+    let company_ids = intersect(company_ids, to_strings(filter.company_ids));
+    let name = filter.name;
+    let slice = paginate(args, context.limits.max_list_items, cursor, |page| {
+        context.team_repo.find(team::TeamFilter {
+            ids,
+            company_ids,
+            name,
+            page,
+        })
+    })
+    .await?;
+    Ok(slice.into())
+}
+
+fn cursor(team: &team::Team) -> Cursor {
+    Cursor {
+        timestamp: None,
+        id: team.id.clone(),
+    }
 }
 
 pub async fn create(context: &Context, input: TeamInput) -> FieldResult<Team> {
@@ -114,15 +169,21 @@ pub async fn delete(context: &Context, id: ID) -> FieldResult<ID> {
     Ok(id)
 }
 
+#[allow(clippy::explicit_auto_deref, clippy::needless_question_mark)]
 pub async fn add_person(
     context: &Context,
     team_id: ID,
     person_id: ID,
+    lead: bool,
 ) -> FieldResult<Option<Team>> {
-    context.team_repo.add_person(&*team_id, &*person_id).await?;
+    context
+        .team_repo
+        .add_person(&*team_id, &*person_id, lead)
+        .await?;
     Ok(get(context, team_id).await?)
 }
 
+#[allow(clippy::explicit_auto_deref, clippy::needless_question_mark)]
 pub async fn remove_person(
     context: &Context,
     team_id: ID,
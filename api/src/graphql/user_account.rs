@@ -1,21 +1,70 @@
-use crate::crockford;
+use crate::auth::{
+    InvitationError, LoginError, RefreshError, RequestThrottled, TotpError, VerificationError,
+    EMAIL_VERIFICATION_TTL_HOURS, LOGIN_CHALLENGE_TTL_MINUTES, PHONE_VERIFICATION_TTL_MINUTES,
+};
 use crate::graphql::company::Company;
-use crate::graphql::Context;
+use crate::graphql::connection::{paginate, PageArgs, PageInfo, Slice};
+use crate::graphql::person::{to_strings, Person};
+use crate::graphql::team::Team;
+use crate::graphql::{conflict_error, require, unauthorized_error, Context};
 use crate::image::PngBytes;
+use crate::mail::Email;
+use crate::password_policy::PolicyError;
+use crate::permission::Permission;
+use crate::repo::one_time_token::Purpose;
+use crate::repo::refresh_token::RefreshToken;
+use crate::repo::tenant::Tenant;
 use crate::repo::user_account;
+use crate::repo::{intersect, Cursor, InsertError, ReplaceError};
+use crate::sms::Sms;
+use crate::{contact, crockford, throttle, totp};
 use anyhow::Context as AnyhowContext;
+use chrono::{DateTime, Duration, Utc};
 use data_encoding::BASE64;
 use derive_more::{Deref, DerefMut, From};
-use futures_util::TryStreamExt;
-use juniper::{FieldResult, ID};
+use juniper::{graphql_value, FieldError, FieldResult, ID};
+
+const INACTIVE_MESSAGE: &str = "User account is deactivated or suspended";
+const THROTTLED_MESSAGE: &str = "Too many requests, try again later";
 
 #[derive(Clone, From, Deref, DerefMut)]
 pub struct UserAccount(pub user_account::UserAccount);
 
+#[derive(juniper::GraphQLObject)]
+#[graphql(context = Context)]
+pub struct UserAccountConnection {
+    pub edges: Vec<UserAccountEdge>,
+    pub page_info: PageInfo,
+}
+
+#[derive(juniper::GraphQLObject)]
+#[graphql(context = Context)]
+pub struct UserAccountEdge {
+    pub cursor: String,
+    pub node: UserAccount,
+}
+
+impl From<Slice<user_account::UserAccount>> for UserAccountConnection {
+    fn from(value: Slice<user_account::UserAccount>) -> Self {
+        Self {
+            edges: value
+                .edges
+                .into_iter()
+                .map(|(cursor, node)| UserAccountEdge {
+                    cursor,
+                    node: node.into(),
+                })
+                .collect(),
+            page_info: value.page_info,
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, juniper::GraphQLEnum)]
 pub enum Access {
     View,
     Admin,
+    SuperAdmin,
 }
 
 impl From<Access> for user_account::Access {
@@ -23,6 +72,7 @@ impl From<Access> for user_account::Access {
         match value {
             Access::View => Self::View,
             Access::Admin => Self::Admin,
+            Access::SuperAdmin => Self::SuperAdmin,
         }
     }
 }
@@ -32,10 +82,126 @@ impl From<user_account::Access> for Access {
         match value {
             user_account::Access::View => Self::View,
             user_account::Access::Admin => Self::Admin,
+            user_account::Access::SuperAdmin => Self::SuperAdmin,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, juniper::GraphQLEnum)]
+pub enum Role {
+    SafetyManager,
+    TeamLead,
+    Worker,
+    Auditor,
+}
+
+impl From<Role> for user_account::Role {
+    fn from(value: Role) -> Self {
+        match value {
+            Role::SafetyManager => Self::SafetyManager,
+            Role::TeamLead => Self::TeamLead,
+            Role::Worker => Self::Worker,
+            Role::Auditor => Self::Auditor,
+        }
+    }
+}
+
+impl From<user_account::Role> for Role {
+    fn from(value: user_account::Role) -> Self {
+        match value {
+            user_account::Role::SafetyManager => Self::SafetyManager,
+            user_account::Role::TeamLead => Self::TeamLead,
+            user_account::Role::Worker => Self::Worker,
+            user_account::Role::Auditor => Self::Auditor,
         }
     }
 }
 
+#[derive(Debug, Copy, Clone, juniper::GraphQLEnum)]
+pub enum UserAccountStatus {
+    Active,
+    /// Temporarily unable to log in until `suspendedUntil`.
+    Suspended,
+    Deactivated,
+}
+
+impl From<UserAccountStatus> for user_account::Status {
+    fn from(value: UserAccountStatus) -> Self {
+        match value {
+            UserAccountStatus::Active => Self::Active,
+            UserAccountStatus::Suspended => Self::Suspended,
+            UserAccountStatus::Deactivated => Self::Deactivated,
+        }
+    }
+}
+
+impl From<user_account::Status> for UserAccountStatus {
+    fn from(value: user_account::Status) -> Self {
+        match value {
+            user_account::Status::Active => Self::Active,
+            user_account::Status::Suspended => Self::Suspended,
+            user_account::Status::Deactivated => Self::Deactivated,
+        }
+    }
+}
+
+#[derive(juniper::GraphQLInputObject, Default)]
+pub struct UserAccountFilter {
+    pub company_ids: Option<Vec<ID>>,
+    pub person_ids: Option<Vec<ID>>,
+    /// Text whose words each start a word of the name, ignoring case. For example, "jo sm"
+    /// matches "John Smith".
+    pub name: Option<String>,
+    pub status: Option<UserAccountStatus>,
+}
+
+#[derive(Clone, From, Deref, DerefMut)]
+pub struct RoleGrant(pub user_account::RoleGrant);
+
+#[derive(juniper::GraphQLInputObject)]
+pub struct RoleGrantInput {
+    pub role: Role,
+    /// Team the role applies to. The role applies across the company if not set.
+    pub team_id: Option<ID>,
+}
+
+/// A short-lived access token and the refresh token used to obtain the next pair.
+#[derive(juniper::GraphQLObject)]
+pub struct AuthTokens {
+    pub access_token: String,
+    pub refresh_token: String,
+}
+
+/// Result of logging in with a password. Tokens are returned unless a second factor is needed, in
+/// which case a challenge token is returned for the next step.
+#[derive(juniper::GraphQLObject)]
+pub struct LoginResult {
+    pub access_token: Option<String>,
+    pub refresh_token: Option<String>,
+    /// Token for `loginWithTotp`, or for `enrollTotp` if enrollment is required.
+    pub challenge_token: Option<String>,
+    /// Whether two-factor authentication must be set up before logging in.
+    pub totp_enrollment_required: bool,
+}
+
+/// A secret to add to an authenticator app.
+#[derive(juniper::GraphQLObject)]
+pub struct TotpEnrollment {
+    pub secret: String,
+    /// `otpauth` URI, usually shown as a QR code.
+    pub uri: String,
+    /// Token for `confirmTotp`, set when enrolling during login.
+    pub challenge_token: Option<String>,
+}
+
+#[derive(juniper::GraphQLObject)]
+pub struct TotpConfirmation {
+    /// Codes that can each be used once in place of a code from the authenticator app.
+    pub recovery_codes: Vec<String>,
+    /// Tokens, set when enrolling during login.
+    pub tokens: Option<AuthTokens>,
+}
+
 #[derive(juniper::GraphQLInputObject)]
 pub struct UserAccountInput {
     pub name: String,
@@ -44,6 +210,46 @@ pub struct UserAccountInput {
     pub email: String,
     pub phone: String,
     pub company_id: ID,
+    /// Roles in addition to the access level. Existing roles are kept if not set.
+    pub roles: Option<Vec<RoleGrantInput>>,
+    /// Person the user account belongs to, if the user is also a worker. A team lead with view
+    /// access only sees the readings and incidents of the teams their person leads.
+    pub person_id: Option<ID>,
+}
+
+#[juniper::graphql_object(context = Context)]
+impl RoleGrant {
+    pub fn role(&self) -> Role {
+        self.role.into()
+    }
+
+    pub async fn team(&self, context: &Context) -> FieldResult<Option<Team>> {
+        Ok(match &self.team_id {
+            Some(team_id) => context.team_loader.load(team_id).await?.map(Into::into),
+            None => None,
+        })
+    }
+}
+
+impl UserAccount {
+    /// Contact details are visible to the user, admins of their company and super admins.
+    fn verify_contact_visible(&self, context: &Context) -> FieldResult<()> {
+        let claims = match &context.claims {
+            Some(claims) => claims,
+            None => return Err(redacted_error("Not authenticated")),
+        };
+        let is_owner = claims.sub == self.id;
+        let is_company_admin = context.permissions.has(Permission::UserAdmin)
+            && (claims.company_id == self.company_id
+                || claims.access == user_account::Access::SuperAdmin);
+        if is_owner || is_company_admin {
+            Ok(())
+        } else {
+            Err(redacted_error(
+                "Only the user and admins of their company can see this field",
+            ))
+        }
+    }
 }
 
 #[juniper::graphql_object(context = Context)]
@@ -64,23 +270,62 @@ impl UserAccount {
         &self.title
     }
 
-    pub fn email(&self) -> &str {
-        &self.email
+    /// Only visible to the user and admins of their company.
+    pub fn email(&self, context: &Context) -> FieldResult<Option<&str>> {
+        self.verify_contact_visible(context)?;
+        Ok(Some(self.email.as_str()))
+    }
+
+    /// Whether the user confirmed the email address with `verifyEmail`. Reset when it changes.
+    pub fn email_verified(&self) -> bool {
+        self.email_verified
+    }
+
+    /// Only visible to the user and admins of their company.
+    pub fn phone(&self, context: &Context) -> FieldResult<Option<&str>> {
+        self.verify_contact_visible(context)?;
+        Ok(Some(self.phone.as_str()))
     }
 
-    pub fn phone(&self) -> &str {
-        &self.phone
+    /// Whether the user confirmed the phone number with `verifyPhone`. Reset when it changes.
+    pub fn phone_verified(&self) -> bool {
+        self.phone_verified
     }
 
     pub async fn company(&self, context: &Context) -> FieldResult<Option<Company>> {
         Ok(context
-            .company_repo
-            .find_one(&self.company_id)
+            .company_loader
+            .load(&self.company_id)
             .await?
             .map(Into::into))
     }
+
+    pub fn roles(&self) -> Vec<RoleGrant> {
+        self.roles.iter().cloned().map(Into::into).collect()
+    }
+
+    /// Whether the user account may log in, unless it is suspended.
+    pub fn active(&self) -> bool {
+        self.active
+    }
+
+    pub fn suspended_until(&self) -> Option<DateTime<Utc>> {
+        self.suspended_until
+    }
+
+    pub fn status(&self) -> UserAccountStatus {
+        self.0.status(Utc::now()).into()
+    }
+
+    pub async fn person(&self, context: &Context) -> FieldResult<Option<Person>> {
+        Ok(match &self.person_id {
+            Some(person_id) => context.person_loader.load(person_id).await?.map(Into::into),
+            None => None,
+        })
+    }
 }
 
+#[allow(clippy::explicit_auto_deref)]
 pub async fn get(context: &Context, id: ID) -> FieldResult<Option<UserAccount>> {
     Ok(context
         .user_account_repo
@@ -89,27 +334,69 @@ pub async fn get(context: &Context, id: ID) -> FieldResult<Option<UserAccount>>
         .map(Into::into))
 }
 
-pub async fn list(context: &Context) -> FieldResult<Vec<UserAccount>> {
-    Ok(context
-        .user_account_repo
-        .find(Default::default())
-        .await?
-        .map_ok(Into::into)
-        .try_collect()
-        .await?)
+/// A page of the user accounts of the given companies or of every company, ordered by ID.
+pub async fn list(
+    context: &Context,
+    company_ids: Option<Vec<String>>,
+    filter: Option<UserAccountFilter>,
+    args: PageArgs,
+) -> FieldResult<UserAccountConnection> {
+    let filter = filter.unwrap_or_default();
+    let company_ids = intersect(company_ids, to_strings(filter.company_ids));
+    let person_ids = to_strings(filter.person_ids);
+    let name = filter.name;
+    let status = filter.status.map(Into::into);
+    let slice = paginate(args, context.limits.max_list_items, cursor, |page| {
+        context
+            .user_account_repo
+            .find(user_account::UserAccountFilter {
+                company_ids,
+                person_ids,
+                name,
+                status,
+                page,
+            })
+    })
+    .await?;
+    Ok(slice.into())
+}
+
+fn cursor(user_account: &user_account::UserAccount) -> Cursor {
+    Cursor {
+        timestamp: None,
+        id: user_account.id.clone(),
+    }
 }
 
 pub async fn create(context: &Context, input: UserAccountInput) -> FieldResult<UserAccount> {
+    verify_access(&context.tenant, input.access.into())?;
+    verify_contact(&input)?;
+    let roles = role_grants(context, &input.company_id, input.roles.unwrap_or_default()).await?;
+    let person_id = linked_person_id(context, &input.company_id, input.person_id).await?;
     let item = user_account::UserAccount {
         id: crockford::random_id(),
         name: input.name,
         access: input.access.into(),
         title: input.title,
         email: input.email,
+        email_verified: false,
         phone: input.phone,
+        phone_verified: false,
         company_id: input.company_id.to_string(),
+        roles,
+        person_id,
+        active: true,
+        suspended_until: None,
     };
-    context.user_account_repo.insert_one(item.clone()).await?;
+    context
+        .user_account_repo
+        .insert_one(item.clone())
+        .await
+        .map_err(|e| match e {
+            InsertError::Conflict => email_conflict_error(),
+            InsertError::Other(e) => e.into(),
+        })?;
+    send_invitation(context, &item).await?;
     Ok(item.into())
 }
 
@@ -118,20 +405,122 @@ pub async fn replace(
     id: ID,
     input: UserAccountInput,
 ) -> FieldResult<UserAccount> {
-    let item = user_account::UserAccount {
+    verify_access(&context.tenant, input.access.into())?;
+    verify_contact(&input)?;
+    let existing = verify_tenant(context, &id).await?;
+    let roles = match input.roles {
+        Some(roles) => role_grants(context, &input.company_id, roles).await?,
+        None => existing.roles,
+    };
+    let person_id = linked_person_id(context, &input.company_id, input.person_id).await?;
+    let mut item = user_account::UserAccount {
         id: id.to_string(),
         name: input.name,
         access: input.access.into(),
         title: input.title,
-        email: input.email,
-        phone: input.phone,
         company_id: input.company_id.to_string(),
+        roles,
+        person_id,
+        ..existing
     };
-    context.user_account_repo.replace_one(item.clone()).await?;
+    let (email_changed, phone_changed) = item.set_contact(input.email, input.phone);
+    context
+        .user_account_repo
+        .replace_one(item.clone())
+        .await
+        .map_err(|e| match e {
+            ReplaceError::Conflict => email_conflict_error(),
+            e => e.into(),
+        })?;
+    // Outstanding links and codes were sent to the old address or number.
+    let one_time_token_repo = &context.auth_provider.one_time_token_repo;
+    if email_changed {
+        one_time_token_repo
+            .delete_by_user_account(&item.id, Purpose::EmailVerification)
+            .await?;
+    }
+    if phone_changed {
+        one_time_token_repo
+            .delete_by_user_account(&item.id, Purpose::PhoneVerification)
+            .await?;
+    }
     Ok(item.into())
 }
 
+/// An email address is required, while a phone number may be left empty.
+fn verify_contact(input: &UserAccountInput) -> FieldResult<()> {
+    if !contact::is_valid_email(&input.email) {
+        return Err("Invalid email address".into());
+    }
+    if !input.phone.is_empty() && !contact::is_valid_phone(&input.phone) {
+        return Err("Invalid phone number".into());
+    }
+    Ok(())
+}
+
+/// Convert role input, checking that teams belong to the company of the user account.
+async fn role_grants(
+    context: &Context,
+    company_id: &str,
+    input: Vec<RoleGrantInput>,
+) -> FieldResult<Vec<user_account::RoleGrant>> {
+    let mut roles = Vec::with_capacity(input.len());
+    for grant in input {
+        if let Some(team_id) = &grant.team_id {
+            let team = context.team_repo.find_one(team_id).await?;
+            if !matches!(team, Some(team) if team.company_id == company_id) {
+                return Err("Team not found".into());
+            }
+        }
+        roles.push(user_account::RoleGrant {
+            role: grant.role.into(),
+            team_id: grant.team_id.map(|id| id.to_string()),
+        });
+    }
+    Ok(roles)
+}
+
+/// Check that a linked person belongs to the company of the user account.
+async fn linked_person_id(
+    context: &Context,
+    company_id: &str,
+    person_id: Option<ID>,
+) -> FieldResult<Option<String>> {
+    if let Some(person_id) = &person_id {
+        let person = context.person_repo.find_one(person_id).await?;
+        if !matches!(person, Some(person) if person.company_id == company_id) {
+            return Err("Person not found".into());
+        }
+    }
+    Ok(person_id.map(|id| id.to_string()))
+}
+
+/// Only super admins can grant super admin access or act on super admin accounts, even those in
+/// the company of the request.
+fn verify_access(tenant: &Tenant, access: user_account::Access) -> FieldResult<()> {
+    match access {
+        user_account::Access::SuperAdmin if *tenant != Tenant::All => Err(unauthorized_error()),
+        _ => Ok(()),
+    }
+}
+
+/// Find a user account the request may act on, before acting on it through the auth provider,
+/// which is not limited to the company of the request.
+pub async fn verify_tenant(
+    context: &Context,
+    user_account_id: &str,
+) -> FieldResult<user_account::UserAccount> {
+    let user_account = context
+        .user_account_repo
+        .find_one(user_account_id)
+        .await?
+        .context("User account not found")?;
+    verify_access(&context.tenant, user_account.access)?;
+    Ok(user_account)
+}
+
 pub async fn delete(context: &Context, id: ID) -> FieldResult<ID> {
+    verify_tenant(context, &id).await?;
     context
         .user_account_repo
         .delete_one(&id.clone().to_string())
@@ -139,23 +528,304 @@ pub async fn delete(context: &Context, id: ID) -> FieldResult<ID> {
     Ok(id)
 }
 
-pub async fn login(
+/// Stop a user account from logging in and end its sessions, until it is reactivated or, if given,
+/// until the suspension ends.
+pub async fn deactivate(
     context: &Context,
-    user_account_id: ID,
-    password: String,
-) -> FieldResult<String> {
+    id: ID,
+    suspended_until: Option<DateTime<Utc>>,
+) -> FieldResult<UserAccount> {
+    let mut item = verify_tenant(context, &id).await?;
+    if matches!(&context.claims, Some(claims) if claims.sub == item.id) {
+        return Err("Cannot deactivate your own user account".into());
+    }
+    match suspended_until {
+        Some(suspended_until) if suspended_until <= Utc::now() => {
+            return Err("Suspension must end in the future".into());
+        }
+        Some(suspended_until) => item.suspended_until = Some(suspended_until),
+        None => item.active = false,
+    }
+    context.user_account_repo.replace_one(item.clone()).await?;
+    context.auth_provider.revoke_sessions(&item.id).await?;
+    Ok(item.into())
+}
+
+/// Let a deactivated or suspended user account log in again.
+pub async fn reactivate(context: &Context, id: ID) -> FieldResult<UserAccount> {
+    let mut item = verify_tenant(context, &id).await?;
+    item.active = true;
+    item.suspended_until = None;
+    context.user_account_repo.replace_one(item.clone()).await?;
+    Ok(item.into())
+}
+
+pub async fn login(context: &Context, email: String, password: String) -> FieldResult<LoginResult> {
+    let user_account = context
+        .auth_provider
+        .authenticate(&email, &password, context.client_ip.as_deref())
+        .await?
+        .map_err(|e| match e {
+            LoginError::IncorrectCredentials => "Incorrect email or password",
+            LoginError::Throttled => "Too many failed login attempts, try again later",
+            LoginError::Inactive => INACTIVE_MESSAGE,
+        })?;
+    login_result(context, &user_account).await
+}
+
+/// Issue tokens to a user account that proved who it is, or a challenge token if it must also
+/// enter a second factor.
+async fn login_result(
+    context: &Context,
+    user_account: &user_account::UserAccount,
+) -> FieldResult<LoginResult> {
+    if !user_account.is_active(Utc::now()) {
+        return Err(INACTIVE_MESSAGE.into());
+    }
+    let totp_enabled = context.auth_provider.totp_enabled(&user_account.id).await?;
+    let totp_enrollment_required =
+        !totp_enabled && context.auth_provider.requires_totp(user_account).await?;
+    if totp_enabled || totp_enrollment_required {
+        return Ok(LoginResult {
+            access_token: None,
+            refresh_token: None,
+            challenge_token: Some(create_challenge_token(context, &user_account.id).await?),
+            totp_enrollment_required,
+        });
+    }
+    let tokens = create_tokens(context, user_account, None).await?;
+    Ok(LoginResult {
+        access_token: Some(tokens.access_token),
+        refresh_token: Some(tokens.refresh_token),
+        challenge_token: None,
+        totp_enrollment_required: false,
+    })
+}
+
+/// Finish logging in with a code from an authenticator app or a recovery code. The challenge token
+/// is used up either way, so a wrong code means logging in with the password again.
+pub async fn login_with_totp(
+    context: &Context,
+    challenge_token: String,
+    code: String,
+) -> FieldResult<AuthTokens> {
+    let user_account_id = use_challenge_token(context, &challenge_token).await?;
     context
         .auth_provider
-        .verify_password(&user_account_id, &password)
+        .verify_second_factor(&user_account_id, &code)
         .await?
-        .map_err(|_| "Incorrect password")?;
+        .map_err(totp_error_message)?;
     let user_account = context
+        .auth_provider
         .user_account_repo
         .find_one(&user_account_id)
         .await?
         .context("User account not found")?;
-    let token = context.claims_provider.create_token(&user_account)?;
-    Ok(token)
+    create_tokens(context, &user_account, None).await
+}
+
+/// Start setting up two-factor authentication, either for the requesting user account or during
+/// login with a challenge token.
+pub async fn enroll_totp(
+    context: &Context,
+    challenge_token: Option<String>,
+) -> FieldResult<TotpEnrollment> {
+    let user_account_id = totp_user_account_id(context, challenge_token.as_deref()).await?;
+    let secret = context
+        .auth_provider
+        .start_totp_enrollment(&user_account_id)
+        .await?
+        .map_err(totp_error_message)?;
+    let user_account = context
+        .auth_provider
+        .user_account_repo
+        .find_one(&user_account_id)
+        .await?
+        .context("User account not found")?;
+    let challenge_token = match challenge_token {
+        Some(_) => Some(create_challenge_token(context, &user_account_id).await?),
+        None => None,
+    };
+    Ok(TotpEnrollment {
+        uri: totp::uri(&secret, &user_account.email),
+        secret,
+        challenge_token,
+    })
+}
+
+pub async fn confirm_totp(
+    context: &Context,
+    code: String,
+    challenge_token: Option<String>,
+) -> FieldResult<TotpConfirmation> {
+    let user_account_id = totp_user_account_id(context, challenge_token.as_deref()).await?;
+    let recovery_codes = context
+        .auth_provider
+        .confirm_totp_enrollment(&user_account_id, &code)
+        .await?
+        .map_err(totp_error_message)?;
+    let tokens = match challenge_token {
+        Some(_) => {
+            let user_account = context
+                .auth_provider
+                .user_account_repo
+                .find_one(&user_account_id)
+                .await?
+                .context("User account not found")?;
+            Some(create_tokens(context, &user_account, None).await?)
+        }
+        None => None,
+    };
+    Ok(TotpConfirmation {
+        recovery_codes,
+        tokens,
+    })
+}
+
+pub async fn disable_my_totp(context: &Context, current_password: String) -> FieldResult<bool> {
+    let claims = context.claims.as_ref().context("Unauthorized")?;
+    context
+        .auth_provider
+        .verify_password(&claims.sub, &current_password)
+        .await?
+        .map_err(|_| "Incorrect password")?;
+    let user_account = context
+        .user_account_repo
+        .find_one(&claims.sub)
+        .await?
+        .context("User account not found")?;
+    if context.auth_provider.requires_totp(&user_account).await? {
+        return Err("Two-factor authentication is required for this account".into());
+    }
+    context.auth_provider.disable_totp(&claims.sub).await?;
+    Ok(true)
+}
+
+/// Remove two-factor authentication from a user account, such as when its device is lost.
+pub async fn reset_totp(context: &Context, user_account_id: ID) -> FieldResult<bool> {
+    verify_tenant(context, &user_account_id).await?;
+    context.auth_provider.disable_totp(&user_account_id).await?;
+    Ok(true)
+}
+
+async fn create_challenge_token(context: &Context, user_account_id: &str) -> FieldResult<String> {
+    Ok(context
+        .auth_provider
+        .create_one_time_token(
+            user_account_id,
+            Purpose::LoginChallenge,
+            Duration::minutes(LOGIN_CHALLENGE_TTL_MINUTES),
+        )
+        .await?)
+}
+
+async fn use_challenge_token(context: &Context, challenge_token: &str) -> FieldResult<String> {
+    Ok(context
+        .auth_provider
+        .use_one_time_token(challenge_token, Purpose::LoginChallenge)
+        .await?
+        .context("Invalid or expired challenge token")?)
+}
+
+/// The user account setting up two-factor authentication, identified by a challenge token during
+/// login or by the access token otherwise.
+async fn totp_user_account_id(
+    context: &Context,
+    challenge_token: Option<&str>,
+) -> FieldResult<String> {
+    match (challenge_token, &context.claims) {
+        (Some(challenge_token), _) => use_challenge_token(context, challenge_token).await,
+        (None, Some(claims)) => {
+            require(context, Permission::AccountOwn)?;
+            Ok(claims.sub.clone())
+        }
+        (None, None) => Err(unauthorized_error()),
+    }
+}
+
+fn totp_error_message(e: TotpError) -> &'static str {
+    match e {
+        TotpError::AlreadyEnabled => "Two-factor authentication is already enabled",
+        TotpError::NotEnrolled => "Two-factor authentication is not set up",
+        TotpError::IncorrectCode => "Incorrect code",
+        TotpError::Throttled => "Too many incorrect codes, try again later",
+    }
+}
+
+/// Clear failed login attempts of a user account so it can log in again immediately.
+pub async fn unlock(context: &Context, user_account_id: ID) -> FieldResult<bool> {
+    verify_tenant(context, &user_account_id).await?;
+    context
+        .auth_provider
+        .login_throttle
+        .reset(&throttle::user_account_key(&user_account_id))
+        .await?;
+    Ok(true)
+}
+
+pub async fn refresh_token(context: &Context, refresh_token: String) -> FieldResult<AuthTokens> {
+    let used = context
+        .auth_provider
+        .use_refresh_token(&refresh_token)
+        .await?
+        .map_err(refresh_error_message)?;
+    let user_account = context
+        .auth_provider
+        .user_account_repo
+        .find_one(&used.user_account_id)
+        .await?
+        .context("User account not found")?;
+    create_tokens(context, &user_account, Some(used)).await
+}
+
+/// Create an access token and a refresh token. The session of the used refresh token is continued
+/// if there is one, otherwise a new session is started.
+async fn create_tokens(
+    context: &Context,
+    user_account: &user_account::UserAccount,
+    used: Option<RefreshToken>,
+) -> FieldResult<AuthTokens> {
+    if !user_account.is_active(Utc::now()) {
+        return Err(INACTIVE_MESSAGE.into());
+    }
+    let claims = context.claims_provider.create_claims(user_account);
+    let access_token = context.claims_provider.create_token(&claims)?;
+    let refresh_token = match used {
+        None => {
+            context
+                .auth_provider
+                .create_session(&user_account.id, &claims.jti)
+                .await?
+        }
+        Some(used) => context
+            .auth_provider
+            .refresh_session(&used, &claims.jti)
+            .await?
+            .map_err(refresh_error_message)?,
+    };
+    Ok(AuthTokens {
+        access_token,
+        refresh_token,
+    })
+}
+
+fn redacted_error(reason: &str) -> FieldError {
+    FieldError::new(
+        "Field is redacted",
+        graphql_value!({ "code": "REDACTED", "reason": reason }),
+    )
+}
+
+fn email_conflict_error() -> FieldError {
+    conflict_error("Email address is already in use")
+}
+
+fn refresh_error_message(e: RefreshError) -> &'static str {
+    match e {
+        RefreshError::Invalid => "Invalid refresh token",
+        RefreshError::Expired => "Expired refresh token",
+        RefreshError::Reused => "Refresh token reuse detected",
+    }
 }
 
 pub async fn set_password(
@@ -163,18 +833,338 @@ pub async fn set_password(
     user_account_id: ID,
     password: String,
 ) -> FieldResult<bool> {
+    verify_tenant(context, &user_account_id).await?;
     context
         .auth_provider
         .set_password(&user_account_id, &password)
-        .await?;
+        .await?
+        .map_err(policy_error_message)?;
     Ok(true)
 }
 
+/// Change the password of the requesting user account. All sessions are revoked, so a new pair of
+/// tokens is returned.
+pub async fn change_my_password(
+    context: &Context,
+    current_password: String,
+    new_password: String,
+) -> FieldResult<AuthTokens> {
+    let claims = context.claims.as_ref().context("Unauthorized")?;
+    context
+        .auth_provider
+        .verify_password(&claims.sub, &current_password)
+        .await?
+        .map_err(|_| "Incorrect password")?;
+    context
+        .auth_provider
+        .set_password(&claims.sub, &new_password)
+        .await?
+        .map_err(policy_error_message)?;
+    let user_account = context
+        .user_account_repo
+        .find_one(&claims.sub)
+        .await?
+        .context("User account not found")?;
+    create_tokens(context, &user_account, None).await
+}
+
+/// Email a password reset link if a user account has the email address. The result is the same
+/// either way, so the response does not reveal which email addresses have accounts.
+pub async fn request_password_reset(context: &Context, email: String) -> FieldResult<bool> {
+    let (user_account, token) = match context
+        .auth_provider
+        .create_password_reset(&email, context.client_ip.as_deref())
+        .await?
+        .map_err(|RequestThrottled| THROTTLED_MESSAGE)?
+    {
+        Some(created) => created,
+        None => return Ok(true),
+    };
+    let ttl = context.auth_provider.password_reset_ttl;
+    let email = Email {
+        to: user_account.email.clone(),
+        subject: "Reset your SafetyWare password".to_string(),
+        body: format!(
+            "Hello {},\n\n\
+            A password reset was requested for your SafetyWare account. Follow the link below to \
+            choose a new password. The link expires in {} minutes.\n\n\
+            {}/reset-password?token={}\n\n\
+            If you did not request a password reset, you can ignore this email.",
+            user_account.name,
+            ttl.num_minutes(),
+            context.app_url,
+            token
+        ),
+    };
+    send_in_background(context, email);
+    Ok(true)
+}
+
+/// Email a link that logs in without a password, if the company of the user account allows it.
+/// Returns true either way, so the response does not reveal whether the user account exists.
+pub async fn request_login_link(context: &Context, email: String) -> FieldResult<bool> {
+    let (user_account, token) = match context
+        .auth_provider
+        .create_login_link(&email, context.client_ip.as_deref())
+        .await?
+        .map_err(|RequestThrottled| THROTTLED_MESSAGE)?
+    {
+        Some(created) => created,
+        None => return Ok(true),
+    };
+    let ttl = context.auth_provider.login_link_ttl;
+    let email = Email {
+        to: user_account.email.clone(),
+        subject: "Log in to SafetyWare".to_string(),
+        body: format!(
+            "Hello {},\n\n\
+            Follow the link below to log in to SafetyWare. The link can be used once and expires \
+            in {} minutes.\n\n\
+            {}/login-link?token={}\n\n\
+            If you did not request this link, you can ignore this email.",
+            user_account.name,
+            ttl.num_minutes(),
+            context.app_url,
+            token
+        ),
+    };
+    send_in_background(context, email);
+    Ok(true)
+}
+
+/// Send an email without waiting for the mail server, so response times do not reveal whether an
+/// email was sent.
+fn send_in_background(context: &Context, email: Email) {
+    let mailer = context.mailer.clone();
+    tokio::spawn(async move {
+        if let Err(e) = mailer.send(email).await {
+            log::error!("{:?}", e);
+        }
+    });
+}
+
+/// Log in with an emailed link. Like `login`, a second factor may still be required.
+pub async fn redeem_login_link(context: &Context, token: String) -> FieldResult<LoginResult> {
+    let user_account_id = context
+        .auth_provider
+        .use_one_time_token(&token, Purpose::LoginLink)
+        .await?
+        .context("Invalid or expired login link")?;
+    let user_account = context
+        .auth_provider
+        .user_account_repo
+        .find_one(&user_account_id)
+        .await?
+        .context("User account not found")?;
+    if !context
+        .auth_provider
+        .login_links_enabled(&user_account)
+        .await?
+    {
+        return Err("Login links are disabled for this company".into());
+    }
+    login_result(context, &user_account).await
+}
+
+/// Email the requesting user account a link that confirms its email address.
+pub async fn request_email_verification(context: &Context) -> FieldResult<bool> {
+    let user_account = my_user_account(context).await?;
+    if user_account.email_verified {
+        return Err("Email address is already verified".into());
+    }
+    let token = context
+        .auth_provider
+        .create_email_verification_token(&user_account)
+        .await?
+        .map_err(|RequestThrottled| THROTTLED_MESSAGE)?;
+    let email = Email {
+        to: user_account.email.clone(),
+        subject: "Verify your SafetyWare email address".to_string(),
+        body: format!(
+            "Hello {},\n\n\
+            Follow the link below to verify the email address of your SafetyWare account. The \
+            link expires in {} hours.\n\n\
+            {}/verify-email?token={}",
+            user_account.name, EMAIL_VERIFICATION_TTL_HOURS, context.app_url, token
+        ),
+    };
+    send_in_background(context, email);
+    Ok(true)
+}
+
+/// Confirm an email address with the token from a verification link. Logging in is not needed,
+/// since the link may be opened on another device.
+pub async fn verify_email(context: &Context, token: String) -> FieldResult<bool> {
+    context
+        .auth_provider
+        .verify_email(&token)
+        .await?
+        .map_err(|e| match e {
+            VerificationError::ContactChanged => "Email address changed since the link was sent",
+            _ => "Invalid or expired verification link",
+        })?;
+    Ok(true)
+}
+
+/// Text the requesting user account a code that confirms its phone number.
+pub async fn request_phone_verification(context: &Context) -> FieldResult<bool> {
+    let user_account = my_user_account(context).await?;
+    if user_account.phone.is_empty() {
+        return Err("User account has no phone number".into());
+    }
+    if user_account.phone_verified {
+        return Err("Phone number is already verified".into());
+    }
+    let code = context
+        .auth_provider
+        .create_phone_verification_code(&user_account)
+        .await?
+        .map_err(|RequestThrottled| THROTTLED_MESSAGE)?;
+    let sms = Sms {
+        to: user_account.phone.clone(),
+        body: format!(
+            "Your SafetyWare verification code is {}. It expires in {} minutes.",
+            code, PHONE_VERIFICATION_TTL_MINUTES
+        ),
+    };
+    context.sms_sender.send(sms).await?;
+    Ok(true)
+}
+
+/// Confirm the phone number of the requesting user account with the texted code.
+pub async fn verify_phone(context: &Context, code: String) -> FieldResult<bool> {
+    let claims = context.claims.as_ref().context("Unauthorized")?;
+    context
+        .auth_provider
+        .verify_phone(&claims.sub, &code)
+        .await?
+        .map_err(|e| match e {
+            VerificationError::IncorrectCode => "Incorrect or expired code",
+            VerificationError::Throttled => "Too many incorrect codes, try again later",
+            VerificationError::ContactChanged => "Phone number changed since the code was sent",
+        })?;
+    Ok(true)
+}
+
+async fn my_user_account(context: &Context) -> FieldResult<user_account::UserAccount> {
+    let claims = context.claims.as_ref().context("Unauthorized")?;
+    Ok(context
+        .user_account_repo
+        .find_one(&claims.sub)
+        .await?
+        .context("User account not found")?)
+}
+
+pub async fn reset_password(
+    context: &Context,
+    token: String,
+    new_password: String,
+) -> FieldResult<bool> {
+    set_password_with_token(context, &token, Purpose::PasswordReset, &new_password).await
+}
+
+/// Email a new user account a link to set its password. The user account cannot log in until
+/// then.
+async fn send_invitation(
+    context: &Context,
+    user_account: &user_account::UserAccount,
+) -> FieldResult<()> {
+    let ttl = context.auth_provider.invitation_ttl;
+    let token = context
+        .auth_provider
+        .create_invitation(&user_account.id)
+        .await?
+        .map_err(invitation_error_message)?;
+    let email = Email {
+        to: user_account.email.clone(),
+        subject: "You are invited to SafetyWare".to_string(),
+        body: format!(
+            "Hello {},\n\n\
+            An account was created for you on SafetyWare. Follow the link below to choose a \
+            password and activate your account. The link expires in {} hours.\n\n\
+            {}/accept-invitation?token={}",
+            user_account.name,
+            ttl.num_hours(),
+            context.app_url,
+            token
+        ),
+    };
+    if let Err(e) = context.mailer.send(email).await {
+        log::error!("{:?}", e);
+    }
+    Ok(())
+}
+
+/// Send a new invitation, such as when the previous one expired. Earlier invitations stop working.
+pub async fn resend_invitation(context: &Context, user_account_id: ID) -> FieldResult<bool> {
+    let user_account = verify_tenant(context, &user_account_id).await?;
+    send_invitation(context, &user_account).await?;
+    Ok(true)
+}
+
+/// Activate an invited user account by setting its password.
+pub async fn accept_invitation(
+    context: &Context,
+    token: String,
+    password: String,
+) -> FieldResult<bool> {
+    context
+        .auth_provider
+        .accept_invitation(&token, &password)
+        .await?
+        .map_err(invitation_error_message)?;
+    Ok(true)
+}
+
+fn invitation_error_message(e: InvitationError) -> String {
+    match e {
+        InvitationError::Invalid => "Invalid or expired token".to_string(),
+        InvitationError::AlreadyActive => "User account is already active".to_string(),
+        InvitationError::Policy(e) => policy_error_message(e),
+    }
+}
+
+async fn set_password_with_token(
+    context: &Context,
+    token: &str,
+    purpose: Purpose,
+    new_password: &str,
+) -> FieldResult<bool> {
+    // Check the password first so a weak password does not use up the token.
+    context
+        .auth_provider
+        .password_policy
+        .check(new_password)
+        .map_err(policy_error_message)?;
+    let user_account_id = context
+        .auth_provider
+        .use_one_time_token(token, purpose)
+        .await?
+        .context("Invalid or expired token")?;
+    context
+        .auth_provider
+        .set_password(&user_account_id, new_password)
+        .await?
+        .map_err(policy_error_message)?;
+    Ok(true)
+}
+
+fn policy_error_message(e: PolicyError) -> String {
+    match e {
+        PolicyError::TooShort(min_length) => {
+            format!("Password must be at least {} characters", min_length)
+        }
+        PolicyError::Common => "Password is too common".to_string(),
+        PolicyError::Reused => "Password was used recently".to_string(),
+    }
+}
+
 pub async fn set_profile_image(
     context: &Context,
     user_account_id: ID,
     image_base64: String,
 ) -> FieldResult<String> {
+    verify_tenant(context, &user_account_id).await?;
     let image_bytes = BASE64.decode(image_base64.as_bytes())?;
     let image = image::load_from_memory(&image_bytes)?;
     let png_bytes = image.png_bytes()?;
@@ -184,3 +1174,124 @@ pub async fn set_profile_image(
         .await?;
     Ok(format!("/v1/userAccount/{}/profile.png", user_account_id))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Claims;
+    use crate::graphql::{schema, test_context};
+    use juniper::{Value, Variables};
+
+    const CONTACT_QUERY: &str = r#"{ userAccount(id: "user") { email phone } }"#;
+
+    #[test]
+    fn test_verify_access_super_admin_target() {
+        // Arrange.
+        let company = Tenant::Company("company".to_string());
+
+        // Act.
+        let from_company = verify_access(&company, user_account::Access::SuperAdmin);
+        let from_all = verify_access(&Tenant::All, user_account::Access::SuperAdmin);
+        let admin_from_company = verify_access(&company, user_account::Access::Admin);
+
+        // Assert.
+        assert!(from_company.is_err());
+        assert!(from_all.is_ok());
+        assert!(admin_from_company.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_contact_redacted() {
+        // Arrange.
+        let claims = claims(
+            "other",
+            user_account::Access::View,
+            user_account::Role::Auditor,
+        );
+        let context = context(Some(claims)).await;
+
+        // Act.
+        let (res, errors) =
+            juniper::execute(CONTACT_QUERY, None, &schema(), &Variables::new(), &context)
+                .await
+                .unwrap();
+
+        // Assert.
+        assert_eq!(
+            res,
+            graphql_value!({ "userAccount": { "email": None, "phone": None } })
+        );
+        assert_eq!(errors.len(), 2);
+        for error in errors {
+            assert_eq!(error.error().message(), "Field is redacted");
+            let extensions = error.error().extensions();
+            assert_eq!(
+                extensions
+                    .as_object_value()
+                    .unwrap()
+                    .get_field_value("code"),
+                Some(&Value::scalar("REDACTED"))
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_contact_visible_to_company_admin() {
+        // Arrange.
+        let claims = claims(
+            "admin",
+            user_account::Access::Admin,
+            user_account::Role::Worker,
+        );
+        let context = context(Some(claims)).await;
+
+        // Act.
+        let (res, errors) =
+            juniper::execute(CONTACT_QUERY, None, &schema(), &Variables::new(), &context)
+                .await
+                .unwrap();
+
+        // Assert.
+        assert!(errors.is_empty());
+        assert_eq!(
+            res,
+            graphql_value!({ "userAccount": { "email": "user@example.com", "phone": "" } })
+        );
+    }
+
+    async fn context(claims: Option<Claims>) -> Context {
+        let user_account = user_account::UserAccount {
+            id: "user".to_string(),
+            name: "User".to_string(),
+            access: user_account::Access::View,
+            title: "".to_string(),
+            email: "user@example.com".to_string(),
+            email_verified: false,
+            phone: "".to_string(),
+            phone_verified: false,
+            company_id: "company".to_string(),
+            roles: Vec::new(),
+            person_id: None,
+            active: true,
+            suspended_until: None,
+        };
+        test_context(vec![user_account], claims).await
+    }
+
+    fn claims(sub: &str, access: user_account::Access, role: user_account::Role) -> Claims {
+        Claims {
+            sub: sub.to_string(),
+            access,
+            company_id: "company".to_string(),
+            roles: vec![user_account::RoleGrant {
+                role,
+                team_id: None,
+            }],
+            person_id: None,
+            act: None,
+            iat: 0,
+            exp: 0,
+            jti: "jti".to_string(),
+        }
+    }
+}
@@ -0,0 +1,402 @@
+use crate::repo::user_account::Creds;
+use anyhow::Context;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, SaltString};
+use argon2::{Argon2, Params, Version};
+use data_encoding::{BASE64_NOPAD, HEXLOWER_PERMISSIVE};
+use ring::digest::SHA512_OUTPUT_LEN;
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::convert::TryFrom;
+use std::num::NonZeroU32;
+use std::sync::Arc;
+
+const ARGON2ID: &str = "argon2id";
+const PBKDF2_SHA512: &str = "pbkdf2-sha512";
+const PBKDF2_SALT_LEN: usize = 16;
+/// Iterations of credentials stored before hashes were PHC strings.
+const LEGACY_PBKDF2_ITERATIONS: u32 = 100_000;
+
+#[derive(thiserror::Error, Debug)]
+pub enum VerifyError {
+    #[error("incorrect password")]
+    IncorrectPassword,
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+/// A password hashing algorithm. Hashes are PHC strings, such as
+/// `$argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>`, so they carry the algorithm and the parameters
+/// they were made with.
+pub trait PasswordHasher {
+    /// PHC identifier of the algorithm.
+    fn algorithm(&self) -> &'static str;
+    fn hash(&self, password: &str) -> anyhow::Result<String>;
+    fn verify(&self, password: &str, hash: &str) -> anyhow::Result<bool>;
+    /// Whether a hash was made with weaker parameters than this hasher uses.
+    fn is_outdated(&self, hash: &str) -> anyhow::Result<bool>;
+}
+
+pub type DynPasswordHasher = dyn PasswordHasher + Send + Sync + 'static;
+
+pub type ArcPasswordHasher = Arc<DynPasswordHasher>;
+
+#[derive(Default, Debug, Clone)]
+pub struct Argon2idHasher {
+    pub params: Params,
+}
+
+impl Argon2idHasher {
+    pub fn new(memory_kib: u32, iterations: u32, parallelism: u32) -> anyhow::Result<Self> {
+        Ok(Self {
+            params: Params::new(memory_kib, iterations, parallelism, None)?,
+        })
+    }
+
+    fn argon2(&self) -> Argon2<'static> {
+        Argon2::new(
+            argon2::Algorithm::Argon2id,
+            Version::V0x13,
+            self.params.clone(),
+        )
+    }
+}
+
+impl PasswordHasher for Argon2idHasher {
+    fn algorithm(&self) -> &'static str {
+        ARGON2ID
+    }
+
+    fn hash(&self, password: &str) -> anyhow::Result<String> {
+        let salt = SaltString::generate(&mut OsRng);
+        let hash =
+            argon2::PasswordHasher::hash_password(&self.argon2(), password.as_bytes(), &salt)?;
+        Ok(hash.to_string())
+    }
+
+    fn verify(&self, password: &str, hash: &str) -> anyhow::Result<bool> {
+        let hash = PasswordHash::new(hash)?;
+        // Parameters are read from the hash, so older hashes still verify.
+        match argon2::PasswordVerifier::verify_password(&self.argon2(), password.as_bytes(), &hash)
+        {
+            Ok(()) => Ok(true),
+            Err(argon2::password_hash::Error::Password) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn is_outdated(&self, hash: &str) -> anyhow::Result<bool> {
+        let hash = PasswordHash::new(hash)?;
+        let params = Params::try_from(&hash)?;
+        Ok(hash.version != Some(Version::V0x13.into())
+            || params.m_cost() < self.params.m_cost()
+            || params.t_cost() < self.params.t_cost()
+            || params.p_cost() < self.params.p_cost())
+    }
+}
+
+/// PBKDF2 with HMAC-SHA512. Kept to verify credentials created before Argon2id was adopted.
+#[derive(Debug, Clone)]
+pub struct Pbkdf2Hasher {
+    pub iterations: u32,
+}
+
+impl Default for Pbkdf2Hasher {
+    fn default() -> Self {
+        Self {
+            iterations: LEGACY_PBKDF2_ITERATIONS,
+        }
+    }
+}
+
+impl Pbkdf2Hasher {
+    /// Encode a PBKDF2 hash as a PHC string.
+    fn encode(iterations: u32, salt: &[u8], hash: &[u8]) -> String {
+        format!(
+            "${}$i={}${}${}",
+            PBKDF2_SHA512,
+            iterations,
+            BASE64_NOPAD.encode(salt),
+            BASE64_NOPAD.encode(hash)
+        )
+    }
+
+    /// Decode a PHC string into iterations, salt and hash.
+    fn decode(hash: &str) -> anyhow::Result<(u32, Vec<u8>, Vec<u8>)> {
+        let parts: Vec<_> = hash.split('$').collect();
+        match parts[..] {
+            ["", PBKDF2_SHA512, params, salt, hash] => {
+                let iterations = params
+                    .strip_prefix("i=")
+                    .context("Missing PBKDF2 iterations")?
+                    .parse()?;
+                let salt = BASE64_NOPAD.decode(salt.as_bytes())?;
+                let hash = BASE64_NOPAD.decode(hash.as_bytes())?;
+                Ok((iterations, salt, hash))
+            }
+            _ => anyhow::bail!("Invalid PBKDF2 hash"),
+        }
+    }
+
+    fn iterations(iterations: u32) -> anyhow::Result<NonZeroU32> {
+        NonZeroU32::new(iterations).context("PBKDF2 iterations must be positive")
+    }
+}
+
+impl PasswordHasher for Pbkdf2Hasher {
+    fn algorithm(&self) -> &'static str {
+        PBKDF2_SHA512
+    }
+
+    fn hash(&self, password: &str) -> anyhow::Result<String> {
+        let rng = SystemRandom::new();
+        let mut salt = [0u8; PBKDF2_SALT_LEN];
+        rng.fill(&mut salt)
+            .map_err(|_| anyhow::anyhow!("Failed to generate salt"))?;
+        let mut hash = [0u8; SHA512_OUTPUT_LEN];
+        pbkdf2::derive(
+            pbkdf2::PBKDF2_HMAC_SHA512,
+            Self::iterations(self.iterations)?,
+            &salt,
+            password.as_bytes(),
+            &mut hash,
+        );
+        Ok(Self::encode(self.iterations, &salt, &hash))
+    }
+
+    fn verify(&self, password: &str, hash: &str) -> anyhow::Result<bool> {
+        let (iterations, salt, hash) = Self::decode(hash)?;
+        Ok(pbkdf2::verify(
+            pbkdf2::PBKDF2_HMAC_SHA512,
+            Self::iterations(iterations)?,
+            &salt,
+            password.as_bytes(),
+            &hash,
+        )
+        .is_ok())
+    }
+
+    fn is_outdated(&self, hash: &str) -> anyhow::Result<bool> {
+        let (iterations, _, _) = Self::decode(hash)?;
+        Ok(iterations < self.iterations)
+    }
+}
+
+/// Hashes new passwords with the current hasher and verifies existing hashes with whichever hasher
+/// made them.
+#[derive(Clone)]
+pub struct Hashers {
+    pub current: ArcPasswordHasher,
+    pub legacy: Vec<ArcPasswordHasher>,
+    /// Credentials checked in place of those of a user account that does not exist.
+    pub dummy_creds: Creds,
+}
+
+impl Hashers {
+    pub fn new(current: ArcPasswordHasher, legacy: Vec<ArcPasswordHasher>) -> anyhow::Result<Self> {
+        let dummy_creds = Creds {
+            password_hash: current.hash("")?,
+            salt: None,
+        };
+        Ok(Self {
+            current,
+            legacy,
+            dummy_creds,
+        })
+    }
+
+    pub fn create_creds(&self, password: &str) -> anyhow::Result<Creds> {
+        Ok(Creds {
+            password_hash: self.current.hash(password)?,
+            salt: None,
+        })
+    }
+
+    pub fn verify_password(&self, password: &str, creds: &Creds) -> Result<(), VerifyError> {
+        let hash = phc_string(creds)?;
+        if self.hasher(&hash)?.verify(password, &hash)? {
+            Ok(())
+        } else {
+            Err(VerifyError::IncorrectPassword)
+        }
+    }
+
+    /// Run `create_creds` on a thread for blocking work, since hashing is slow on purpose and would
+    /// hold up other requests.
+    pub async fn spawn_create_creds(&self, password: &str) -> anyhow::Result<Creds> {
+        let (hashers, password) = (self.clone(), password.to_string());
+        tokio::task::spawn_blocking(move || hashers.create_creds(&password)).await?
+    }
+
+    /// Run `verify_password` on a thread for blocking work.
+    pub async fn spawn_verify_password(
+        &self,
+        password: &str,
+        creds: Creds,
+    ) -> Result<(), VerifyError> {
+        let (hashers, password) = (self.clone(), password.to_string());
+        tokio::task::spawn_blocking(move || hashers.verify_password(&password, &creds))
+            .await
+            .map_err(anyhow::Error::from)?
+    }
+
+    /// Whether the password matches any of the credentials, checked on a thread for blocking work.
+    pub async fn spawn_verify_any(
+        &self,
+        password: &str,
+        history: Vec<Creds>,
+    ) -> anyhow::Result<bool> {
+        let (hashers, password) = (self.clone(), password.to_string());
+        Ok(tokio::task::spawn_blocking(move || {
+            history
+                .iter()
+                .any(|creds| hashers.verify_password(&password, creds).is_ok())
+        })
+        .await?)
+    }
+
+    /// Whether credentials should be hashed again, because they were made by a legacy hasher or
+    /// with weaker parameters than the current hasher uses.
+    pub fn needs_rehash(&self, creds: &Creds) -> anyhow::Result<bool> {
+        let hash = phc_string(creds)?;
+        if algorithm(&hash) != Some(self.current.algorithm()) {
+            return Ok(true);
+        }
+        self.current.is_outdated(&hash)
+    }
+
+    fn hasher(&self, hash: &str) -> anyhow::Result<&ArcPasswordHasher> {
+        let algorithm = algorithm(hash).context("Invalid password hash")?;
+        std::iter::once(&self.current)
+            .chain(&self.legacy)
+            .find(|hasher| hasher.algorithm() == algorithm)
+            .with_context(|| format!("Unsupported password hash algorithm: {}", algorithm))
+    }
+}
+
+impl Default for Hashers {
+    fn default() -> Self {
+        Self::new(
+            Arc::new(Argon2idHasher::default()),
+            vec![Arc::new(Pbkdf2Hasher::default())],
+        )
+        .unwrap()
+    }
+}
+
+fn algorithm(hash: &str) -> Option<&str> {
+    hash.split('$').nth(1)
+}
+
+/// Credentials stored before hashes were PHC strings have a hex PBKDF2 hash and a separate salt.
+fn phc_string(creds: &Creds) -> anyhow::Result<String> {
+    match &creds.salt {
+        None => Ok(creds.password_hash.clone()),
+        Some(salt) => {
+            let salt = HEXLOWER_PERMISSIVE.decode(salt.as_bytes())?;
+            let hash = HEXLOWER_PERMISSIVE.decode(creds.password_hash.as_bytes())?;
+            Ok(Pbkdf2Hasher::encode(LEGACY_PBKDF2_ITERATIONS, &salt, &hash))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_correct() {
+        // Arrange.
+        let hashers = Hashers::default();
+        let password = "rightpass";
+        let creds = hashers.create_creds(password).unwrap();
+
+        // Act.
+        let res = hashers.verify_password(password, &creds);
+
+        // Assert.
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_spawn_verify_any() {
+        // Arrange.
+        let hashers = Hashers::default();
+        let history = vec![
+            hashers.spawn_create_creds("newpass").await.unwrap(),
+            hashers.spawn_create_creds("oldpass").await.unwrap(),
+        ];
+
+        // Act.
+        let reused = hashers.spawn_verify_any("oldpass", history.clone()).await;
+        let fresh = hashers.spawn_verify_any("otherpass", history).await;
+
+        // Assert.
+        assert!(reused.unwrap());
+        assert!(!fresh.unwrap());
+    }
+
+    #[test]
+    fn test_verify_incorrect() {
+        // Arrange.
+        let hashers = Hashers::default();
+        let password = "rightpass";
+        let creds = hashers.create_creds(password).unwrap();
+
+        // Act.
+        let res = hashers.verify_password("wrongpass", &creds);
+
+        // Assert.
+        assert!(matches!(res, Err(VerifyError::IncorrectPassword)));
+    }
+
+    #[test]
+    fn test_verify_legacy() {
+        // Arrange.
+        let hashers = Hashers::default();
+        let legacy = Pbkdf2Hasher::default().hash("rightpass").unwrap();
+        let (_, salt, hash) = Pbkdf2Hasher::decode(&legacy).unwrap();
+        let creds = Creds {
+            password_hash: HEXLOWER_PERMISSIVE.encode(&hash),
+            salt: Some(HEXLOWER_PERMISSIVE.encode(&salt)),
+        };
+
+        // Act.
+        let res = hashers.verify_password("rightpass", &creds);
+
+        // Assert.
+        assert!(res.is_ok());
+        assert!(hashers.needs_rehash(&creds).unwrap());
+    }
+
+    #[test]
+    fn test_needs_rehash_current() {
+        // Arrange.
+        let hashers = Hashers::default();
+        let creds = hashers.create_creds("rightpass").unwrap();
+
+        // Act.
+        let res = hashers.needs_rehash(&creds);
+
+        // Assert.
+        assert!(!res.unwrap());
+    }
+
+    #[test]
+    fn test_needs_rehash_weaker_params() {
+        // Arrange.
+        let weak = Argon2idHasher::new(Params::MIN_M_COST, 1, 1).unwrap();
+        let creds = Creds {
+            password_hash: weak.hash("rightpass").unwrap(),
+            salt: None,
+        };
+        let hashers = Hashers::default();
+
+        // Act.
+        let res = hashers.needs_rehash(&creds);
+
+        // Assert.
+        assert!(res.unwrap());
+        assert!(hashers.verify_password("rightpass", &creds).is_ok());
+    }
+}
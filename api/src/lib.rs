@@ -1,9 +1,20 @@
 pub mod auth;
+pub mod contact;
 pub mod crockford;
 pub mod db;
+pub mod event;
 pub mod graphql;
+pub mod hashing;
 pub mod image;
+pub mod jwt_keys;
+pub mod mail;
+pub mod oidc;
+pub mod password_policy;
+pub mod permission;
 pub mod repo;
 pub mod rest;
 pub mod settings;
+pub mod sms;
+pub mod throttle;
+pub mod totp;
 pub mod warp_ext;
@@ -0,0 +1,112 @@
+use crate::crockford;
+use chrono::Utc;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[async_trait::async_trait]
+pub trait Mailer {
+    async fn send(&self, email: Email) -> anyhow::Result<()>;
+}
+
+pub type DynMailer = dyn Mailer + Send + Sync + 'static;
+
+pub type ArcMailer = Arc<DynMailer>;
+
+#[derive(Debug, Clone)]
+pub struct SmtpSettings {
+    pub host: String,
+    pub port: Option<u16>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+/// Sends email through an SMTP relay over TLS.
+#[derive(Clone)]
+pub struct SmtpMailer {
+    pub transport: AsyncSmtpTransport<Tokio1Executor>,
+    pub from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn new(settings: SmtpSettings, from: &str) -> anyhow::Result<Self> {
+        let mut builder = AsyncSmtpTransport::<Tokio1Executor>::relay(&settings.host)?;
+        if let Some(port) = settings.port {
+            builder = builder.port(port);
+        }
+        if let (Some(username), Some(password)) = (settings.username, settings.password) {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+        Ok(Self {
+            transport: builder.build(),
+            from: from.parse()?,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, email: Email) -> anyhow::Result<()> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(email.to.parse()?)
+            .subject(email.subject)
+            .body(email.body)?;
+        self.transport.send(message).await?;
+        Ok(())
+    }
+}
+
+/// Writes each email to a file in a directory instead of sending it. Intended for local
+/// development and testing.
+#[derive(Debug, Clone)]
+pub struct FileMailer {
+    pub dir: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+#[async_trait::async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, email: Email) -> anyhow::Result<()> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let file_name = format!(
+            "{}-{}.txt",
+            Utc::now().format("%Y%m%dT%H%M%S"),
+            crockford::random_id()
+        );
+        let path = self.dir.join(file_name);
+        let contents = format!(
+            "To: {}\nSubject: {}\n\n{}\n",
+            email.to, email.subject, email.body
+        );
+        tokio::fs::write(&path, contents).await?;
+        log::info!("Wrote email to {} at {}", email.to, path.display());
+        Ok(())
+    }
+}
+
+impl From<SmtpMailer> for ArcMailer {
+    fn from(value: SmtpMailer) -> Self {
+        Arc::new(value)
+    }
+}
+
+impl From<FileMailer> for ArcMailer {
+    fn from(value: FileMailer) -> Self {
+        Arc::new(value)
+    }
+}
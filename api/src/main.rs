@@ -1,27 +1,57 @@
 pub mod auth;
+pub mod contact;
 pub mod crockford;
 pub mod db;
+pub mod event;
 pub mod graphql;
+pub mod hashing;
 pub mod image;
+pub mod jwt_keys;
+pub mod mail;
+pub mod oidc;
+pub mod password_policy;
+pub mod permission;
 pub mod repo;
 pub mod rest;
 pub mod settings;
+pub mod sms;
+pub mod throttle;
+pub mod totp;
 pub mod warp_ext;
 
 use crate::auth::{AuthProvider, ClaimsProvider};
+use crate::event::EventBus;
+use crate::graphql::limits::QueryLimits;
+use crate::hashing::{Argon2idHasher, Hashers, Pbkdf2Hasher};
+use crate::jwt_keys::JwtKeySet;
+use crate::mail::{ArcMailer, FileMailer, SmtpMailer, SmtpSettings};
+use crate::oidc::{OidcClient, SsoProvider};
+use crate::password_policy::PasswordPolicy;
+use crate::repo::api_key::MongoApiKeyRepo;
 use crate::repo::company::MongoCompanyRepo;
 use crate::repo::device::MongoDeviceRepo;
 use crate::repo::gas_reading::MongoGasReadingRepo;
+use crate::repo::impersonation::MongoImpersonationRepo;
 use crate::repo::incident::MongoIncidentRepo;
 use crate::repo::incident_stats::MongoIncidentStatsRepo;
 use crate::repo::location_reading::MongoLocationReadingRepo;
+use crate::repo::login_attempt::MongoLoginAttemptRepo;
+use crate::repo::oidc_login::MongoOidcLoginRepo;
+use crate::repo::one_time_token::MongoOneTimeTokenRepo;
 use crate::repo::person::MongoPersonRepo;
+use crate::repo::refresh_token::MongoRefreshTokenRepo;
+use crate::repo::session::MongoSessionRepo;
 use crate::repo::team::MongoTeamRepo;
 use crate::repo::user_account::MongoUserAccountRepo;
 use crate::settings::Settings;
+use crate::sms::{ArcSmsSender, FileSmsSender, TwilioSmsSender};
+use crate::throttle::LoginThrottle;
+use anyhow::Context;
+use chrono::Duration;
 use mongodb::Database;
 use std::env;
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
 use warp::cors::Cors;
 use warp::filters::BoxedFilter;
 use warp::{Filter, Reply};
@@ -33,39 +63,191 @@ async fn main() -> anyhow::Result<()> {
     env_logger::init();
     let settings = Settings::read();
     let db = db::connect_and_prepare(&settings.db_uri).await?;
-    let graphql_deps = graphql_deps(db.clone(), &settings.private_key);
-    let rest_context = rest_context(db.clone());
+    let mailer = mailer(&settings)?;
+    let sms_sender = sms_sender(&settings)?;
+    let jwt_key_set = jwt_key_set(&settings)?;
+    let auth_provider = auth_provider(db.clone(), &settings)?;
+    let claims_provider = ClaimsProvider {
+        jwt_key_set,
+        access_token_ttl: Duration::minutes(settings.access_token_ttl_minutes),
+        impersonation_ttl: Duration::minutes(settings.impersonation_ttl_minutes),
+    };
+    let graphql_deps = graphql_deps(
+        db.clone(),
+        &settings,
+        EventBus::new(),
+        mailer,
+        sms_sender,
+        auth_provider.clone(),
+        claims_provider.clone(),
+    )?;
+    let rest_context = rest_context(db.clone(), &settings, auth_provider, claims_provider)?;
     let route = filter(graphql_deps, rest_context).with(log()).with(cors());
     let port = get_port();
     warp::serve(route).run((Ipv4Addr::UNSPECIFIED, port)).await;
     Ok(())
 }
 
-fn graphql_deps(db: Database, private_key: &str) -> graphql::Deps {
-    graphql::Deps {
+fn graphql_deps(
+    db: Database,
+    settings: &Settings,
+    events: EventBus,
+    mailer: ArcMailer,
+    sms_sender: ArcSmsSender,
+    auth_provider: AuthProvider,
+    claims_provider: ClaimsProvider,
+) -> anyhow::Result<graphql::Deps> {
+    Ok(graphql::Deps {
         company_repo: MongoCompanyRepo::new(db.clone()).into(),
         device_repo: MongoDeviceRepo::new(db.clone()).into(),
-        gas_reading_repo: MongoGasReadingRepo::new(db.clone()).into(),
-        incident_repo: MongoIncidentRepo::new(db.clone()).into(),
+        gas_reading_repo: MongoGasReadingRepo::new(db.clone(), events.clone()).into(),
+        impersonation_repo: MongoImpersonationRepo::new(db.clone()).into(),
+        incident_repo: MongoIncidentRepo::new(db.clone(), events.clone()).into(),
         incident_stats_repo: MongoIncidentStatsRepo::new(db.clone()).into(),
-        location_reading_repo: MongoLocationReadingRepo::new(db.clone()).into(),
+        location_reading_repo: MongoLocationReadingRepo::new(db.clone(), events.clone()).into(),
         person_repo: MongoPersonRepo::new(db.clone()).into(),
+        session_repo: MongoSessionRepo::new(db.clone()).into(),
         team_repo: MongoTeamRepo::new(db.clone()).into(),
         user_account_repo: MongoUserAccountRepo::new(db.clone()).into(),
-        auth_provider: AuthProvider {
-            user_account_repo: MongoUserAccountRepo::new(db).into(),
+        auth_provider,
+        claims_provider,
+        events,
+        mailer,
+        sms_sender,
+        app_url: settings.app_url.clone(),
+        limits: QueryLimits {
+            max_depth: settings.graphql_max_depth,
+            max_cost: settings.graphql_max_cost,
+            max_list_items: settings.graphql_max_list_items,
         },
-        claims_provider: ClaimsProvider {
-            private_key: private_key.to_string(),
+        trusted_proxies: trusted_proxies(settings)?,
+    })
+}
+
+fn auth_provider(db: Database, settings: &Settings) -> anyhow::Result<AuthProvider> {
+    let argon2_settings =
+        "Invalid SW_ARGON2_MEMORY_KIB, SW_ARGON2_ITERATIONS or SW_ARGON2_PARALLELISM";
+    let hashers = Hashers::new(
+        Arc::new(
+            Argon2idHasher::new(
+                settings.argon2_memory_kib,
+                settings.argon2_iterations,
+                settings.argon2_parallelism,
+            )
+            .context(argon2_settings)?,
+        ),
+        vec![Arc::new(Pbkdf2Hasher::default())],
+    )
+    .context(argon2_settings)?;
+    Ok(AuthProvider {
+        user_account_repo: MongoUserAccountRepo::new(db.clone()).into(),
+        company_repo: MongoCompanyRepo::new(db.clone()).into(),
+        api_key_repo: MongoApiKeyRepo::new(db.clone()).into(),
+        refresh_token_repo: MongoRefreshTokenRepo::new(db.clone()).into(),
+        session_repo: MongoSessionRepo::new(db.clone()).into(),
+        one_time_token_repo: MongoOneTimeTokenRepo::new(db.clone()).into(),
+        refresh_token_ttl: Duration::days(settings.refresh_token_ttl_days),
+        password_reset_ttl: Duration::minutes(settings.password_reset_ttl_minutes),
+        invitation_ttl: Duration::hours(settings.invitation_ttl_hours),
+        login_link_ttl: Duration::minutes(settings.login_link_ttl_minutes),
+        password_policy: PasswordPolicy::new(
+            settings.password_min_length,
+            settings.password_history_len,
+        ),
+        hashers,
+        login_throttle: LoginThrottle {
+            login_attempt_repo: MongoLoginAttemptRepo::new(db).into(),
+            free_attempts: settings.login_free_attempts,
+            base_delay: Duration::seconds(1),
+            max_delay: Duration::minutes(settings.login_lockout_minutes),
+        },
+    })
+}
+
+fn mailer(settings: &Settings) -> anyhow::Result<ArcMailer> {
+    // Without SMTP, emails are written to files for local development.
+    Ok(match &settings.smtp_host {
+        None if !settings.dev_mode => {
+            anyhow::bail!("SW_SMTP_HOST is required unless SW_DEV_MODE is set")
+        }
+        Some(host) => SmtpMailer::new(
+            SmtpSettings {
+                host: host.clone(),
+                port: settings.smtp_port,
+                username: settings.smtp_username.clone(),
+                password: settings.smtp_password.clone(),
+            },
+            &settings.mail_from,
+        )?
+        .into(),
+        None => FileMailer::new(&settings.mail_dir).into(),
+    })
+}
+
+fn sms_sender(settings: &Settings) -> anyhow::Result<ArcSmsSender> {
+    // Without Twilio, text messages are written to files for local development.
+    Ok(
+        match (
+            &settings.twilio_account_sid,
+            &settings.twilio_auth_token,
+            &settings.sms_from,
+        ) {
+            (Some(account_sid), Some(auth_token), Some(from)) => {
+                TwilioSmsSender::new(account_sid.clone(), auth_token.clone(), from.clone()).into()
+            }
+            _ if !settings.dev_mode => anyhow::bail!(
+                "SW_TWILIO_ACCOUNT_SID, SW_TWILIO_AUTH_TOKEN and SW_SMS_FROM are required unless \
+             SW_DEV_MODE is set"
+            ),
+            _ => FileSmsSender::new(&settings.sms_dir).into(),
         },
+    )
+}
+
+fn trusted_proxies(settings: &Settings) -> anyhow::Result<Vec<IpAddr>> {
+    match &settings.trusted_proxies {
+        Some(ips) => ips
+            .split(',')
+            .map(|ip| {
+                ip.trim()
+                    .parse()
+                    .with_context(|| format!("Invalid trusted proxy IP address: {}", ip))
+            })
+            .collect(),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn jwt_key_set(settings: &Settings) -> anyhow::Result<JwtKeySet> {
+    match &settings.jwt_keys {
+        Some(json) => JwtKeySet::from_json(json),
+        None if settings.dev_mode => {
+            log::warn!("No JWT keys configured, generating a key that lasts until restart");
+            JwtKeySet::ephemeral()
+        }
+        None => anyhow::bail!("SW_JWT_KEYS is required unless SW_DEV_MODE is set"),
     }
 }
 
-fn rest_context(db: Database) -> rest::Context {
-    rest::Context {
+fn rest_context(
+    db: Database,
+    settings: &Settings,
+    auth_provider: AuthProvider,
+    claims_provider: ClaimsProvider,
+) -> anyhow::Result<rest::Context> {
+    Ok(rest::Context {
         user_account_repo: MongoUserAccountRepo::new(db.clone()).into(),
+        sso_provider: SsoProvider {
+            oidc_client: OidcClient::new(format!("{}/v1/oidc/callback", settings.api_url))?,
+            company_repo: MongoCompanyRepo::new(db.clone()).into(),
+            oidc_login_repo: MongoOidcLoginRepo::new(db.clone()).into(),
+            user_account_repo: MongoUserAccountRepo::new(db.clone()).into(),
+        },
+        auth_provider,
+        claims_provider,
+        app_url: settings.app_url.clone(),
         db,
-    }
+    })
 }
 
 fn filter(graphql_deps: graphql::Deps, rest_context: rest::Context) -> BoxedFilter<(impl Reply,)> {
@@ -99,6 +281,7 @@ fn cors() -> Cors {
             "User-Agent",
             "Sec-Fetch-Mode",
             "Authorization",
+            "X-Api-Key",
         ])
         .allow_methods(vec!["POST", "GET"])
         .build()
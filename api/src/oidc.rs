@@ -0,0 +1,549 @@
+use crate::auth::{hash_token, random_token};
+use crate::repo::company::{ArcCompanyRepo, OidcConfig};
+use crate::repo::oidc_login::{ArcOidcLoginRepo, OidcLogin};
+use crate::repo::user_account::{ArcUserAccountRepo, UserAccount};
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use data_encoding::BASE64URL_NOPAD;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use ring::digest;
+use serde::Deserialize;
+
+/// How long a user has to log in at the identity provider.
+pub const LOGIN_TTL_MINUTES: i64 = 10;
+const HTTP_TIMEOUT_SECONDS: u64 = 10;
+/// Only asymmetric algorithms, so a published key can never be used as an HMAC secret.
+const ID_TOKEN_ALGORITHMS: [Algorithm; 7] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+#[derive(thiserror::Error, Debug)]
+pub enum SsoError {
+    #[error("single sign-on is not configured")]
+    NotConfigured,
+    #[error("login expired")]
+    Expired,
+    #[error("no user account has the email")]
+    UnknownUser,
+    #[error("email is not verified")]
+    EmailNotVerified,
+    #[error("user account is deactivated or suspended")]
+    Inactive,
+    #[error("user account must log in with a second factor")]
+    SecondFactorRequired,
+}
+
+impl SsoError {
+    /// Error code passed to the frontend.
+    pub fn code(&self) -> &'static str {
+        match self {
+            SsoError::NotConfigured => "not_configured",
+            SsoError::Expired => "expired",
+            SsoError::UnknownUser => "unknown_user",
+            SsoError::EmailNotVerified => "email_not_verified",
+            SsoError::Inactive => "inactive",
+            SsoError::SecondFactorRequired => "second_factor_required",
+        }
+    }
+}
+
+/// Endpoints of an identity provider, from its discovery document.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Discovery {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+/// Claims of a verified ID token that identify the user.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Identity {
+    pub email: String,
+    /// Identity providers that do not verify emails may leave this out, in which case the email
+    /// is not trusted.
+    pub email_verified: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Deserialize)]
+struct IdTokenClaims {
+    email: Option<String>,
+    email_verified: Option<bool>,
+    nonce: Option<String>,
+}
+
+/// OpenID Connect relying party using the authorization code flow with PKCE.
+#[derive(Clone)]
+pub struct OidcClient {
+    pub http: reqwest::Client,
+    /// Callback the identity provider redirects to, which must be registered with it.
+    pub redirect_uri: String,
+}
+
+impl OidcClient {
+    pub fn new(redirect_uri: String) -> anyhow::Result<Self> {
+        Ok(Self {
+            http: reqwest::Client::builder()
+                .timeout(std::time::Duration::from_secs(HTTP_TIMEOUT_SECONDS))
+                .build()?,
+            redirect_uri,
+        })
+    }
+
+    pub async fn discover(&self, issuer: &str) -> anyhow::Result<Discovery> {
+        let url = format!("{}/.well-known/openid-configuration", issuer);
+        let discovery: Discovery = self
+            .http
+            .get(&url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .context("Invalid discovery document")?;
+        anyhow::ensure!(
+            discovery.issuer == issuer,
+            "Discovery document is for issuer {}",
+            discovery.issuer
+        );
+        Ok(discovery)
+    }
+
+    pub fn authorization_url(
+        &self,
+        discovery: &Discovery,
+        config: &OidcConfig,
+        state: &str,
+        nonce: &str,
+        code_verifier: &str,
+    ) -> anyhow::Result<String> {
+        let url = reqwest::Url::parse_with_params(
+            &discovery.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("scope", "openid email"),
+                ("client_id", &config.client_id),
+                ("redirect_uri", &self.redirect_uri),
+                ("state", state),
+                ("nonce", nonce),
+                ("code_challenge", &code_challenge(code_verifier)),
+                ("code_challenge_method", "S256"),
+            ],
+        )?;
+        Ok(url.into())
+    }
+
+    /// Exchange an authorization code for an ID token.
+    pub async fn exchange_code(
+        &self,
+        discovery: &Discovery,
+        config: &OidcConfig,
+        code: &str,
+        code_verifier: &str,
+    ) -> anyhow::Result<String> {
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.redirect_uri),
+            ("client_id", &config.client_id),
+            ("code_verifier", code_verifier),
+        ];
+        if let Some(client_secret) = &config.client_secret {
+            form.push(("client_secret", client_secret));
+        }
+        let res: TokenResponse = self
+            .http
+            .post(&discovery.token_endpoint)
+            .form(&form)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .context("Invalid token response")?;
+        Ok(res.id_token)
+    }
+
+    /// Check the signature, issuer, audience, expiry and nonce of an ID token.
+    pub async fn verify_id_token(
+        &self,
+        discovery: &Discovery,
+        config: &OidcConfig,
+        id_token: &str,
+        nonce: &str,
+    ) -> anyhow::Result<Identity> {
+        let header = jsonwebtoken::decode_header(id_token)?;
+        anyhow::ensure!(
+            ID_TOKEN_ALGORITHMS.contains(&header.alg),
+            "Unsupported ID token algorithm {:?}",
+            header.alg
+        );
+        let jwks: JwkSet = self
+            .http
+            .get(&discovery.jwks_uri)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .context("Invalid JWKS")?;
+        let jwk = match &header.kid {
+            Some(kid) => jwks.find(kid),
+            None if jwks.keys.len() == 1 => jwks.keys.first(),
+            None => None,
+        }
+        .context("Unknown ID token key")?;
+        if let Some(alg) = jwk.common.algorithm {
+            anyhow::ensure!(alg == header.alg, "ID token algorithm does not match key");
+        }
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&discovery.issuer]);
+        validation.set_audience(&[&config.client_id]);
+        let claims: IdTokenClaims =
+            jsonwebtoken::decode(id_token, &DecodingKey::from_jwk(jwk)?, &validation)?.claims;
+        anyhow::ensure!(
+            claims.nonce.as_deref() == Some(nonce),
+            "ID token nonce does not match"
+        );
+        Ok(Identity {
+            email: claims.email.context("ID token has no email")?,
+            email_verified: claims.email_verified,
+        })
+    }
+}
+
+/// Single sign-on through the identity provider of a company.
+#[derive(Clone)]
+pub struct SsoProvider {
+    pub oidc_client: OidcClient,
+    pub company_repo: ArcCompanyRepo,
+    pub oidc_login_repo: ArcOidcLoginRepo,
+    /// Unscoped, since user accounts are looked up before their company is known.
+    pub user_account_repo: ArcUserAccountRepo,
+}
+
+impl SsoProvider {
+    /// Start logging in to a company and return the URL of its identity provider to redirect to.
+    pub async fn start_login(&self, company_id: &str) -> anyhow::Result<Result<String, SsoError>> {
+        let config = match self.config(company_id).await? {
+            Some(config) => config,
+            None => return Ok(Err(SsoError::NotConfigured)),
+        };
+        let discovery = self.oidc_client.discover(&config.issuer).await?;
+        let state = random_token();
+        let login = OidcLogin {
+            state_hash: hash_token(&state),
+            company_id: company_id.to_string(),
+            code_verifier: random_token(),
+            nonce: random_token(),
+            expires_at: Utc::now() + Duration::minutes(LOGIN_TTL_MINUTES),
+        };
+        let url = self.oidc_client.authorization_url(
+            &discovery,
+            &config,
+            &state,
+            &login.nonce,
+            &login.code_verifier,
+        )?;
+        self.oidc_login_repo.insert_one(login).await?;
+        Ok(Ok(url))
+    }
+
+    /// Finish logging in with the authorization code the identity provider redirected back with.
+    /// The email of the user at the identity provider must belong to a user account of the company.
+    pub async fn finish_login(
+        &self,
+        code: &str,
+        state: &str,
+    ) -> anyhow::Result<Result<UserAccount, SsoError>> {
+        let login = match self.oidc_login_repo.take_one(&hash_token(state)).await? {
+            Some(login) => login,
+            None => return Ok(Err(SsoError::Expired)),
+        };
+        let config = match self.config(&login.company_id).await? {
+            Some(config) => config,
+            None => return Ok(Err(SsoError::NotConfigured)),
+        };
+        let discovery = self.oidc_client.discover(&config.issuer).await?;
+        let id_token = self
+            .oidc_client
+            .exchange_code(&discovery, &config, code, &login.code_verifier)
+            .await?;
+        let identity = self
+            .oidc_client
+            .verify_id_token(&discovery, &config, &id_token, &login.nonce)
+            .await?;
+        if identity.email_verified != Some(true) {
+            return Ok(Err(SsoError::EmailNotVerified));
+        }
+        let user_account = self
+            .user_account_repo
+            .find_by_email(&identity.email)
+            .await?;
+        Ok(account_for_identity(
+            user_account,
+            &login.company_id,
+            Utc::now(),
+        ))
+    }
+
+    /// Configuration of a company, ignoring issuers that are not allowed, which may have been
+    /// stored before they were checked.
+    async fn config(&self, company_id: &str) -> anyhow::Result<Option<OidcConfig>> {
+        Ok(self
+            .company_repo
+            .find_one(company_id)
+            .await?
+            .and_then(|company| company.oidc)
+            .filter(|config| is_valid_issuer(&config.issuer)))
+    }
+}
+
+/// The user account with the email of an identity, which must belong to the company the login
+/// started for.
+fn account_for_identity(
+    user_account: Option<UserAccount>,
+    company_id: &str,
+    now: DateTime<Utc>,
+) -> Result<UserAccount, SsoError> {
+    match user_account {
+        Some(user_account) if user_account.company_id != company_id => Err(SsoError::UnknownUser),
+        Some(user_account) if !user_account.is_active(now) => Err(SsoError::Inactive),
+        Some(user_account) => Ok(user_account),
+        None => Err(SsoError::UnknownUser),
+    }
+}
+
+/// Whether an issuer may be configured. The API fetches documents from the issuer, so only HTTPS
+/// URLs are allowed, to keep it from being pointed at plain HTTP services on internal networks.
+pub fn is_valid_issuer(issuer: &str) -> bool {
+    match reqwest::Url::parse(issuer) {
+        Ok(url) => {
+            url.scheme() == "https"
+                && url.host_str().is_some()
+                && url.username().is_empty()
+                && url.password().is_none()
+                && url.query().is_none()
+                && url.fragment().is_none()
+        }
+        Err(_) => false,
+    }
+}
+
+/// PKCE code challenge of a code verifier, using the S256 method.
+pub fn code_challenge(code_verifier: &str) -> String {
+    BASE64URL_NOPAD.encode(digest::digest(&digest::SHA256, code_verifier.as_bytes()).as_ref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jwt_keys::JwtKeySet;
+    use jsonwebtoken::Header;
+    use serde_json::json;
+    use std::collections::HashMap;
+    use warp::Filter;
+
+    const CLIENT_ID: &str = "client";
+    const CODE_VERIFIER: &str = "verifier";
+    const NONCE: &str = "nonce";
+
+    #[test]
+    fn test_account_for_identity() {
+        // Arrange.
+        let now = Utc::now();
+        let mut suspended = user_account("company");
+        suspended.suspended_until = Some(now + Duration::hours(1));
+
+        // Act.
+        let found = account_for_identity(Some(user_account("company")), "company", now);
+        let other_company = account_for_identity(Some(user_account("other")), "company", now);
+        let inactive = account_for_identity(Some(suspended), "company", now);
+        let unknown = account_for_identity(None, "company", now);
+
+        // Assert.
+        assert_eq!(found.unwrap().id, "user");
+        assert!(matches!(other_company, Err(SsoError::UnknownUser)));
+        assert!(matches!(inactive, Err(SsoError::Inactive)));
+        assert!(matches!(unknown, Err(SsoError::UnknownUser)));
+    }
+
+    #[test]
+    fn test_is_valid_issuer() {
+        // Assert.
+        assert!(is_valid_issuer("https://idp.example.com"));
+        assert!(is_valid_issuer("https://idp.example.com/realms/a"));
+        assert!(!is_valid_issuer("http://idp.example.com"));
+        assert!(!is_valid_issuer("https://user@idp.example.com"));
+        assert!(!is_valid_issuer("file:///etc/passwd"));
+        assert!(!is_valid_issuer("idp.example.com"));
+    }
+
+    #[test]
+    fn test_code_challenge() {
+        // Act.
+        let challenge = code_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk");
+
+        // Assert.
+        assert_eq!(challenge, "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM");
+    }
+
+    #[tokio::test]
+    async fn test_verify_id_token_from_mock_idp() {
+        // Arrange.
+        let issuer = mock_idp();
+        let client = OidcClient::new("http://localhost/callback".to_string()).unwrap();
+        let discovery = client.discover(&issuer).await.unwrap();
+        let id_token = client
+            .exchange_code(&discovery, &config(&issuer), "code", CODE_VERIFIER)
+            .await
+            .unwrap();
+
+        // Act.
+        let identity = client
+            .verify_id_token(&discovery, &config(&issuer), &id_token, NONCE)
+            .await;
+
+        // Assert.
+        assert_eq!(
+            identity.unwrap(),
+            Identity {
+                email: "user.a@example.com".to_string(),
+                email_verified: Some(true),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verify_id_token_wrong_nonce() {
+        // Arrange.
+        let issuer = mock_idp();
+        let client = OidcClient::new("http://localhost/callback".to_string()).unwrap();
+        let discovery = client.discover(&issuer).await.unwrap();
+        let id_token = client
+            .exchange_code(&discovery, &config(&issuer), "code", CODE_VERIFIER)
+            .await
+            .unwrap();
+
+        // Act.
+        let identity = client
+            .verify_id_token(&discovery, &config(&issuer), &id_token, "other")
+            .await;
+
+        // Assert.
+        assert!(identity.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_exchange_code_wrong_verifier() {
+        // Arrange.
+        let issuer = mock_idp();
+        let client = OidcClient::new("http://localhost/callback".to_string()).unwrap();
+        let discovery = client.discover(&issuer).await.unwrap();
+
+        // Act.
+        let res = client
+            .exchange_code(&discovery, &config(&issuer), "code", "other")
+            .await;
+
+        // Assert.
+        assert!(res.is_err());
+    }
+
+    fn user_account(company_id: &str) -> UserAccount {
+        UserAccount {
+            id: "user".to_string(),
+            name: "User".to_string(),
+            access: Default::default(),
+            title: "".to_string(),
+            email: "user.a@example.com".to_string(),
+            email_verified: true,
+            phone: "".to_string(),
+            phone_verified: false,
+            company_id: company_id.to_string(),
+            roles: Vec::new(),
+            person_id: None,
+            active: true,
+            suspended_until: None,
+        }
+    }
+
+    fn config(issuer: &str) -> OidcConfig {
+        OidcConfig {
+            issuer: issuer.to_string(),
+            client_id: CLIENT_ID.to_string(),
+            client_secret: None,
+        }
+    }
+
+    /// Serve the discovery document, keys and token endpoint of an identity provider on a free
+    /// port. Returns the issuer, which the provider derives from the host it is reached at.
+    fn mock_idp() -> String {
+        let key_set = JwtKeySet::ephemeral().unwrap();
+        let (addr, server) = warp::serve(
+            warp::path!(".well-known" / "openid-configuration")
+                .and(warp::header::<String>("host"))
+                .map(|host: String| {
+                    let issuer = format!("http://{}", host);
+                    warp::reply::json(&json!({
+                        "issuer": issuer,
+                        "authorization_endpoint": format!("{}/authorize", issuer),
+                        "token_endpoint": format!("{}/token", issuer),
+                        "jwks_uri": format!("{}/jwks", issuer),
+                    }))
+                })
+                .or(warp::path("jwks").map({
+                    let key_set = key_set.clone();
+                    move || warp::reply::json(&key_set.jwks(Utc::now()))
+                }))
+                .or(warp::path("token")
+                    .and(warp::post())
+                    .and(warp::body::form())
+                    .and(warp::header::<String>("host"))
+                    .map(move |form: HashMap<String, String>, host: String| {
+                        let verified =
+                            form.get("code_verifier").map(String::as_str) == Some(CODE_VERIFIER);
+                        if !verified {
+                            return warp::reply::with_status(
+                                warp::reply::json(&json!({ "error": "invalid_grant" })),
+                                warp::http::StatusCode::BAD_REQUEST,
+                            );
+                        }
+                        let key = key_set.signing_key(Utc::now()).unwrap();
+                        let mut header = Header::new(Algorithm::EdDSA);
+                        header.kid = Some(key.kid.clone());
+                        let claims = json!({
+                            "iss": format!("http://{}", host),
+                            "sub": "idp-user",
+                            "aud": CLIENT_ID,
+                            "exp": (Utc::now() + Duration::minutes(5)).timestamp(),
+                            "iat": Utc::now().timestamp(),
+                            "nonce": NONCE,
+                            "email": "user.a@example.com",
+                            "email_verified": true,
+                        });
+                        let id_token =
+                            jsonwebtoken::encode(&header, &claims, &key.encoding_key).unwrap();
+                        warp::reply::with_status(
+                            warp::reply::json(&json!({ "id_token": id_token })),
+                            warp::http::StatusCode::OK,
+                        )
+                    })),
+        )
+        .bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        format!("http://{}", addr)
+    }
+}
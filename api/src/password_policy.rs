@@ -0,0 +1,91 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+const COMMON_PASSWORDS: &str = include_str!("../res/common-passwords.txt");
+
+#[derive(thiserror::Error, Debug)]
+pub enum PolicyError {
+    #[error("password is shorter than {0} characters")]
+    TooShort(usize),
+    #[error("password is too common")]
+    Common,
+    #[error("password was used recently")]
+    Reused,
+}
+
+/// Rules a new password must follow. Reuse of recent passwords is checked separately, since it
+/// requires stored credentials.
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    /// How many previous passwords of an account cannot be reused.
+    pub history_len: usize,
+    common_passwords: Arc<HashSet<String>>,
+}
+
+impl PasswordPolicy {
+    pub fn new(min_length: usize, history_len: usize) -> Self {
+        let common_passwords = COMMON_PASSWORDS
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .map(str::to_lowercase)
+            .collect();
+        Self {
+            min_length,
+            history_len,
+            common_passwords: Arc::new(common_passwords),
+        }
+    }
+
+    pub fn check(&self, password: &str) -> Result<(), PolicyError> {
+        if password.chars().count() < self.min_length {
+            return Err(PolicyError::TooShort(self.min_length));
+        }
+        if self.common_passwords.contains(&password.to_lowercase()) {
+            return Err(PolicyError::Common);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_too_short() {
+        // Arrange.
+        let policy = PasswordPolicy::new(8, 0);
+
+        // Act.
+        let res = policy.check("x7#kQ");
+
+        // Assert.
+        assert!(matches!(res, Err(PolicyError::TooShort(8))));
+    }
+
+    #[test]
+    fn test_check_common() {
+        // Arrange.
+        let policy = PasswordPolicy::new(8, 0);
+
+        // Act.
+        let res = policy.check("Password123");
+
+        // Assert.
+        assert!(matches!(res, Err(PolicyError::Common)));
+    }
+
+    #[test]
+    fn test_check_valid() {
+        // Arrange.
+        let policy = PasswordPolicy::new(8, 0);
+
+        // Act.
+        let res = policy.check("correct horse battery staple");
+
+        // Assert.
+        assert!(res.is_ok());
+    }
+}
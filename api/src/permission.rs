@@ -0,0 +1,275 @@
+use crate::auth::Claims;
+use crate::repo::api_key::{ApiKey, Scope};
+use crate::repo::user_account::{Access, Role, RoleGrant};
+use std::collections::{BTreeSet, HashMap};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Permission {
+    /// Manage the user account making the request, such as its password and sessions.
+    AccountOwn,
+    ApiKeyAdmin,
+    /// Create and delete companies.
+    CompanyAdmin,
+    CompanyRead,
+    CompanyWrite,
+    DeviceRead,
+    DeviceWrite,
+    IncidentRead,
+    IncidentWrite,
+    PersonRead,
+    PersonWrite,
+    ReadingRead,
+    ReadingWrite,
+    TeamRead,
+    TeamWrite,
+    UserAdmin,
+    /// Act as another user account of the company.
+    UserImpersonate,
+    UserRead,
+}
+
+impl Permission {
+    pub const ALL: [Permission; 18] = [
+        Permission::AccountOwn,
+        Permission::ApiKeyAdmin,
+        Permission::CompanyAdmin,
+        Permission::CompanyRead,
+        Permission::CompanyWrite,
+        Permission::DeviceRead,
+        Permission::DeviceWrite,
+        Permission::IncidentRead,
+        Permission::IncidentWrite,
+        Permission::PersonRead,
+        Permission::PersonWrite,
+        Permission::ReadingRead,
+        Permission::ReadingWrite,
+        Permission::TeamRead,
+        Permission::TeamWrite,
+        Permission::UserAdmin,
+        Permission::UserImpersonate,
+        Permission::UserRead,
+    ];
+
+    /// Permissions to read the data of a company.
+    pub const READ: [Permission; 6] = [
+        Permission::CompanyRead,
+        Permission::DeviceRead,
+        Permission::IncidentRead,
+        Permission::PersonRead,
+        Permission::ReadingRead,
+        Permission::TeamRead,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Permission::AccountOwn => "account:own",
+            Permission::ApiKeyAdmin => "api_key:admin",
+            Permission::CompanyAdmin => "company:admin",
+            Permission::CompanyRead => "company:read",
+            Permission::CompanyWrite => "company:write",
+            Permission::DeviceRead => "device:read",
+            Permission::DeviceWrite => "device:write",
+            Permission::IncidentRead => "incident:read",
+            Permission::IncidentWrite => "incident:write",
+            Permission::PersonRead => "person:read",
+            Permission::PersonWrite => "person:write",
+            Permission::ReadingRead => "reading:read",
+            Permission::ReadingWrite => "reading:write",
+            Permission::TeamRead => "team:read",
+            Permission::TeamWrite => "team:write",
+            Permission::UserAdmin => "user:admin",
+            Permission::UserImpersonate => "user:impersonate",
+            Permission::UserRead => "user:read",
+        }
+    }
+}
+
+/// Permissions of an access level.
+pub fn access_permissions(access: Access) -> Vec<Permission> {
+    match access {
+        Access::View => [&Permission::READ[..], &[Permission::AccountOwn]].concat(),
+        Access::Admin => Permission::ALL
+            .iter()
+            .copied()
+            .filter(|&p| p != Permission::CompanyAdmin)
+            .collect(),
+        Access::SuperAdmin => Permission::ALL.to_vec(),
+    }
+}
+
+/// Permissions of a role.
+pub fn role_permissions(role: Role) -> Vec<Permission> {
+    let extra: &[Permission] = match role {
+        Role::SafetyManager => &[
+            Permission::DeviceWrite,
+            Permission::IncidentWrite,
+            Permission::PersonWrite,
+            Permission::ReadingWrite,
+            Permission::TeamWrite,
+            Permission::UserRead,
+        ],
+        Role::TeamLead => &[Permission::IncidentWrite, Permission::TeamWrite],
+        Role::Worker => &[],
+        Role::Auditor => &[Permission::UserRead],
+    };
+    [&Permission::READ[..], extra].concat()
+}
+
+/// Permissions of an API key scope.
+pub fn scope_permissions(scope: Scope) -> Vec<Permission> {
+    match scope {
+        Scope::IngestReadings => vec![Permission::ReadingWrite],
+        Scope::ReadIncidents => vec![Permission::IncidentRead],
+    }
+}
+
+/// Permissions held by a request, across its company and for individual teams.
+#[derive(Debug, Clone, Default)]
+pub struct Permissions {
+    pub company: BTreeSet<Permission>,
+    pub teams: HashMap<String, BTreeSet<Permission>>,
+}
+
+impl Permissions {
+    pub fn for_user_account(access: Access, roles: &[RoleGrant]) -> Self {
+        let mut permissions = Self {
+            company: access_permissions(access).into_iter().collect(),
+            teams: HashMap::new(),
+        };
+        for grant in roles {
+            let granted = role_permissions(grant.role);
+            match &grant.team_id {
+                None => permissions.company.extend(granted),
+                Some(team_id) => permissions
+                    .teams
+                    .entry(team_id.clone())
+                    .or_default()
+                    .extend(granted),
+            }
+        }
+        permissions
+    }
+
+    /// Impersonated tokens can only read, so an admin acting as a user cannot make changes as
+    /// them.
+    pub fn for_claims(claims: &Claims) -> Self {
+        let permissions = Self::for_user_account(claims.access, &claims.roles);
+        match claims.act {
+            Some(_) => permissions.read_only(),
+            None => permissions,
+        }
+    }
+
+    pub fn for_api_key(api_key: &ApiKey) -> Self {
+        Self {
+            company: api_key
+                .scopes
+                .iter()
+                .flat_map(|&scope| scope_permissions(scope))
+                .collect(),
+            teams: HashMap::new(),
+        }
+    }
+
+    pub fn read_only(mut self) -> Self {
+        // User accounts are not company data in `READ`, but reading them is still only reading.
+        let is_read = |p: &Permission| Permission::READ.contains(p) || *p == Permission::UserRead;
+        self.company.retain(is_read);
+        for team in self.teams.values_mut() {
+            team.retain(is_read);
+        }
+        self
+    }
+
+    /// Whether the permission is held across the company.
+    pub fn has(&self, permission: Permission) -> bool {
+        self.company.contains(&permission)
+    }
+
+    /// Whether the permission is held across the company or for the team.
+    pub fn has_in_team(&self, permission: Permission, team_id: &str) -> bool {
+        self.has(permission)
+            || matches!(self.teams.get(team_id), Some(team) if team.contains(&permission))
+    }
+
+    /// Permissions held across the company, and for the team if one is given.
+    pub fn list(&self, team_id: Option<&str>) -> Vec<Permission> {
+        let mut permissions = self.company.clone();
+        if let Some(team) = team_id.and_then(|team_id| self.teams.get(team_id)) {
+            permissions.extend(team);
+        }
+        permissions.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Actor;
+
+    #[test]
+    fn test_view_cannot_write() {
+        // Act.
+        let permissions = Permissions::for_user_account(Access::View, &[]);
+
+        // Assert.
+        assert!(permissions.has(Permission::IncidentRead));
+        assert!(!permissions.has(Permission::IncidentWrite));
+    }
+
+    #[test]
+    fn test_team_role_applies_to_team() {
+        // Arrange.
+        let roles = vec![RoleGrant {
+            role: Role::TeamLead,
+            team_id: Some("team".to_string()),
+        }];
+
+        // Act.
+        let permissions = Permissions::for_user_account(Access::View, &roles);
+
+        // Assert.
+        assert!(permissions.has_in_team(Permission::TeamWrite, "team"));
+        assert!(!permissions.has_in_team(Permission::TeamWrite, "other"));
+        assert!(!permissions.has(Permission::TeamWrite));
+    }
+
+    #[test]
+    fn test_impersonation_is_read_only() {
+        // Arrange.
+        let claims = Claims {
+            sub: "user".to_string(),
+            access: Access::Admin,
+            company_id: "company".to_string(),
+            roles: Vec::new(),
+            person_id: None,
+            act: Some(Actor {
+                sub: "admin".to_string(),
+            }),
+            iat: 0,
+            exp: 0,
+            jti: "jti".to_string(),
+        };
+
+        // Act.
+        let permissions = Permissions::for_claims(&claims);
+
+        // Assert.
+        assert!(permissions.has(Permission::IncidentRead));
+        assert!(permissions.has(Permission::UserRead));
+        assert!(!permissions.has(Permission::IncidentWrite));
+        assert!(!permissions.has(Permission::AccountOwn));
+        assert!(!permissions.has(Permission::UserImpersonate));
+    }
+
+    #[test]
+    fn test_only_super_admin_has_company_admin() {
+        // Act.
+        let admin = Permissions::for_user_account(Access::Admin, &[]);
+        let super_admin = Permissions::for_user_account(Access::SuperAdmin, &[]);
+
+        // Assert.
+        assert!(!admin.has(Permission::CompanyAdmin));
+        assert!(super_admin.has(Permission::CompanyAdmin));
+    }
+}
@@ -0,0 +1,121 @@
+use crate::db::coll;
+use crate::repo::mongo_util::{filter, FindStream, FromDeletedCount, InsertOpt};
+use crate::repo::DeleteResult;
+use crate::repo::ItemStream;
+use bson::Document;
+use chrono::{DateTime, Utc};
+use mongodb::options::FindOptions;
+use mongodb::{Collection, Database};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Scope {
+    #[serde(rename = "ingest:readings")]
+    IngestReadings,
+    #[serde(rename = "read:incidents")]
+    ReadIncidents,
+}
+
+/// A credential for a machine client, such as a device gateway, acting for a company. Only a hash
+/// of the key is stored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKey {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub company_id: String,
+    pub name: String,
+    pub key_hash: String,
+    pub scopes: Vec<Scope>,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<Utc>,
+}
+
+impl ApiKey {
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope)
+    }
+}
+
+#[derive(Default, Debug, Clone)]
+pub struct ApiKeyFilter {
+    pub company_ids: Option<Vec<String>>,
+}
+
+#[async_trait::async_trait]
+pub trait ApiKeyRepo {
+    async fn insert_one(&self, api_key: ApiKey) -> anyhow::Result<()>;
+    async fn find_one(&self, id: &str) -> anyhow::Result<Option<ApiKey>>;
+    async fn find_by_hash(&self, key_hash: &str) -> anyhow::Result<Option<ApiKey>>;
+    async fn find(&self, filter: ApiKeyFilter) -> anyhow::Result<Box<dyn ItemStream<ApiKey>>>;
+    async fn delete_one(&self, id: &str) -> DeleteResult;
+}
+
+pub type DynApiKeyRepo = dyn ApiKeyRepo + Send + Sync + 'static;
+
+pub type ArcApiKeyRepo = Arc<DynApiKeyRepo>;
+
+#[derive(Debug, Clone)]
+pub struct MongoApiKeyRepo {
+    pub db: Database,
+}
+
+impl MongoApiKeyRepo {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    pub fn collection(&self) -> Collection<ApiKey> {
+        self.db.collection(coll::API_KEY)
+    }
+}
+
+#[async_trait::async_trait]
+impl ApiKeyRepo for MongoApiKeyRepo {
+    async fn insert_one(&self, api_key: ApiKey) -> anyhow::Result<()> {
+        self.collection().insert_one(api_key, None).await?;
+        Ok(())
+    }
+
+    async fn find_one(&self, id: &str) -> anyhow::Result<Option<ApiKey>> {
+        Ok(self
+            .collection()
+            .find_one(bson::doc! {"_id": id}, None)
+            .await?)
+    }
+
+    async fn find_by_hash(&self, key_hash: &str) -> anyhow::Result<Option<ApiKey>> {
+        Ok(self
+            .collection()
+            .find_one(bson::doc! {"key_hash": key_hash}, None)
+            .await?)
+    }
+
+    async fn find(&self, filter: ApiKeyFilter) -> anyhow::Result<Box<dyn ItemStream<ApiKey>>> {
+        let mut mongo_filter = Document::new();
+        mongo_filter.insert_opt("company_id", filter::one_of(filter.company_ids));
+        self.collection()
+            .find_stream(
+                mongo_filter,
+                FindOptions::builder()
+                    .sort(bson::doc! {"created_at": 1})
+                    .build(),
+            )
+            .await
+    }
+
+    async fn delete_one(&self, id: &str) -> DeleteResult {
+        let res = self
+            .collection()
+            .delete_one(bson::doc! {"_id": id}, None)
+            .await
+            .map_err(anyhow::Error::from)?;
+        DeleteResult::from_deleted_count(res.deleted_count)
+    }
+}
+
+impl From<MongoApiKeyRepo> for ArcApiKeyRepo {
+    fn from(value: MongoApiKeyRepo) -> Self {
+        Arc::new(value)
+    }
+}
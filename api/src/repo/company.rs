@@ -2,6 +2,7 @@ use crate::db::coll;
 use crate::repo::mongo_util::{FindStream, FromDeletedCount, FromMatchedCount};
 use crate::repo::DeleteResult;
 use crate::repo::{ItemStream, ReplaceResult};
+use futures_util::TryStreamExt;
 use mongodb::{Collection, Database};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
@@ -11,6 +12,24 @@ pub struct Company {
     #[serde(rename = "_id")]
     pub id: String,
     pub name: String,
+    /// Whether admin user accounts must use two-factor authentication.
+    #[serde(default)]
+    pub require_admin_two_factor: bool,
+    /// Whether user accounts may log in with a link emailed to them instead of a password.
+    #[serde(default)]
+    pub login_links_enabled: bool,
+    /// Identity provider for single sign-on, if the company uses one.
+    #[serde(default)]
+    pub oidc: Option<OidcConfig>,
+}
+
+/// OpenID Connect client registration of a company at its identity provider.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct OidcConfig {
+    pub issuer: String,
+    pub client_id: String,
+    /// Absent for public clients, which rely on PKCE alone.
+    pub client_secret: Option<String>,
 }
 
 #[async_trait::async_trait]
@@ -18,6 +37,8 @@ pub trait CompanyRepo {
     async fn insert_one(&self, company: Company) -> anyhow::Result<()>;
     async fn replace_one(&self, company: Company) -> ReplaceResult;
     async fn find_one(&self, id: &str) -> anyhow::Result<Option<Company>>;
+    /// Find the items with the given IDs, in no particular order. Unknown IDs are skipped.
+    async fn find_many(&self, ids: &[String]) -> anyhow::Result<Vec<Company>>;
     async fn find(&self) -> anyhow::Result<Box<dyn ItemStream<Company>>>;
     async fn delete_one(&self, id: &str) -> DeleteResult;
 }
@@ -64,6 +85,14 @@ impl CompanyRepo for MongoCompanyRepo {
             .await?)
     }
 
+    async fn find_many(&self, ids: &[String]) -> anyhow::Result<Vec<Company>> {
+        self.collection()
+            .find_stream(bson::doc! {"_id": {"$in": ids}}, None)
+            .await?
+            .try_collect()
+            .await
+    }
+
     async fn find(&self) -> anyhow::Result<Box<dyn ItemStream<Company>>> {
         self.collection().find_stream(None, None).await
     }
@@ -1,7 +1,9 @@
 use crate::db::coll;
-use crate::repo::mongo_util::{filter, FindStream, FromDeletedCount, FromMatchedCount, InsertOpt};
+use crate::repo::mongo_util::{
+    filter, find_stream_of_companies, page_by_id, FromDeletedCount, FromMatchedCount, InsertOpt,
+};
 use crate::repo::DeleteResult;
-use crate::repo::{ItemStream, ReplaceResult};
+use crate::repo::{ItemStream, Page, ReplaceResult};
 use bson::Document;
 use mongodb::{Collection, Database};
 use serde::{Deserialize, Serialize};
@@ -17,6 +19,9 @@ pub struct Device {
 #[derive(Default, Debug, Clone)]
 pub struct DeviceFilter {
     pub owner_ids: Option<Vec<String>>,
+    /// Companies of the owners.
+    pub company_ids: Option<Vec<String>>,
+    pub page: Page,
 }
 
 #[async_trait::async_trait]
@@ -73,7 +78,16 @@ impl DeviceRepo for MongoDeviceRepo {
     async fn find(&self, filter: DeviceFilter) -> anyhow::Result<Box<dyn ItemStream<Device>>> {
         let mut mongo_filter = Document::new();
         mongo_filter.insert_opt("owner_id", filter::one_of(filter.owner_ids));
-        self.collection().find_stream(mongo_filter, None).await
+        let options = page_by_id(&mut mongo_filter, &filter.page)?;
+        let collection = self.collection();
+        find_stream_of_companies(
+            &collection,
+            mongo_filter,
+            options,
+            "owner_id",
+            filter.company_ids,
+        )
+        .await
     }
 
     async fn delete_one(&self, id: &str) -> DeleteResult {
@@ -1,15 +1,20 @@
 use crate::db::coll;
-use crate::repo::mongo_util::{filter, FindStream, InsertOpt};
-use crate::repo::ItemStream;
+use crate::event::{Event, EventBus};
+use crate::repo::mongo_util::{
+    filter, find_stream_of_companies, object_id, page_by_timestamp, InsertOpt,
+};
+use crate::repo::{ItemStream, Page};
+use bson::oid::ObjectId;
 use bson::Document;
 use chrono::{DateTime, Utc};
-use mongodb::options::FindOptions;
 use mongodb::{Collection, Database};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DbGasReading {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
     #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
     pub timestamp: DateTime<Utc>,
     pub person_id: String,
@@ -27,6 +32,7 @@ pub struct DbLocation {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GasReading {
+    pub id: String,
     pub timestamp: DateTime<Utc>,
     pub person_id: String,
     pub gas: String,
@@ -38,6 +44,7 @@ pub struct GasReading {
 impl From<DbGasReading> for GasReading {
     fn from(value: DbGasReading) -> Self {
         Self {
+            id: value.id.to_hex(),
             timestamp: value.timestamp,
             person_id: value.person_id,
             gas: value.gas,
@@ -51,6 +58,9 @@ impl From<DbGasReading> for GasReading {
 impl From<GasReading> for DbGasReading {
     fn from(value: GasReading) -> Self {
         Self {
+            // Readings get their IDs from `mongo_util::new_object_id`, so this only replaces
+            // malformed ones.
+            id: ObjectId::parse_str(&value.id).unwrap_or_else(|_| ObjectId::new()),
             timestamp: value.timestamp,
             person_id: value.person_id,
             gas: value.gas,
@@ -67,8 +77,12 @@ impl From<GasReading> for DbGasReading {
 #[derive(Default, Debug, Clone)]
 pub struct GasReadingFilter {
     pub person_ids: Option<Vec<String>>,
+    /// Companies of the people.
+    pub company_ids: Option<Vec<String>>,
+    pub gases: Option<Vec<String>>,
     pub min_timestamp: Option<DateTime<Utc>>,
     pub max_timestamp: Option<DateTime<Utc>>,
+    pub page: Page,
 }
 
 #[async_trait::async_trait]
@@ -88,11 +102,12 @@ pub type ArcGasReadingRepo = Arc<DynGasReadingRepo>;
 #[derive(Debug, Clone)]
 pub struct MongoGasReadingRepo {
     pub db: Database,
+    pub events: EventBus,
 }
 
 impl MongoGasReadingRepo {
-    pub fn new(db: Database) -> Self {
-        Self { db }
+    pub fn new(db: Database, events: EventBus) -> Self {
+        Self { db, events }
     }
 
     pub fn collection(&self) -> Collection<DbGasReading> {
@@ -103,8 +118,11 @@ impl MongoGasReadingRepo {
 #[async_trait::async_trait]
 impl GasReadingRepo for MongoGasReadingRepo {
     async fn insert_many(&self, gas_readings: Vec<GasReading>) -> anyhow::Result<()> {
-        let db_readings: Vec<DbGasReading> = gas_readings.into_iter().map(Into::into).collect();
+        let db_readings: Vec<DbGasReading> = gas_readings.iter().cloned().map(Into::into).collect();
         self.collection().insert_many(db_readings, None).await?;
+        for reading in gas_readings {
+            self.events.publish(Event::GasReadingAdded(reading));
+        }
         Ok(())
     }
 
@@ -116,18 +134,21 @@ impl GasReadingRepo for MongoGasReadingRepo {
         mongo_filter.insert("hidden", filter::not_true());
         mongo_filter.insert("density", filter::not(0));
         mongo_filter.insert_opt("person_id", filter::one_of(filter.person_ids));
+        mongo_filter.insert_opt("gas", filter::one_of(filter.gases));
         mongo_filter.insert_opt(
             "timestamp",
             filter::clamp(filter.min_timestamp, filter.max_timestamp),
         );
-        self.collection()
-            .find_stream(
-                mongo_filter,
-                FindOptions::builder()
-                    .sort(bson::doc! {"timestamp": 1})
-                    .build(),
-            )
-            .await
+        let options = page_by_timestamp(&mut mongo_filter, &filter.page, object_id)?;
+        let collection = self.collection();
+        find_stream_of_companies(
+            &collection,
+            mongo_filter,
+            options,
+            "person_id",
+            filter.company_ids,
+        )
+        .await
     }
 }
 
@@ -0,0 +1,94 @@
+use crate::db::coll;
+use crate::repo::mongo_util::{filter, FindStream, InsertOpt};
+use crate::repo::ItemStream;
+use bson::Document;
+use chrono::{DateTime, Utc};
+use mongodb::options::FindOptions;
+use mongodb::{Collection, Database};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Audit record of an admin acting as another user account.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Impersonation {
+    #[serde(rename = "_id")]
+    pub id: String,
+    /// User account of the admin.
+    pub actor_id: String,
+    /// User account being impersonated.
+    pub user_account_id: String,
+    /// Company of the impersonated user account.
+    pub company_id: String,
+    pub reason: String,
+    pub session_id: String,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Default, Debug, Clone)]
+pub struct ImpersonationFilter {
+    pub company_ids: Option<Vec<String>>,
+    pub user_account_ids: Option<Vec<String>>,
+}
+
+#[async_trait::async_trait]
+pub trait ImpersonationRepo {
+    async fn insert_one(&self, impersonation: Impersonation) -> anyhow::Result<()>;
+    /// Find impersonations, most recent first.
+    async fn find(
+        &self,
+        filter: ImpersonationFilter,
+    ) -> anyhow::Result<Box<dyn ItemStream<Impersonation>>>;
+}
+
+pub type DynImpersonationRepo = dyn ImpersonationRepo + Send + Sync + 'static;
+
+pub type ArcImpersonationRepo = Arc<DynImpersonationRepo>;
+
+#[derive(Debug, Clone)]
+pub struct MongoImpersonationRepo {
+    pub db: Database,
+}
+
+impl MongoImpersonationRepo {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    pub fn collection(&self) -> Collection<Impersonation> {
+        self.db.collection(coll::IMPERSONATION)
+    }
+}
+
+#[async_trait::async_trait]
+impl ImpersonationRepo for MongoImpersonationRepo {
+    async fn insert_one(&self, impersonation: Impersonation) -> anyhow::Result<()> {
+        self.collection().insert_one(impersonation, None).await?;
+        Ok(())
+    }
+
+    async fn find(
+        &self,
+        filter: ImpersonationFilter,
+    ) -> anyhow::Result<Box<dyn ItemStream<Impersonation>>> {
+        let mut mongo_filter = Document::new();
+        mongo_filter.insert_opt("company_id", filter::one_of(filter.company_ids));
+        mongo_filter.insert_opt("user_account_id", filter::one_of(filter.user_account_ids));
+        self.collection()
+            .find_stream(
+                mongo_filter,
+                FindOptions::builder()
+                    .sort(bson::doc! {"created_at": -1})
+                    .build(),
+            )
+            .await
+    }
+}
+
+impl From<MongoImpersonationRepo> for ArcImpersonationRepo {
+    fn from(value: MongoImpersonationRepo) -> Self {
+        Arc::new(value)
+    }
+}
@@ -1,9 +1,12 @@
 use crate::db::coll;
-use crate::repo::mongo_util::{filter, FindStream, FromDeletedCount, FromMatchedCount, InsertOpt};
-use crate::repo::{DeleteResult, ItemStream, ReplaceResult};
+use crate::event::{Event, EventBus};
+use crate::repo::mongo_util::{
+    filter, find_stream_of_companies, page_by_timestamp, string_id, FromDeletedCount,
+    FromMatchedCount, InsertOpt, HIDDEN_INCIDENTS,
+};
+use crate::repo::{DeleteResult, ItemStream, Page, ReplaceResult};
 use bson::Document;
 use chrono::{DateTime, Utc};
-use mongodb::options::FindOptions;
 use mongodb::{Collection, Database};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
@@ -64,8 +67,12 @@ impl From<Incident> for DbIncident {
 #[derive(Default, Debug, Clone)]
 pub struct IncidentFilter {
     pub person_ids: Option<Vec<String>>,
+    /// Companies of the people.
+    pub company_ids: Option<Vec<String>>,
+    pub types: Option<Vec<String>>,
     pub min_timestamp: Option<DateTime<Utc>>,
     pub max_timestamp: Option<DateTime<Utc>>,
+    pub page: Page,
 }
 
 #[async_trait::async_trait]
@@ -85,29 +92,41 @@ pub type ArcIncidentRepo = Arc<DynIncidentRepo>;
 #[derive(Debug, Clone)]
 pub struct MongoIncidentRepo {
     pub db: Database,
+    pub events: EventBus,
 }
 
 impl MongoIncidentRepo {
-    pub fn new(db: Database) -> Self {
-        Self { db }
+    pub fn new(db: Database, events: EventBus) -> Self {
+        Self { db, events }
     }
 
     pub fn collection(&self) -> Collection<DbIncident> {
         self.db.collection(coll::INCIDENT)
     }
+
+    /// Hidden incident types are not announced, just as they are not found.
+    fn publish(&self, incident: Incident) {
+        if !HIDDEN_INCIDENTS.contains(&incident.r#type.as_str()) {
+            self.events.publish(Event::IncidentCreated(incident));
+        }
+    }
 }
 
 #[async_trait::async_trait]
 impl IncidentRepo for MongoIncidentRepo {
     async fn insert_one(&self, incident: Incident) -> anyhow::Result<()> {
-        let db_incident: DbIncident = incident.into();
+        let db_incident: DbIncident = incident.clone().into();
         self.collection().insert_one(db_incident, None).await?;
+        self.publish(incident);
         Ok(())
     }
 
     async fn insert_many(&self, incidents: Vec<Incident>) -> anyhow::Result<()> {
-        let db_incidents: Vec<DbIncident> = incidents.into_iter().map(Into::into).collect();
+        let db_incidents: Vec<DbIncident> = incidents.iter().cloned().map(Into::into).collect();
         self.collection().insert_many(db_incidents, None).await?;
+        for incident in incidents {
+            self.publish(incident);
+        }
         Ok(())
     }
 
@@ -140,20 +159,22 @@ impl IncidentRepo for MongoIncidentRepo {
     async fn find(&self, filter: IncidentFilter) -> anyhow::Result<Box<dyn ItemStream<Incident>>> {
         let mut mongo_filter = Document::new();
         mongo_filter.insert("hidden", filter::not_true());
-        mongo_filter.insert("type", filter::not_hidden_incident());
+        mongo_filter.insert("type", filter::incident_type(filter.types));
         mongo_filter.insert_opt("person_id", filter::one_of(filter.person_ids));
         mongo_filter.insert_opt(
             "timestamp",
             filter::clamp(filter.min_timestamp, filter.max_timestamp),
         );
-        self.collection()
-            .find_stream(
-                mongo_filter,
-                FindOptions::builder()
-                    .sort(bson::doc! {"timestamp": 1})
-                    .build(),
-            )
-            .await
+        let options = page_by_timestamp(&mut mongo_filter, &filter.page, string_id)?;
+        let collection = self.collection();
+        find_stream_of_companies(
+            &collection,
+            mongo_filter,
+            options,
+            "person_id",
+            filter.company_ids,
+        )
+        .await
     }
 
     async fn delete_one(&self, id: &str) -> DeleteResult {
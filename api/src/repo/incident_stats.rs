@@ -1,5 +1,5 @@
 use crate::db::coll;
-use crate::repo::mongo_util::{filter, InsertOpt};
+use crate::repo::mongo_util::{filter, of_companies, InsertOpt};
 use crate::repo::ItemStream;
 use bson::Document;
 use chrono::{DateTime, Utc};
@@ -17,6 +17,9 @@ pub struct IncidentStats {
 #[derive(Default, Debug, Clone)]
 pub struct IncidentStatsFilter {
     pub person_ids: Option<Vec<String>>,
+    /// Companies of the people.
+    pub company_ids: Option<Vec<String>>,
+    pub types: Option<Vec<String>>,
     pub min_timestamp: Option<DateTime<Utc>>,
     pub max_timestamp: Option<DateTime<Utc>>,
 }
@@ -56,23 +59,19 @@ impl IncidentStatsRepo for MongoIncidentStatsRepo {
     ) -> anyhow::Result<Box<dyn ItemStream<IncidentStats>>> {
         let mut mongo_filter = Document::new();
         mongo_filter.insert("hidden", filter::not_true());
-        mongo_filter.insert("type", filter::not_hidden_incident());
+        mongo_filter.insert("type", filter::incident_type(filter.types));
         mongo_filter.insert_opt("person_id", filter::one_of(filter.person_ids));
         mongo_filter.insert_opt(
             "timestamp",
             filter::clamp(filter.min_timestamp, filter.max_timestamp),
         );
-        let cursor = self
-            .collection()
-            .aggregate(
-                vec![
-                    bson::doc! { "$match": mongo_filter },
-                    bson::doc! { "$group": { "_id": "$type", "count": { "$sum": 1 } } },
-                    bson::doc! { "$set": { "type": "$_id" } },
-                ],
-                None,
-            )
-            .await?;
+        let mut pipeline = vec![bson::doc! { "$match": mongo_filter }];
+        if let Some(company_ids) = filter.company_ids {
+            pipeline.extend(of_companies("person_id", company_ids));
+        }
+        pipeline.push(bson::doc! { "$group": { "_id": "$type", "count": { "$sum": 1 } } });
+        pipeline.push(bson::doc! { "$set": { "type": "$_id" } });
+        let cursor = self.collection().aggregate(pipeline, None).await?;
         let stream = cursor
             .map_err(anyhow::Error::from)
             .map(|r| r.and_then(|d| bson::from_document(d).map_err(Into::into)));
@@ -1,15 +1,20 @@
 use crate::db::coll;
-use crate::repo::mongo_util::{filter, FindStream, InsertOpt};
-use crate::repo::ItemStream;
+use crate::event::{Event, EventBus};
+use crate::repo::mongo_util::{
+    filter, find_stream_of_companies, object_id, page_by_timestamp, InsertOpt,
+};
+use crate::repo::{ItemStream, Page};
+use bson::oid::ObjectId;
 use bson::Document;
 use chrono::{DateTime, Utc};
-use mongodb::options::FindOptions;
 use mongodb::{Collection, Database};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DbLocationReading {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
     #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
     pub timestamp: DateTime<Utc>,
     pub person_id: String,
@@ -24,6 +29,7 @@ pub struct DbLocation {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LocationReading {
+    pub id: String,
     pub timestamp: DateTime<Utc>,
     pub person_id: String,
     pub coordinates: Vec<f64>,
@@ -32,6 +38,7 @@ pub struct LocationReading {
 impl From<DbLocationReading> for LocationReading {
     fn from(value: DbLocationReading) -> Self {
         Self {
+            id: value.id.to_hex(),
             person_id: value.person_id,
             timestamp: value.timestamp,
             coordinates: value.location.coordinates,
@@ -42,6 +49,9 @@ impl From<DbLocationReading> for LocationReading {
 impl From<LocationReading> for DbLocationReading {
     fn from(value: LocationReading) -> Self {
         Self {
+            // Readings get their IDs from `mongo_util::new_object_id`, so this only replaces
+            // malformed ones.
+            id: ObjectId::parse_str(&value.id).unwrap_or_else(|_| ObjectId::new()),
             person_id: value.person_id,
             timestamp: value.timestamp,
             location: DbLocation {
@@ -55,8 +65,11 @@ impl From<LocationReading> for DbLocationReading {
 #[derive(Default, Debug, Clone)]
 pub struct LocationReadingFilter {
     pub person_ids: Option<Vec<String>>,
+    /// Companies of the people.
+    pub company_ids: Option<Vec<String>>,
     pub min_timestamp: Option<DateTime<Utc>>,
     pub max_timestamp: Option<DateTime<Utc>>,
+    pub page: Page,
 }
 
 #[async_trait::async_trait]
@@ -76,11 +89,12 @@ pub type ArcLocationReadingRepo = Arc<DynLocationReadingRepo>;
 #[derive(Debug, Clone)]
 pub struct MongoLocationReadingRepo {
     pub db: Database,
+    pub events: EventBus,
 }
 
 impl MongoLocationReadingRepo {
-    pub fn new(db: Database) -> Self {
-        Self { db }
+    pub fn new(db: Database, events: EventBus) -> Self {
+        Self { db, events }
     }
 
     pub fn collection(&self) -> Collection<DbLocationReading> {
@@ -91,9 +105,15 @@ impl MongoLocationReadingRepo {
 #[async_trait::async_trait]
 impl LocationReadingRepo for MongoLocationReadingRepo {
     async fn insert_many(&self, location_readings: Vec<LocationReading>) -> anyhow::Result<()> {
-        let db_readings: Vec<DbLocationReading> =
-            location_readings.into_iter().map(|r| r.into()).collect();
+        let db_readings: Vec<DbLocationReading> = location_readings
+            .iter()
+            .cloned()
+            .map(|r| r.into())
+            .collect();
         self.collection().insert_many(db_readings, None).await?;
+        for reading in location_readings {
+            self.events.publish(Event::LocationReadingAdded(reading));
+        }
         Ok(())
     }
 
@@ -108,14 +128,16 @@ impl LocationReadingRepo for MongoLocationReadingRepo {
             "timestamp",
             filter::clamp(filter.min_timestamp, filter.max_timestamp),
         );
-        self.collection()
-            .find_stream(
-                mongo_filter,
-                FindOptions::builder()
-                    .sort(bson::doc! {"timestamp": 1})
-                    .build(),
-            )
-            .await
+        let options = page_by_timestamp(&mut mongo_filter, &filter.page, object_id)?;
+        let collection = self.collection();
+        find_stream_of_companies(
+            &collection,
+            mongo_filter,
+            options,
+            "person_id",
+            filter.company_ids,
+        )
+        .await
     }
 }
 
@@ -0,0 +1,131 @@
+use crate::db::coll;
+use crate::repo::mongo_util::is_duplicate_key;
+use chrono::{DateTime, Utc};
+use mongodb::{Collection, Database};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Failed login attempts for a key, such as a user account or an IP address.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoginAttempts {
+    #[serde(rename = "_id")]
+    pub key: String,
+    /// Attempts that did not succeed, counting ones that are still being checked.
+    pub failures: u32,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub last_failure_at: DateTime<Utc>,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub expires_at: DateTime<Utc>,
+}
+
+#[async_trait::async_trait]
+pub trait LoginAttemptRepo {
+    async fn find_one(&self, key: &str) -> anyhow::Result<Option<LoginAttempts>>;
+    /// Count an attempt if the attempts are still the given ones, and return whether it was
+    /// counted.
+    async fn record_attempt(
+        &self,
+        key: &str,
+        previous: Option<&LoginAttempts>,
+        expires_at: DateTime<Utc>,
+    ) -> anyhow::Result<bool>;
+    /// Uncount an attempt.
+    async fn take_back(&self, key: &str) -> anyhow::Result<()>;
+    async fn delete_one(&self, key: &str) -> anyhow::Result<()>;
+}
+
+pub type DynLoginAttemptRepo = dyn LoginAttemptRepo + Send + Sync + 'static;
+
+pub type ArcLoginAttemptRepo = Arc<DynLoginAttemptRepo>;
+
+#[derive(Debug, Clone)]
+pub struct MongoLoginAttemptRepo {
+    pub db: Database,
+}
+
+impl MongoLoginAttemptRepo {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    pub fn collection(&self) -> Collection<LoginAttempts> {
+        self.db.collection(coll::LOGIN_ATTEMPT)
+    }
+}
+
+#[async_trait::async_trait]
+impl LoginAttemptRepo for MongoLoginAttemptRepo {
+    async fn find_one(&self, key: &str) -> anyhow::Result<Option<LoginAttempts>> {
+        Ok(self
+            .collection()
+            .find_one(bson::doc! {"_id": key}, None)
+            .await?)
+    }
+
+    async fn record_attempt(
+        &self,
+        key: &str,
+        previous: Option<&LoginAttempts>,
+        expires_at: DateTime<Utc>,
+    ) -> anyhow::Result<bool> {
+        let previous = match previous {
+            Some(previous) => previous,
+            None => {
+                let attempts = LoginAttempts {
+                    key: key.to_string(),
+                    failures: 1,
+                    last_failure_at: Utc::now(),
+                    expires_at,
+                };
+                return match self.collection().insert_one(attempts, None).await {
+                    Ok(_) => Ok(true),
+                    Err(err) if is_duplicate_key(&err) => Ok(false),
+                    Err(err) => Err(err.into()),
+                };
+            }
+        };
+        let result = self
+            .collection()
+            .update_one(
+                bson::doc! {
+                    "_id": key,
+                    "failures": previous.failures,
+                    "last_failure_at": bson::DateTime::from_chrono(previous.last_failure_at),
+                },
+                bson::doc! {
+                    "$inc": { "failures": 1 },
+                    "$set": {
+                        "last_failure_at": bson::DateTime::from_chrono(Utc::now()),
+                        "expires_at": bson::DateTime::from_chrono(expires_at),
+                    },
+                },
+                None,
+            )
+            .await?;
+        Ok(result.matched_count == 1)
+    }
+
+    async fn take_back(&self, key: &str) -> anyhow::Result<()> {
+        self.collection()
+            .update_one(
+                bson::doc! {"_id": key, "failures": {"$gt": 0}},
+                bson::doc! {"$inc": { "failures": -1 }},
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_one(&self, key: &str) -> anyhow::Result<()> {
+        self.collection()
+            .delete_one(bson::doc! {"_id": key}, None)
+            .await?;
+        Ok(())
+    }
+}
+
+impl From<MongoLoginAttemptRepo> for ArcLoginAttemptRepo {
+    fn from(value: MongoLoginAttemptRepo) -> Self {
+        Arc::new(value)
+    }
+}
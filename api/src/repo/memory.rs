@@ -0,0 +1,748 @@
+//! Repos that keep items in memory, so tests can run the auth provider, tenant scopes and GraphQL
+//! queries without a database. Only the lookups that tests need are supported.
+
+use crate::repo::api_key::{ApiKey, ApiKeyFilter, ApiKeyRepo};
+use crate::repo::company::{Company, CompanyRepo};
+use crate::repo::device::{Device, DeviceFilter, DeviceRepo};
+use crate::repo::gas_reading::{GasReading, GasReadingFilter, GasReadingRepo};
+use crate::repo::impersonation::{Impersonation, ImpersonationFilter, ImpersonationRepo};
+use crate::repo::incident::{Incident, IncidentFilter, IncidentRepo};
+use crate::repo::incident_stats::{IncidentStats, IncidentStatsFilter, IncidentStatsRepo};
+use crate::repo::location_reading::{LocationReading, LocationReadingFilter, LocationReadingRepo};
+use crate::repo::login_attempt::{LoginAttemptRepo, LoginAttempts};
+use crate::repo::one_time_token::{OneTimeToken, OneTimeTokenRepo, Purpose};
+use crate::repo::person::{Person, PersonFilter, PersonRepo};
+use crate::repo::refresh_token::{RefreshToken, RefreshTokenRepo};
+use crate::repo::session::{Session, SessionFilter, SessionRepo};
+use crate::repo::team::{Team, TeamFilter, TeamPerson, TeamRepo};
+use crate::repo::user_account::{Creds, Totp, UserAccount, UserAccountFilter, UserAccountRepo};
+use crate::repo::{
+    DeleteError, DeleteResult, InsertError, InsertResult, ItemStream, ReplaceError, ReplaceResult,
+};
+use chrono::{DateTime, Utc};
+use futures_util::stream;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+fn item_stream<T: Unpin + Send + 'static>(items: Vec<T>) -> Box<dyn ItemStream<T>> {
+    Box::new(stream::iter(items.into_iter().map(Ok)))
+}
+
+#[derive(Default)]
+pub struct MemoryUserAccountRepo {
+    pub user_accounts: Mutex<HashMap<String, UserAccount>>,
+    /// Current credentials followed by previous ones, by user account.
+    pub creds: Mutex<HashMap<String, Vec<Creds>>>,
+    pub totp: Mutex<HashMap<String, Totp>>,
+}
+
+#[async_trait::async_trait]
+impl UserAccountRepo for MemoryUserAccountRepo {
+    async fn insert_one(&self, user_account: UserAccount) -> InsertResult {
+        if self.find_by_email(&user_account.email).await?.is_some() {
+            return Err(InsertError::Conflict);
+        }
+        let mut user_accounts = self.user_accounts.lock().unwrap();
+        user_accounts.insert(user_account.id.clone(), user_account);
+        Ok(())
+    }
+
+    async fn replace_one(&self, user_account: UserAccount) -> ReplaceResult {
+        let mut user_accounts = self.user_accounts.lock().unwrap();
+        match user_accounts.get_mut(&user_account.id) {
+            Some(item) => *item = user_account,
+            None => return Err(ReplaceError::NotFound),
+        }
+        Ok(())
+    }
+
+    async fn find_one(&self, id: &str) -> anyhow::Result<Option<UserAccount>> {
+        Ok(self.user_accounts.lock().unwrap().get(id).cloned())
+    }
+
+    async fn find_many(&self, ids: &[String]) -> anyhow::Result<Vec<UserAccount>> {
+        let user_accounts = self.user_accounts.lock().unwrap();
+        Ok(ids
+            .iter()
+            .filter_map(|id| user_accounts.get(id).cloned())
+            .collect())
+    }
+
+    async fn find_by_email(&self, email: &str) -> anyhow::Result<Option<UserAccount>> {
+        let user_accounts = self.user_accounts.lock().unwrap();
+        Ok(user_accounts
+            .values()
+            .find(|item| item.email.eq_ignore_ascii_case(email))
+            .cloned())
+    }
+
+    async fn find(
+        &self,
+        _filter: UserAccountFilter,
+    ) -> anyhow::Result<Box<dyn ItemStream<UserAccount>>> {
+        Err(anyhow::anyhow!("Not supported by the in-memory repo"))
+    }
+
+    async fn delete_one(&self, id: &str) -> DeleteResult {
+        match self.user_accounts.lock().unwrap().remove(id) {
+            Some(_) => Ok(()),
+            None => Err(DeleteError::NotFound),
+        }
+    }
+
+    async fn set_email_verified(&self, id: &str, email: &str) -> anyhow::Result<bool> {
+        match self.user_accounts.lock().unwrap().get_mut(id) {
+            Some(item) if item.email.eq_ignore_ascii_case(email) => {
+                item.email_verified = true;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn set_phone_verified(&self, id: &str, phone: &str) -> anyhow::Result<bool> {
+        match self.user_accounts.lock().unwrap().get_mut(id) {
+            Some(item) if item.phone == phone => {
+                item.phone_verified = true;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn creds(&self, user_account_id: &str) -> anyhow::Result<Option<Creds>> {
+        Ok(self
+            .creds_history(user_account_id)
+            .await?
+            .into_iter()
+            .next())
+    }
+
+    async fn creds_history(&self, user_account_id: &str) -> anyhow::Result<Vec<Creds>> {
+        let creds = self.creds.lock().unwrap();
+        Ok(creds.get(user_account_id).cloned().unwrap_or_default())
+    }
+
+    async fn set_creds(
+        &self,
+        user_account_id: &str,
+        creds: Creds,
+        mut history: Vec<Creds>,
+    ) -> anyhow::Result<()> {
+        history.insert(0, creds);
+        let mut all_creds = self.creds.lock().unwrap();
+        all_creds.insert(user_account_id.to_string(), history);
+        Ok(())
+    }
+
+    async fn totp(&self, user_account_id: &str) -> anyhow::Result<Option<Totp>> {
+        Ok(self.totp.lock().unwrap().get(user_account_id).cloned())
+    }
+
+    async fn set_totp(&self, user_account_id: &str, totp: Option<Totp>) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.creds(user_account_id).await?.is_some(),
+            "User account has no credentials"
+        );
+        let mut all_totp = self.totp.lock().unwrap();
+        match totp {
+            Some(totp) => all_totp.insert(user_account_id.to_string(), totp),
+            None => all_totp.remove(user_account_id),
+        };
+        Ok(())
+    }
+
+    async fn use_totp_step(&self, user_account_id: &str, step: i64) -> anyhow::Result<bool> {
+        let mut all_totp = self.totp.lock().unwrap();
+        match all_totp.get_mut(user_account_id) {
+            Some(totp) if totp.last_used_step.is_none_or(|last| last < step) => {
+                totp.last_used_step = Some(step);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn use_recovery_code(
+        &self,
+        user_account_id: &str,
+        code_hash: &str,
+    ) -> anyhow::Result<bool> {
+        let mut all_totp = self.totp.lock().unwrap();
+        let totp = match all_totp.get_mut(user_account_id) {
+            Some(totp) => totp,
+            None => return Ok(false),
+        };
+        let len = totp.recovery_code_hashes.len();
+        totp.recovery_code_hashes.retain(|hash| hash != code_hash);
+        Ok(totp.recovery_code_hashes.len() < len)
+    }
+
+    async fn profile_image_png(&self, _user_account_id: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        Err(anyhow::anyhow!("Not supported by the in-memory repo"))
+    }
+
+    async fn set_profile_image_png(
+        &self,
+        _user_account_id: &str,
+        _png_bytes: Vec<u8>,
+    ) -> anyhow::Result<()> {
+        Err(anyhow::anyhow!("Not supported by the in-memory repo"))
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryCompanyRepo {
+    pub companies: Mutex<HashMap<String, Company>>,
+}
+
+#[async_trait::async_trait]
+impl CompanyRepo for MemoryCompanyRepo {
+    async fn insert_one(&self, company: Company) -> anyhow::Result<()> {
+        let mut companies = self.companies.lock().unwrap();
+        companies.insert(company.id.clone(), company);
+        Ok(())
+    }
+
+    async fn replace_one(&self, company: Company) -> ReplaceResult {
+        let mut companies = self.companies.lock().unwrap();
+        match companies.get_mut(&company.id) {
+            Some(item) => *item = company,
+            None => return Err(ReplaceError::NotFound),
+        }
+        Ok(())
+    }
+
+    async fn find_one(&self, id: &str) -> anyhow::Result<Option<Company>> {
+        Ok(self.companies.lock().unwrap().get(id).cloned())
+    }
+
+    async fn find_many(&self, ids: &[String]) -> anyhow::Result<Vec<Company>> {
+        let companies = self.companies.lock().unwrap();
+        Ok(ids
+            .iter()
+            .filter_map(|id| companies.get(id).cloned())
+            .collect())
+    }
+
+    async fn find(&self) -> anyhow::Result<Box<dyn ItemStream<Company>>> {
+        let companies = self.companies.lock().unwrap();
+        Ok(item_stream(companies.values().cloned().collect()))
+    }
+
+    async fn delete_one(&self, id: &str) -> DeleteResult {
+        match self.companies.lock().unwrap().remove(id) {
+            Some(_) => Ok(()),
+            None => Err(DeleteError::NotFound),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryApiKeyRepo {
+    pub api_keys: Mutex<HashMap<String, ApiKey>>,
+}
+
+#[async_trait::async_trait]
+impl ApiKeyRepo for MemoryApiKeyRepo {
+    async fn insert_one(&self, api_key: ApiKey) -> anyhow::Result<()> {
+        let mut api_keys = self.api_keys.lock().unwrap();
+        api_keys.insert(api_key.id.clone(), api_key);
+        Ok(())
+    }
+
+    async fn find_one(&self, id: &str) -> anyhow::Result<Option<ApiKey>> {
+        Ok(self.api_keys.lock().unwrap().get(id).cloned())
+    }
+
+    async fn find_by_hash(&self, key_hash: &str) -> anyhow::Result<Option<ApiKey>> {
+        let api_keys = self.api_keys.lock().unwrap();
+        Ok(api_keys
+            .values()
+            .find(|item| item.key_hash == key_hash)
+            .cloned())
+    }
+
+    async fn find(&self, _filter: ApiKeyFilter) -> anyhow::Result<Box<dyn ItemStream<ApiKey>>> {
+        Err(anyhow::anyhow!("Not supported by the in-memory repo"))
+    }
+
+    async fn delete_one(&self, id: &str) -> DeleteResult {
+        match self.api_keys.lock().unwrap().remove(id) {
+            Some(_) => Ok(()),
+            None => Err(DeleteError::NotFound),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryRefreshTokenRepo {
+    pub refresh_tokens: Mutex<Vec<RefreshToken>>,
+}
+
+#[async_trait::async_trait]
+impl RefreshTokenRepo for MemoryRefreshTokenRepo {
+    async fn insert_one(&self, refresh_token: RefreshToken) -> anyhow::Result<()> {
+        self.refresh_tokens.lock().unwrap().push(refresh_token);
+        Ok(())
+    }
+
+    async fn find_one(&self, token_hash: &str) -> anyhow::Result<Option<RefreshToken>> {
+        let refresh_tokens = self.refresh_tokens.lock().unwrap();
+        Ok(refresh_tokens
+            .iter()
+            .find(|item| item.token_hash == token_hash)
+            .cloned())
+    }
+
+    async fn use_one(&self, token_hash: &str) -> anyhow::Result<Option<RefreshToken>> {
+        let mut refresh_tokens = self.refresh_tokens.lock().unwrap();
+        match refresh_tokens
+            .iter_mut()
+            .find(|item| item.token_hash == token_hash && !item.used)
+        {
+            Some(item) => {
+                let unused = item.clone();
+                item.used = true;
+                Ok(Some(unused))
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn delete_family(&self, family_id: &str) -> anyhow::Result<()> {
+        let mut refresh_tokens = self.refresh_tokens.lock().unwrap();
+        refresh_tokens.retain(|item| item.family_id != family_id);
+        Ok(())
+    }
+
+    async fn delete_by_user_account(&self, user_account_id: &str) -> anyhow::Result<()> {
+        let mut refresh_tokens = self.refresh_tokens.lock().unwrap();
+        refresh_tokens.retain(|item| item.user_account_id != user_account_id);
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct MemorySessionRepo {
+    pub sessions: Mutex<HashMap<String, Session>>,
+}
+
+#[async_trait::async_trait]
+impl SessionRepo for MemorySessionRepo {
+    async fn insert_one(&self, session: Session) -> anyhow::Result<()> {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.insert(session.id.clone(), session);
+        Ok(())
+    }
+
+    async fn find_one(&self, id: &str) -> anyhow::Result<Option<Session>> {
+        Ok(self.sessions.lock().unwrap().get(id).cloned())
+    }
+
+    async fn find_one_by_jti(&self, jti: &str) -> anyhow::Result<Option<Session>> {
+        let sessions = self.sessions.lock().unwrap();
+        Ok(sessions.values().find(|item| item.jti == jti).cloned())
+    }
+
+    async fn find(&self, _filter: SessionFilter) -> anyhow::Result<Box<dyn ItemStream<Session>>> {
+        Err(anyhow::anyhow!("Not supported by the in-memory repo"))
+    }
+
+    async fn refresh_one(&self, id: &str, jti: &str, expires_at: DateTime<Utc>) -> ReplaceResult {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions.get_mut(id).ok_or(ReplaceError::NotFound)?;
+        session.jti = jti.to_string();
+        session.refreshed_at = Utc::now();
+        session.expires_at = expires_at;
+        Ok(())
+    }
+
+    async fn delete_one(&self, id: &str) -> DeleteResult {
+        match self.sessions.lock().unwrap().remove(id) {
+            Some(_) => Ok(()),
+            None => Err(DeleteError::NotFound),
+        }
+    }
+
+    async fn delete_by_user_account(&self, user_account_id: &str) -> anyhow::Result<()> {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, item| item.user_account_id != user_account_id);
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryOneTimeTokenRepo {
+    pub one_time_tokens: Mutex<Vec<OneTimeToken>>,
+}
+
+#[async_trait::async_trait]
+impl OneTimeTokenRepo for MemoryOneTimeTokenRepo {
+    async fn insert_one(&self, one_time_token: OneTimeToken) -> anyhow::Result<()> {
+        self.one_time_tokens.lock().unwrap().push(one_time_token);
+        Ok(())
+    }
+
+    async fn take_one(
+        &self,
+        token_hash: &str,
+        purpose: Purpose,
+    ) -> anyhow::Result<Option<OneTimeToken>> {
+        let mut one_time_tokens = self.one_time_tokens.lock().unwrap();
+        let now = Utc::now();
+        let index = one_time_tokens.iter().position(|item| {
+            item.token_hash == token_hash && item.purpose == purpose && item.expires_at > now
+        });
+        Ok(index.map(|index| one_time_tokens.remove(index)))
+    }
+
+    async fn delete_by_user_account(
+        &self,
+        user_account_id: &str,
+        purpose: Purpose,
+    ) -> anyhow::Result<()> {
+        let mut one_time_tokens = self.one_time_tokens.lock().unwrap();
+        one_time_tokens
+            .retain(|item| !(item.user_account_id == user_account_id && item.purpose == purpose));
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryLoginAttemptRepo {
+    pub login_attempts: Mutex<HashMap<String, LoginAttempts>>,
+}
+
+#[async_trait::async_trait]
+impl LoginAttemptRepo for MemoryLoginAttemptRepo {
+    async fn find_one(&self, key: &str) -> anyhow::Result<Option<LoginAttempts>> {
+        Ok(self.login_attempts.lock().unwrap().get(key).cloned())
+    }
+
+    async fn record_attempt(
+        &self,
+        key: &str,
+        previous: Option<&LoginAttempts>,
+        expires_at: DateTime<Utc>,
+    ) -> anyhow::Result<bool> {
+        let mut login_attempts = self.login_attempts.lock().unwrap();
+        let current = login_attempts
+            .get(key)
+            .map(|attempts| (attempts.failures, attempts.last_failure_at));
+        if current != previous.map(|attempts| (attempts.failures, attempts.last_failure_at)) {
+            return Ok(false);
+        }
+        let attempts = login_attempts
+            .entry(key.to_string())
+            .or_insert_with(|| LoginAttempts {
+                key: key.to_string(),
+                failures: 0,
+                last_failure_at: Utc::now(),
+                expires_at,
+            });
+        attempts.failures += 1;
+        attempts.last_failure_at = Utc::now();
+        attempts.expires_at = expires_at;
+        Ok(true)
+    }
+
+    async fn take_back(&self, key: &str) -> anyhow::Result<()> {
+        if let Some(attempts) = self.login_attempts.lock().unwrap().get_mut(key) {
+            attempts.failures = attempts.failures.saturating_sub(1);
+        }
+        Ok(())
+    }
+
+    async fn delete_one(&self, key: &str) -> anyhow::Result<()> {
+        self.login_attempts.lock().unwrap().remove(key);
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryPersonRepo {
+    pub people: Mutex<HashMap<String, Person>>,
+}
+
+#[async_trait::async_trait]
+impl PersonRepo for MemoryPersonRepo {
+    async fn insert_one(&self, person: Person) -> anyhow::Result<()> {
+        let mut people = self.people.lock().unwrap();
+        people.insert(person.id.clone(), person);
+        Ok(())
+    }
+
+    async fn replace_one(&self, person: Person) -> ReplaceResult {
+        let mut people = self.people.lock().unwrap();
+        match people.get_mut(&person.id) {
+            Some(item) => *item = person,
+            None => return Err(ReplaceError::NotFound),
+        }
+        Ok(())
+    }
+
+    async fn find_one(&self, id: &str) -> anyhow::Result<Option<Person>> {
+        Ok(self.people.lock().unwrap().get(id).cloned())
+    }
+
+    async fn find_many(&self, ids: &[String]) -> anyhow::Result<Vec<Person>> {
+        let people = self.people.lock().unwrap();
+        Ok(ids
+            .iter()
+            .filter_map(|id| people.get(id).cloned())
+            .collect())
+    }
+
+    /// Only filters by IDs and companies.
+    async fn find(&self, filter: PersonFilter) -> anyhow::Result<Box<dyn ItemStream<Person>>> {
+        let people = self.people.lock().unwrap();
+        let items = people
+            .values()
+            .filter(|item| filter.ids.as_ref().is_none_or(|ids| ids.contains(&item.id)))
+            .filter(|item| {
+                filter
+                    .company_ids
+                    .as_ref()
+                    .is_none_or(|ids| ids.contains(&item.company_id))
+            })
+            .cloned()
+            .collect();
+        Ok(item_stream(items))
+    }
+
+    async fn delete_one(&self, id: &str) -> DeleteResult {
+        match self.people.lock().unwrap().remove(id) {
+            Some(_) => Ok(()),
+            None => Err(DeleteError::NotFound),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryTeamRepo {
+    pub teams: Mutex<HashMap<String, Team>>,
+    pub team_people: Mutex<Vec<TeamPerson>>,
+}
+
+impl MemoryTeamRepo {
+    fn team_people(&self, f: impl Fn(&TeamPerson) -> bool) -> Box<dyn ItemStream<TeamPerson>> {
+        let team_people = self.team_people.lock().unwrap();
+        item_stream(team_people.iter().filter(|item| f(item)).cloned().collect())
+    }
+}
+
+#[async_trait::async_trait]
+impl TeamRepo for MemoryTeamRepo {
+    async fn insert_one(&self, team: Team) -> anyhow::Result<()> {
+        let mut teams = self.teams.lock().unwrap();
+        teams.insert(team.id.clone(), team);
+        Ok(())
+    }
+
+    async fn find_one(&self, id: &str) -> anyhow::Result<Option<Team>> {
+        Ok(self.teams.lock().unwrap().get(id).cloned())
+    }
+
+    async fn find_many(&self, ids: &[String]) -> anyhow::Result<Vec<Team>> {
+        let teams = self.teams.lock().unwrap();
+        Ok(ids.iter().filter_map(|id| teams.get(id).cloned()).collect())
+    }
+
+    async fn find(&self, _filter: TeamFilter) -> anyhow::Result<Box<dyn ItemStream<Team>>> {
+        Err(anyhow::anyhow!("Not supported by the in-memory repo"))
+    }
+
+    async fn delete_one(&self, id: &str) -> DeleteResult {
+        match self.teams.lock().unwrap().remove(id) {
+            Some(_) => Ok(()),
+            None => Err(DeleteError::NotFound),
+        }
+    }
+
+    async fn find_people(&self, team_id: &str) -> anyhow::Result<Box<dyn ItemStream<TeamPerson>>> {
+        Ok(self.team_people(|item| item.team_id == team_id))
+    }
+
+    async fn find_members(
+        &self,
+        team_ids: &[String],
+    ) -> anyhow::Result<Box<dyn ItemStream<TeamPerson>>> {
+        Ok(self.team_people(|item| team_ids.contains(&item.team_id)))
+    }
+
+    async fn find_memberships(
+        &self,
+        person_id: &str,
+    ) -> anyhow::Result<Box<dyn ItemStream<TeamPerson>>> {
+        Ok(self.team_people(|item| item.person_id == person_id))
+    }
+
+    async fn add_person(&self, team_id: &str, person_id: &str, lead: bool) -> anyhow::Result<()> {
+        let mut team_people = self.team_people.lock().unwrap();
+        team_people.retain(|item| !(item.team_id == team_id && item.person_id == person_id));
+        team_people.push(TeamPerson {
+            team_id: team_id.to_string(),
+            person_id: person_id.to_string(),
+            lead,
+        });
+        Ok(())
+    }
+
+    async fn remove_person(&self, team_id: &str, person_id: &str) -> DeleteResult {
+        let mut team_people = self.team_people.lock().unwrap();
+        let len = team_people.len();
+        team_people.retain(|item| !(item.team_id == team_id && item.person_id == person_id));
+        if team_people.len() < len {
+            Ok(())
+        } else {
+            Err(DeleteError::NotFound)
+        }
+    }
+}
+
+/// Stands in for the repos of readings, incidents and devices, which tests do not use yet.
+#[derive(Default)]
+pub struct UnsupportedRepo;
+
+fn unsupported<T>() -> anyhow::Result<T> {
+    Err(anyhow::anyhow!("Not supported by the in-memory repo"))
+}
+
+#[async_trait::async_trait]
+impl DeviceRepo for UnsupportedRepo {
+    async fn insert_one(&self, _device: Device) -> anyhow::Result<()> {
+        unsupported()
+    }
+
+    async fn replace_one(&self, _device: Device) -> ReplaceResult {
+        Ok(unsupported()?)
+    }
+
+    async fn find_one(&self, _id: &str) -> anyhow::Result<Option<Device>> {
+        unsupported()
+    }
+
+    async fn find(&self, _filter: DeviceFilter) -> anyhow::Result<Box<dyn ItemStream<Device>>> {
+        unsupported()
+    }
+
+    async fn delete_one(&self, _id: &str) -> DeleteResult {
+        Ok(unsupported()?)
+    }
+}
+
+#[async_trait::async_trait]
+impl GasReadingRepo for UnsupportedRepo {
+    async fn insert_many(&self, _gas_readings: Vec<GasReading>) -> anyhow::Result<()> {
+        unsupported()
+    }
+
+    async fn find(
+        &self,
+        _filter: GasReadingFilter,
+    ) -> anyhow::Result<Box<dyn ItemStream<GasReading>>> {
+        unsupported()
+    }
+}
+
+#[async_trait::async_trait]
+impl ImpersonationRepo for UnsupportedRepo {
+    async fn insert_one(&self, _impersonation: Impersonation) -> anyhow::Result<()> {
+        unsupported()
+    }
+
+    async fn find(
+        &self,
+        _filter: ImpersonationFilter,
+    ) -> anyhow::Result<Box<dyn ItemStream<Impersonation>>> {
+        unsupported()
+    }
+}
+
+#[async_trait::async_trait]
+impl IncidentRepo for UnsupportedRepo {
+    async fn insert_one(&self, _incident: Incident) -> anyhow::Result<()> {
+        unsupported()
+    }
+
+    async fn insert_many(&self, _incidents: Vec<Incident>) -> anyhow::Result<()> {
+        unsupported()
+    }
+
+    async fn replace_one(&self, _incident: Incident) -> ReplaceResult {
+        Ok(unsupported()?)
+    }
+
+    async fn find_one(&self, _id: &str) -> anyhow::Result<Option<Incident>> {
+        unsupported()
+    }
+
+    async fn find(&self, _filter: IncidentFilter) -> anyhow::Result<Box<dyn ItemStream<Incident>>> {
+        unsupported()
+    }
+
+    async fn delete_one(&self, _id: &str) -> DeleteResult {
+        Ok(unsupported()?)
+    }
+}
+
+#[async_trait::async_trait]
+impl IncidentStatsRepo for UnsupportedRepo {
+    async fn find(
+        &self,
+        _filter: IncidentStatsFilter,
+    ) -> anyhow::Result<Box<dyn ItemStream<IncidentStats>>> {
+        unsupported()
+    }
+}
+
+#[async_trait::async_trait]
+impl LocationReadingRepo for UnsupportedRepo {
+    async fn insert_many(&self, _location_readings: Vec<LocationReading>) -> anyhow::Result<()> {
+        unsupported()
+    }
+
+    async fn find(
+        &self,
+        _filter: LocationReadingFilter,
+    ) -> anyhow::Result<Box<dyn ItemStream<LocationReading>>> {
+        unsupported()
+    }
+}
+
+/// People and teams of company "c", where "lead" leads team "a" with "member" and "other" is in
+/// team "b", along with "outsider" of company "d".
+pub async fn company_with_teams() -> (Arc<MemoryPersonRepo>, Arc<MemoryTeamRepo>) {
+    let person_repo = MemoryPersonRepo::default();
+    for (id, company_id) in [
+        ("lead", "c"),
+        ("member", "c"),
+        ("other", "c"),
+        ("outsider", "d"),
+    ] {
+        person_repo
+            .insert_one(Person {
+                id: id.to_string(),
+                name: id.to_string(),
+                company_id: company_id.to_string(),
+            })
+            .await
+            .unwrap();
+    }
+    let team_repo = MemoryTeamRepo::default();
+    for id in ["a", "b"] {
+        team_repo
+            .insert_one(Team {
+                id: id.to_string(),
+                name: id.to_string(),
+                company_id: "c".to_string(),
+            })
+            .await
+            .unwrap();
+    }
+    team_repo.add_person("a", "lead", true).await.unwrap();
+    team_repo.add_person("a", "member", false).await.unwrap();
+    team_repo.add_person("b", "other", false).await.unwrap();
+    (Arc::new(person_repo), Arc::new(team_repo))
+}
@@ -1,14 +1,25 @@
+use chrono::{DateTime, Utc};
 use futures_util::Stream;
 
+pub mod api_key;
 pub mod company;
 pub mod device;
 pub mod gas_reading;
+pub mod impersonation;
 pub mod incident;
 pub mod incident_stats;
 pub mod location_reading;
+pub mod login_attempt;
+#[cfg(test)]
+pub mod memory;
 pub mod mongo_util;
+pub mod oidc_login;
+pub mod one_time_token;
 pub mod person;
+pub mod refresh_token;
+pub mod session;
 pub mod team;
+pub mod tenant;
 pub mod user_account;
 
 pub trait ItemStream<T: Unpin + Send>: Stream<Item = anyhow::Result<T>> + Unpin + Send {}
@@ -20,10 +31,22 @@ where
 {
 }
 
+#[derive(thiserror::Error, Debug)]
+pub enum InsertError {
+    #[error("conflict")]
+    Conflict,
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+pub type InsertResult = Result<(), InsertError>;
+
 #[derive(thiserror::Error, Debug)]
 pub enum ReplaceError {
     #[error("not found")]
     NotFound,
+    #[error("conflict")]
+    Conflict,
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
@@ -39,3 +62,41 @@ pub enum DeleteError {
 }
 
 pub type DeleteResult = Result<(), DeleteError>;
+
+/// Intersection of requested IDs and allowed IDs, where none means any ID.
+pub fn intersect(
+    requested: Option<Vec<String>>,
+    allowed: Option<Vec<String>>,
+) -> Option<Vec<String>> {
+    match (requested, allowed) {
+        (requested, None) => requested,
+        (None, allowed) => allowed,
+        (Some(requested), Some(allowed)) => Some(
+            requested
+                .into_iter()
+                .filter(|id| allowed.contains(id))
+                .collect(),
+        ),
+    }
+}
+
+/// Position of an item in a list ordered by timestamp and then ID, or by ID alone for lists without
+/// timestamps.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor {
+    pub timestamp: Option<DateTime<Utc>>,
+    pub id: String,
+}
+
+/// Part of a list, between two cursors.
+#[derive(Default, Debug, Clone)]
+pub struct Page {
+    /// Only items after this one.
+    pub after: Option<Cursor>,
+    /// Only items before this one.
+    pub before: Option<Cursor>,
+    /// Most items to find. Unlimited if not set.
+    pub limit: Option<i64>,
+    /// Take items from the end instead of the start. They are found last first.
+    pub from_end: bool,
+}
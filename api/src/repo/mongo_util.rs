@@ -1,12 +1,38 @@
-use crate::repo::{DeleteError, DeleteResult, ItemStream, ReplaceError, ReplaceResult};
+use crate::db::{coll, err_code};
+use crate::repo::{
+    Cursor, DeleteError, DeleteResult, InsertError, ItemStream, Page, ReplaceError, ReplaceResult,
+};
+use anyhow::Context;
+use bson::oid::ObjectId;
 use bson::{Bson, Document};
-use futures_util::TryStreamExt;
+use futures_util::{StreamExt, TryStreamExt};
+use mongodb::error::{ErrorKind, WriteFailure};
 use mongodb::options::FindOptions;
 use mongodb::Collection;
 use serde::de::DeserializeOwned;
+use serde::Serialize;
 
 pub const HIDDEN_INCIDENTS: [&str; 1] = ["Logged off"];
 
+/// Field with the lower-case words of the name, so that `filter::name_starts_with` can use an
+/// index. Written by the repos next to the name, it is not part of the items themselves.
+pub const NAME_WORDS: &str = "name_words";
+
+/// Lower-case words of a name, as stored in `NAME_WORDS`.
+pub fn name_words(name: &str) -> Vec<String> {
+    name.to_lowercase()
+        .split_whitespace()
+        .map(String::from)
+        .collect()
+}
+
+/// Serialize an item along with the words of its name.
+pub fn with_name_words<T: Serialize>(item: &T, name: &str) -> anyhow::Result<Document> {
+    let mut doc = bson::to_document(item)?;
+    doc.insert(NAME_WORDS, name_words(name));
+    Ok(doc)
+}
+
 pub trait InsertOpt {
     fn insert_opt<KT: Into<String>, BT: Into<Bson>>(
         &mut self,
@@ -61,6 +87,85 @@ impl<D: DeserializeOwned + Unpin + Send + Sync + 'static> FindStream for Collect
     }
 }
 
+/// Find items like `find_stream`, limited to those whose person, named by `person_field`, belongs
+/// to one of the companies if given. Items do not store their company, so people are joined.
+pub async fn find_stream_of_companies<D, T>(
+    collection: &Collection<D>,
+    mongo_filter: Document,
+    options: impl Into<Option<FindOptions>> + Send,
+    person_field: &str,
+    company_ids: Option<Vec<String>>,
+) -> anyhow::Result<Box<dyn ItemStream<T>>>
+where
+    D: DeserializeOwned + Unpin + Send + Sync + 'static,
+    T: Unpin + Send + 'static,
+    D: Into<T>,
+{
+    let company_ids = match company_ids {
+        None => return collection.find_stream(mongo_filter, options).await,
+        Some(company_ids) => company_ids,
+    };
+    let options = options.into().unwrap_or_default();
+    let mut pipeline = vec![bson::doc! { "$match": mongo_filter }];
+    if let Some(sort) = options.sort {
+        pipeline.push(bson::doc! { "$sort": sort });
+    }
+    pipeline.extend(of_companies(person_field, company_ids));
+    if let Some(limit) = options.limit {
+        pipeline.push(bson::doc! { "$limit": limit });
+    }
+    let cursor = collection.aggregate(pipeline, None).await?;
+    let stream = cursor.map_err(anyhow::Error::from).map(|r| {
+        r.and_then(|d| bson::from_document::<D>(d).map_err(Into::into))
+            .map(Into::into)
+    });
+    Ok(Box::new(stream))
+}
+
+/// Aggregation stages that keep the documents whose person, named by `person_field`, belongs to
+/// one of the companies.
+pub fn of_companies(person_field: &str, company_ids: Vec<String>) -> Vec<Document> {
+    vec![
+        bson::doc! {
+            "$lookup": {
+                "from": coll::PERSON,
+                "localField": person_field,
+                "foreignField": "_id",
+                "as": "_person",
+            }
+        },
+        bson::doc! { "$match": { "_person.company_id": { "$in": company_ids } } },
+        bson::doc! { "$unset": "_person" },
+    ]
+}
+
+pub fn is_duplicate_key(err: &mongodb::error::Error) -> bool {
+    matches!(
+        err.kind.as_ref(),
+        ErrorKind::Write(WriteFailure::WriteError(e)) if e.code == err_code::DUPLICATE_KEY
+    )
+}
+
+impl From<mongodb::error::Error> for InsertError {
+    fn from(value: mongodb::error::Error) -> Self {
+        if is_duplicate_key(&value) {
+            Self::Conflict
+        } else {
+            Self::Other(value.into())
+        }
+    }
+}
+
+impl From<mongodb::error::Error> for ReplaceError {
+    fn from(value: mongodb::error::Error) -> Self {
+        if is_duplicate_key(&value) {
+            Self::Conflict
+        } else {
+            Self::Other(value.into())
+        }
+    }
+}
+
 pub trait FromMatchedCount {
     fn from_matched_count(matched_count: u64) -> Self;
 }
@@ -87,9 +192,98 @@ impl FromDeletedCount for DeleteResult {
     }
 }
 
+/// Limit a query to a page of items ordered by `timestamp` and then `_id`, where `id` converts the
+/// IDs of cursors. Returns the options that sort and limit it.
+pub fn page_by_timestamp(
+    mongo_filter: &mut Document,
+    page: &Page,
+    id: fn(&str) -> anyhow::Result<Bson>,
+) -> anyhow::Result<FindOptions> {
+    paginate(mongo_filter, page, true, id)
+}
+
+/// Limit a query to a page of items ordered by `_id`, where IDs are strings. Returns the options
+/// that sort and limit it.
+pub fn page_by_id(mongo_filter: &mut Document, page: &Page) -> anyhow::Result<FindOptions> {
+    paginate(mongo_filter, page, false, string_id)
+}
+
+pub fn string_id(id: &str) -> anyhow::Result<Bson> {
+    Ok(id.into())
+}
+
+pub fn object_id(id: &str) -> anyhow::Result<Bson> {
+    Ok(ObjectId::parse_str(id)
+        .ok()
+        .context("Invalid cursor")?
+        .into())
+}
+
+fn paginate(
+    mongo_filter: &mut Document,
+    page: &Page,
+    by_timestamp: bool,
+    id: fn(&str) -> anyhow::Result<Bson>,
+) -> anyhow::Result<FindOptions> {
+    let mut bounds = Vec::new();
+    if let Some(cursor) = &page.after {
+        bounds.push(beyond(cursor, "$gt", by_timestamp, id)?);
+    }
+    if let Some(cursor) = &page.before {
+        bounds.push(beyond(cursor, "$lt", by_timestamp, id)?);
+    }
+    if !bounds.is_empty() {
+        and(mongo_filter, bounds);
+    }
+    let direction = if page.from_end { -1 } else { 1 };
+    let sort = if by_timestamp {
+        bson::doc! {"timestamp": direction, "_id": direction}
+    } else {
+        bson::doc! {"_id": direction}
+    };
+    Ok(FindOptions::builder().sort(sort).limit(page.limit).build())
+}
+
+/// Add conditions under `$and`, so they do not replace other conditions on the same fields. Joins
+/// any `$and` the filter already has.
+fn and(mongo_filter: &mut Document, conditions: Vec<Document>) {
+    let mut all = match mongo_filter.remove("$and") {
+        Some(Bson::Array(existing)) => existing,
+        Some(existing) => vec![existing],
+        None => Vec::new(),
+    };
+    all.extend(conditions.into_iter().map(Bson::from));
+    mongo_filter.insert("$and", all);
+}
+
+/// Condition matching the items on one side of a cursor, where `op` is `$gt` or `$lt`.
+fn beyond(
+    cursor: &Cursor,
+    op: &str,
+    by_timestamp: bool,
+    id: fn(&str) -> anyhow::Result<Bson>,
+) -> anyhow::Result<Document> {
+    let id = id(&cursor.id)?;
+    if !by_timestamp {
+        return Ok(bson::doc! {"_id": {op: id}});
+    }
+    let timestamp = bson::DateTime::from_chrono(cursor.timestamp.context("Invalid cursor")?);
+    Ok(bson::doc! {
+        "$or": [
+            {"timestamp": {op: timestamp}},
+            {"timestamp": timestamp, "_id": {op: id}},
+        ]
+    })
+}
+
+/// A new object ID, as stored in `_id` by collections that do not choose their own IDs.
+pub fn new_object_id() -> String {
+    ObjectId::new().to_hex()
+}
+
 pub mod filter {
-    use crate::repo::mongo_util::HIDDEN_INCIDENTS;
-    use bson::{Bson, Document};
+    use crate::repo::mongo_util::{InsertOpt, HIDDEN_INCIDENTS};
+    use bson::{Bson, Document, Regex};
 
     pub fn clamp<T: Into<Bson>>(
         min_inclusive: Option<T>,
@@ -109,6 +303,36 @@ pub mod filter {
         values.map(|v| (bson::doc! { "$in":  v }).into())
     }
 
+    /// Match `NAME_WORDS` of names in which each word of the text starts a word, ignoring case.
+    /// The patterns are anchored and lower-case, so they can use an index on the field. Text
+    /// without words matches everything.
+    pub fn name_starts_with(text: Option<String>) -> Option<Bson> {
+        let patterns: Vec<Bson> = super::name_words(&text?)
+            .iter()
+            .map(|word| {
+                Bson::RegularExpression(Regex {
+                    pattern: format!("^{}", escape_regex(word)),
+                    options: String::new(),
+                })
+            })
+            .collect();
+        if patterns.is_empty() {
+            return None;
+        }
+        Some((bson::doc! { "$all": patterns }).into())
+    }
+
+    fn escape_regex(text: &str) -> String {
+        let mut escaped = String::with_capacity(text.len());
+        for c in text.chars() {
+            if "\\^$.|?*+()[]{}".contains(c) {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+        escaped
+    }
+
     pub fn not_true() -> Bson {
         not(true)
     }
@@ -120,4 +344,100 @@ pub mod filter {
     pub fn not_hidden_incident() -> Bson {
         (bson::doc! { "$nin":  HIDDEN_INCIDENTS.to_vec() }).into()
     }
+
+    /// Incident types that are not hidden, limited to the given ones if any.
+    pub fn incident_type(types: Option<Vec<String>>) -> Bson {
+        let mut doc = bson::doc! { "$nin":  HIDDEN_INCIDENTS.to_vec() };
+        doc.insert_opt("$in", types);
+        doc.into()
+    }
+}
+
+/// Serde helper for optional dates stored as BSON dates, like
+/// `bson::serde_helpers::chrono_datetime_as_bson_datetime`.
+pub mod opt_chrono_datetime_as_bson_datetime {
+    use chrono::{DateTime, Utc};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        value: &Option<DateTime<Utc>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        value.map(bson::DateTime::from_chrono).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<DateTime<Utc>>, D::Error> {
+        Ok(Option::<bson::DateTime>::deserialize(deserializer)?.map(bson::DateTime::to_chrono))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_name_starts_with_escapes_regex() {
+        // Act.
+        let res = filter::name_starts_with(Some("A.b  (C)".to_string()));
+
+        // Assert.
+        let expected: Bson = (bson::doc! { "$all": [
+            Bson::RegularExpression(bson::Regex { pattern: r"^a\.b".to_string(), options: String::new() }),
+            Bson::RegularExpression(bson::Regex { pattern: r"^\(c\)".to_string(), options: String::new() }),
+        ] })
+        .into();
+        assert_eq!(res, Some(expected));
+    }
+
+    #[test]
+    fn test_name_starts_with_blank() {
+        // Act.
+        let res = filter::name_starts_with(Some("  ".to_string()));
+
+        // Assert.
+        assert_eq!(res, None);
+    }
+
+    #[test]
+    fn test_page_by_id_keeps_and() {
+        // Arrange.
+        let mut mongo_filter = bson::doc! { "$and": [{ "name": "a" }] };
+        let page = Page {
+            after: Some(Cursor {
+                timestamp: None,
+                id: "b".to_string(),
+            }),
+            before: None,
+            limit: Some(10),
+            from_end: false,
+        };
+
+        // Act.
+        page_by_id(&mut mongo_filter, &page).unwrap();
+
+        // Assert.
+        assert_eq!(
+            mongo_filter,
+            bson::doc! { "$and": [{ "name": "a" }, { "_id": { "$gt": "b" } }] }
+        );
+    }
+
+    #[test]
+    fn test_incident_type_limits_types() {
+        // Act.
+        let unfiltered = filter::incident_type(None);
+        let filtered = filter::incident_type(Some(vec!["fall".to_string()]));
+
+        // Assert.
+        assert_eq!(
+            unfiltered,
+            (bson::doc! { "$nin": HIDDEN_INCIDENTS.to_vec() }).into()
+        );
+        assert_eq!(
+            filtered,
+            (bson::doc! { "$nin": HIDDEN_INCIDENTS.to_vec(), "$in": ["fall"] }).into()
+        );
+    }
 }
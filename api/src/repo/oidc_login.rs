@@ -0,0 +1,71 @@
+use crate::db::coll;
+use chrono::{DateTime, Utc};
+use mongodb::{Collection, Database};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// A single sign-on login in progress, between the redirect to the identity provider and the
+/// callback. Only a hash of the state parameter is stored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OidcLogin {
+    #[serde(rename = "_id")]
+    pub state_hash: String,
+    pub company_id: String,
+    pub code_verifier: String,
+    pub nonce: String,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub expires_at: DateTime<Utc>,
+}
+
+#[async_trait::async_trait]
+pub trait OidcLoginRepo {
+    async fn insert_one(&self, oidc_login: OidcLogin) -> anyhow::Result<()>;
+    /// Delete an unexpired login. Returns the login if it was found.
+    async fn take_one(&self, state_hash: &str) -> anyhow::Result<Option<OidcLogin>>;
+}
+
+pub type DynOidcLoginRepo = dyn OidcLoginRepo + Send + Sync + 'static;
+
+pub type ArcOidcLoginRepo = Arc<DynOidcLoginRepo>;
+
+#[derive(Debug, Clone)]
+pub struct MongoOidcLoginRepo {
+    pub db: Database,
+}
+
+impl MongoOidcLoginRepo {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    pub fn collection(&self) -> Collection<OidcLogin> {
+        self.db.collection(coll::OIDC_LOGIN)
+    }
+}
+
+#[async_trait::async_trait]
+impl OidcLoginRepo for MongoOidcLoginRepo {
+    async fn insert_one(&self, oidc_login: OidcLogin) -> anyhow::Result<()> {
+        self.collection().insert_one(oidc_login, None).await?;
+        Ok(())
+    }
+
+    async fn take_one(&self, state_hash: &str) -> anyhow::Result<Option<OidcLogin>> {
+        Ok(self
+            .collection()
+            .find_one_and_delete(
+                bson::doc! {
+                    "_id": state_hash,
+                    "expires_at": { "$gt": bson::DateTime::from_chrono(Utc::now()) },
+                },
+                None,
+            )
+            .await?)
+    }
+}
+
+impl From<MongoOidcLoginRepo> for ArcOidcLoginRepo {
+    fn from(value: MongoOidcLoginRepo) -> Self {
+        Arc::new(value)
+    }
+}
@@ -0,0 +1,120 @@
+use crate::db::coll;
+use chrono::{DateTime, Utc};
+use mongodb::{Collection, Database};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Purpose {
+    PasswordReset,
+    /// Second step of a login that requires two-factor authentication.
+    LoginChallenge,
+    /// Invitation to set the password of a new user account.
+    Invitation,
+    /// Emailed link that logs in without a password.
+    LoginLink,
+    /// Emailed link that proves the user account owns its email address.
+    EmailVerification,
+    /// Code sent by text message that proves the user account owns its phone number.
+    PhoneVerification,
+}
+
+/// A single-use token sent to a user, such as in a password reset email. Only a hash of the token
+/// is stored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OneTimeToken {
+    #[serde(rename = "_id")]
+    pub token_hash: String,
+    pub purpose: Purpose,
+    pub user_account_id: String,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub expires_at: DateTime<Utc>,
+    /// Email address or phone number a verification token was sent to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contact: Option<String>,
+}
+
+#[async_trait::async_trait]
+pub trait OneTimeTokenRepo {
+    async fn insert_one(&self, one_time_token: OneTimeToken) -> anyhow::Result<()>;
+    /// Delete an unexpired token with the given purpose. Returns the token if it was found.
+    async fn take_one(
+        &self,
+        token_hash: &str,
+        purpose: Purpose,
+    ) -> anyhow::Result<Option<OneTimeToken>>;
+    async fn delete_by_user_account(
+        &self,
+        user_account_id: &str,
+        purpose: Purpose,
+    ) -> anyhow::Result<()>;
+}
+
+pub type DynOneTimeTokenRepo = dyn OneTimeTokenRepo + Send + Sync + 'static;
+
+pub type ArcOneTimeTokenRepo = Arc<DynOneTimeTokenRepo>;
+
+#[derive(Debug, Clone)]
+pub struct MongoOneTimeTokenRepo {
+    pub db: Database,
+}
+
+impl MongoOneTimeTokenRepo {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    pub fn collection(&self) -> Collection<OneTimeToken> {
+        self.db.collection(coll::ONE_TIME_TOKEN)
+    }
+}
+
+#[async_trait::async_trait]
+impl OneTimeTokenRepo for MongoOneTimeTokenRepo {
+    async fn insert_one(&self, one_time_token: OneTimeToken) -> anyhow::Result<()> {
+        self.collection().insert_one(one_time_token, None).await?;
+        Ok(())
+    }
+
+    async fn take_one(
+        &self,
+        token_hash: &str,
+        purpose: Purpose,
+    ) -> anyhow::Result<Option<OneTimeToken>> {
+        Ok(self
+            .collection()
+            .find_one_and_delete(
+                bson::doc! {
+                    "_id": token_hash,
+                    "purpose": bson::to_bson(&purpose)?,
+                    "expires_at": { "$gt": bson::DateTime::from_chrono(Utc::now()) },
+                },
+                None,
+            )
+            .await?)
+    }
+
+    async fn delete_by_user_account(
+        &self,
+        user_account_id: &str,
+        purpose: Purpose,
+    ) -> anyhow::Result<()> {
+        self.collection()
+            .delete_many(
+                bson::doc! {
+                    "user_account_id": user_account_id,
+                    "purpose": bson::to_bson(&purpose)?,
+                },
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+impl From<MongoOneTimeTokenRepo> for ArcOneTimeTokenRepo {
+    fn from(value: MongoOneTimeTokenRepo) -> Self {
+        Arc::new(value)
+    }
+}
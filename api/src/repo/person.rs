@@ -1,8 +1,12 @@
 use crate::db::coll;
-use crate::repo::mongo_util::{filter, FindStream, FromDeletedCount, FromMatchedCount, InsertOpt};
+use crate::repo::mongo_util::{
+    filter, page_by_id, with_name_words, FindStream, FromDeletedCount, FromMatchedCount, InsertOpt,
+    NAME_WORDS,
+};
 use crate::repo::DeleteResult;
-use crate::repo::{ItemStream, ReplaceResult};
+use crate::repo::{ItemStream, Page, ReplaceResult};
 use bson::Document;
+use futures_util::TryStreamExt;
 use mongodb::{Collection, Database};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
@@ -17,7 +21,11 @@ pub struct Person {
 
 #[derive(Default, Debug, Clone)]
 pub struct PersonFilter {
+    pub ids: Option<Vec<String>>,
     pub company_ids: Option<Vec<String>>,
+    /// Text whose words each start a word of the name, ignoring case.
+    pub name: Option<String>,
+    pub page: Page,
 }
 
 #[async_trait::async_trait]
@@ -25,6 +33,8 @@ pub trait PersonRepo {
     async fn insert_one(&self, person: Person) -> anyhow::Result<()>;
     async fn replace_one(&self, person: Person) -> ReplaceResult;
     async fn find_one(&self, id: &str) -> anyhow::Result<Option<Person>>;
+    /// Find the items with the given IDs, in no particular order. Unknown IDs are skipped.
+    async fn find_many(&self, ids: &[String]) -> anyhow::Result<Vec<Person>>;
     async fn find(&self, filter: PersonFilter) -> anyhow::Result<Box<dyn ItemStream<Person>>>;
     async fn delete_one(&self, id: &str) -> DeleteResult;
 }
@@ -51,14 +61,20 @@ impl MongoPersonRepo {
 #[async_trait::async_trait]
 impl PersonRepo for MongoPersonRepo {
     async fn insert_one(&self, person: Person) -> anyhow::Result<()> {
-        self.collection().insert_one(person, None).await?;
+        let doc = with_name_words(&person, &person.name)?;
+        self.collection()
+            .clone_with_type::<Document>()
+            .insert_one(doc, None)
+            .await?;
         Ok(())
     }
 
     async fn replace_one(&self, person: Person) -> ReplaceResult {
+        let doc = with_name_words(&person, &person.name)?;
         let res = self
             .collection()
-            .replace_one(bson::doc! {"_id": &person.id}, person, None)
+            .clone_with_type::<Document>()
+            .replace_one(bson::doc! {"_id": &person.id}, doc, None)
             .await
             .map_err(anyhow::Error::from)?;
         ReplaceResult::from_matched_count(res.matched_count)
@@ -71,10 +87,21 @@ impl PersonRepo for MongoPersonRepo {
             .await?)
     }
 
+    async fn find_many(&self, ids: &[String]) -> anyhow::Result<Vec<Person>> {
+        self.collection()
+            .find_stream(bson::doc! {"_id": {"$in": ids}}, None)
+            .await?
+            .try_collect()
+            .await
+    }
+
     async fn find(&self, filter: PersonFilter) -> anyhow::Result<Box<dyn ItemStream<Person>>> {
         let mut mongo_filter = Document::new();
+        mongo_filter.insert_opt("_id", filter::one_of(filter.ids));
         mongo_filter.insert_opt("company_id", filter::one_of(filter.company_ids));
-        self.collection().find_stream(mongo_filter, None).await
+        mongo_filter.insert_opt(NAME_WORDS, filter::name_starts_with(filter.name));
+        let options = page_by_id(&mut mongo_filter, &filter.page)?;
+        self.collection().find_stream(mongo_filter, options).await
     }
 
     async fn delete_one(&self, id: &str) -> DeleteResult {
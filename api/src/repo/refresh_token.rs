@@ -0,0 +1,93 @@
+use crate::db::coll;
+use chrono::{DateTime, Utc};
+use mongodb::{Collection, Database};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// A persisted refresh token. Only a hash of the token is stored. Tokens issued by rotating another
+/// token share its family ID.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RefreshToken {
+    #[serde(rename = "_id")]
+    pub token_hash: String,
+    pub family_id: String,
+    pub user_account_id: String,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub expires_at: DateTime<Utc>,
+    pub used: bool,
+}
+
+#[async_trait::async_trait]
+pub trait RefreshTokenRepo {
+    async fn insert_one(&self, refresh_token: RefreshToken) -> anyhow::Result<()>;
+    async fn find_one(&self, token_hash: &str) -> anyhow::Result<Option<RefreshToken>>;
+    /// Mark an unused token as used. Returns the token if it was unused before this call.
+    async fn use_one(&self, token_hash: &str) -> anyhow::Result<Option<RefreshToken>>;
+    async fn delete_family(&self, family_id: &str) -> anyhow::Result<()>;
+    async fn delete_by_user_account(&self, user_account_id: &str) -> anyhow::Result<()>;
+}
+
+pub type DynRefreshTokenRepo = dyn RefreshTokenRepo + Send + Sync + 'static;
+
+pub type ArcRefreshTokenRepo = Arc<DynRefreshTokenRepo>;
+
+#[derive(Debug, Clone)]
+pub struct MongoRefreshTokenRepo {
+    pub db: Database,
+}
+
+impl MongoRefreshTokenRepo {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    pub fn collection(&self) -> Collection<RefreshToken> {
+        self.db.collection(coll::REFRESH_TOKEN)
+    }
+}
+
+#[async_trait::async_trait]
+impl RefreshTokenRepo for MongoRefreshTokenRepo {
+    async fn insert_one(&self, refresh_token: RefreshToken) -> anyhow::Result<()> {
+        self.collection().insert_one(refresh_token, None).await?;
+        Ok(())
+    }
+
+    async fn find_one(&self, token_hash: &str) -> anyhow::Result<Option<RefreshToken>> {
+        Ok(self
+            .collection()
+            .find_one(bson::doc! {"_id": token_hash}, None)
+            .await?)
+    }
+
+    async fn use_one(&self, token_hash: &str) -> anyhow::Result<Option<RefreshToken>> {
+        Ok(self
+            .collection()
+            .find_one_and_update(
+                bson::doc! {"_id": token_hash, "used": false},
+                bson::doc! {"$set": {"used": true}},
+                None,
+            )
+            .await?)
+    }
+
+    async fn delete_family(&self, family_id: &str) -> anyhow::Result<()> {
+        self.collection()
+            .delete_many(bson::doc! {"family_id": family_id}, None)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_by_user_account(&self, user_account_id: &str) -> anyhow::Result<()> {
+        self.collection()
+            .delete_many(bson::doc! {"user_account_id": user_account_id}, None)
+            .await?;
+        Ok(())
+    }
+}
+
+impl From<MongoRefreshTokenRepo> for ArcRefreshTokenRepo {
+    fn from(value: MongoRefreshTokenRepo) -> Self {
+        Arc::new(value)
+    }
+}
@@ -0,0 +1,138 @@
+use crate::db::coll;
+use crate::repo::mongo_util::{filter, FindStream, FromDeletedCount, FromMatchedCount, InsertOpt};
+use crate::repo::{DeleteResult, ItemStream, ReplaceResult};
+use bson::Document;
+use chrono::{DateTime, Utc};
+use mongodb::options::FindOptions;
+use mongodb::{Collection, Database};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// A login session. The session ID is shared with the family of refresh tokens issued for it. The
+/// token ID is replaced each time the session is refreshed, so only the latest access token is
+/// accepted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub user_account_id: String,
+    pub jti: String,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub refreshed_at: DateTime<Utc>,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub expires_at: DateTime<Utc>,
+    /// Admin acting as the user account, if the session is an impersonation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub impersonated_by: Option<String>,
+}
+
+#[derive(Default, Debug, Clone)]
+pub struct SessionFilter {
+    pub user_account_ids: Option<Vec<String>>,
+}
+
+#[async_trait::async_trait]
+pub trait SessionRepo {
+    async fn insert_one(&self, session: Session) -> anyhow::Result<()>;
+    async fn find_one(&self, id: &str) -> anyhow::Result<Option<Session>>;
+    async fn find_one_by_jti(&self, jti: &str) -> anyhow::Result<Option<Session>>;
+    async fn find(&self, filter: SessionFilter) -> anyhow::Result<Box<dyn ItemStream<Session>>>;
+    async fn refresh_one(&self, id: &str, jti: &str, expires_at: DateTime<Utc>) -> ReplaceResult;
+    async fn delete_one(&self, id: &str) -> DeleteResult;
+    async fn delete_by_user_account(&self, user_account_id: &str) -> anyhow::Result<()>;
+}
+
+pub type DynSessionRepo = dyn SessionRepo + Send + Sync + 'static;
+
+pub type ArcSessionRepo = Arc<DynSessionRepo>;
+
+#[derive(Debug, Clone)]
+pub struct MongoSessionRepo {
+    pub db: Database,
+}
+
+impl MongoSessionRepo {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    pub fn collection(&self) -> Collection<Session> {
+        self.db.collection(coll::SESSION)
+    }
+}
+
+#[async_trait::async_trait]
+impl SessionRepo for MongoSessionRepo {
+    async fn insert_one(&self, session: Session) -> anyhow::Result<()> {
+        self.collection().insert_one(session, None).await?;
+        Ok(())
+    }
+
+    async fn find_one(&self, id: &str) -> anyhow::Result<Option<Session>> {
+        Ok(self
+            .collection()
+            .find_one(bson::doc! {"_id": id}, None)
+            .await?)
+    }
+
+    async fn find_one_by_jti(&self, jti: &str) -> anyhow::Result<Option<Session>> {
+        Ok(self
+            .collection()
+            .find_one(bson::doc! {"jti": jti}, None)
+            .await?)
+    }
+
+    async fn find(&self, filter: SessionFilter) -> anyhow::Result<Box<dyn ItemStream<Session>>> {
+        let mut mongo_filter = Document::new();
+        mongo_filter.insert_opt("user_account_id", filter::one_of(filter.user_account_ids));
+        self.collection()
+            .find_stream(
+                mongo_filter,
+                FindOptions::builder()
+                    .sort(bson::doc! {"created_at": 1})
+                    .build(),
+            )
+            .await
+    }
+
+    async fn refresh_one(&self, id: &str, jti: &str, expires_at: DateTime<Utc>) -> ReplaceResult {
+        let res = self
+            .collection()
+            .update_one(
+                bson::doc! {"_id": id},
+                bson::doc! {"$set": {
+                    "jti": jti,
+                    "refreshed_at": bson::DateTime::from_chrono(Utc::now()),
+                    "expires_at": bson::DateTime::from_chrono(expires_at),
+                }},
+                None,
+            )
+            .await
+            .map_err(anyhow::Error::from)?;
+        ReplaceResult::from_matched_count(res.matched_count)
+    }
+
+    async fn delete_one(&self, id: &str) -> DeleteResult {
+        let res = self
+            .collection()
+            .delete_one(bson::doc! {"_id": id}, None)
+            .await
+            .map_err(anyhow::Error::from)?;
+        DeleteResult::from_deleted_count(res.deleted_count)
+    }
+
+    async fn delete_by_user_account(&self, user_account_id: &str) -> anyhow::Result<()> {
+        self.collection()
+            .delete_many(bson::doc! {"user_account_id": user_account_id}, None)
+            .await?;
+        Ok(())
+    }
+}
+
+impl From<MongoSessionRepo> for ArcSessionRepo {
+    fn from(value: MongoSessionRepo) -> Self {
+        Arc::new(value)
+    }
+}
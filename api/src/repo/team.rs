@@ -1,8 +1,12 @@
 use crate::db::coll;
-use crate::repo::mongo_util::{filter, FindStream, FromDeletedCount, InsertOpt};
+use crate::repo::mongo_util::{
+    filter, page_by_id, with_name_words, FindStream, FromDeletedCount, InsertOpt, NAME_WORDS,
+};
 use crate::repo::DeleteResult;
-use crate::repo::ItemStream;
+use crate::repo::{ItemStream, Page};
 use bson::Document;
+use futures_util::TryStreamExt;
+use mongodb::options::UpdateOptions;
 use mongodb::{Collection, Database};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
@@ -19,21 +23,41 @@ pub struct Team {
 pub struct TeamPerson {
     pub team_id: String,
     pub person_id: String,
+    /// Whether the person leads the team.
+    #[serde(default)]
+    pub lead: bool,
 }
 
 #[derive(Default, Debug, Clone)]
 pub struct TeamFilter {
+    pub ids: Option<Vec<String>>,
     pub company_ids: Option<Vec<String>>,
+    /// Text whose words each start a word of the name, ignoring case.
+    pub name: Option<String>,
+    pub page: Page,
 }
 
 #[async_trait::async_trait]
 pub trait TeamRepo {
     async fn insert_one(&self, team: Team) -> anyhow::Result<()>;
     async fn find_one(&self, id: &str) -> anyhow::Result<Option<Team>>;
+    /// Find the items with the given IDs, in no particular order. Unknown IDs are skipped.
+    async fn find_many(&self, ids: &[String]) -> anyhow::Result<Vec<Team>>;
     async fn find(&self, filter: TeamFilter) -> anyhow::Result<Box<dyn ItemStream<Team>>>;
     async fn delete_one(&self, id: &str) -> DeleteResult;
     async fn find_people(&self, team_id: &str) -> anyhow::Result<Box<dyn ItemStream<TeamPerson>>>;
-    async fn add_person(&self, team_id: &str, person_id: &str) -> anyhow::Result<()>;
+    /// People who belong to any of the teams.
+    async fn find_members(
+        &self,
+        team_ids: &[String],
+    ) -> anyhow::Result<Box<dyn ItemStream<TeamPerson>>>;
+    /// Teams a person belongs to.
+    async fn find_memberships(
+        &self,
+        person_id: &str,
+    ) -> anyhow::Result<Box<dyn ItemStream<TeamPerson>>>;
+    /// Add a person to a team, or update whether they lead it if they already belong to it.
+    async fn add_person(&self, team_id: &str, person_id: &str, lead: bool) -> anyhow::Result<()>;
     async fn remove_person(&self, team_id: &str, person_id: &str) -> DeleteResult;
 }
 
@@ -63,7 +87,11 @@ impl MongoTeamRepo {
 #[async_trait::async_trait]
 impl TeamRepo for MongoTeamRepo {
     async fn insert_one(&self, team: Team) -> anyhow::Result<()> {
-        self.collection().insert_one(team, None).await?;
+        let doc = with_name_words(&team, &team.name)?;
+        self.collection()
+            .clone_with_type::<Document>()
+            .insert_one(doc, None)
+            .await?;
         Ok(())
     }
 
@@ -74,10 +102,21 @@ impl TeamRepo for MongoTeamRepo {
             .await?)
     }
 
+    async fn find_many(&self, ids: &[String]) -> anyhow::Result<Vec<Team>> {
+        self.collection()
+            .find_stream(bson::doc! {"_id": {"$in": ids}}, None)
+            .await?
+            .try_collect()
+            .await
+    }
+
     async fn find(&self, filter: TeamFilter) -> anyhow::Result<Box<dyn ItemStream<Team>>> {
         let mut mongo_filter = Document::new();
+        mongo_filter.insert_opt("_id", filter::one_of(filter.ids));
         mongo_filter.insert_opt("company_id", filter::one_of(filter.company_ids));
-        self.collection().find_stream(mongo_filter, None).await
+        mongo_filter.insert_opt(NAME_WORDS, filter::name_starts_with(filter.name));
+        let options = page_by_id(&mut mongo_filter, &filter.page)?;
+        self.collection().find_stream(mongo_filter, options).await
     }
 
     async fn delete_one(&self, id: &str) -> DeleteResult {
@@ -95,14 +134,30 @@ impl TeamRepo for MongoTeamRepo {
             .await
     }
 
-    async fn add_person(&self, team_id: &str, person_id: &str) -> anyhow::Result<()> {
+    async fn find_members(
+        &self,
+        team_ids: &[String],
+    ) -> anyhow::Result<Box<dyn ItemStream<TeamPerson>>> {
         self.person_collection()
-            .insert_one(
-                TeamPerson {
-                    team_id: team_id.to_string(),
-                    person_id: person_id.to_string(),
-                },
-                None,
+            .find_stream(bson::doc! { "team_id": { "$in": team_ids } }, None)
+            .await
+    }
+
+    async fn find_memberships(
+        &self,
+        person_id: &str,
+    ) -> anyhow::Result<Box<dyn ItemStream<TeamPerson>>> {
+        self.person_collection()
+            .find_stream(bson::doc! { "person_id": person_id }, None)
+            .await
+    }
+
+    async fn add_person(&self, team_id: &str, person_id: &str, lead: bool) -> anyhow::Result<()> {
+        self.person_collection()
+            .update_one(
+                bson::doc! { "team_id": team_id, "person_id": person_id },
+                bson::doc! { "$set": { "lead": lead } },
+                UpdateOptions::builder().upsert(true).build(),
             )
             .await?;
         Ok(())
@@ -0,0 +1,1018 @@
+use crate::repo::company::{ArcCompanyRepo, Company, CompanyRepo};
+use crate::repo::device::{ArcDeviceRepo, Device, DeviceFilter, DeviceRepo};
+use crate::repo::gas_reading::{ArcGasReadingRepo, GasReading, GasReadingFilter, GasReadingRepo};
+use crate::repo::impersonation::{
+    ArcImpersonationRepo, Impersonation, ImpersonationFilter, ImpersonationRepo,
+};
+use crate::repo::incident::{ArcIncidentRepo, Incident, IncidentFilter, IncidentRepo};
+use crate::repo::incident_stats::{
+    ArcIncidentStatsRepo, IncidentStats, IncidentStatsFilter, IncidentStatsRepo,
+};
+use crate::repo::location_reading::{
+    ArcLocationReadingRepo, LocationReading, LocationReadingFilter, LocationReadingRepo,
+};
+use crate::repo::person::{ArcPersonRepo, Person, PersonFilter, PersonRepo};
+use crate::repo::team::{ArcTeamRepo, Team, TeamFilter, TeamPerson, TeamRepo};
+use crate::repo::user_account::{
+    ArcUserAccountRepo, Creds, Totp, UserAccount, UserAccountFilter, UserAccountRepo,
+};
+use crate::repo::{
+    intersect, DeleteError, DeleteResult, InsertResult, ItemStream, ReplaceError, ReplaceResult,
+};
+use futures_util::{future, TryStreamExt};
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokio::sync::OnceCell;
+
+/// The companies a request may access.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Tenant {
+    /// Every company, for super admins.
+    All,
+    /// A single company.
+    Company(String),
+    /// No company, for unauthenticated requests.
+    Nobody,
+}
+
+impl Tenant {
+    pub fn allows(&self, company_id: &str) -> bool {
+        match self {
+            Tenant::All => true,
+            Tenant::Company(id) => id == company_id,
+            Tenant::Nobody => false,
+        }
+    }
+
+    /// IDs of the companies that may be accessed, or none if every company may be accessed.
+    pub fn company_ids(&self) -> Option<Vec<String>> {
+        match self {
+            Tenant::All => None,
+            Tenant::Company(id) => Some(vec![id.clone()]),
+            Tenant::Nobody => Some(Vec::new()),
+        }
+    }
+
+    /// Limit a filter on company IDs to the companies that may be accessed.
+    pub fn restrict(&self, company_ids: Option<Vec<String>>) -> Option<Vec<String>> {
+        intersect(company_ids, self.company_ids())
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("item belongs to another company")]
+pub struct TenantError;
+
+/// Wraps repos so they only read and write data of the companies a tenant may access. Resolvers
+/// use the wrapped repos, so a query cannot reach data of another company even if it forgets to
+/// filter by company.
+///
+/// Readings, incidents and teams can be further limited to the teams a person leads, for team
+/// leads who may only see their own teams.
+#[derive(Clone)]
+pub struct TenantScope {
+    pub tenant: Tenant,
+    /// Person whose led teams limit access, if any.
+    pub lead_person_id: Option<String>,
+    /// Unscoped repo used to find which company people belong to.
+    pub person_repo: ArcPersonRepo,
+    /// Unscoped repo used to find which teams a person leads.
+    pub team_repo: ArcTeamRepo,
+    led_teams: Arc<OnceCell<Option<LedTeams>>>,
+}
+
+/// Teams a person leads and the people in them.
+#[derive(Debug, Clone)]
+struct LedTeams {
+    team_ids: Vec<String>,
+    person_ids: Vec<String>,
+}
+
+impl TenantScope {
+    pub fn new(
+        tenant: Tenant,
+        lead_person_id: Option<String>,
+        person_repo: ArcPersonRepo,
+        team_repo: ArcTeamRepo,
+    ) -> Self {
+        Self {
+            tenant,
+            lead_person_id,
+            person_repo,
+            team_repo,
+            led_teams: Arc::new(OnceCell::new()),
+        }
+    }
+
+    /// Copy of the scope that finds the led teams again, for connections that outlive a request.
+    pub fn refreshed(&self) -> Self {
+        Self {
+            led_teams: Arc::new(OnceCell::new()),
+            ..self.clone()
+        }
+    }
+
+    pub fn company_repo(&self, inner: ArcCompanyRepo) -> ArcCompanyRepo {
+        match self.tenant {
+            Tenant::All => inner,
+            _ => Arc::new(TenantCompanyRepo {
+                inner,
+                scope: self.clone(),
+            }),
+        }
+    }
+
+    pub fn device_repo(&self, inner: ArcDeviceRepo) -> ArcDeviceRepo {
+        match self.tenant {
+            Tenant::All => inner,
+            _ => Arc::new(TenantDeviceRepo {
+                inner,
+                scope: self.clone(),
+            }),
+        }
+    }
+
+    pub fn impersonation_repo(&self, inner: ArcImpersonationRepo) -> ArcImpersonationRepo {
+        match self.tenant {
+            Tenant::All => inner,
+            _ => Arc::new(TenantImpersonationRepo {
+                inner,
+                scope: self.clone(),
+            }),
+        }
+    }
+
+    pub fn gas_reading_repo(&self, inner: ArcGasReadingRepo) -> ArcGasReadingRepo {
+        if self.is_unrestricted() {
+            inner
+        } else {
+            Arc::new(TenantGasReadingRepo {
+                inner,
+                scope: self.clone(),
+            })
+        }
+    }
+
+    pub fn incident_repo(&self, inner: ArcIncidentRepo) -> ArcIncidentRepo {
+        if self.is_unrestricted() {
+            inner
+        } else {
+            Arc::new(TenantIncidentRepo {
+                inner,
+                scope: self.clone(),
+            })
+        }
+    }
+
+    pub fn incident_stats_repo(&self, inner: ArcIncidentStatsRepo) -> ArcIncidentStatsRepo {
+        if self.is_unrestricted() {
+            inner
+        } else {
+            Arc::new(TenantIncidentStatsRepo {
+                inner,
+                scope: self.clone(),
+            })
+        }
+    }
+
+    pub fn location_reading_repo(&self, inner: ArcLocationReadingRepo) -> ArcLocationReadingRepo {
+        if self.is_unrestricted() {
+            inner
+        } else {
+            Arc::new(TenantLocationReadingRepo {
+                inner,
+                scope: self.clone(),
+            })
+        }
+    }
+
+    pub fn person_repo(&self, inner: ArcPersonRepo) -> ArcPersonRepo {
+        match self.tenant {
+            Tenant::All => inner,
+            _ => Arc::new(TenantPersonRepo {
+                inner,
+                scope: self.clone(),
+            }),
+        }
+    }
+
+    pub fn team_repo(&self, inner: ArcTeamRepo) -> ArcTeamRepo {
+        if self.is_unrestricted() {
+            inner
+        } else {
+            Arc::new(TenantTeamRepo {
+                inner,
+                scope: self.clone(),
+            })
+        }
+    }
+
+    pub fn user_account_repo(&self, inner: ArcUserAccountRepo) -> ArcUserAccountRepo {
+        match self.tenant {
+            Tenant::All => inner,
+            _ => Arc::new(TenantUserAccountRepo {
+                inner,
+                scope: self.clone(),
+            }),
+        }
+    }
+
+    fn is_unrestricted(&self) -> bool {
+        self.tenant == Tenant::All && self.lead_person_id.is_none()
+    }
+
+    /// Limit a filter on person IDs to the people in the led teams, if any. The companies of the
+    /// people are limited by the company filter of the query, so their IDs are not loaded here.
+    async fn restrict_subjects(
+        &self,
+        person_ids: Option<Vec<String>>,
+    ) -> anyhow::Result<Option<Vec<String>>> {
+        Ok(match self.led_teams().await? {
+            Some(led_teams) => intersect(person_ids, Some(led_teams.person_ids.clone())),
+            None => person_ids,
+        })
+    }
+
+    /// Teams the lead person leads, or none if access is not limited to led teams. A person who
+    /// leads no team can access no team.
+    async fn led_teams(&self) -> anyhow::Result<Option<&LedTeams>> {
+        let led_teams = self
+            .led_teams
+            .get_or_try_init(|| async {
+                let lead_person_id = match &self.lead_person_id {
+                    Some(lead_person_id) => lead_person_id,
+                    None => return Ok::<_, anyhow::Error>(None),
+                };
+                let team_ids: Vec<String> = self
+                    .team_repo
+                    .find_memberships(lead_person_id)
+                    .await?
+                    .try_filter(|membership| future::ready(membership.lead))
+                    .map_ok(|membership| membership.team_id)
+                    .try_collect()
+                    .await?;
+                let mut person_ids = HashSet::new();
+                for team_id in &team_ids {
+                    let members: Vec<String> = self
+                        .team_repo
+                        .find_people(team_id)
+                        .await?
+                        .map_ok(|member| member.person_id)
+                        .try_collect()
+                        .await?;
+                    person_ids.extend(members);
+                }
+                Ok(Some(LedTeams {
+                    team_ids,
+                    person_ids: person_ids.into_iter().collect(),
+                }))
+            })
+            .await?;
+        Ok(led_teams.as_ref())
+    }
+
+    async fn allows_person(&self, person_id: &str) -> anyhow::Result<bool> {
+        if self.tenant == Tenant::All {
+            return Ok(true);
+        }
+        let person = self.person_repo.find_one(person_id).await?;
+        Ok(matches!(person, Some(person) if self.tenant.allows(&person.company_id)))
+    }
+
+    /// Whether the readings and incidents of a person may be accessed.
+    pub async fn allows_subject(&self, person_id: &str) -> anyhow::Result<bool> {
+        if let Some(led_teams) = self.led_teams().await? {
+            if !led_teams.person_ids.iter().any(|id| id == person_id) {
+                return Ok(false);
+            }
+        }
+        self.allows_person(person_id).await
+    }
+
+    async fn allows_team(&self, team: &Team) -> anyhow::Result<bool> {
+        if !self.tenant.allows(&team.company_id) {
+            return Ok(false);
+        }
+        Ok(match self.led_teams().await? {
+            Some(led_teams) => led_teams.team_ids.contains(&team.id),
+            None => true,
+        })
+    }
+
+    /// Check that all the people may be accessed, finding them in a single query.
+    async fn verify_people<'a>(
+        &self,
+        person_ids: impl IntoIterator<Item = &'a str>,
+    ) -> anyhow::Result<()> {
+        if self.tenant == Tenant::All {
+            return Ok(());
+        }
+        let person_ids: HashSet<_> = person_ids.into_iter().collect();
+        let found: Vec<Person> = self
+            .person_repo
+            .find(PersonFilter {
+                ids: Some(person_ids.iter().map(|id| id.to_string()).collect()),
+                company_ids: self.tenant.company_ids(),
+                ..Default::default()
+            })
+            .await?
+            .try_collect()
+            .await?;
+        if found.len() != person_ids.len() {
+            return Err(TenantError.into());
+        }
+        Ok(())
+    }
+
+    async fn verify_subjects<'a>(
+        &self,
+        person_ids: impl IntoIterator<Item = &'a str>,
+    ) -> anyhow::Result<()> {
+        let person_ids: HashSet<_> = person_ids.into_iter().collect();
+        if let Some(led_teams) = self.led_teams().await? {
+            if !person_ids
+                .iter()
+                .all(|id| led_teams.person_ids.iter().any(|p| p == id))
+            {
+                return Err(TenantError.into());
+            }
+        }
+        self.verify_people(person_ids).await
+    }
+
+    fn verify_company(&self, company_id: &str) -> anyhow::Result<()> {
+        if self.tenant.allows(company_id) {
+            Ok(())
+        } else {
+            Err(TenantError.into())
+        }
+    }
+}
+
+struct TenantCompanyRepo {
+    inner: ArcCompanyRepo,
+    scope: TenantScope,
+}
+
+#[async_trait::async_trait]
+impl CompanyRepo for TenantCompanyRepo {
+    async fn insert_one(&self, company: Company) -> anyhow::Result<()> {
+        self.scope.verify_company(&company.id)?;
+        self.inner.insert_one(company).await
+    }
+
+    async fn replace_one(&self, company: Company) -> ReplaceResult {
+        if !self.scope.tenant.allows(&company.id) {
+            return Err(ReplaceError::NotFound);
+        }
+        self.inner.replace_one(company).await
+    }
+
+    async fn find_one(&self, id: &str) -> anyhow::Result<Option<Company>> {
+        if !self.scope.tenant.allows(id) {
+            return Ok(None);
+        }
+        self.inner.find_one(id).await
+    }
+
+    async fn find_many(&self, ids: &[String]) -> anyhow::Result<Vec<Company>> {
+        let ids: Vec<String> = ids
+            .iter()
+            .filter(|id| self.scope.tenant.allows(id))
+            .cloned()
+            .collect();
+        self.inner.find_many(&ids).await
+    }
+
+    async fn find(&self) -> anyhow::Result<Box<dyn ItemStream<Company>>> {
+        let company_ids = match self.scope.tenant.company_ids() {
+            None => return self.inner.find().await,
+            Some(company_ids) => company_ids,
+        };
+        let mut companies = Vec::new();
+        for company_id in company_ids {
+            companies.extend(self.inner.find_one(&company_id).await?);
+        }
+        Ok(item_stream(companies))
+    }
+
+    async fn delete_one(&self, id: &str) -> DeleteResult {
+        if !self.scope.tenant.allows(id) {
+            return Err(DeleteError::NotFound);
+        }
+        self.inner.delete_one(id).await
+    }
+}
+
+struct TenantDeviceRepo {
+    inner: ArcDeviceRepo,
+    scope: TenantScope,
+}
+
+#[async_trait::async_trait]
+impl DeviceRepo for TenantDeviceRepo {
+    async fn insert_one(&self, device: Device) -> anyhow::Result<()> {
+        self.scope.verify_people([device.owner_id.as_str()]).await?;
+        self.inner.insert_one(device).await
+    }
+
+    async fn replace_one(&self, device: Device) -> ReplaceResult {
+        if self.find_one(&device.id).await?.is_none() {
+            return Err(ReplaceError::NotFound);
+        }
+        self.scope.verify_people([device.owner_id.as_str()]).await?;
+        self.inner.replace_one(device).await
+    }
+
+    async fn find_one(&self, id: &str) -> anyhow::Result<Option<Device>> {
+        match self.inner.find_one(id).await? {
+            Some(device) if self.scope.allows_person(&device.owner_id).await? => Ok(Some(device)),
+            _ => Ok(None),
+        }
+    }
+
+    async fn find(&self, filter: DeviceFilter) -> anyhow::Result<Box<dyn ItemStream<Device>>> {
+        self.inner
+            .find(DeviceFilter {
+                company_ids: self.scope.tenant.restrict(filter.company_ids),
+                ..filter
+            })
+            .await
+    }
+
+    async fn delete_one(&self, id: &str) -> DeleteResult {
+        if self.find_one(id).await?.is_none() {
+            return Err(DeleteError::NotFound);
+        }
+        self.inner.delete_one(id).await
+    }
+}
+
+struct TenantImpersonationRepo {
+    inner: ArcImpersonationRepo,
+    scope: TenantScope,
+}
+
+#[async_trait::async_trait]
+impl ImpersonationRepo for TenantImpersonationRepo {
+    async fn insert_one(&self, impersonation: Impersonation) -> anyhow::Result<()> {
+        self.scope.verify_company(&impersonation.company_id)?;
+        self.inner.insert_one(impersonation).await
+    }
+
+    async fn find(
+        &self,
+        filter: ImpersonationFilter,
+    ) -> anyhow::Result<Box<dyn ItemStream<Impersonation>>> {
+        self.inner
+            .find(ImpersonationFilter {
+                company_ids: self.scope.tenant.restrict(filter.company_ids),
+                user_account_ids: filter.user_account_ids,
+            })
+            .await
+    }
+}
+
+struct TenantGasReadingRepo {
+    inner: ArcGasReadingRepo,
+    scope: TenantScope,
+}
+
+#[async_trait::async_trait]
+impl GasReadingRepo for TenantGasReadingRepo {
+    async fn insert_many(&self, gas_readings: Vec<GasReading>) -> anyhow::Result<()> {
+        self.scope
+            .verify_subjects(gas_readings.iter().map(|r| r.person_id.as_str()))
+            .await?;
+        self.inner.insert_many(gas_readings).await
+    }
+
+    async fn find(
+        &self,
+        filter: GasReadingFilter,
+    ) -> anyhow::Result<Box<dyn ItemStream<GasReading>>> {
+        self.inner
+            .find(GasReadingFilter {
+                person_ids: self.scope.restrict_subjects(filter.person_ids).await?,
+                company_ids: self.scope.tenant.restrict(filter.company_ids),
+                ..filter
+            })
+            .await
+    }
+}
+
+struct TenantIncidentRepo {
+    inner: ArcIncidentRepo,
+    scope: TenantScope,
+}
+
+#[async_trait::async_trait]
+impl IncidentRepo for TenantIncidentRepo {
+    async fn insert_one(&self, incident: Incident) -> anyhow::Result<()> {
+        self.scope
+            .verify_subjects([incident.person_id.as_str()])
+            .await?;
+        self.inner.insert_one(incident).await
+    }
+
+    async fn insert_many(&self, incidents: Vec<Incident>) -> anyhow::Result<()> {
+        self.scope
+            .verify_subjects(incidents.iter().map(|i| i.person_id.as_str()))
+            .await?;
+        self.inner.insert_many(incidents).await
+    }
+
+    async fn replace_one(&self, incident: Incident) -> ReplaceResult {
+        if self.find_one(&incident.id).await?.is_none() {
+            return Err(ReplaceError::NotFound);
+        }
+        self.scope
+            .verify_subjects([incident.person_id.as_str()])
+            .await?;
+        self.inner.replace_one(incident).await
+    }
+
+    async fn find_one(&self, id: &str) -> anyhow::Result<Option<Incident>> {
+        match self.inner.find_one(id).await? {
+            Some(incident) if self.scope.allows_subject(&incident.person_id).await? => {
+                Ok(Some(incident))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    async fn find(&self, filter: IncidentFilter) -> anyhow::Result<Box<dyn ItemStream<Incident>>> {
+        self.inner
+            .find(IncidentFilter {
+                person_ids: self.scope.restrict_subjects(filter.person_ids).await?,
+                company_ids: self.scope.tenant.restrict(filter.company_ids),
+                ..filter
+            })
+            .await
+    }
+
+    async fn delete_one(&self, id: &str) -> DeleteResult {
+        if self.find_one(id).await?.is_none() {
+            return Err(DeleteError::NotFound);
+        }
+        self.inner.delete_one(id).await
+    }
+}
+
+struct TenantIncidentStatsRepo {
+    inner: ArcIncidentStatsRepo,
+    scope: TenantScope,
+}
+
+#[async_trait::async_trait]
+impl IncidentStatsRepo for TenantIncidentStatsRepo {
+    async fn find(
+        &self,
+        filter: IncidentStatsFilter,
+    ) -> anyhow::Result<Box<dyn ItemStream<IncidentStats>>> {
+        self.inner
+            .find(IncidentStatsFilter {
+                person_ids: self.scope.restrict_subjects(filter.person_ids).await?,
+                company_ids: self.scope.tenant.restrict(filter.company_ids),
+                ..filter
+            })
+            .await
+    }
+}
+
+struct TenantLocationReadingRepo {
+    inner: ArcLocationReadingRepo,
+    scope: TenantScope,
+}
+
+#[async_trait::async_trait]
+impl LocationReadingRepo for TenantLocationReadingRepo {
+    async fn insert_many(&self, location_readings: Vec<LocationReading>) -> anyhow::Result<()> {
+        self.scope
+            .verify_subjects(location_readings.iter().map(|r| r.person_id.as_str()))
+            .await?;
+        self.inner.insert_many(location_readings).await
+    }
+
+    async fn find(
+        &self,
+        filter: LocationReadingFilter,
+    ) -> anyhow::Result<Box<dyn ItemStream<LocationReading>>> {
+        self.inner
+            .find(LocationReadingFilter {
+                person_ids: self.scope.restrict_subjects(filter.person_ids).await?,
+                company_ids: self.scope.tenant.restrict(filter.company_ids),
+                ..filter
+            })
+            .await
+    }
+}
+
+struct TenantPersonRepo {
+    inner: ArcPersonRepo,
+    scope: TenantScope,
+}
+
+#[async_trait::async_trait]
+impl PersonRepo for TenantPersonRepo {
+    async fn insert_one(&self, person: Person) -> anyhow::Result<()> {
+        self.scope.verify_company(&person.company_id)?;
+        self.inner.insert_one(person).await
+    }
+
+    async fn replace_one(&self, person: Person) -> ReplaceResult {
+        if self.find_one(&person.id).await?.is_none() {
+            return Err(ReplaceError::NotFound);
+        }
+        self.scope.verify_company(&person.company_id)?;
+        self.inner.replace_one(person).await
+    }
+
+    async fn find_one(&self, id: &str) -> anyhow::Result<Option<Person>> {
+        Ok(self
+            .inner
+            .find_one(id)
+            .await?
+            .filter(|person| self.scope.tenant.allows(&person.company_id)))
+    }
+
+    async fn find_many(&self, ids: &[String]) -> anyhow::Result<Vec<Person>> {
+        let mut people = self.inner.find_many(ids).await?;
+        people.retain(|person| self.scope.tenant.allows(&person.company_id));
+        Ok(people)
+    }
+
+    async fn find(&self, filter: PersonFilter) -> anyhow::Result<Box<dyn ItemStream<Person>>> {
+        self.inner
+            .find(PersonFilter {
+                company_ids: self.scope.tenant.restrict(filter.company_ids),
+                ..filter
+            })
+            .await
+    }
+
+    async fn delete_one(&self, id: &str) -> DeleteResult {
+        if self.find_one(id).await?.is_none() {
+            return Err(DeleteError::NotFound);
+        }
+        self.inner.delete_one(id).await
+    }
+}
+
+struct TenantTeamRepo {
+    inner: ArcTeamRepo,
+    scope: TenantScope,
+}
+
+#[async_trait::async_trait]
+impl TeamRepo for TenantTeamRepo {
+    async fn insert_one(&self, team: Team) -> anyhow::Result<()> {
+        self.scope.verify_company(&team.company_id)?;
+        self.inner.insert_one(team).await
+    }
+
+    async fn find_one(&self, id: &str) -> anyhow::Result<Option<Team>> {
+        match self.inner.find_one(id).await? {
+            Some(team) if self.scope.allows_team(&team).await? => Ok(Some(team)),
+            _ => Ok(None),
+        }
+    }
+
+    async fn find_many(&self, ids: &[String]) -> anyhow::Result<Vec<Team>> {
+        let mut teams = Vec::new();
+        for team in self.inner.find_many(ids).await? {
+            if self.scope.allows_team(&team).await? {
+                teams.push(team);
+            }
+        }
+        Ok(teams)
+    }
+
+    async fn find(&self, filter: TeamFilter) -> anyhow::Result<Box<dyn ItemStream<Team>>> {
+        // Limited by ID rather than afterwards, so that pages are not cut short.
+        let ids = match self.scope.led_teams().await? {
+            None => filter.ids,
+            Some(led_teams) => intersect(filter.ids, Some(led_teams.team_ids.clone())),
+        };
+        self.inner
+            .find(TeamFilter {
+                ids,
+                company_ids: self.scope.tenant.restrict(filter.company_ids),
+                ..filter
+            })
+            .await
+    }
+
+    async fn delete_one(&self, id: &str) -> DeleteResult {
+        if self.find_one(id).await?.is_none() {
+            return Err(DeleteError::NotFound);
+        }
+        self.inner.delete_one(id).await
+    }
+
+    async fn find_people(&self, team_id: &str) -> anyhow::Result<Box<dyn ItemStream<TeamPerson>>> {
+        if self.find_one(team_id).await?.is_none() {
+            return Ok(item_stream(Vec::new()));
+        }
+        self.inner.find_people(team_id).await
+    }
+
+    async fn find_members(
+        &self,
+        team_ids: &[String],
+    ) -> anyhow::Result<Box<dyn ItemStream<TeamPerson>>> {
+        let team_ids: Vec<String> = self
+            .find_many(team_ids)
+            .await?
+            .into_iter()
+            .map(|team| team.id)
+            .collect();
+        self.inner.find_members(&team_ids).await
+    }
+
+    async fn find_memberships(
+        &self,
+        person_id: &str,
+    ) -> anyhow::Result<Box<dyn ItemStream<TeamPerson>>> {
+        if !self.scope.allows_person(person_id).await? {
+            return Ok(item_stream(Vec::new()));
+        }
+        self.inner.find_memberships(person_id).await
+    }
+
+    async fn add_person(&self, team_id: &str, person_id: &str, lead: bool) -> anyhow::Result<()> {
+        if self.find_one(team_id).await?.is_none() {
+            return Err(TenantError.into());
+        }
+        self.scope.verify_people([person_id]).await?;
+        self.inner.add_person(team_id, person_id, lead).await
+    }
+
+    async fn remove_person(&self, team_id: &str, person_id: &str) -> DeleteResult {
+        if self.find_one(team_id).await?.is_none() {
+            return Err(DeleteError::NotFound);
+        }
+        self.inner.remove_person(team_id, person_id).await
+    }
+}
+
+struct TenantUserAccountRepo {
+    inner: ArcUserAccountRepo,
+    scope: TenantScope,
+}
+
+impl TenantUserAccountRepo {
+    async fn verify(&self, user_account_id: &str) -> anyhow::Result<()> {
+        match self.find_one(user_account_id).await? {
+            Some(_) => Ok(()),
+            None => Err(TenantError.into()),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl UserAccountRepo for TenantUserAccountRepo {
+    async fn insert_one(&self, user_account: UserAccount) -> InsertResult {
+        self.scope.verify_company(&user_account.company_id)?;
+        self.inner.insert_one(user_account).await
+    }
+
+    async fn replace_one(&self, user_account: UserAccount) -> ReplaceResult {
+        if self.find_one(&user_account.id).await?.is_none() {
+            return Err(ReplaceError::NotFound);
+        }
+        self.scope.verify_company(&user_account.company_id)?;
+        self.inner.replace_one(user_account).await
+    }
+
+    async fn find_one(&self, id: &str) -> anyhow::Result<Option<UserAccount>> {
+        Ok(self
+            .inner
+            .find_one(id)
+            .await?
+            .filter(|user_account| self.scope.tenant.allows(&user_account.company_id)))
+    }
+
+    async fn find_many(&self, ids: &[String]) -> anyhow::Result<Vec<UserAccount>> {
+        let mut user_accounts = self.inner.find_many(ids).await?;
+        user_accounts.retain(|user_account| self.scope.tenant.allows(&user_account.company_id));
+        Ok(user_accounts)
+    }
+
+    async fn find_by_email(&self, email: &str) -> anyhow::Result<Option<UserAccount>> {
+        Ok(self
+            .inner
+            .find_by_email(email)
+            .await?
+            .filter(|user_account| self.scope.tenant.allows(&user_account.company_id)))
+    }
+
+    async fn find(
+        &self,
+        filter: UserAccountFilter,
+    ) -> anyhow::Result<Box<dyn ItemStream<UserAccount>>> {
+        self.inner
+            .find(UserAccountFilter {
+                company_ids: self.scope.tenant.restrict(filter.company_ids),
+                ..filter
+            })
+            .await
+    }
+
+    async fn delete_one(&self, id: &str) -> DeleteResult {
+        if self.find_one(id).await?.is_none() {
+            return Err(DeleteError::NotFound);
+        }
+        self.inner.delete_one(id).await
+    }
+
+    async fn set_email_verified(&self, id: &str, email: &str) -> anyhow::Result<bool> {
+        self.verify(id).await?;
+        self.inner.set_email_verified(id, email).await
+    }
+
+    async fn set_phone_verified(&self, id: &str, phone: &str) -> anyhow::Result<bool> {
+        self.verify(id).await?;
+        self.inner.set_phone_verified(id, phone).await
+    }
+
+    async fn creds(&self, user_account_id: &str) -> anyhow::Result<Option<Creds>> {
+        self.verify(user_account_id).await?;
+        self.inner.creds(user_account_id).await
+    }
+
+    async fn creds_history(&self, user_account_id: &str) -> anyhow::Result<Vec<Creds>> {
+        self.verify(user_account_id).await?;
+        self.inner.creds_history(user_account_id).await
+    }
+
+    async fn set_creds(
+        &self,
+        user_account_id: &str,
+        creds: Creds,
+        history: Vec<Creds>,
+    ) -> anyhow::Result<()> {
+        self.verify(user_account_id).await?;
+        self.inner.set_creds(user_account_id, creds, history).await
+    }
+
+    async fn totp(&self, user_account_id: &str) -> anyhow::Result<Option<Totp>> {
+        self.verify(user_account_id).await?;
+        self.inner.totp(user_account_id).await
+    }
+
+    async fn set_totp(&self, user_account_id: &str, totp: Option<Totp>) -> anyhow::Result<()> {
+        self.verify(user_account_id).await?;
+        self.inner.set_totp(user_account_id, totp).await
+    }
+
+    async fn use_totp_step(&self, user_account_id: &str, step: i64) -> anyhow::Result<bool> {
+        self.verify(user_account_id).await?;
+        self.inner.use_totp_step(user_account_id, step).await
+    }
+
+    async fn use_recovery_code(
+        &self,
+        user_account_id: &str,
+        code_hash: &str,
+    ) -> anyhow::Result<bool> {
+        self.verify(user_account_id).await?;
+        self.inner
+            .use_recovery_code(user_account_id, code_hash)
+            .await
+    }
+
+    async fn profile_image_png(&self, user_account_id: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        self.verify(user_account_id).await?;
+        self.inner.profile_image_png(user_account_id).await
+    }
+
+    async fn set_profile_image_png(
+        &self,
+        user_account_id: &str,
+        png_bytes: Vec<u8>,
+    ) -> anyhow::Result<()> {
+        self.verify(user_account_id).await?;
+        self.inner
+            .set_profile_image_png(user_account_id, png_bytes)
+            .await
+    }
+}
+
+fn item_stream<T: Unpin + Send + 'static>(items: Vec<T>) -> Box<dyn ItemStream<T>> {
+    Box::new(futures_util::stream::iter(items.into_iter().map(Ok)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::memory::company_with_teams;
+
+    #[test]
+    fn test_restrict_company() {
+        // Arrange.
+        let tenant = Tenant::Company("a".to_string());
+
+        // Act.
+        let unfiltered = tenant.restrict(None);
+        let filtered = tenant.restrict(Some(vec!["a".to_string(), "b".to_string()]));
+
+        // Assert.
+        assert_eq!(unfiltered, Some(vec!["a".to_string()]));
+        assert_eq!(filtered, Some(vec!["a".to_string()]));
+    }
+
+    #[test]
+    fn test_restrict_all() {
+        // Arrange.
+        let tenant = Tenant::All;
+
+        // Act.
+        let unfiltered = tenant.restrict(None);
+        let filtered = tenant.restrict(Some(vec!["b".to_string()]));
+
+        // Assert.
+        assert_eq!(unfiltered, None);
+        assert_eq!(filtered, Some(vec!["b".to_string()]));
+    }
+
+    #[test]
+    fn test_restrict_nobody() {
+        // Arrange.
+        let tenant = Tenant::Nobody;
+
+        // Act.
+        let res = tenant.restrict(None);
+
+        // Assert.
+        assert_eq!(res, Some(Vec::new()));
+        assert!(!tenant.allows(""));
+    }
+
+    #[tokio::test]
+    async fn test_led_teams() {
+        // Arrange.
+        let scope = scope(Some("lead")).await;
+
+        // Act.
+        let led_team = scope.allows_team(&team("a")).await.unwrap();
+        let other_team = scope.allows_team(&team("b")).await.unwrap();
+        let member = scope.allows_subject("member").await.unwrap();
+        let other_person = scope.allows_subject("other").await.unwrap();
+
+        // Assert.
+        assert!(led_team);
+        assert!(!other_team);
+        assert!(member);
+        assert!(!other_person);
+    }
+
+    #[tokio::test]
+    async fn test_led_teams_none() {
+        // Arrange.
+        let scope = scope(Some("member")).await;
+
+        // Act.
+        let team = scope.allows_team(&team("a")).await.unwrap();
+        let subject = scope.allows_subject("member").await.unwrap();
+        let subjects = scope.restrict_subjects(None).await.unwrap();
+
+        // Assert.
+        assert!(!team);
+        assert!(!subject);
+        assert_eq!(subjects, Some(Vec::new()));
+    }
+
+    #[tokio::test]
+    async fn test_led_teams_no_lead() {
+        // Arrange.
+        let scope = scope(None).await;
+
+        // Act.
+        let team = scope.allows_team(&team("b")).await.unwrap();
+        let subject = scope.allows_subject("other").await.unwrap();
+
+        // Assert.
+        assert!(team);
+        assert!(subject);
+    }
+
+    /// Scope of company "c" from `company_with_teams`.
+    async fn scope(lead_person_id: Option<&str>) -> TenantScope {
+        let (person_repo, team_repo) = company_with_teams().await;
+        TenantScope::new(
+            Tenant::Company("c".to_string()),
+            lead_person_id.map(str::to_string),
+            person_repo,
+            team_repo,
+        )
+    }
+
+    fn team(id: &str) -> Team {
+        Team {
+            id: id.to_string(),
+            name: id.to_string(),
+            company_id: "c".to_string(),
+        }
+    }
+}
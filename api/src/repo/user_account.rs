@@ -1,10 +1,16 @@
+use crate::db;
 use crate::db::coll;
-use crate::repo::mongo_util::{filter, FindStream, FromDeletedCount, FromMatchedCount, InsertOpt};
-use crate::repo::DeleteResult;
-use crate::repo::{ItemStream, ReplaceResult};
+use crate::repo::mongo_util::{
+    filter, opt_chrono_datetime_as_bson_datetime, page_by_id, with_name_words, FindStream,
+    FromDeletedCount, FromMatchedCount, InsertOpt, NAME_WORDS,
+};
+use crate::repo::{DeleteResult, InsertResult};
+use crate::repo::{ItemStream, Page, ReplaceResult};
 use bson::spec::BinarySubtype;
 use bson::Document;
-use mongodb::options::UpdateOptions;
+use chrono::{DateTime, Utc};
+use futures_util::TryStreamExt;
+use mongodb::options::{FindOneOptions, UpdateOptions};
 use mongodb::{Collection, Database};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
@@ -14,14 +20,36 @@ use std::sync::Arc;
 pub enum Access {
     View,
     Admin,
+    /// Platform-level admin with access to every company.
+    #[serde(rename = "super_admin")]
+    SuperAdmin,
 }
 
+#[allow(clippy::derivable_impls)]
 impl Default for Access {
     fn default() -> Self {
         Self::View
     }
 }
 
+/// Named set of permissions granted in addition to those of the access level.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    SafetyManager,
+    TeamLead,
+    Worker,
+    Auditor,
+}
+
+/// A role held by a user account, either across its company or for a single team.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RoleGrant {
+    pub role: Role,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub team_id: Option<String>,
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct UserAccount {
     #[serde(rename = "_id")]
@@ -31,21 +59,105 @@ pub struct UserAccount {
     pub access: Access,
     pub title: String,
     pub email: String,
+    /// Whether the user proved they receive email at the address.
+    #[serde(default)]
+    pub email_verified: bool,
     pub phone: String,
+    /// Whether the user proved they receive text messages at the number.
+    #[serde(default)]
+    pub phone_verified: bool,
     pub company_id: String,
+    #[serde(default)]
+    pub roles: Vec<RoleGrant>,
+    /// Person the user account belongs to, if the user is also a worker.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub person_id: Option<String>,
+    /// Whether the user account may log in. Deactivated accounts are kept so history still refers
+    /// to them.
+    #[serde(default = "default_active")]
+    pub active: bool,
+    /// End of a temporary suspension, during which the user account may not log in.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "opt_chrono_datetime_as_bson_datetime"
+    )]
+    pub suspended_until: Option<DateTime<Utc>>,
+}
+
+impl UserAccount {
+    pub fn status(&self, now: DateTime<Utc>) -> Status {
+        match self.suspended_until {
+            _ if !self.active => Status::Deactivated,
+            Some(suspended_until) if suspended_until > now => Status::Suspended,
+            _ => Status::Active,
+        }
+    }
+
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.status(now) == Status::Active
+    }
+
+    /// Change the email address and phone number. Changed ones are no longer verified. Returns
+    /// whether the email address and the phone number changed.
+    pub fn set_contact(&mut self, email: String, phone: String) -> (bool, bool) {
+        let email_changed = !email.eq_ignore_ascii_case(&self.email);
+        let phone_changed = phone != self.phone;
+        self.email_verified &= !email_changed;
+        self.phone_verified &= !phone_changed;
+        self.email = email;
+        self.phone = phone;
+        (email_changed, phone_changed)
+    }
+}
+
+fn default_active() -> bool {
+    true
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Status {
+    Active,
+    Suspended,
+    Deactivated,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Creds {
+    /// PHC string naming the hash algorithm and its parameters. Hex-encoded PBKDF2 hash for
+    /// credentials created before hashes were PHC strings.
     pub password_hash: String,
-    pub salt: String,
+    /// Hex-encoded salt, only set for credentials created before hashes were PHC strings.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub salt: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DbCreds {
     pub user_account_id: String,
     pub password_hash: String,
-    pub salt: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub salt: Option<String>,
+    /// Previous credentials, most recent first.
+    #[serde(default)]
+    pub history: Vec<Creds>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub totp: Option<Totp>,
+}
+
+/// Time-based one-time password second factor.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Totp {
+    /// Base 32 encoded shared secret.
+    pub secret: String,
+    /// Whether enrollment was confirmed with a valid code. Unconfirmed secrets are not required to
+    /// log in.
+    pub enabled: bool,
+    #[serde(default)]
+    pub recovery_code_hashes: Vec<String>,
+    /// Time step of the last accepted code, so each code is only accepted once.
+    #[serde(default)]
+    pub last_used_step: Option<i64>,
 }
 
 impl From<DbCreds> for Creds {
@@ -66,20 +178,53 @@ pub struct ProfileImage {
 #[derive(Default, Debug, Clone)]
 pub struct UserAccountFilter {
     pub company_ids: Option<Vec<String>>,
+    pub person_ids: Option<Vec<String>>,
+    /// Text whose words each start a word of the name, ignoring case.
+    pub name: Option<String>,
+    pub status: Option<Status>,
+    pub page: Page,
 }
 
 #[async_trait::async_trait]
 pub trait UserAccountRepo {
-    async fn insert_one(&self, user_account: UserAccount) -> anyhow::Result<()>;
+    async fn insert_one(&self, user_account: UserAccount) -> InsertResult;
     async fn replace_one(&self, user_account: UserAccount) -> ReplaceResult;
     async fn find_one(&self, id: &str) -> anyhow::Result<Option<UserAccount>>;
+    /// Find the items with the given IDs, in no particular order. Unknown IDs are skipped.
+    async fn find_many(&self, ids: &[String]) -> anyhow::Result<Vec<UserAccount>>;
+    /// Find a user account by email address, ignoring case.
+    async fn find_by_email(&self, email: &str) -> anyhow::Result<Option<UserAccount>>;
     async fn find(
         &self,
         filter: UserAccountFilter,
     ) -> anyhow::Result<Box<dyn ItemStream<UserAccount>>>;
     async fn delete_one(&self, id: &str) -> DeleteResult;
+    /// Mark the email address of a user account verified. Returns false if the user account no
+    /// longer has the address, ignoring case.
+    async fn set_email_verified(&self, id: &str, email: &str) -> anyhow::Result<bool>;
+    /// Mark the phone number of a user account verified. Returns false if the user account no
+    /// longer has the number.
+    async fn set_phone_verified(&self, id: &str, phone: &str) -> anyhow::Result<bool>;
     async fn creds(&self, user_account_id: &str) -> anyhow::Result<Option<Creds>>;
-    async fn set_creds(&self, user_account_id: &str, creds: Creds) -> anyhow::Result<()>;
+    /// Current credentials followed by previous credentials, most recent first.
+    async fn creds_history(&self, user_account_id: &str) -> anyhow::Result<Vec<Creds>>;
+    async fn set_creds(
+        &self,
+        user_account_id: &str,
+        creds: Creds,
+        history: Vec<Creds>,
+    ) -> anyhow::Result<()>;
+    async fn totp(&self, user_account_id: &str) -> anyhow::Result<Option<Totp>>;
+    /// Set or remove the second factor. Fails if the user account has no credentials.
+    async fn set_totp(&self, user_account_id: &str, totp: Option<Totp>) -> anyhow::Result<()>;
+    /// Record a time step as used. Returns false if it or a later step was already used.
+    async fn use_totp_step(&self, user_account_id: &str, step: i64) -> anyhow::Result<bool>;
+    /// Remove a recovery code. Returns false if the code was not found.
+    async fn use_recovery_code(
+        &self,
+        user_account_id: &str,
+        code_hash: &str,
+    ) -> anyhow::Result<bool>;
     async fn profile_image_png(&self, user_account_id: &str) -> anyhow::Result<Option<Vec<u8>>>;
     async fn set_profile_image_png(
         &self,
@@ -117,17 +262,22 @@ impl MongoUserAccountRepo {
 
 #[async_trait::async_trait]
 impl UserAccountRepo for MongoUserAccountRepo {
-    async fn insert_one(&self, user_account: UserAccount) -> anyhow::Result<()> {
-        self.collection().insert_one(user_account, None).await?;
+    async fn insert_one(&self, user_account: UserAccount) -> InsertResult {
+        let doc = with_name_words(&user_account, &user_account.name)?;
+        self.collection()
+            .clone_with_type::<Document>()
+            .insert_one(doc, None)
+            .await?;
         Ok(())
     }
 
     async fn replace_one(&self, user_account: UserAccount) -> ReplaceResult {
+        let doc = with_name_words(&user_account, &user_account.name)?;
         let res = self
             .collection()
-            .replace_one(bson::doc! {"_id": &user_account.id}, user_account, None)
-            .await
-            .map_err(anyhow::Error::from)?;
+            .clone_with_type::<Document>()
+            .replace_one(bson::doc! {"_id": &user_account.id}, doc, None)
+            .await?;
         ReplaceResult::from_matched_count(res.matched_count)
     }
 
@@ -138,13 +288,57 @@ impl UserAccountRepo for MongoUserAccountRepo {
             .await?)
     }
 
+    async fn find_many(&self, ids: &[String]) -> anyhow::Result<Vec<UserAccount>> {
+        self.collection()
+            .find_stream(bson::doc! {"_id": {"$in": ids}}, None)
+            .await?
+            .try_collect()
+            .await
+    }
+
+    async fn find_by_email(&self, email: &str) -> anyhow::Result<Option<UserAccount>> {
+        if email.is_empty() {
+            return Ok(None);
+        }
+        Ok(self
+            .collection()
+            .find_one(
+                bson::doc! {"email": email},
+                FindOneOptions::builder()
+                    .collation(db::case_insensitive_collation())
+                    .build(),
+            )
+            .await?)
+    }
+
     async fn find(
         &self,
         filter: UserAccountFilter,
     ) -> anyhow::Result<Box<dyn ItemStream<UserAccount>>> {
         let mut mongo_filter = Document::new();
         mongo_filter.insert_opt("company_id", filter::one_of(filter.company_ids));
-        self.collection().find_stream(mongo_filter, None).await
+        mongo_filter.insert_opt("person_id", filter::one_of(filter.person_ids));
+        mongo_filter.insert_opt(NAME_WORDS, filter::name_starts_with(filter.name));
+        if let Some(status) = filter.status {
+            let now = bson::DateTime::from_chrono(Utc::now());
+            let status_filter = match status {
+                Status::Active => bson::doc! {
+                    "active": filter::not(false),
+                    "$or": [
+                        { "suspended_until": null },
+                        { "suspended_until": { "$lte": now } },
+                    ],
+                },
+                Status::Suspended => bson::doc! {
+                    "active": filter::not(false),
+                    "suspended_until": { "$gt": now },
+                },
+                Status::Deactivated => bson::doc! { "active": false },
+            };
+            mongo_filter.extend(status_filter);
+        }
+        let options = page_by_id(&mut mongo_filter, &filter.page)?;
+        self.collection().find_stream(mongo_filter, options).await
     }
 
     async fn delete_one(&self, id: &str) -> DeleteResult {
@@ -156,6 +350,32 @@ impl UserAccountRepo for MongoUserAccountRepo {
         DeleteResult::from_deleted_count(res.deleted_count)
     }
 
+    async fn set_email_verified(&self, id: &str, email: &str) -> anyhow::Result<bool> {
+        let res = self
+            .collection()
+            .update_one(
+                bson::doc! {"_id": id, "email": email},
+                bson::doc! {"$set": {"email_verified": true}},
+                UpdateOptions::builder()
+                    .collation(db::case_insensitive_collation())
+                    .build(),
+            )
+            .await?;
+        Ok(res.matched_count == 1)
+    }
+
+    async fn set_phone_verified(&self, id: &str, phone: &str) -> anyhow::Result<bool> {
+        let res = self
+            .collection()
+            .update_one(
+                bson::doc! {"_id": id, "phone": phone},
+                bson::doc! {"$set": {"phone_verified": true}},
+                None,
+            )
+            .await?;
+        Ok(res.matched_count == 1)
+    }
+
     async fn creds(&self, user_account_id: &str) -> anyhow::Result<Option<Creds>> {
         Ok(self
             .creds_collection()
@@ -164,21 +384,107 @@ impl UserAccountRepo for MongoUserAccountRepo {
             .map(Into::into))
     }
 
-    async fn set_creds(&self, user_account_id: &str, creds: Creds) -> anyhow::Result<()> {
+    async fn creds_history(&self, user_account_id: &str) -> anyhow::Result<Vec<Creds>> {
+        let opt = self
+            .creds_collection()
+            .find_one(bson::doc! {"user_account_id": user_account_id}, None)
+            .await?;
+        match opt {
+            None => Ok(Vec::new()),
+            Some(mut db_creds) => {
+                let mut history = std::mem::take(&mut db_creds.history);
+                history.insert(0, db_creds.into());
+                Ok(history)
+            }
+        }
+    }
+
+    async fn set_creds(
+        &self,
+        user_account_id: &str,
+        creds: Creds,
+        history: Vec<Creds>,
+    ) -> anyhow::Result<()> {
+        let mut update = bson::doc! {
+            "$set": {
+                "password_hash": creds.password_hash,
+                "history": bson::to_bson(&history)?,
+            },
+        };
+        // Credentials created before hashes were PHC strings have a separate salt.
+        match creds.salt {
+            Some(salt) => update.get_document_mut("$set")?.insert("salt", salt),
+            None => update.insert("$unset", bson::doc! {"salt": ""}),
+        };
         self.creds_collection()
             .update_one(
                 bson::doc! {"user_account_id": user_account_id},
-                bson::doc! { "$set" : bson::to_document(&DbCreds {
-                    user_account_id: user_account_id.to_string(),
-                    password_hash: creds.password_hash,
-                    salt: creds.salt,
-                })? },
+                update,
                 UpdateOptions::builder().upsert(true).build(),
             )
             .await?;
         Ok(())
     }
 
+    async fn totp(&self, user_account_id: &str) -> anyhow::Result<Option<Totp>> {
+        Ok(self
+            .creds_collection()
+            .find_one(bson::doc! {"user_account_id": user_account_id}, None)
+            .await?
+            .and_then(|db_creds| db_creds.totp))
+    }
+
+    async fn set_totp(&self, user_account_id: &str, totp: Option<Totp>) -> anyhow::Result<()> {
+        let update = match totp {
+            Some(totp) => bson::doc! {"$set": {"totp": bson::to_bson(&totp)?}},
+            None => bson::doc! {"$unset": {"totp": ""}},
+        };
+        let res = self
+            .creds_collection()
+            .update_one(
+                bson::doc! {"user_account_id": user_account_id},
+                update,
+                None,
+            )
+            .await?;
+        anyhow::ensure!(res.matched_count == 1, "User account has no password");
+        Ok(())
+    }
+
+    async fn use_totp_step(&self, user_account_id: &str, step: i64) -> anyhow::Result<bool> {
+        let res = self
+            .creds_collection()
+            .update_one(
+                bson::doc! {
+                    "user_account_id": user_account_id,
+                    "totp.last_used_step": {"$not": {"$gte": step}},
+                },
+                bson::doc! {"$set": {"totp.last_used_step": step}},
+                None,
+            )
+            .await?;
+        Ok(res.modified_count == 1)
+    }
+
+    async fn use_recovery_code(
+        &self,
+        user_account_id: &str,
+        code_hash: &str,
+    ) -> anyhow::Result<bool> {
+        let res = self
+            .creds_collection()
+            .update_one(
+                bson::doc! {
+                    "user_account_id": user_account_id,
+                    "totp.recovery_code_hashes": code_hash,
+                },
+                bson::doc! {"$pull": {"totp.recovery_code_hashes": code_hash}},
+                None,
+            )
+            .await?;
+        Ok(res.modified_count == 1)
+    }
+
     async fn profile_image_png(&self, user_account_id: &str) -> anyhow::Result<Option<Vec<u8>>> {
         let opt = self
             .profile_image_collection()
@@ -217,3 +523,102 @@ impl From<MongoUserAccountRepo> for ArcUserAccountRepo {
         Arc::new(value)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_status_suspension_ended() {
+        // Arrange.
+        let now = Utc::now();
+        let user_account = UserAccount {
+            suspended_until: Some(now - Duration::minutes(1)),
+            ..user_account()
+        };
+
+        // Act.
+        let status = user_account.status(now);
+
+        // Assert.
+        assert_eq!(status, Status::Active);
+    }
+
+    #[test]
+    fn test_status_deactivated_while_suspended() {
+        // Arrange.
+        let now = Utc::now();
+        let user_account = UserAccount {
+            active: false,
+            suspended_until: Some(now + Duration::days(1)),
+            ..user_account()
+        };
+
+        // Act.
+        let status = user_account.status(now);
+
+        // Assert.
+        assert_eq!(status, Status::Deactivated);
+    }
+
+    #[test]
+    fn test_set_contact_changed() {
+        // Arrange.
+        let mut user_account = UserAccount {
+            email: "user@example.com".to_string(),
+            email_verified: true,
+            phone: "111-1111".to_string(),
+            phone_verified: true,
+            ..user_account()
+        };
+
+        // Act.
+        let changed =
+            user_account.set_contact("other@example.com".to_string(), "222-2222".to_string());
+
+        // Assert.
+        assert_eq!(changed, (true, true));
+        assert!(!user_account.email_verified);
+        assert!(!user_account.phone_verified);
+    }
+
+    #[test]
+    fn test_set_contact_unchanged() {
+        // Arrange.
+        let mut user_account = UserAccount {
+            email: "user@example.com".to_string(),
+            email_verified: true,
+            phone: "111-1111".to_string(),
+            phone_verified: true,
+            ..user_account()
+        };
+
+        // Act.
+        let changed =
+            user_account.set_contact("User@Example.com".to_string(), "111-1111".to_string());
+
+        // Assert.
+        assert_eq!(changed, (false, false));
+        assert!(user_account.email_verified);
+        assert!(user_account.phone_verified);
+    }
+
+    fn user_account() -> UserAccount {
+        UserAccount {
+            id: "user".to_string(),
+            name: "User".to_string(),
+            access: Access::View,
+            title: "".to_string(),
+            email: "".to_string(),
+            email_verified: false,
+            phone: "".to_string(),
+            phone_verified: false,
+            company_id: "".to_string(),
+            roles: Vec::new(),
+            person_id: None,
+            active: true,
+            suspended_until: None,
+        }
+    }
+}
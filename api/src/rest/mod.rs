@@ -1,11 +1,16 @@
+use crate::auth::{AuthProvider, ClaimsProvider};
 use crate::image::PngBytes;
+use crate::oidc::{SsoError, SsoProvider};
 use crate::repo::user_account::ArcUserAccountRepo;
-use crate::warp_ext;
+use crate::warp_ext::{self, BoxReply};
+use chrono::Utc;
 use image::{DynamicImage, RgbImage};
 use mongodb::Database;
+use serde::Deserialize;
+use std::convert::TryFrom;
 use warp::filters::BoxedFilter;
 use warp::http::header::CONTENT_TYPE;
-use warp::http::HeaderValue;
+use warp::http::{HeaderValue, StatusCode, Uri};
 use warp::reply::Response;
 use warp::{Filter, Reply};
 
@@ -31,12 +36,25 @@ lazy_static::lazy_static! {
 #[derive(Clone)]
 pub struct Context {
     pub user_account_repo: ArcUserAccountRepo,
+    pub sso_provider: SsoProvider,
+    pub auth_provider: AuthProvider,
+    pub claims_provider: ClaimsProvider,
+    /// Base URL of the frontend, which single sign-on redirects back to.
+    pub app_url: String,
     pub db: Database,
 }
 
 pub fn v1(context: Context) -> BoxedFilter<(impl Reply,)> {
     warp::path("v1")
-        .and(health(context.db).or(user_account_profile_image(context.user_account_repo)))
+        .and(
+            health(context.db.clone())
+                .or(jwks(context.claims_provider.clone()))
+                .or(user_account_profile_image(
+                    context.user_account_repo.clone(),
+                ))
+                .or(oidc_login(context.sso_provider.clone()))
+                .or(oidc_callback(context)),
+        )
         .boxed()
 }
 
@@ -51,6 +69,16 @@ fn health(db: Database) -> BoxedFilter<(impl Reply,)> {
         .boxed()
 }
 
+/// Public keys that verify access tokens, so other services can verify them offline.
+fn jwks(claims_provider: ClaimsProvider) -> BoxedFilter<(impl Reply,)> {
+    warp::path!(".well-known" / "jwks.json")
+        .and(warp_ext::with_clone(claims_provider))
+        .map(|claims_provider: ClaimsProvider| {
+            warp::reply::json(&claims_provider.jwt_key_set.jwks(Utc::now()))
+        })
+        .boxed()
+}
+
 fn user_account_profile_image(user_account_repo: ArcUserAccountRepo) -> BoxedFilter<(impl Reply,)> {
     warp::path!("userAccount" / String / "profile.png")
         .and(warp_ext::with_clone(user_account_repo))
@@ -70,3 +98,87 @@ fn user_account_profile_image(user_account_repo: ArcUserAccountRepo) -> BoxedFil
         .map(warp_ext::convert_err)
         .boxed()
 }
+
+/// Start single sign-on by redirecting to the identity provider of a company.
+fn oidc_login(sso_provider: SsoProvider) -> BoxedFilter<(impl Reply,)> {
+    warp::path!("oidc" / String / "login")
+        .and(warp::get())
+        .and(warp_ext::with_clone(sso_provider))
+        .then(
+            move |company_id: String, sso_provider: SsoProvider| async move {
+                Ok(match sso_provider.start_login(&company_id).await? {
+                    Ok(url) => warp::redirect::found(Uri::try_from(url)?).boxed(),
+                    Err(_) => StatusCode::NOT_FOUND.boxed(),
+                })
+            },
+        )
+        .map(warp_ext::convert_err)
+        .boxed()
+}
+
+#[derive(Deserialize)]
+struct OidcCallbackQuery {
+    /// Absent when login failed at the identity provider, such as when the user cancelled.
+    code: Option<String>,
+    state: Option<String>,
+}
+
+/// Finish single sign-on and redirect to the frontend with tokens or an error code. They are passed
+/// in the URL fragment, which browsers do not send to servers.
+fn oidc_callback(context: Context) -> BoxedFilter<(impl Reply,)> {
+    warp::path!("oidc" / "callback")
+        .and(warp::get())
+        .and(warp::query::<OidcCallbackQuery>())
+        .and(warp_ext::with_clone(context))
+        .then(
+            move |query: OidcCallbackQuery, context: Context| async move {
+                let fragment = match (query.code, query.state) {
+                    (Some(code), Some(state)) => {
+                        match finish_oidc_login(&context, &code, &state).await {
+                            Ok(Ok(tokens)) => tokens,
+                            Ok(Err(e)) => format!("error={}", e.code()),
+                            Err(err) => {
+                                log::error!("{:?}", err);
+                                "error=failed".to_string()
+                            }
+                        }
+                    }
+                    _ => "error=denied".to_string(),
+                };
+                let url = format!("{}/login/callback#{}", context.app_url, fragment);
+                Ok(warp::redirect::found(Uri::try_from(url)?))
+            },
+        )
+        .map(warp_ext::convert_err)
+        .boxed()
+}
+
+/// Issue our own tokens to the user account that logged in at the identity provider.
+async fn finish_oidc_login(
+    context: &Context,
+    code: &str,
+    state: &str,
+) -> anyhow::Result<Result<String, SsoError>> {
+    let user_account = match context.sso_provider.finish_login(code, state).await? {
+        Ok(user_account) => user_account,
+        Err(e) => return Ok(Err(e)),
+    };
+    // Single sign-on has no step for a second factor, so accounts that need one log in with a
+    // password instead.
+    let auth_provider = &context.auth_provider;
+    if auth_provider.totp_enabled(&user_account.id).await?
+        || auth_provider.requires_totp(&user_account).await?
+    {
+        return Ok(Err(SsoError::SecondFactorRequired));
+    }
+    let claims = context.claims_provider.create_claims(&user_account);
+    let access_token = context.claims_provider.create_token(&claims)?;
+    let refresh_token = context
+        .auth_provider
+        .create_session(&user_account.id, &claims.jti)
+        .await?;
+    Ok(Ok(format!(
+        "access_token={}&refresh_token={}",
+        access_token, refresh_token
+    )))
+}
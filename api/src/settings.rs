@@ -4,7 +4,78 @@ use serde::Deserialize;
 #[derive(Debug, Deserialize)]
 pub struct Settings {
     pub db_uri: String,
-    pub private_key: String,
+    /// JSON array of Ed25519 keys that sign access tokens. See `JwtKeyConfig`. Required unless
+    /// `dev_mode` is set, in which case a key is generated at startup.
+    pub jwt_keys: Option<String>,
+    /// Allow shortcuts that only suit local development, such as signing keys that last until
+    /// restart and emails written to files.
+    #[serde(default)]
+    pub dev_mode: bool,
+    #[serde(default = "default_access_token_ttl_minutes")]
+    pub access_token_ttl_minutes: i64,
+    /// Lifetime of access tokens for admins acting as another user account.
+    #[serde(default = "default_impersonation_ttl_minutes")]
+    pub impersonation_ttl_minutes: i64,
+    #[serde(default = "default_refresh_token_ttl_days")]
+    pub refresh_token_ttl_days: i64,
+    #[serde(default = "default_password_min_length")]
+    pub password_min_length: usize,
+    #[serde(default = "default_password_history_len")]
+    pub password_history_len: usize,
+    /// Argon2id memory cost of new password hashes. Hashes with lower costs are replaced on login.
+    #[serde(default = "default_argon2_memory_kib")]
+    pub argon2_memory_kib: u32,
+    #[serde(default = "default_argon2_iterations")]
+    pub argon2_iterations: u32,
+    #[serde(default = "default_argon2_parallelism")]
+    pub argon2_parallelism: u32,
+    #[serde(default = "default_password_reset_ttl_minutes")]
+    pub password_reset_ttl_minutes: i64,
+    #[serde(default = "default_invitation_ttl_hours")]
+    pub invitation_ttl_hours: i64,
+    #[serde(default = "default_login_link_ttl_minutes")]
+    pub login_link_ttl_minutes: i64,
+    /// Base URL of the frontend, used for links in emails.
+    #[serde(default = "default_app_url")]
+    pub app_url: String,
+    /// Public base URL of this API, used for single sign-on callbacks.
+    #[serde(default = "default_api_url")]
+    pub api_url: String,
+    #[serde(default = "default_mail_from")]
+    pub mail_from: String,
+    /// Directory emails are written to when SMTP is not configured in dev mode.
+    #[serde(default = "default_mail_dir")]
+    pub mail_dir: String,
+    pub smtp_host: Option<String>,
+    pub smtp_port: Option<u16>,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+    /// Directory text messages are written to when Twilio is not configured in dev mode.
+    #[serde(default = "default_sms_dir")]
+    pub sms_dir: String,
+    pub twilio_account_sid: Option<String>,
+    pub twilio_auth_token: Option<String>,
+    /// Phone number text messages are sent from through Twilio.
+    pub sms_from: Option<String>,
+    /// Failed login attempts allowed before each attempt is delayed.
+    #[serde(default = "default_login_free_attempts")]
+    pub login_free_attempts: u32,
+    /// Longest delay between failed login attempts, effectively a temporary lockout.
+    #[serde(default = "default_login_lockout_minutes")]
+    pub login_lockout_minutes: i64,
+    /// Comma-separated IP addresses of reverse proxies in front of the API. The client IP address
+    /// is only read from the X-Forwarded-For header of requests from these.
+    pub trusted_proxies: Option<String>,
+    /// Most levels of nested fields in a GraphQL query.
+    #[serde(default = "default_graphql_max_depth")]
+    pub graphql_max_depth: usize,
+    /// Highest estimated number of fields a GraphQL request may resolve, counting the fields of
+    /// each list item.
+    #[serde(default = "default_graphql_max_cost")]
+    pub graphql_max_cost: usize,
+    /// Most items a GraphQL list field returns, including pages.
+    #[serde(default = "default_graphql_max_list_items")]
+    pub graphql_max_list_items: usize,
 }
 
 impl Settings {
@@ -16,3 +87,87 @@ impl Settings {
             .unwrap()
     }
 }
+
+fn default_access_token_ttl_minutes() -> i64 {
+    15
+}
+
+fn default_impersonation_ttl_minutes() -> i64 {
+    10
+}
+
+fn default_refresh_token_ttl_days() -> i64 {
+    30
+}
+
+fn default_password_min_length() -> usize {
+    8
+}
+
+fn default_password_history_len() -> usize {
+    5
+}
+
+fn default_argon2_memory_kib() -> u32 {
+    argon2::Params::DEFAULT_M_COST
+}
+
+fn default_argon2_iterations() -> u32 {
+    argon2::Params::DEFAULT_T_COST
+}
+
+fn default_argon2_parallelism() -> u32 {
+    argon2::Params::DEFAULT_P_COST
+}
+
+fn default_password_reset_ttl_minutes() -> i64 {
+    60
+}
+
+fn default_invitation_ttl_hours() -> i64 {
+    72
+}
+
+fn default_login_link_ttl_minutes() -> i64 {
+    15
+}
+
+fn default_app_url() -> String {
+    "http://localhost:3000".to_string()
+}
+
+fn default_api_url() -> String {
+    "http://localhost:3001".to_string()
+}
+
+fn default_mail_from() -> String {
+    "SafetyWare <noreply@safetyware.ca>".to_string()
+}
+
+fn default_mail_dir() -> String {
+    "mail".to_string()
+}
+
+fn default_sms_dir() -> String {
+    "sms".to_string()
+}
+
+fn default_login_free_attempts() -> u32 {
+    5
+}
+
+fn default_login_lockout_minutes() -> i64 {
+    15
+}
+
+fn default_graphql_max_depth() -> usize {
+    10
+}
+
+fn default_graphql_max_cost() -> usize {
+    1_000_000
+}
+
+fn default_graphql_max_list_items() -> usize {
+    1000
+}
@@ -0,0 +1,106 @@
+use crate::crockford;
+use chrono::Utc;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+const TWILIO_API_URL: &str = "https://api.twilio.com/2010-04-01";
+
+#[derive(Debug, Clone)]
+pub struct Sms {
+    pub to: String,
+    pub body: String,
+}
+
+#[async_trait::async_trait]
+pub trait SmsSender {
+    async fn send(&self, sms: Sms) -> anyhow::Result<()>;
+}
+
+pub type DynSmsSender = dyn SmsSender + Send + Sync + 'static;
+
+pub type ArcSmsSender = Arc<DynSmsSender>;
+
+/// Sends text messages through the Twilio Messaging API.
+#[derive(Clone)]
+pub struct TwilioSmsSender {
+    pub http: reqwest::Client,
+    pub account_sid: String,
+    pub auth_token: String,
+    /// Phone number or messaging service the messages are sent from.
+    pub from: String,
+}
+
+impl TwilioSmsSender {
+    pub fn new(account_sid: String, auth_token: String, from: String) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            account_sid,
+            auth_token,
+            from,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl SmsSender for TwilioSmsSender {
+    async fn send(&self, sms: Sms) -> anyhow::Result<()> {
+        let url = format!(
+            "{}/Accounts/{}/Messages.json",
+            TWILIO_API_URL, self.account_sid
+        );
+        self.http
+            .post(&url)
+            .basic_auth(&self.account_sid, Some(&self.auth_token))
+            .form(&[
+                ("To", sms.to.as_str()),
+                ("From", &self.from),
+                ("Body", &sms.body),
+            ])
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+/// Writes each text message to a file in a directory instead of sending it. Intended for local
+/// development and testing.
+#[derive(Debug, Clone)]
+pub struct FileSmsSender {
+    pub dir: PathBuf,
+}
+
+impl FileSmsSender {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+#[async_trait::async_trait]
+impl SmsSender for FileSmsSender {
+    async fn send(&self, sms: Sms) -> anyhow::Result<()> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let file_name = format!(
+            "{}-{}.txt",
+            Utc::now().format("%Y%m%dT%H%M%S"),
+            crockford::random_id()
+        );
+        let path = self.dir.join(file_name);
+        let contents = format!("To: {}\n\n{}\n", sms.to, sms.body);
+        tokio::fs::write(&path, contents).await?;
+        log::info!("Wrote text message to {} at {}", sms.to, path.display());
+        Ok(())
+    }
+}
+
+impl From<TwilioSmsSender> for ArcSmsSender {
+    fn from(value: TwilioSmsSender) -> Self {
+        Arc::new(value)
+    }
+}
+
+impl From<FileSmsSender> for ArcSmsSender {
+    fn from(value: FileSmsSender) -> Self {
+        Arc::new(value)
+    }
+}
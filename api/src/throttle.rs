@@ -0,0 +1,271 @@
+use crate::repo::login_attempt::{ArcLoginAttemptRepo, LoginAttempts};
+use chrono::{Duration, Utc};
+
+/// How long failed attempts are remembered after the last attempt.
+const FAILURE_MEMORY_HOURS: i64 = 24;
+
+/// Slows down password guessing. Once a key has more failed attempts than are free, each further
+/// attempt must wait twice as long as the last, up to a maximum that acts as a temporary lockout.
+/// Attempts made while waiting are refused without being counted, so they cannot extend the wait.
+#[derive(Clone)]
+pub struct LoginThrottle {
+    pub login_attempt_repo: ArcLoginAttemptRepo,
+    pub free_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl LoginThrottle {
+    /// Count an attempt for each key that need not wait and return whether any of them must wait.
+    /// Attempts are counted before they are made, so that concurrent attempts cannot all pass the
+    /// check. Once an attempt succeeds, `reset` the key it was for and `take_back` the others.
+    pub async fn record_attempt(&self, keys: &[String]) -> anyhow::Result<bool> {
+        let mut blocked = false;
+        for key in keys {
+            blocked |= self.record_key_attempt(key).await?;
+        }
+        Ok(blocked)
+    }
+
+    async fn record_key_attempt(&self, key: &str) -> anyhow::Result<bool> {
+        // Counting only succeeds if no other attempt was counted since the check, so retry until
+        // it does or the key must wait.
+        loop {
+            let previous = self.login_attempt_repo.find_one(key).await?;
+            if matches!(&previous, Some(attempts) if self.blocked(attempts)) {
+                return Ok(true);
+            }
+            let expires_at = Utc::now() + Duration::hours(FAILURE_MEMORY_HOURS);
+            if self
+                .login_attempt_repo
+                .record_attempt(key, previous.as_ref(), expires_at)
+                .await?
+            {
+                return Ok(false);
+            }
+        }
+    }
+
+    /// Stop counting a successful attempt, such as for the IP address a user logged in from.
+    pub async fn take_back(&self, keys: &[String]) -> anyhow::Result<()> {
+        for key in keys {
+            self.login_attempt_repo.take_back(key).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn reset(&self, key: &str) -> anyhow::Result<()> {
+        self.login_attempt_repo.delete_one(key).await
+    }
+
+    fn blocked(&self, attempts: &LoginAttempts) -> bool {
+        let delay = backoff(
+            attempts.failures,
+            self.free_attempts,
+            self.base_delay,
+            self.max_delay,
+        );
+        Utc::now() < attempts.last_failure_at + delay
+    }
+}
+
+/// Time to wait after a number of failures.
+pub fn backoff(
+    failures: u32,
+    free_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+) -> Duration {
+    if failures < free_attempts {
+        return Duration::zero();
+    }
+    let exponent = (failures - free_attempts).min(30);
+    (base_delay * 2i32.pow(exponent)).min(max_delay)
+}
+
+pub fn user_account_key(user_account_id: &str) -> String {
+    format!("user_account:{}", user_account_id)
+}
+
+/// Key for an email address without a user account, so unknown addresses are throttled the same
+/// way as known ones.
+pub fn email_key(email: &str) -> String {
+    format!("email:{}", email.to_lowercase())
+}
+
+pub fn ip_key(ip: &str) -> String {
+    format!("ip:{}", ip)
+}
+
+/// Key for emails requested to an email address, such as login links, so that nobody can flood
+/// an inbox.
+pub fn mail_request_key(email: &str) -> String {
+    format!("mail_request:{}", email.to_lowercase())
+}
+
+/// Key for emails requested from an IP address. Kept apart from `ip_key`, so that requesting
+/// emails does not slow down logging in.
+pub fn mail_request_ip_key(ip: &str) -> String {
+    format!("mail_request_ip:{}", ip)
+}
+
+/// Key for text messages requested to a phone number.
+pub fn sms_request_key(phone: &str) -> String {
+    format!("sms_request:{}", phone)
+}
+
+/// Key for guesses of the phone verification code of a user account.
+pub fn phone_verification_key(user_account_id: &str) -> String {
+    format!("phone_verification:{}", user_account_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::login_attempt::LoginAttemptRepo;
+    use crate::repo::memory::MemoryLoginAttemptRepo;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_record_attempt_concurrent() {
+        // Arrange.
+        let login_throttle = login_throttle();
+        let keys = [user_account_key("user")];
+
+        // Act.
+        let blocked = futures_util::future::try_join_all(
+            (0..5).map(|_| login_throttle.record_attempt(&keys)),
+        )
+        .await
+        .unwrap();
+
+        // Assert.
+        assert_eq!(blocked, vec![false, false, false, true, true]);
+    }
+
+    #[tokio::test]
+    async fn test_take_back() {
+        // Arrange.
+        let login_throttle = login_throttle();
+        let keys = [ip_key("203.0.113.7")];
+        for _ in 0..3 {
+            login_throttle.record_attempt(&keys).await.unwrap();
+            login_throttle.take_back(&keys).await.unwrap();
+        }
+
+        // Act.
+        let blocked = login_throttle.record_attempt(&keys).await.unwrap();
+
+        // Assert.
+        assert!(!blocked);
+    }
+
+    #[tokio::test]
+    async fn test_record_attempt_while_blocked() {
+        // Arrange.
+        let login_throttle = login_throttle();
+        let keys = [user_account_key("user")];
+        for _ in 0..4 {
+            login_throttle.record_attempt(&keys).await.unwrap();
+        }
+        let before = login_throttle
+            .login_attempt_repo
+            .find_one(&keys[0])
+            .await
+            .unwrap()
+            .unwrap();
+
+        // Act.
+        let blocked = login_throttle.record_attempt(&keys).await.unwrap();
+
+        // Assert.
+        let after = login_throttle
+            .login_attempt_repo
+            .find_one(&keys[0])
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(blocked);
+        assert_eq!(after.failures, 3);
+        assert_eq!(after.last_failure_at, before.last_failure_at);
+    }
+
+    #[tokio::test]
+    async fn test_record_attempt_after_delay() {
+        // Arrange.
+        let login_attempt_repo = Arc::new(MemoryLoginAttemptRepo::default());
+        let login_throttle = LoginThrottle {
+            login_attempt_repo: login_attempt_repo.clone(),
+            ..login_throttle()
+        };
+        let keys = [user_account_key("user")];
+        for _ in 0..4 {
+            login_throttle.record_attempt(&keys).await.unwrap();
+        }
+        if let Some(attempts) = login_attempt_repo
+            .login_attempts
+            .lock()
+            .unwrap()
+            .get_mut(&keys[0])
+        {
+            attempts.last_failure_at = attempts.last_failure_at - Duration::seconds(2);
+        }
+
+        // Act.
+        let blocked = login_throttle.record_attempt(&keys).await.unwrap();
+
+        // Assert.
+        let attempts = login_attempt_repo
+            .find_one(&keys[0])
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!blocked);
+        assert_eq!(attempts.failures, 4);
+    }
+
+    fn login_throttle() -> LoginThrottle {
+        LoginThrottle {
+            login_attempt_repo: Arc::new(MemoryLoginAttemptRepo::default()),
+            free_attempts: 3,
+            base_delay: Duration::seconds(1),
+            max_delay: Duration::minutes(15),
+        }
+    }
+
+    #[test]
+    fn test_backoff_free() {
+        // Act.
+        let delay = backoff(2, 3, Duration::seconds(1), Duration::minutes(15));
+
+        // Assert.
+        assert_eq!(delay, Duration::zero());
+    }
+
+    #[test]
+    fn test_backoff_doubles() {
+        // Act.
+        let delays: Vec<_> = (3..6)
+            .map(|f| backoff(f, 3, Duration::seconds(1), Duration::minutes(15)))
+            .collect();
+
+        // Assert.
+        assert_eq!(
+            delays,
+            vec![
+                Duration::seconds(1),
+                Duration::seconds(2),
+                Duration::seconds(4)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_backoff_capped() {
+        // Act.
+        let delay = backoff(1000, 3, Duration::seconds(1), Duration::minutes(15));
+
+        // Assert.
+        assert_eq!(delay, Duration::minutes(15));
+    }
+}
@@ -0,0 +1,134 @@
+use chrono::{DateTime, Utc};
+use data_encoding::BASE32_NOPAD;
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// Length of a secret in bytes, as recommended by RFC 4226.
+const SECRET_LEN: usize = 20;
+const DIGITS: u32 = 6;
+const STEP_SECONDS: i64 = 30;
+/// Time steps a code may be ahead or behind, to allow for clock drift.
+const SKEW_STEPS: i64 = 1;
+const ISSUER: &str = "SafetyWare";
+
+/// Generate a random secret, encoded as Base 32 for authenticator apps.
+pub fn generate_secret() -> String {
+    let rng = SystemRandom::new();
+    let mut secret = [0u8; SECRET_LEN];
+    rng.fill(&mut secret).unwrap();
+    BASE32_NOPAD.encode(&secret)
+}
+
+/// Time step of a time, counted from the Unix epoch.
+pub fn time_step(time: DateTime<Utc>) -> i64 {
+    time.timestamp().div_euclid(STEP_SECONDS)
+}
+
+/// Code for a time step, as defined by RFC 6238 with HMAC-SHA1.
+pub fn code(secret: &[u8], time_step: i64) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, secret);
+    let tag = hmac::sign(&key, &time_step.to_be_bytes());
+    let tag = tag.as_ref();
+    let offset = (tag[tag.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        tag[offset] & 0x7f,
+        tag[offset + 1],
+        tag[offset + 2],
+        tag[offset + 3],
+    ]);
+    format!(
+        "{:0width$}",
+        binary % 10u32.pow(DIGITS),
+        width = DIGITS as usize
+    )
+}
+
+/// Check a code against a Base 32 secret. Returns the time step the code is valid for, so callers
+/// can reject codes that were already used.
+pub fn verify(secret: &str, code: &str, now: DateTime<Utc>) -> anyhow::Result<Option<i64>> {
+    let secret = BASE32_NOPAD.decode(secret.as_bytes())?;
+    let code = code.trim();
+    let now_step = time_step(now);
+    let step = (now_step - SKEW_STEPS..=now_step + SKEW_STEPS).find(|&step| {
+        ring::constant_time::verify_slices_are_equal(
+            self::code(&secret, step).as_bytes(),
+            code.as_bytes(),
+        )
+        .is_ok()
+    });
+    Ok(step)
+}
+
+/// URI that authenticator apps read from a QR code to add an account.
+pub fn uri(secret: &str, account: &str) -> String {
+    format!(
+        "otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&digits={digits}&period={period}",
+        issuer = ISSUER,
+        account = percent_encode(account),
+        secret = secret,
+        digits = DIGITS,
+        period = STEP_SECONDS
+    )
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'@' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn test_code_rfc_vectors() {
+        // Act.
+        let codes: Vec<_> = [59, 1_111_111_109, 1_234_567_890, 2_000_000_000]
+            .iter()
+            .map(|&t| code(RFC_SECRET, time_step(Utc.timestamp(t, 0))))
+            .collect();
+
+        // Assert.
+        assert_eq!(codes, vec!["287082", "081804", "005924", "279037"]);
+    }
+
+    #[test]
+    fn test_verify_allows_skew() {
+        // Arrange.
+        let secret = generate_secret();
+        let now = Utc::now();
+        let previous = time_step(now) - 1;
+        let code = code(&BASE32_NOPAD.decode(secret.as_bytes()).unwrap(), previous);
+
+        // Act.
+        let res = verify(&secret, &code, now);
+
+        // Assert.
+        assert_eq!(res.unwrap(), Some(previous));
+    }
+
+    #[test]
+    fn test_verify_rejects_old() {
+        // Arrange.
+        let secret = generate_secret();
+        let now = Utc::now();
+        let old = time_step(now) - 5;
+        let code = code(&BASE32_NOPAD.decode(secret.as_bytes()).unwrap(), old);
+
+        // Act.
+        let res = verify(&secret, &code, now);
+
+        // Assert.
+        assert_eq!(res.unwrap(), None);
+    }
+}
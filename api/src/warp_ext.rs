@@ -1,5 +1,7 @@
 use serde::Serialize;
 use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use warp::filters::BoxedFilter;
 use warp::http::StatusCode;
 use warp::{Filter, Reply};
 
@@ -9,6 +11,46 @@ pub fn with_clone<T: Clone + Send>(
     warp::any().map(move || item.clone())
 }
 
+/// Extract the IP address of the client. The X-Forwarded-For header is only trusted when the
+/// request comes from one of the trusted proxies, since clients can set it to anything.
+pub fn client_ip(trusted_proxies: Vec<IpAddr>) -> BoxedFilter<(Option<String>,)> {
+    warp::header::optional::<String>("x-forwarded-for")
+        .and(warp::addr::remote())
+        .map(
+            move |forwarded_for: Option<String>, remote: Option<SocketAddr>| {
+                forwarded_ip(
+                    forwarded_for.as_deref(),
+                    remote.map(|addr| addr.ip()),
+                    &trusted_proxies,
+                )
+                .map(|ip| ip.to_string())
+            },
+        )
+        .boxed()
+}
+
+/// Find the client IP address by following X-Forwarded-For from the right, as long as each hop
+/// is a trusted proxy.
+fn forwarded_ip(
+    forwarded_for: Option<&str>,
+    remote: Option<IpAddr>,
+    trusted_proxies: &[IpAddr],
+) -> Option<IpAddr> {
+    let mut ip = remote?;
+    if let Some(forwarded_for) = forwarded_for {
+        for hop in forwarded_for.rsplit(',') {
+            if !trusted_proxies.contains(&ip) {
+                break;
+            }
+            match hop.trim().parse() {
+                Ok(hop) => ip = hop,
+                Err(_) => break,
+            }
+        }
+    }
+    Some(ip)
+}
+
 pub fn convert_err<T>(res: anyhow::Result<T>) -> Box<dyn Reply>
 where
     T: Reply + 'static,
@@ -41,3 +83,61 @@ impl<T: Reply + 'static> BoxReply for T {
         Box::new(self) as Box<dyn Reply>
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIENT: &str = "203.0.113.7";
+    const PROXY: &str = "10.0.0.2";
+
+    #[test]
+    fn test_forwarded_ip_untrusted_remote() {
+        // Act.
+        let client_ip = forwarded_ip(Some(CLIENT), Some(ip(PROXY)), &[]);
+
+        // Assert.
+        assert_eq!(client_ip, Some(ip(PROXY)));
+    }
+
+    #[test]
+    fn test_forwarded_ip_trusted_proxy() {
+        // Arrange.
+        let forwarded_for = format!("198.51.100.1, {}", CLIENT);
+
+        // Act.
+        let client_ip = forwarded_ip(Some(&forwarded_for), Some(ip(PROXY)), &[ip(PROXY)]);
+
+        // Assert.
+        assert_eq!(client_ip, Some(ip(CLIENT)));
+    }
+
+    #[test]
+    fn test_forwarded_ip_chained_proxies() {
+        // Arrange.
+        let forwarded_for = format!("{}, 10.0.0.1", CLIENT);
+
+        // Act.
+        let client_ip = forwarded_ip(
+            Some(&forwarded_for),
+            Some(ip(PROXY)),
+            &[ip(PROXY), ip("10.0.0.1")],
+        );
+
+        // Assert.
+        assert_eq!(client_ip, Some(ip(CLIENT)));
+    }
+
+    #[test]
+    fn test_forwarded_ip_invalid_hop() {
+        // Act.
+        let client_ip = forwarded_ip(Some("unknown"), Some(ip(PROXY)), &[ip(PROXY)]);
+
+        // Assert.
+        assert_eq!(client_ip, Some(ip(PROXY)));
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+}